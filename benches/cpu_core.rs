@@ -0,0 +1,54 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nes_rs::cpu::CpuBuilder;
+
+// instructions executed per iteration, so throughput reads as emulated
+// instructions per second
+const INSTRUCTIONS: u64 = 10_000;
+
+// loop: CLC; ADC #$03; SEC; SBC #$01; ADC $10; SBC $11; JMP loop
+const ARITHMETIC: [u8; 13] = [
+    0x18, 0x69, 0x03, 0x38, 0xe9, 0x01, 0x65, 0x10, 0xe5, 0x11, 0x4c, 0x00, 0x06,
+];
+
+// start: LDX #$00; copy: LDA $0200,X; STA $0300,X; INX; BNE copy; JMP start
+const MEMORY_COPY: [u8; 14] = [
+    0xa2, 0x00, 0xbd, 0x00, 0x02, 0x9d, 0x00, 0x03, 0xe8, 0xd0, 0xf7, 0x4c, 0x00, 0x06,
+];
+
+// loop: DEX; BEQ +2; BMI +0; BPL +0; BNE loop; JMP loop
+const BRANCHES: [u8; 12] = [
+    0xca, 0xf0, 0x02, 0x30, 0x00, 0x10, 0x00, 0xd0, 0xf7, 0x4c, 0x00, 0x06,
+];
+
+fn bench_program(c: &mut Criterion, name: &str, program: &[u8]) {
+    let mut cpu = CpuBuilder::new()
+        .program(program)
+        .mem(0x0200, &[0x5a; 0x100])
+        .build();
+
+    let mut group = c.benchmark_group("cpu_core");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                cpu.step().unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn arithmetic(c: &mut Criterion) {
+    bench_program(c, "arithmetic", &ARITHMETIC);
+}
+
+fn memory_copy(c: &mut Criterion) {
+    bench_program(c, "memory_copy", &MEMORY_COPY);
+}
+
+fn branches(c: &mut Criterion) {
+    bench_program(c, "branches", &BRANCHES);
+}
+
+criterion_group!(benches, arithmetic, memory_copy, branches);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nes_rs::bus::Bus;
+use nes_rs::cartridge::Rom;
+use nes_rs::cpu::{Mem, CPU};
+
+const INSTRUCTIONS: u64 = 10_000;
+
+// loop: INX; DEY; ADC #$01; BNE loop; JMP loop
+const BUSY_LOOP: [u8; 9] = [0xe8, 0x88, 0x69, 0x01, 0xd0, 0xfa, 0x4c, 0x00, 0x06];
+
+fn busy_loop(c: &mut Criterion) {
+    let mut cpu = CPU::new(Bus::new(Rom::empty()));
+    for (i, byte) in BUSY_LOOP.iter().enumerate() {
+        cpu.mem_write(0x0600 + i as u16, *byte);
+    }
+    cpu.program_counter = 0x0600;
+
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function("busy_loop", |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                cpu.step().unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, busy_loop);
+criterion_main!(benches);
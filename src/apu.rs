@@ -0,0 +1,56 @@
+/// The part of the DMC channel that reads sample bytes from CPU memory.
+/// Each fetch halts the CPU for 4 cycles.
+pub trait SampleFetcher {
+    /// The address of the next sample byte, if the channel wants it now.
+    fn pending_fetch(&mut self) -> Option<u16>;
+
+    /// Hands over the byte read for `pending_fetch`.
+    fn deliver(&mut self, data: u8);
+
+    /// Drops any fetch in progress, as the reset button does.
+    fn reset(&mut self) {}
+}
+
+/// Stands in for the APU until sound is emulated. It keeps whatever is
+/// written to its registers, so games can initialise it, and reports every
+/// channel as silent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Apu {
+    // 0x4000-0x4017, including the slots that belong to other devices
+    registers: [u8; 0x18],
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            registers: [0; 0x18],
+        }
+    }
+
+    /// Silences every channel, as the reset button does by clearing 0x4015.
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0);
+    }
+
+    /// Handles a write to 0x4000-0x4013, 0x4015 or 0x4017.
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        self.registers[(addr - 0x4000) as usize] = data;
+    }
+
+    /// The last value written to `addr`.
+    pub fn register(&self, addr: u16) -> u8 {
+        self.registers[(addr - 0x4000) as usize]
+    }
+
+    /// Reads 0x4015: no channel is playing and no interrupt is pending.
+    pub fn read_status(&self) -> u8 {
+        0
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu::new()
+    }
+}
@@ -1,23 +1,754 @@
-use crate::{cartridge::Rom, cpu::Mem};
+use std::io::{self, Read, Write};
+
+use crate::{
+    apu::{Apu, SampleFetcher},
+    cartridge::{BankInfo, Mapper, Rom, RomError},
+    cpu::Mem,
+    frame::Frame,
+    joypad::Joypad,
+    mirroring::{mirror_cpu_ram, mirror_ppu_register},
+    ppu::{self, PpuRegisters},
+};
+
+#[cfg(feature = "access-log")]
+use crate::testing::Access;
 
 pub struct Bus {
     cpu_vram: [u8; 2048],
-    rom: Rom,
+    mapper: Box<dyn Mapper>,
+    // None until a PPU is attached; its registers are open bus until then
+    ppu: Option<Box<dyn PpuRegisters>>,
+    apu: Apu,
+    dmc: Option<Box<dyn SampleFetcher>>,
+    // what the last OAM DMA copied, until the PPU has its own OAM
+    oam: [u8; 256],
+    // cycles DMA halted the CPU for that it has not been told about
+    stall_cycles: u64,
+    joypads: [Joypad; 2],
+    // the byte the last read or write put on the data bus, which is what
+    // reads of addresses nothing answers return
+    last_bus_value: u8,
+    // CPU cycles since power on
+    cycles: u64,
+    frame: Frame,
+    // CPU cycles into the current frame
+    frame_cycles: u64,
+    on_frame: Option<Box<FrameCallback>>,
+    // a bit per RAM byte written with a new value; None unless tracking
+    dirty: Option<Box<[u64; 32]>>,
+    #[cfg(feature = "access-log")]
+    access_logger: Option<Box<AccessLogger>>,
+    // the instruction the CPU is running, for logs and warnings
+    pc: u16,
+    // whether the next write the cartridge rejects gets reported
+    warn_rom_writes: bool,
+    granularity: TickGranularity,
+    rom_overrides: Vec<RomOverride>,
+    // the cartridge's IRQ output as of the last tick
+    irq_line: bool,
+    // None unless counting accesses
+    access_stats: Option<Box<AccessStats>>,
+    // cycles the CPU's accesses have ticked so far in this instruction; None
+    // unless ticking per access
+    access_cycles: Option<u8>,
+}
+
+// a Game Genie style patch: reads of `addr` return `value`, if what is
+// really there matches `compare`
+struct RomOverride {
+    addr: u16,
+    compare: Option<u8>,
+    value: u8,
+}
+
+/// How often the bus lets its devices catch up with the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TickGranularity {
+    /// Once per instruction, for all of its cycles. The fastest.
+    #[default]
+    Instruction,
+    /// One cycle before each read and write the CPU makes, then whatever
+    /// the instruction took beyond those at its end, so devices see the
+    /// accesses inside an instruction at about the right time.
+    Access,
+}
+
+/// One read or write passed to the logger set with `Bus::set_access_logger`.
+#[cfg(feature = "access-log")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusAccess {
+    /// The bus is ticked per instruction, so this is the cycle the
+    /// instruction making the access started on.
+    pub cycle: u64,
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+    pub kind: Access,
+}
+
+/// What answers in a range of the CPU address space, see `Bus::memory_map`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Ram,
+    PpuRegisters,
+    ApuIo,
+    PrgRam,
+    PrgRom,
+    Unmapped,
+}
+
+const REGION_KINDS: usize = 6;
+
+/// Reads and writes per kind of region, see `Bus::count_accesses`. The
+/// cartridge's 0x6000-0x7FFF counts as PRG RAM whether or not it has any.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessStats {
+    reads: [u64; REGION_KINDS],
+    writes: [u64; REGION_KINDS],
+}
+
+impl AccessStats {
+    pub fn reads(&self, kind: RegionKind) -> u64 {
+        self.reads[kind as usize]
+    }
+
+    pub fn writes(&self, kind: RegionKind) -> u64 {
+        self.writes[kind as usize]
+    }
+}
+
+/// A range of the CPU address space, `start` to `end` inclusive. A mirror
+/// names the address its first byte repeats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub kind: RegionKind,
+    pub mirrors_of: Option<u16>,
+    pub writable: bool,
+}
+
+impl Region {
+    pub fn new(start: u16, end: u16, kind: RegionKind, writable: bool) -> Self {
+        Region {
+            start,
+            end,
+            kind,
+            mirrors_of: None,
+            writable,
+        }
+    }
+
+    /// The same range, marked as repeating what is at `addr`.
+    pub fn mirroring(mut self, addr: u16) -> Self {
+        self.mirrors_of = Some(addr);
+        self
+    }
+}
+
+/// The contents of CPU RAM at some point, see `Bus::snapshot_ram`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RamSnapshot {
+    #[cfg_attr(feature = "serde", serde(with = "byte_array"))]
+    ram: [u8; 2048],
+}
+
+/// Everything on the bus that running a program changes, see
+/// `Bus::snapshot`. ROM and attached devices are left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BusSnapshot {
+    pub ram: RamSnapshot,
+    // whatever the mapper needs to pick up where it was, e.g. PRG RAM
+    mapper: Vec<u8>,
+    apu: Apu,
+    #[cfg_attr(feature = "serde", serde(with = "byte_array"))]
+    oam: [u8; 256],
+    stall_cycles: u64,
+    joypads: [Joypad; 2],
+    last_bus_value: u8,
+    cycles: u64,
+    frame_cycles: u64,
+}
+
+// serde only derives arrays up to 32 elements, so RAM and OAM go through as
+// bytes
+#[cfg(feature = "serde")]
+mod byte_array {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &format!("{} bytes", N).as_str()))
+    }
 }
 
 impl Bus {
+    /// A bus with `rom` plugged in. No PPU is attached, so 0x2000-0x3FFF
+    /// read back the open bus and take writes without effect, letting
+    /// programs that set up the PPU run without one; see `attach_ppu`.
+    ///
+    /// # Panics
+    ///
+    /// If no mapper supports `rom`; `Rom::into_mapper` and `with_mapper`
+    /// report that as an error instead.
     pub fn new(rom: Rom) -> Self {
+        match rom.into_mapper() {
+            Ok(mapper) => Bus::with_mapper(mapper),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// A bus with `mapper` as the cartridge.
+    pub fn with_mapper(mapper: Box<dyn Mapper>) -> Self {
         Bus {
             cpu_vram: [0; 2048],
-            rom: rom,
+            mapper,
+            ppu: None,
+            apu: Apu::new(),
+            dmc: None,
+            oam: [0; 256],
+            stall_cycles: 0,
+            joypads: [Joypad::new(), Joypad::new()],
+            last_bus_value: 0,
+            cycles: 0,
+            frame: Frame::new(),
+            frame_cycles: 0,
+            on_frame: None,
+            dirty: None,
+            #[cfg(feature = "access-log")]
+            access_logger: None,
+            pc: 0,
+            warn_rom_writes: false,
+            granularity: TickGranularity::Instruction,
+            rom_overrides: Vec::new(),
+            irq_line: false,
+            access_stats: None,
+            access_cycles: None,
+        }
+    }
+
+    /// A bus that calls `callback` once per frame with the finished picture,
+    /// so a frontend can present it and update the controllers. A PPU that
+    /// draws ends frames as it enters vertical blank; otherwise they end
+    /// every 29781 CPU cycles and are blank.
+    pub fn new_with_callback<F>(rom: Rom, callback: F) -> Self
+    where
+        F: FnMut(&Frame, &mut [Joypad; 2]) + 'static,
+    {
+        let mut bus = Bus::new(rom);
+        bus.on_frame = Some(Box::new(callback));
+        bus
+    }
+
+    /// Swaps in the cartridge `rom`, as if the console had been powered on
+    /// with it: RAM and the cartridge's PRG RAM start cleared, and the open
+    /// bus, DMA and ROM overrides of the old game are dropped. The CPU still
+    /// has to be reset to pick up the new vectors, see
+    /// `NesCpu::insert_cartridge`.
+    pub fn insert_cartridge(&mut self, rom: Rom) -> Result<(), RomError> {
+        self.mapper = rom.into_mapper()?;
+        self.restore_ram(&RamSnapshot { ram: [0; 2048] });
+        self.oam = [0; 256];
+        self.stall_cycles = 0;
+        self.last_bus_value = 0;
+        self.rom_overrides.clear();
+        Ok(())
+    }
+
+    /// Which banks the cartridge has mapped right now.
+    pub fn bank_info(&self) -> BankInfo {
+        self.mapper.bank_info()
+    }
+
+    /// Whether the cartridge keeps its PRG RAM with a battery.
+    pub fn has_battery(&self) -> bool {
+        self.mapper.battery_ram().is_some()
+    }
+
+    /// Writes out the battery-backed PRG RAM as it is, typically 8KB, for
+    /// a `.sav` file. Writes nothing for cartridges without a battery.
+    pub fn save_prg_ram(&self, mut writer: impl Write) -> io::Result<()> {
+        match self.mapper.battery_ram() {
+            Some(ram) => writer.write_all(ram),
+            None => Ok(()),
+        }
+    }
+
+    /// Fills the battery-backed PRG RAM from what `save_prg_ram` wrote.
+    /// Reads nothing for cartridges without a battery.
+    pub fn load_prg_ram(&mut self, mut reader: impl Read) -> io::Result<()> {
+        match self.mapper.battery_ram_mut() {
+            Some(ram) => reader.read_exact(ram),
+            None => Ok(()),
+        }
+    }
+
+    /// Presses the reset button for everything on the bus: the PPU, APU,
+    /// controllers, DMA and cartridge go back to their reset state, while RAM
+    /// keeps its contents. `NesCpu::reset_system` resets the CPU after this.
+    pub fn reset(&mut self) {
+        if let Some(ppu) = self.ppu.as_mut() {
+            ppu.reset();
+        }
+        self.apu.reset();
+        if let Some(dmc) = self.dmc.as_mut() {
+            dmc.reset();
+        }
+        for joypad in self.joypads.iter_mut() {
+            joypad.reset();
+        }
+        self.stall_cycles = 0;
+        self.access_cycles = None;
+        self.mapper.reset();
+        self.irq_line = self.mapper.irq_pending();
+    }
+
+    pub fn snapshot_ram(&self) -> RamSnapshot {
+        RamSnapshot { ram: self.cpu_vram }
+    }
+
+    pub fn restore_ram(&mut self, snapshot: &RamSnapshot) {
+        if self.dirty.is_none() {
+            self.cpu_vram = snapshot.ram;
+            return;
+        }
+        for (index, data) in snapshot.ram.iter().enumerate() {
+            self.write_ram(index, *data);
+        }
+    }
+
+    /// The cartridge's PRG RAM at 0x6000-0x7FFF, if it has any, for test
+    /// harnesses to look at, e.g. for the results blargg's test ROMs leave
+    /// there.
+    pub fn prg_ram(&self) -> Option<&[u8]> {
+        self.mapper.prg_ram()
+    }
+
+    /// The state of RAM, the cartridge, the DMA and the controllers, and the
+    /// cycle count, to go with a `CpuSnapshot`.
+    pub fn snapshot(&self) -> BusSnapshot {
+        BusSnapshot {
+            ram: self.snapshot_ram(),
+            mapper: self.mapper.snapshot(),
+            apu: self.apu.clone(),
+            oam: self.oam,
+            stall_cycles: self.stall_cycles,
+            joypads: self.joypads,
+            last_bus_value: self.last_bus_value,
+            cycles: self.cycles,
+            frame_cycles: self.frame_cycles,
         }
     }
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.rom.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            addr = addr % 0x4000
+
+    /// Puts everything `snapshot` covers back. The same cartridge has to be
+    /// plugged in as when it was taken.
+    pub fn restore(&mut self, snapshot: &BusSnapshot) {
+        self.restore_ram(&snapshot.ram);
+        self.mapper.restore(&snapshot.mapper);
+        self.apu = snapshot.apu.clone();
+        self.oam = snapshot.oam;
+        self.stall_cycles = snapshot.stall_cycles;
+        self.joypads = snapshot.joypads;
+        self.last_bus_value = snapshot.last_bus_value;
+        self.cycles = snapshot.cycles;
+        self.frame_cycles = snapshot.frame_cycles;
+    }
+
+    /// The CPU address space from 0x0000 to 0xFFFF, in order and without
+    /// gaps. The cartridge describes its own part, so the map follows it
+    /// banking PRG RAM in or out.
+    pub fn memory_map(&self) -> Vec<Region> {
+        let ram_size = self.cpu_vram.len() as u16;
+        let mut map = vec![
+            Region::new(RAM, RAM + ram_size - 1, RegionKind::Ram, true),
+            Region::new(RAM + ram_size, RAM_MIRRORS_END, RegionKind::Ram, true).mirroring(RAM),
+            Region::new(
+                PPU_REGISTERS,
+                PPU_REGISTERS + 7,
+                RegionKind::PpuRegisters,
+                true,
+            ),
+            Region::new(
+                PPU_REGISTERS + 8,
+                PPU_REGISTERS_MIRRORS_END,
+                RegionKind::PpuRegisters,
+                true,
+            )
+            .mirroring(PPU_REGISTERS),
+            Region::new(APU_REGISTERS, JOYPAD_2, RegionKind::ApuIo, true),
+            Region::new(APU_TEST_MODE, APU_TEST_MODE_END, RegionKind::ApuIo, false),
+        ];
+        let mut next = APU_TEST_MODE_END as u32 + 1;
+        for cartridge in self.mapper.cpu_regions() {
+            if (cartridge.start as u32) > next {
+                map.push(Region::new(
+                    next as u16,
+                    cartridge.start - 1,
+                    RegionKind::Unmapped,
+                    false,
+                ));
+            }
+            next = cartridge.end as u32 + 1;
+            map.push(cartridge);
+        }
+        if next <= CARTRIDGE_END as u32 {
+            map.push(Region::new(
+                next as u16,
+                CARTRIDGE_END,
+                RegionKind::Unmapped,
+                false,
+            ));
+        }
+        map
+    }
+
+    /// The controller plugged into `port`, 0 or 1, for pressing its buttons.
+    pub fn joypad_mut(&mut self, port: usize) -> &mut Joypad {
+        &mut self.joypads[port]
+    }
+
+    /// CPU cycles the bus has been ticked for, to time the other devices by.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Maps `ppu`'s registers, e.g. those of a `ppu::PPU`, into
+    /// 0x2000-0x3FFF, and runs it alongside the CPU.
+    pub fn attach_ppu(&mut self, ppu: Box<dyn PpuRegisters>) {
+        self.ppu = Some(ppu);
+    }
+
+    /// Lets `dmc` fetch samples, stealing cycles from the CPU to do so.
+    pub fn attach_dmc(&mut self, dmc: Box<dyn SampleFetcher>) {
+        self.dmc = Some(dmc);
+    }
+
+    /// Calls `logger` with every read and write made through the bus, in the
+    /// order they happen. Peeks are not logged.
+    #[cfg(feature = "access-log")]
+    pub fn set_access_logger(&mut self, logger: Box<AccessLogger>) {
+        self.access_logger = Some(logger);
+    }
+
+    #[cfg(feature = "access-log")]
+    pub fn clear_access_logger(&mut self) {
+        self.access_logger = None;
+    }
+
+    pub fn set_tick_granularity(&mut self, granularity: TickGranularity) {
+        self.granularity = granularity;
+    }
+
+    /// Makes reads of `addr`, in PRG ROM, return `value` instead, as a Game
+    /// Genie does. With `compare`, only while the ROM holds that byte, so the
+    /// patch stays off when another bank is switched in. Meant for a handful
+    /// of codes: every ROM read checks them all. Replaces any override
+    /// already at `addr`.
+    pub fn add_rom_override(&mut self, addr: u16, compare: Option<u8>, value: u8) {
+        assert!(addr >= PRG_ROM, "{:#06x} is not in PRG ROM", addr);
+        self.remove_override(addr);
+        self.rom_overrides.push(RomOverride {
+            addr,
+            compare,
+            value,
+        });
+    }
+
+    pub fn remove_override(&mut self, addr: u16) {
+        self.rom_overrides.retain(|patch| patch.addr != addr);
+    }
+
+    /// Tells the cartridge the level of PPU address line A12, for MMC3's
+    /// scanline counter. An attached PPU does this itself as it renders.
+    pub fn notify_a12(&mut self, high: bool) {
+        self.mapper.notify_a12(high);
+        self.irq_line = self.mapper.irq_pending();
+    }
+
+    /// Tells the cartridge which scanline the PPU has started, for MMC5's
+    /// scanline IRQ. An attached PPU does this itself.
+    pub fn notify_scanline(&mut self, scanline: u16) {
+        self.mapper.notify_scanline(scanline);
+        self.irq_line = self.mapper.irq_pending();
+    }
+
+    /// Writes the cartridge had no use for, e.g. a program storing into NROM's
+    /// PRG ROM. Mappers that switch banks take every write and report none.
+    pub fn rom_write_violations(&self) -> u64 {
+        self.mapper.rom_write_violations()
+    }
+
+    /// Prints the address and PC of the next write the cartridge rejects, then
+    /// stays quiet, so one stray store does not flood the output.
+    pub fn warn_on_rom_writes(&mut self, enabled: bool) {
+        self.warn_rom_writes = enabled;
+    }
+
+    /// Counts reads and writes per kind of region, for `access_stats`. Off by
+    /// default; turning it on starts from zero.
+    pub fn count_accesses(&mut self, enabled: bool) {
+        self.access_stats = if enabled { Some(Box::default()) } else { None };
+    }
+
+    /// The counts so far, if counting.
+    pub fn access_stats(&self) -> Option<&AccessStats> {
+        self.access_stats.as_deref()
+    }
+
+    /// Returns the counts so far and starts again from zero, e.g. once a
+    /// frame.
+    pub fn take_access_stats(&mut self) -> Option<AccessStats> {
+        self.access_stats.as_deref_mut().map(std::mem::take)
+    }
+
+    /// Keeps track of which RAM bytes change value, for `take_dirty`. Off by
+    /// default; turning it on starts with nothing changed.
+    pub fn track_ram_changes(&mut self, enabled: bool) {
+        self.dirty = if enabled {
+            Some(Box::new([0; 32]))
+        } else {
+            None
+        };
+    }
+
+    /// The RAM offsets, from 0x0000 to 0x07FF, written with a new value since
+    /// the last call, lowest first. Empty unless tracking is on.
+    pub fn take_dirty(&mut self) -> Vec<u16> {
+        let dirty = match self.dirty.as_mut() {
+            Some(dirty) => dirty,
+            None => return vec![],
+        };
+        let mut changed = vec![];
+        for (word, bits) in dirty.iter_mut().enumerate() {
+            while *bits != 0 {
+                let bit = bits.trailing_zeros();
+                changed.push((word * 64) as u16 + bit as u16);
+                *bits &= *bits - 1;
+            }
+        }
+        changed
+    }
+
+    fn write_ram(&mut self, index: usize, data: u8) {
+        if let Some(dirty) = self.dirty.as_mut() {
+            if self.cpu_vram[index] != data {
+                dirty[index / 64] |= 1 << (index % 64);
+            }
+        }
+        self.cpu_vram[index] = data;
+    }
+
+    // the decoding behind `mem_read`, also used for DMA, which ticks nothing
+    fn read(&mut self, addr: u16) -> u8 {
+        if let Some(stats) = self.access_stats.as_mut() {
+            stats.reads[region_kind(addr) as usize] += 1;
+        }
+        let data = match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[mirror_cpu_ram(addr) as usize],
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let register = ppu_register(addr);
+                match self.ppu.as_mut() {
+                    Some(ppu) if ppu::is_readable(register) => {
+                        ppu.read_register(register, self.mapper.as_mut())
+                    }
+                    // write-only registers, and every one without a PPU, leave
+                    // the data bus as it was
+                    _ => self.last_bus_value,
+                }
+            }
+            APU_REGISTERS..=OAM_DMA => self.last_bus_value,
+            APU_STATUS => self.apu.read_status(),
+            JOYPAD_1 => self.last_bus_value & JOYPAD_OPEN_BUS_BITS | self.joypads[0].read(),
+            JOYPAD_2 => self.last_bus_value & JOYPAD_OPEN_BUS_BITS | self.joypads[1].read(),
+            APU_TEST_MODE..=APU_TEST_MODE_END => self.last_bus_value,
+            CARTRIDGE..=CARTRIDGE_END => match self.mapper.cpu_read(addr) {
+                Some(data) => self.patch_rom(addr, data),
+                None => self.last_bus_value,
+            },
+        };
+        self.last_bus_value = data;
+        #[cfg(feature = "access-log")]
+        self.log_access(Access::Read, addr, data);
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let Some(stats) = self.access_stats.as_mut() {
+            stats.writes[region_kind(addr) as usize] += 1;
+        }
+        self.last_bus_value = data;
+        #[cfg(feature = "access-log")]
+        self.log_access(Access::Write, addr, data);
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.write_ram(mirror_cpu_ram(addr) as usize, data),
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let register = ppu_register(addr);
+                match self.ppu.as_mut() {
+                    Some(ppu) if ppu::is_writable(register) => {
+                        ppu.write_register(register, data, self.mapper.as_mut())
+                    }
+                    // PPUSTATUS ignores writes, bar leaving them on the bus, as
+                    // do all the registers without a PPU
+                    _ => {}
+                }
+            }
+            APU_REGISTERS..=APU_REGISTERS_END | APU_STATUS | JOYPAD_2 => {
+                self.apu.write_register(addr, data)
+            }
+            OAM_DMA => self.oam_dma(data),
+            // the strobe line runs to both ports
+            JOYPAD_1 => {
+                for joypad in self.joypads.iter_mut() {
+                    joypad.write(data);
+                }
+            }
+            APU_TEST_MODE..=APU_TEST_MODE_END => {}
+            CARTRIDGE..=CARTRIDGE_END => {
+                let violations = self.mapper.rom_write_violations();
+                self.mapper.cpu_write(addr, data);
+                if self.warn_rom_writes && self.mapper.rom_write_violations() > violations {
+                    eprintln!(
+                        "Write of {:#04x} to ROM at {:#06x} from PC {:#06x}; further ROM writes are not reported",
+                        data, addr, self.pc
+                    );
+                    self.warn_rom_writes = false;
+                }
+            }
+        }
+    }
+
+    // copies page `page` to OAM, halting the CPU for 513 cycles, or 514 if
+    // the copy starts on an odd cycle. The bus is ticked per instruction, so
+    // the parity is taken from the start of the writing instruction.
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        for i in 0..=255 {
+            self.oam[i as usize] = self.read(start + i);
+        }
+        if let Some(ppu) = self.ppu.as_mut() {
+            for byte in self.oam {
+                ppu.write_register(ppu::OAMDATA, byte, self.mapper.as_mut());
+            }
+        }
+        let stall = 513 + self.cycles % 2;
+        self.advance(stall);
+        self.stall_cycles += stall;
+    }
+
+    #[cfg(feature = "access-log")]
+    fn log_access(&mut self, kind: Access, addr: u16, value: u8) {
+        if let Some(logger) = self.access_logger.as_mut() {
+            logger(BusAccess {
+                cycle: self.cycles,
+                pc: self.pc,
+                addr,
+                value,
+                kind,
+            });
+        }
+    }
+
+    // what a read of PRG ROM that found `data` returns once overrides apply
+    fn patch_rom(&self, addr: u16, data: u8) -> u8 {
+        self.rom_overrides
+            .iter()
+            .find(|patch| patch.addr == addr && patch.compare.is_none_or(|byte| byte == data))
+            .map_or(data, |patch| patch.value)
+    }
+
+    fn tick_access(&mut self) {
+        if let Some(ticked) = self.access_cycles.as_mut() {
+            *ticked += 1;
+            self.advance(1);
+            self.fetch_sample();
+        }
+    }
+
+    fn advance(&mut self, cpu_cycles: u64) {
+        if let Some(ppu) = self.ppu.as_mut() {
+            ppu.tick(cpu_cycles, self.mapper.as_mut());
+        }
+        self.mapper.tick(cpu_cycles);
+        self.irq_line = self.mapper.irq_pending();
+        self.cycles += cpu_cycles;
+        self.frame_cycles += cpu_cycles;
+        // a PPU that draws keeps its own frame timing
+        let frame_end = match self.ppu.as_mut() {
+            Some(ppu) if ppu.frame().is_some() => {
+                let end = ppu.take_frame_end();
+                if end {
+                    self.frame_cycles = 0;
+                }
+                end
+            }
+            _ if self.frame_cycles >= CYCLES_PER_FRAME => {
+                self.frame_cycles -= CYCLES_PER_FRAME;
+                true
+            }
+            _ => false,
+        };
+        if frame_end {
+            let frame = self
+                .ppu
+                .as_ref()
+                .and_then(|ppu| ppu.frame())
+                .unwrap_or(&self.frame);
+            if let Some(callback) = self.on_frame.as_mut() {
+                callback(frame, &mut self.joypads);
+            }
+        }
+    }
+
+    // reads the byte the DMC is waiting for, if any, halting the CPU for the
+    // 4 cycles that takes
+    fn fetch_sample(&mut self) {
+        let addr = match self.dmc.as_mut().and_then(|dmc| dmc.pending_fetch()) {
+            Some(addr) => addr,
+            None => return,
+        };
+        let data = self.read(addr);
+        if let Some(dmc) = self.dmc.as_mut() {
+            dmc.deliver(data);
+        }
+        self.advance(DMC_FETCH_CYCLES);
+        self.stall_cycles += DMC_FETCH_CYCLES;
+    }
+
+    // the slice of `cpu_vram` behind `len` bytes from `addr`, if they all
+    // sit in RAM without crossing from one mirror into the next
+    fn ram_range(&self, addr: u16, len: usize) -> Option<std::ops::Range<usize>> {
+        // a logger and the access counters have to see each byte
+        #[cfg(feature = "access-log")]
+        if self.access_logger.is_some() {
+            return None;
+        }
+        if self.access_stats.is_some() {
+            return None;
+        }
+        let end = addr as usize + len;
+        if addr > RAM_MIRRORS_END {
+            return None;
+        }
+        let start = mirror_cpu_ram(addr) as usize;
+        if end <= RAM_MIRRORS_END as usize + 1 && start + len <= self.cpu_vram.len() {
+            Some(start..start + len)
+        } else {
+            None
         }
-        self.rom.prg_rom[addr as usize]
     }
 }
 
@@ -25,42 +756,1195 @@ const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_REGISTERS: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4013;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD_1: u16 = 0x4016;
+// reads come from the second controller, writes go to the APU frame counter
+const JOYPAD_2: u16 = 0x4017;
+// APU and I/O test-mode registers, disabled on retail consoles: reads are
+// open bus and writes do nothing
+const APU_TEST_MODE: u16 = 0x4018;
+const APU_TEST_MODE_END: u16 = 0x401F;
+// the expansion area, PRG RAM and PRG ROM, as the cartridge fills them
+const CARTRIDGE: u16 = 0x4020;
+const CARTRIDGE_END: u16 = 0xFFFF;
+const PRG_RAM: u16 = 0x6000;
+const PRG_ROM: u16 = 0x8000;
+
+const DMC_FETCH_CYCLES: u64 = 4;
+// 262 scanlines of 341 PPU dots, at three dots per CPU cycle
+const CYCLES_PER_FRAME: u64 = 29781;
+
+type FrameCallback = dyn FnMut(&Frame, &mut [Joypad; 2]);
+
+#[cfg(feature = "access-log")]
+type AccessLogger = dyn FnMut(BusAccess);
+
+// controllers only drive the low bits of the data bus; the rest keep
+// whatever was last on it
+const JOYPAD_OPEN_BUS_BITS: u8 = 0b1110_0000;
 
 impl Mem for Bus {
-    fn mem_read(&self, addr: u16) -> u8 {
+    /// Reads of unmapped addresses and write-only registers return open bus:
+    /// the last byte read or written, such as the high byte of the address
+    /// of an absolute LDA.
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.tick_access();
+        self.read(addr)
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
         match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mirror_down_addr = addr & 0b00000111_11111111;
-                self.cpu_vram[mirror_down_addr as usize]
-            }
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[mirror_cpu_ram(addr) as usize],
             PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0b00100000_00000111;
-                todo!("PPU is not supported yet")
-            }
-            0x8000..=0xFFFF => self.read_prg_rom(addr),
-            _ => {
-                println!("Ignoring mem access at {}", addr);
-                0
+                let register = ppu_register(addr);
+                match self.ppu.as_ref() {
+                    Some(ppu) if ppu::is_readable(register) => {
+                        ppu.peek_register(register, self.mapper.as_ref())
+                    }
+                    _ => self.last_bus_value,
+                }
             }
+            APU_REGISTERS..=OAM_DMA => self.last_bus_value,
+            APU_STATUS => self.apu.read_status(),
+            JOYPAD_1 => self.last_bus_value & JOYPAD_OPEN_BUS_BITS | self.joypads[0].peek(),
+            JOYPAD_2 => self.last_bus_value & JOYPAD_OPEN_BUS_BITS | self.joypads[1].peek(),
+            APU_TEST_MODE..=APU_TEST_MODE_END => self.last_bus_value,
+            CARTRIDGE..=CARTRIDGE_END => match self.mapper.cpu_peek(addr) {
+                Some(data) => self.patch_rom(addr, data),
+                None => self.last_bus_value,
+            },
         }
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mirror_down_addr = addr & 0b11111111111;
-                self.cpu_vram[mirror_down_addr as usize] = data;
+        self.tick_access();
+        self.write(addr, data);
+    }
+
+    fn mem_read_into(&mut self, addr: u16, buf: &mut [u8]) {
+        match self.ram_range(addr, buf.len()) {
+            Some(range) => {
+                buf.copy_from_slice(&self.cpu_vram[range]);
+                self.last_bus_value = buf.last().copied().unwrap_or(self.last_bus_value);
             }
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0b00100000_00000111;
-                todo!("PPU is not supported yet");
+            None => {
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    *byte = self.mem_read(addr.wrapping_add(i as u16));
+                }
             }
-            0x8000..=0xFFFF => {
-                panic!("Attempt to write to Cartridge ROM space")
+        }
+    }
+
+    fn mem_write_slice(&mut self, addr: u16, data: &[u8]) {
+        match self.ram_range(addr, data.len()) {
+            Some(range) if self.dirty.is_none() => {
+                self.cpu_vram[range].copy_from_slice(data);
+                self.last_bus_value = data.last().copied().unwrap_or(self.last_bus_value);
             }
             _ => {
-                println!("Ignoring mem write-access at {}", addr);
+                for (i, byte) in data.iter().enumerate() {
+                    self.mem_write(addr.wrapping_add(i as u16), *byte);
+                }
+            }
+        }
+    }
+
+    fn take_fault(&mut self) -> Option<u16> {
+        self.mapper.take_fault()
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_line
+    }
+
+    fn has_nmi_source(&self) -> bool {
+        self.ppu.is_some()
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        self.ppu.as_mut().is_some_and(|ppu| ppu.take_nmi())
+    }
+
+    // the APU will run one cycle per CPU cycle, once it is emulated
+    fn tick(&mut self, cpu_cycles: u8) {
+        let ticked = self.access_cycles.take().unwrap_or(0);
+        // every access is a cycle of its own, so more accesses than cycles
+        // means an operand was fetched twice
+        debug_assert!(
+            ticked <= cpu_cycles,
+            "{} bus accesses in a {} cycle instruction",
+            ticked,
+            cpu_cycles
+        );
+        self.advance(cpu_cycles.saturating_sub(ticked) as u64);
+        self.fetch_sample();
+    }
+
+    fn take_stall_cycles(&mut self) -> u64 {
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    fn begin_instruction(&mut self, pc: u16) {
+        self.pc = pc;
+        if self.granularity == TickGranularity::Access {
+            self.access_cycles = Some(0);
+        }
+    }
+}
+
+// what `addr` decodes to, leaving the cartridge out of it
+fn region_kind(addr: u16) -> RegionKind {
+    match addr {
+        RAM..=RAM_MIRRORS_END => RegionKind::Ram,
+        PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => RegionKind::PpuRegisters,
+        APU_REGISTERS..=APU_TEST_MODE_END => RegionKind::ApuIo,
+        PRG_RAM..=0x7FFF => RegionKind::PrgRam,
+        PRG_ROM..=CARTRIDGE_END => RegionKind::PrgRom,
+        _ => RegionKind::Unmapped,
+    }
+}
+
+// which of the eight PPU registers `addr` lands on
+fn ppu_register(addr: u16) -> u8 {
+    (mirror_ppu_register(addr) - PPU_REGISTERS) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::{test_rom, Mirroring};
+    use crate::cpu::CPU;
+    use crate::joypad::JoypadButton;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Debug, PartialEq)]
+    enum RegisterAccess {
+        Read(u8),
+        Write(u8, u8),
+    }
+
+    // logs every access the bus forwards; reads return the register number
+    struct MockPpu(Rc<RefCell<Vec<RegisterAccess>>>);
+
+    impl PpuRegisters for MockPpu {
+        fn read_register(&mut self, register: u8, _cartridge: &mut dyn Mapper) -> u8 {
+            self.0.borrow_mut().push(RegisterAccess::Read(register));
+            register
+        }
+
+        fn peek_register(&self, register: u8, _cartridge: &dyn Mapper) -> u8 {
+            register
+        }
+
+        fn write_register(&mut self, register: u8, data: u8, _cartridge: &mut dyn Mapper) {
+            self.0
+                .borrow_mut()
+                .push(RegisterAccess::Write(register, data));
+        }
+    }
+
+    fn bus_with_mock_ppu() -> (Bus, Rc<RefCell<Vec<RegisterAccess>>>) {
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut bus = Bus::new(Rom::empty());
+        bus.attach_ppu(Box::new(MockPpu(log.clone())));
+        (bus, log)
+    }
+
+    #[test]
+    fn test_u16_at_0xffff_wraps_into_ram() {
+        let mut rom = Rom::empty();
+        rom.prg_rom[0x7fff] = 0x34;
+        let mut bus = Bus::new(rom);
+        bus.mem_write(0x0000, 0x12);
+        assert_eq!(bus.mem_read_u16(0xffff), 0x1234);
+        assert_eq!(bus.mem_peek_u16(0xffff), 0x1234);
+    }
+
+    #[test]
+    fn test_peek_has_no_side_effects() {
+        let (mut bus, log) = bus_with_mock_ppu();
+        assert_eq!(bus.mem_peek(0x2002), 0x02);
+        assert!(log.borrow().is_empty());
+
+        bus.mem_read(0x2002);
+        assert_eq!(*log.borrow(), [RegisterAccess::Read(0x02)]);
+    }
+
+    #[test]
+    fn test_ppu_registers_are_mirrored_every_eight_bytes() {
+        let (mut bus, log) = bus_with_mock_ppu();
+        bus.mem_write(0x3456, 0x21);
+        assert_eq!(bus.mem_read(0x2002), 2);
+        assert_eq!(bus.mem_read(0x200a), 2);
+        assert_eq!(bus.mem_peek(0x3fff), 7);
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                RegisterAccess::Write(ppu::PPUADDR, 0x21),
+                RegisterAccess::Read(ppu::PPUSTATUS),
+                RegisterAccess::Read(ppu::PPUSTATUS),
+            ]
+        );
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_ppu_access_violations_are_not_forwarded() {
+        let (mut bus, log) = bus_with_mock_ppu();
+        // reads of write-only registers are open bus
+        bus.mem_write(0x0010, 0x5a);
+        assert_eq!(bus.mem_read(0x2000), 0x5a);
+        assert_eq!(bus.mem_peek(0x2006), 0x5a);
+        bus.mem_write(0x2002, 0xff);
+        assert!(log.borrow().is_empty());
+        assert_eq!(bus.mem_read(0x2000), 0xff);
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_ppu_reads_chr_and_reports_vblank() {
+        let rom = test_rom()
+            .chr_banks(1)
+            .chr_bytes(0x0234, &[0x5a, 0xa5])
+            .build();
+        let mut bus = Bus::new(rom);
+        bus.attach_ppu(Box::new(ppu::PPU::new()));
+        bus.mem_write(0x2006, 0x02);
+        bus.mem_write(0x2006, 0x34);
+        // the first read only fills the buffer
+        bus.mem_read(0x2007);
+        assert_eq!(bus.mem_read(0x2007), 0x5a);
+        assert_eq!(bus.mem_peek(0x2007), 0xa5);
+        assert_eq!(bus.mem_read(0x2007), 0xa5);
+
+        // scanline 241 starts 241 * 341 dots, at 3 a cycle, after power on
+        assert_eq!(bus.mem_read(0x2002) & 0x80, 0);
+        bus.mem_write(0x2000, 0x80);
+        bus.advance(241 * 341 / 3 + 1);
+        assert!(bus.take_nmi());
+        assert_eq!(bus.mem_peek(0x2002) & 0x80, 0x80);
+        assert_eq!(bus.mem_read(0x2002) & 0x80, 0x80);
+        assert_eq!(bus.mem_read(0x2002) & 0x80, 0);
+    }
+
+    #[test]
+    fn test_ppu_registers_are_open_bus_without_a_ppu() {
+        let mut bus = Bus::new(Rom::empty());
+        assert!(!bus.has_nmi_source());
+        bus.mem_write(0x2000, 0x80);
+        assert_eq!(bus.take_fault(), None);
+        assert_eq!(bus.mem_read(0x2002), 0x80);
+        assert_eq!(bus.mem_peek(0x2007), 0x80);
+        assert_eq!(bus.take_fault(), None);
+
+        let (bus, _) = bus_with_mock_ppu();
+        assert!(bus.has_nmi_source());
+    }
+
+    #[test]
+    fn test_io_writes_reach_their_devices() {
+        let mut bus = Bus::new(Rom::empty());
+        bus.mem_write(0x4000, 0x30);
+        bus.mem_write(0x4013, 0x01);
+        bus.mem_write(0x4015, 0x0f);
+        bus.mem_write(0x4017, 0x40);
+        bus.mem_write(0x4014, 0x02);
+        bus.mem_write(0x4016, 0x01);
+
+        assert_eq!(bus.apu.register(0x4000), 0x30);
+        assert_eq!(bus.apu.register(0x4013), 0x01);
+        assert_eq!(bus.apu.register(0x4015), 0x0f);
+        assert_eq!(bus.apu.register(0x4017), 0x40);
+        assert_eq!(bus.apu.register(0x4014), 0);
+        assert_eq!(bus.apu.register(0x4016), 0);
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_io_reads_go_to_distinct_handlers() {
+        let mut bus = Bus::new(Rom::empty());
+        bus.mem_write(0x4015, 0x0f);
+        bus.joypad_mut(0).set_button(JoypadButton::BUTTON_A, true);
+        bus.mem_write(0x4016, 1);
+        // the status register reports silence rather than what was written
+        assert_eq!(bus.mem_read(0x4015), 0x00);
+        assert_eq!(bus.mem_read(0x4016), 0x01);
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_joypad_bits_shift_out_of_0x4016() {
+        let mut bus = Bus::new(Rom::empty());
+        // A, Start, Down and Right: 0b1010_1001
+        let pattern =
+            JoypadButton::BUTTON_A | JoypadButton::START | JoypadButton::DOWN | JoypadButton::RIGHT;
+        bus.joypad_mut(0).set_button(pattern, true);
+        bus.joypad_mut(1).set_button(JoypadButton::all(), true);
+
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        let bits: Vec<u8> = (0..8).map(|_| bus.mem_read(0x4016) & 1).collect();
+        assert_eq!(bits, [1, 0, 0, 1, 0, 1, 0, 1]);
+        assert_eq!(bus.mem_read(0x4016) & 1, 1);
+        assert_eq!(bus.mem_read(0x4016) & 1, 1);
+
+        // the second controller was strobed too, and shifts on its own
+        assert_eq!(bus.mem_peek(0x4017) & 1, 1);
+        assert_eq!(bus.mem_read(0x4017) & 1, 1);
+    }
+
+    #[test]
+    fn test_unmapped_reads_return_the_last_bus_value() {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        // LDA #$37; LDA $5000; LDA $4016; STA $00; LDA $4000
+        cpu.mem_write_slice(
+            0x0600,
+            &[
+                0xa9, 0x37, 0xad, 0x00, 0x50, 0xad, 0x16, 0x40, 0x85, 0x00, 0xad, 0x00, 0x40,
+            ],
+        );
+        cpu.program_counter = 0x0600;
+        cpu.bus
+            .joypad_mut(0)
+            .set_button(JoypadButton::BUTTON_A, true);
+        cpu.bus.mem_write(0x4016, 1);
+
+        cpu.step().unwrap();
+        // the immediate operand was the last byte fetched
+        assert_eq!(cpu.bus.mem_peek(0x5000), 0x37);
+        assert_eq!(cpu.bus.mem_read(0x5000), 0x37);
+
+        // an absolute read leaves the high byte of its address on the bus
+        cpu.step().unwrap();
+        assert_eq!(cpu.register_a, 0x50);
+        cpu.step().unwrap();
+        assert_eq!(cpu.register_a, 0x41);
+        // write-only registers read back the same way
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.register_a, 0x40);
+    }
+
+    #[test]
+    fn test_bus_is_ticked_with_the_cpu() {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        // LDX #$03; loop: DEX; BNE loop
+        cpu.mem_write_slice(0x0600, &[0xa2, 0x03, 0xca, 0xd0, 0xfd]);
+        cpu.program_counter = 0x0600;
+        for _ in 0..7 {
+            cpu.step().unwrap();
+        }
+        // 2 + 3 * 2 for DEX + 2 * 3 taken + 2 falling through
+        assert_eq!(cpu.bus.cycles(), 16);
+        assert_eq!(cpu.bus.cycles(), cpu.cycles);
+
+        cpu.reset();
+        assert_eq!(cpu.bus.cycles(), 16 + 7);
+    }
+
+    #[test]
+    fn test_oam_dma_copies_a_page_and_stalls_the_cpu() {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let pattern: Vec<u8> = (0..=255).map(|i: u8| i.wrapping_mul(7)).collect();
+        cpu.mem_write_slice(0x0200, &pattern);
+        // LDA #$02; STA $4014
+        cpu.mem_write_slice(0x0600, &[0xa9, 0x02, 0x8d, 0x14, 0x40]);
+        cpu.program_counter = 0x0600;
+
+        cpu.step().unwrap();
+        let info = cpu.step().unwrap();
+        assert_eq!(&cpu.bus.oam[..], &pattern[..]);
+        // STA started on cycle 2, which is even
+        assert_eq!(info.cycles, 4 + 513);
+        assert_eq!(cpu.cycles, 2 + 4 + 513);
+        assert_eq!(cpu.bus.cycles(), cpu.cycles);
+    }
+
+    #[cfg(feature = "access-log")]
+    #[test]
+    fn test_access_logger_sees_accesses_in_order() {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        cpu.mem_write(0x0010, 0x5a);
+        // LDA $10; STA $0200
+        cpu.mem_write_slice(0x0600, &[0xa5, 0x10, 0x8d, 0x00, 0x02]);
+        cpu.program_counter = 0x0600;
+
+        let log = Rc::new(RefCell::new(vec![]));
+        let sink = log.clone();
+        cpu.bus
+            .set_access_logger(Box::new(move |access| sink.borrow_mut().push(access)));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let log: Vec<_> = log
+            .borrow()
+            .iter()
+            .map(|a| (a.cycle, a.pc, a.kind, a.addr, a.value))
+            .collect();
+        assert_eq!(
+            log,
+            vec![
+                (0, 0x0600, Access::Read, 0x0600, 0xa5),
+                (0, 0x0600, Access::Read, 0x0601, 0x10),
+                (0, 0x0600, Access::Read, 0x0010, 0x5a),
+                (3, 0x0602, Access::Read, 0x0602, 0x8d),
+                (3, 0x0602, Access::Read, 0x0603, 0x00),
+                (3, 0x0602, Access::Read, 0x0604, 0x02),
+                (3, 0x0602, Access::Write, 0x0200, 0x5a),
+            ]
+        );
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Tick,
+        Ppu(RegisterAccess),
+    }
+
+    // a DMC that never wants a sample, only counts the ticks asking it
+    struct TickCounter(Rc<RefCell<Vec<Event>>>);
+
+    impl SampleFetcher for TickCounter {
+        fn pending_fetch(&mut self) -> Option<u16> {
+            self.0.borrow_mut().push(Event::Tick);
+            None
+        }
+
+        fn deliver(&mut self, _data: u8) {}
+    }
+
+    struct EventPpu(Rc<RefCell<Vec<Event>>>);
+
+    impl PpuRegisters for EventPpu {
+        fn read_register(&mut self, register: u8, _cartridge: &mut dyn Mapper) -> u8 {
+            self.0
+                .borrow_mut()
+                .push(Event::Ppu(RegisterAccess::Read(register)));
+            0x10
+        }
+
+        fn peek_register(&self, _register: u8, _cartridge: &dyn Mapper) -> u8 {
+            0x10
+        }
+
+        fn write_register(&mut self, register: u8, data: u8, _cartridge: &mut dyn Mapper) {
+            self.0
+                .borrow_mut()
+                .push(Event::Ppu(RegisterAccess::Write(register, data)));
+        }
+    }
+
+    // runs INC $2007 and returns what the PPU and the DMC saw, in order
+    fn events_of_inc(granularity: TickGranularity) -> (Vec<Event>, u64) {
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut bus = Bus::new(Rom::empty());
+        bus.set_tick_granularity(granularity);
+        bus.attach_ppu(Box::new(EventPpu(events.clone())));
+        bus.attach_dmc(Box::new(TickCounter(events.clone())));
+        let mut cpu = CPU::new(bus);
+        cpu.mem_write_slice(0x0600, &[0xee, 0x07, 0x20]);
+        cpu.program_counter = 0x0600;
+
+        cpu.step().unwrap();
+        let events = events.replace(vec![]);
+        (events, cpu.bus.cycles())
+    }
+
+    #[test]
+    fn test_instruction_ticks_come_after_the_accesses() {
+        let (events, cycles) = events_of_inc(TickGranularity::Instruction);
+        assert_eq!(
+            events,
+            vec![
+                Event::Ppu(RegisterAccess::Read(ppu::PPUDATA)),
+                Event::Ppu(RegisterAccess::Write(ppu::PPUDATA, 0x11)),
+                Event::Tick,
+            ]
+        );
+        assert_eq!(cycles, 6);
+    }
+
+    #[test]
+    fn test_access_ticks_interleave_with_the_accesses() {
+        let (events, cycles) = events_of_inc(TickGranularity::Access);
+        assert_eq!(
+            events,
+            vec![
+                // opcode and operand fetches
+                Event::Tick,
+                Event::Tick,
+                Event::Tick,
+                Event::Tick,
+                Event::Ppu(RegisterAccess::Read(ppu::PPUDATA)),
+                Event::Tick,
+                Event::Ppu(RegisterAccess::Write(ppu::PPUDATA, 0x11)),
+                // the sixth cycle, which the CPU makes no access in
+                Event::Tick,
+            ]
+        );
+        assert_eq!(cycles, 6);
+    }
+
+    #[test]
+    fn test_access_ticks_add_up_to_the_cpu_cycles() {
+        let mut bus = Bus::new(Rom::empty());
+        bus.set_tick_granularity(TickGranularity::Access);
+        let mut cpu = CPU::new(bus);
+        cpu.mem_write_slice(0x0010, &[0xff, 0x02]);
+        cpu.mem_write_slice(0x0020, &[0x00, 0x07]);
+        cpu.mem_write(0x0700, 0x60); // RTS
+        #[rustfmt::skip]
+        let program = [
+            0xa2, 0x01, // LDX #$01
+            0xa0, 0x01, // LDY #$01
+            0xbd, 0x00, 0x02, // LDA $0200,X
+            0xbd, 0xff, 0x02, // LDA $02FF,X, crossing a page
+            0xb9, 0xff, 0x02, // LDA $02FF,Y
+            0xb1, 0x10, // LDA ($10),Y, crossing a page
+            0xa1, 0x0f, // LDA ($0F,X)
+            0xb5, 0x10, // LDA $10,X
+            0x9d, 0xff, 0x02, // STA $02FF,X
+            0x91, 0x10, // STA ($10),Y
+            0xfe, 0x00, 0x02, // INC $0200,X
+            0x1e, 0xff, 0x02, // ASL $02FF,X
+            0x36, 0x10, // ROL $10,X
+            0xce, 0x00, 0x03, // DEC $0300
+            0x4a, // LSR A
+            0x48, // PHA
+            0x68, // PLA
+            0x20, 0x00, 0x07, // JSR $0700
+            0x6c, 0x20, 0x00, // JMP ($0020)
+        ];
+        cpu.mem_write_slice(0x0600, &program);
+        cpu.program_counter = 0x0600;
+
+        for _ in 0..20 {
+            let info = cpu.step().unwrap();
+            assert_eq!(
+                cpu.bus.cycles(),
+                cpu.cycles,
+                "after {:#04x} at {:#06x}",
+                info.opcode,
+                info.pc
+            );
+        }
+        assert_eq!(cpu.program_counter, 0x0700);
+    }
+
+    #[test]
+    fn test_oam_dma_starts_at_oamaddr() {
+        let mut bus = Bus::new(Rom::empty());
+        bus.attach_ppu(Box::new(ppu::PPU::new()));
+        for i in 0..=255u16 {
+            bus.mem_write(0x0200 + i, i as u8);
+        }
+        bus.mem_write(0x2003, 0x08);
+        bus.mem_write(0x4014, 0x02);
+
+        // the page wraps round OAM, so byte 0 is at 0x08 and 0xf8 at 0x00
+        for (addr, expected) in [(0x08, 0x00), (0x0c, 0x04), (0xff, 0xf7), (0x00, 0xf8)] {
+            bus.mem_write(0x2003, addr);
+            assert_eq!(bus.mem_read(0x2004), expected, "{:#04x}", addr);
+        }
+        // attribute bytes lose bits 2-4
+        bus.mem_write(0x2003, 0x02);
+        assert_eq!(bus.mem_read(0x2004), 0xfa & 0xe3);
+    }
+
+    #[test]
+    fn test_oam_dma_from_an_odd_cycle_takes_one_more() {
+        let (mut bus, log) = bus_with_mock_ppu();
+        bus.mem_write(0x0300, 0xab);
+        bus.tick(3);
+        bus.mem_write(0x4014, 0x03);
+        assert_eq!(bus.take_stall_cycles(), 514);
+        assert_eq!(bus.take_stall_cycles(), 0);
+
+        let log = log.borrow();
+        assert_eq!(log.len(), 256);
+        assert_eq!(log[0], RegisterAccess::Write(ppu::OAMDATA, 0xab));
+    }
+
+    // asks for the bytes at `addresses`, one per tick, and keeps what it gets
+    struct FakeDmc {
+        addresses: Vec<u16>,
+        samples: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl SampleFetcher for FakeDmc {
+        fn pending_fetch(&mut self) -> Option<u16> {
+            self.addresses.pop()
+        }
+
+        fn deliver(&mut self, data: u8) {
+            self.samples.borrow_mut().push(data);
+        }
+    }
+
+    #[test]
+    fn test_dmc_fetches_stall_the_cpu() {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        // NOP; NOP; NOP
+        cpu.mem_write_slice(0x0600, &[0xea, 0xea, 0xea]);
+        cpu.mem_write_slice(0x0300, &[0x11, 0x22]);
+        cpu.program_counter = 0x0600;
+        let samples = Rc::new(RefCell::new(vec![]));
+        cpu.bus.attach_dmc(Box::new(FakeDmc {
+            addresses: vec![0x0301, 0x0300],
+            samples: samples.clone(),
+        }));
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(*samples.borrow(), vec![0x11, 0x22]);
+        assert_eq!(cpu.cycles, 3 * 2 + 2 * 4);
+        assert_eq!(cpu.bus.cycles(), cpu.cycles);
+        assert_eq!(cpu.stats().cycles, cpu.cycles);
+    }
+
+    #[test]
+    fn test_frame_callback_fires_once_per_frame() {
+        let frames = Rc::new(RefCell::new(0));
+        let counter = frames.clone();
+        let mut bus = Bus::new_with_callback(Rom::empty(), move |frame, joypads| {
+            assert_eq!(frame.data.len(), 256 * 240 * 3);
+            *counter.borrow_mut() += 1;
+            joypads[0].set_button(JoypadButton::START, true);
+        });
+
+        for _ in 0..(CYCLES_PER_FRAME / 7) {
+            bus.tick(7);
+        }
+        assert_eq!(*frames.borrow(), 0);
+        bus.tick(7);
+        assert_eq!(*frames.borrow(), 1);
+
+        // input set by the callback is what the game reads next
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        let bits: Vec<u8> = (0..4).map(|_| bus.mem_read(0x4016) & 1).collect();
+        assert_eq!(bits, [0, 0, 0, 1]);
+
+        for _ in 0..(2 * CYCLES_PER_FRAME / 7 + 1) {
+            bus.tick(7);
+        }
+        assert_eq!(*frames.borrow(), 3);
+    }
+
+    #[test]
+    fn test_frame_callback_follows_the_ppu_vblank() {
+        let frames = Rc::new(RefCell::new(0));
+        let counter = frames.clone();
+        let mut bus = Bus::new_with_callback(Rom::empty(), move |_, _| {
+            *counter.borrow_mut() += 1;
+        });
+        bus.attach_ppu(Box::new(ppu::PPU::new()));
+
+        // scanline 241 starts 241 * 341 dots, at 3 a cycle, after power on
+        for _ in 0..27393 {
+            bus.tick(1);
+        }
+        assert_eq!(*frames.borrow(), 0);
+        bus.tick(1);
+        assert_eq!(*frames.borrow(), 1);
+        assert_eq!(bus.mem_peek(0x2002) & 0x80, 0x80);
+
+        // then every 262 * 341 dots
+        for _ in 0..(CYCLES_PER_FRAME - 1) {
+            bus.tick(1);
+        }
+        assert_eq!(*frames.borrow(), 1);
+        bus.tick(1);
+        assert_eq!(*frames.borrow(), 2);
+        for _ in 0..(2 * CYCLES_PER_FRAME) {
+            bus.tick(1);
+        }
+        assert_eq!(*frames.borrow(), 4);
+    }
+
+    #[test]
+    fn test_16kb_prg_rom_is_mirrored_into_both_halves() {
+        let mut rom = Rom::empty();
+        rom.prg_rom = vec![0; 0x4000];
+        rom.prg_rom[0x3fff] = 0xc3;
+        rom.prg_rom[0x3ffc..0x3ffe].copy_from_slice(&[0x34, 0xc2]);
+        let mut bus = Bus::new(rom);
+        assert_eq!(bus.mem_read(0xffff), 0xc3);
+        assert_eq!(bus.mem_read(0xbfff), 0xc3);
+
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0xc234);
+    }
+
+    #[test]
+    fn test_writes_to_prg_rom_are_ignored() {
+        let mut rom = Rom::empty();
+        rom.prg_rom[0x0000] = 0xea;
+        let mut bus = Bus::new(rom);
+        bus.mem_write(0x8000, 0x00);
+        assert_eq!(bus.take_fault(), Some(0x8000));
+        assert_eq!(bus.mem_read(0x8000), 0xea);
+    }
+
+    // reads return the low byte of the address; writes are kept in order
+    struct MockMapper(Rc<RefCell<Vec<(u16, u8)>>>);
+
+    impl Mapper for MockMapper {
+        fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+            Some(addr as u8)
+        }
+
+        fn cpu_peek(&self, addr: u16) -> Option<u8> {
+            Some(addr as u8)
+        }
+
+        fn cpu_write(&mut self, addr: u16, data: u8) {
+            self.0.borrow_mut().push((addr, data));
+        }
+
+        fn ppu_read(&mut self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Horizontal
+        }
+
+        fn irq_pending(&mut self) -> bool {
+            false
+        }
+
+        fn cpu_regions(&self) -> Vec<Region> {
+            vec![Region::new(0x8000, 0xFFFF, RegionKind::PrgRom, true)]
+        }
+
+        fn bank_info(&self) -> BankInfo {
+            BankInfo {
+                prg: Default::default(),
+                chr: Default::default(),
+                mirroring: Mirroring::Horizontal,
             }
         }
     }
+
+    #[test]
+    fn test_nrom_memory_map() {
+        let mut rom = Rom::empty();
+        rom.prg_rom = vec![0; 0x4000];
+        let mut bus = Bus::new(rom);
+        let map: Vec<_> = bus
+            .memory_map()
+            .iter()
+            .map(|r| (r.start, r.end, r.kind, r.mirrors_of, r.writable))
+            .collect();
+        assert_eq!(
+            map,
+            vec![
+                (0x0000, 0x07ff, RegionKind::Ram, None, true),
+                (0x0800, 0x1fff, RegionKind::Ram, Some(0x0000), true),
+                (0x2000, 0x2007, RegionKind::PpuRegisters, None, true),
+                (0x2008, 0x3fff, RegionKind::PpuRegisters, Some(0x2000), true),
+                (0x4000, 0x4017, RegionKind::ApuIo, None, true),
+                (0x4018, 0x401f, RegionKind::ApuIo, None, false),
+                (0x4020, 0x5fff, RegionKind::Unmapped, None, false),
+                (0x6000, 0x7fff, RegionKind::PrgRam, None, true),
+                (0x8000, 0xbfff, RegionKind::PrgRom, None, false),
+                (0xc000, 0xffff, RegionKind::PrgRom, Some(0x8000), false),
+            ]
+        );
+
+        // the test-mode registers read open bus and ignore writes
+        bus.mem_write(0x4018, 0x5a);
+        bus.mem_write(0x0010, 0x33);
+        assert_eq!(bus.mem_read(0x401f), 0x33);
+        assert_eq!(bus.mem_peek(0x4018), 0x33);
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_memory_map_fills_cartridge_gaps() {
+        let bus = Bus::with_mapper(Box::new(MockMapper(Rc::new(RefCell::new(vec![])))));
+        let map = bus.memory_map();
+        assert_eq!(map.len(), 8);
+        assert_eq!(
+            map[6],
+            Region::new(0x4020, 0x7fff, RegionKind::Unmapped, false)
+        );
+        assert_eq!(
+            map[7],
+            Region::new(0x8000, 0xffff, RegionKind::PrgRom, true)
+        );
+    }
+
+    #[test]
+    fn test_cartridge_space_goes_through_the_mapper() {
+        let writes = Rc::new(RefCell::new(vec![]));
+        let mut bus = Bus::with_mapper(Box::new(MockMapper(writes.clone())));
+        assert_eq!(bus.mem_read(0x8012), 0x12);
+        assert_eq!(bus.mem_peek(0xffab), 0xab);
+        assert_eq!(bus.mem_read(0x4020), 0x20);
+        // 0x401f is below the cartridge, so it stays open bus
+        assert_eq!(bus.mem_read(0x401f), 0x20);
+
+        bus.mem_write(0x8000, 0x01);
+        bus.mem_write(0xe000, 0x02);
+        bus.mem_write(0x5000, 0x03);
+        assert_eq!(
+            *writes.borrow(),
+            vec![(0x8000, 0x01), (0xe000, 0x02), (0x5000, 0x03)]
+        );
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_prg_ram_is_separate_from_work_ram() {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        // LDA #$11; STA $6000; LDA #$22; STA $7FFF; LDA $6000; STA $00
+        let program = [
+            0xa9, 0x11, 0x8d, 0x00, 0x60, 0xa9, 0x22, 0x8d, 0xff, 0x7f, 0xad, 0x00, 0x60, 0x85,
+            0x00,
+        ];
+        cpu.mem_write_slice(0x0600, &program);
+        cpu.program_counter = 0x0600;
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        let prg_ram = cpu.bus.prg_ram().unwrap();
+        assert_eq!(prg_ram.len(), 0x2000);
+        assert_eq!((prg_ram[0x0000], prg_ram[0x1fff]), (0x11, 0x22));
+        assert_eq!(cpu.mem_read(0x0000), 0x11);
+        // work RAM mirrors every 2KB, PRG RAM is somewhere else entirely
+        assert_eq!(cpu.mem_read(0x07ff), 0x00);
+        assert_eq!(cpu.mem_read(0x7fff), 0x22);
+        assert_eq!(cpu.bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_prg_ram_sizes_from_the_header() {
+        let mut rom = Rom::empty();
+        rom.prg_ram_size = 0x0800;
+        let mut bus = Bus::new(rom);
+        bus.mem_write(0x6001, 0x5a);
+        assert_eq!(bus.prg_ram().map(|ram| ram.len()), Some(0x0800));
+        assert_eq!(bus.mem_read(0x6801), 0x5a);
+
+        // a cartridge without any leaves the addresses open
+        let mut rom = Rom::empty();
+        rom.prg_ram_size = 0;
+        let mut bus = Bus::new(rom);
+        assert_eq!(bus.prg_ram(), None);
+        bus.mem_write(0x6000, 0x5a);
+        bus.mem_write(0x0010, 0x33);
+        assert_eq!(bus.mem_read(0x6000), 0x33);
+        assert_eq!(bus.mem_read(0x0011), 0x00);
+        assert_eq!(bus.mem_read(0x6000), 0x00);
+        assert_eq!(bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_stray_nrom_writes_are_counted() {
+        let mut rom = Rom::empty();
+        rom.prg_rom[0x0000] = 0xea;
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.bus.warn_on_rom_writes(true);
+        // LDA #$01; STA $8000; STA $8000
+        cpu.mem_write_slice(0x0600, &[0xa9, 0x01, 0x8d, 0x00, 0x80, 0x8d, 0x00, 0x80]);
+        cpu.program_counter = 0x0600;
+
+        cpu.step().unwrap();
+        assert!(cpu.step().is_err());
+        assert!(cpu.step().is_err());
+        assert_eq!(cpu.bus.rom_write_violations(), 2);
+        assert!(!cpu.bus.warn_rom_writes);
+        assert_eq!(cpu.mem_read(0x8000), 0xea);
+    }
+
+    // INX; STX $10; INC $6001; STX $6000; JMP $0600
+    const COUNTING_LOOP: [u8; 12] = [
+        0xe8, 0x86, 0x10, 0xee, 0x01, 0x60, 0x8e, 0x00, 0x60, 0x4c, 0x00, 0x06,
+    ];
+
+    fn run_steps(cpu: &mut CPU<Bus>, steps: usize) {
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_snapshot_restores_ram_prg_ram_and_cycles() {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        cpu.mem_write_slice(0x0600, &COUNTING_LOOP);
+        cpu.program_counter = 0x0600;
+        run_steps(&mut cpu, 23);
+        let cpu_snapshot = cpu.snapshot();
+        let bus_snapshot = cpu.bus.snapshot();
+
+        run_steps(&mut cpu, 40);
+        let later = (cpu.snapshot(), cpu.bus.snapshot());
+        assert_ne!(later.1, bus_snapshot);
+
+        let mut fresh = CPU::new(Bus::new(Rom::empty()));
+        fresh.restore(&cpu_snapshot);
+        fresh.bus.restore(&bus_snapshot);
+        assert_eq!(fresh.mem_read(0x0010), 5);
+        assert_eq!(fresh.mem_read(0x6001), 5);
+        run_steps(&mut fresh, 40);
+        assert_eq!((fresh.snapshot(), fresh.bus.snapshot()), later);
+
+        cpu.restore(&cpu_snapshot);
+        cpu.bus.restore(&bus_snapshot);
+        assert_eq!(cpu.bus.snapshot(), bus_snapshot);
+        assert_eq!(cpu.bus.cycles(), cpu_snapshot.cycles);
+    }
+
+    #[test]
+    fn test_rom_override_patches_the_reset_routine() {
+        let mut rom = Rom::empty();
+        // LDA #$05; STA $10; BRK
+        rom.prg_rom[..5].copy_from_slice(&[0xa9, 0x05, 0x85, 0x10, 0x00]);
+        rom.prg_rom[0x7ffc..0x7ffe].copy_from_slice(&[0x00, 0x80]);
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.bus.add_rom_override(0x8001, Some(0x05), 0x09);
+        // does not match, so stays off
+        cpu.bus.add_rom_override(0x8003, Some(0x11), 0x20);
+
+        cpu.reset();
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x0010), 0x09);
+        assert_eq!(cpu.bus.mapper.cpu_peek(0x8001), Some(0x05));
+
+        cpu.bus.remove_override(0x8001);
+        assert_eq!(cpu.mem_read(0x8001), 0x05);
+        cpu.bus.add_rom_override(0x8001, None, 0x07);
+        assert_eq!(cpu.mem_peek(0x8001), 0x07);
+    }
+
+    // a ROM whose reset routine at `start` runs `code`
+    fn rom_running(start: u16, code: &[u8]) -> Rom {
+        test_rom()
+            .prg_bytes((start - 0x8000) as usize, code)
+            .reset_vector(start)
+            .build()
+    }
+
+    #[test]
+    fn test_battery_ram_round_trip() {
+        let mut bus = Bus::new(test_rom().battery(true).build());
+        assert!(bus.has_battery());
+        bus.mem_write(0x6000, 0x12);
+        bus.mem_write(0x7fff, 0x34);
+        let mut save = vec![];
+        bus.save_prg_ram(&mut save).unwrap();
+        assert_eq!(save.len(), 0x2000);
+
+        let mut bus = Bus::new(test_rom().battery(true).build());
+        bus.load_prg_ram(&save[..]).unwrap();
+        assert_eq!(bus.mem_read(0x6000), 0x12);
+        assert_eq!(bus.mem_read(0x7fff), 0x34);
+
+        // a save that is too short is an error
+        assert!(bus.load_prg_ram(&save[..0x100]).is_err());
+    }
+
+    #[test]
+    fn test_no_battery_no_save() {
+        let mut bus = Bus::new(Rom::empty());
+        assert!(!bus.has_battery());
+        bus.mem_write(0x6000, 0x12);
+        let mut save = vec![];
+        bus.save_prg_ram(&mut save).unwrap();
+        assert!(save.is_empty());
+
+        bus.load_prg_ram(&[0xff; 0x2000][..]).unwrap();
+        assert_eq!(bus.mem_read(0x6000), 0x12);
+    }
+
+    #[test]
+    fn test_insert_cartridge_starts_the_new_game_afresh() {
+        // LDA #$AA; STA $10; STA $6000; BRK
+        let a = rom_running(0x8000, &[0xa9, 0xaa, 0x85, 0x10, 0x8d, 0x00, 0x60, 0x00]);
+        // LDA #$BB; STA $11; BRK
+        let b = rom_running(0x9000, &[0xa9, 0xbb, 0x85, 0x11, 0x00]);
+        let mut cpu = CPU::new(Bus::new(a));
+        cpu.reset();
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x6000), 0xaa);
+
+        cpu.insert_cartridge(b).unwrap();
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.mem_read(0x0010), 0);
+        assert_eq!(cpu.mem_read(0x6000), 0);
+
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x0011), 0xbb);
+    }
+
+    #[test]
+    fn test_insert_cartridge_keeps_the_old_one_if_unsupported() {
+        let mut bus = Bus::new(rom_running(0x8000, &[0xea]));
+        let mut rom = Rom::empty();
+        rom.mapper = 0xff;
+        assert!(bus.insert_cartridge(rom).is_err());
+        assert_eq!(bus.mem_read(0x8000), 0xea);
+    }
+
+    struct ResetPpu(Rc<RefCell<u32>>);
+
+    impl PpuRegisters for ResetPpu {
+        fn read_register(&mut self, _register: u8, _cartridge: &mut dyn Mapper) -> u8 {
+            0
+        }
+
+        fn peek_register(&self, _register: u8, _cartridge: &dyn Mapper) -> u8 {
+            0
+        }
+
+        fn write_register(&mut self, _register: u8, _data: u8, _cartridge: &mut dyn Mapper) {}
+
+        fn reset(&mut self) {
+            *self.0.borrow_mut() += 1;
+        }
+    }
+
+    #[test]
+    fn test_reset_system_keeps_ram_and_resets_devices() {
+        let resets = Rc::new(RefCell::new(0));
+        let mut bus = Bus::new(rom_running(0x8000, &[0xea]));
+        bus.attach_ppu(Box::new(ResetPpu(resets.clone())));
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        cpu.mem_write(0x0123, 0x45);
+        cpu.mem_write(0x6000, 0x67);
+        cpu.bus
+            .joypad_mut(0)
+            .set_button(JoypadButton::BUTTON_A, true);
+        cpu.mem_write(0x4016, 1);
+        cpu.program_counter = 0x0200;
+
+        cpu.reset_system();
+        assert_eq!(*resets.borrow(), 1);
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.mem_read(0x0123), 0x45);
+        assert_eq!(cpu.mem_read(0x6000), 0x67);
+        // the strobe is released, so reads shift past A
+        assert_eq!(cpu.mem_read(0x4016) & 1, 1);
+        assert_eq!(cpu.mem_read(0x4016) & 1, 0);
+    }
+
+    #[test]
+    fn test_access_stats_count_ram_and_rom() {
+        // LDA $10; STA $0200; LDA $8000; BRK
+        let rom = rom_running(
+            0x8000,
+            &[0xa5, 0x10, 0x8d, 0x00, 0x02, 0xad, 0x00, 0x80, 0x00],
+        );
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.reset();
+        assert_eq!(cpu.bus.access_stats(), None);
+
+        cpu.bus.count_accesses(true);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        let stats = cpu.bus.take_access_stats().unwrap();
+        // eight bytes of instructions, then the data at 0x8000
+        assert_eq!(stats.reads(RegionKind::PrgRom), 9);
+        assert_eq!(stats.writes(RegionKind::PrgRom), 0);
+        assert_eq!(stats.reads(RegionKind::Ram), 1);
+        assert_eq!(stats.writes(RegionKind::Ram), 1);
+        assert_eq!(stats.reads(RegionKind::PpuRegisters), 0);
+        assert_eq!(cpu.bus.access_stats(), Some(&AccessStats::default()));
+    }
+
+    #[test]
+    fn test_ram_is_mirrored_up_to_0x1fff() {
+        let mut bus = Bus::new(Rom::empty());
+        bus.mem_write(0x0000, 0x42);
+        for mirror in [0x0800, 0x1000, 0x1800] {
+            assert_eq!(bus.mem_read(mirror), 0x42, "{:#06x}", mirror);
+        }
+
+        bus.mem_write_u16(0x17ff, 0x1234);
+        assert_eq!(bus.mem_read(0x07ff), 0x34);
+        assert_eq!(bus.mem_read(0x0000), 0x12);
+        assert_eq!(bus.mem_read_u16(0x0fff), 0x1234);
+    }
+
+    #[test]
+    fn test_stack_is_visible_through_the_mirrors() {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        // LDA #$5A; PHA
+        cpu.mem_write_slice(0x0600, &[0xa9, 0x5a, 0x48]);
+        cpu.program_counter = 0x0600;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let pushed = cpu.stack_pointer.wrapping_add(1) as u16;
+        assert_eq!(cpu.mem_read(0x0100 + pushed), 0x5a);
+        assert_eq!(cpu.mem_read(0x1900 + pushed), 0x5a);
+    }
+
+    #[test]
+    fn test_write_slice_within_ram_is_copied_and_mirrored() {
+        let mut bus = Bus::new(Rom::empty());
+        bus.mem_write_slice(0x0800 + 0x0123, &[1, 2, 3, 4]);
+        assert_eq!(&bus.cpu_vram[0x0123..0x0127], &[1, 2, 3, 4]);
+
+        let mut buf = [0; 4];
+        bus.mem_read_into(0x1923, &mut buf);
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_write_slice_across_a_mirror_boundary() {
+        let mut bus = Bus::new(Rom::empty());
+        bus.mem_write_slice(0x07fe, &[1, 2, 3, 4]);
+        assert_eq!(&bus.cpu_vram[0x07fe..], &[1, 2]);
+        assert_eq!(&bus.cpu_vram[..2], &[3, 4]);
+        assert_eq!(bus.take_fault(), None);
+
+        // running off the end of RAM reaches the PPU registers, which are
+        // open bus without a PPU
+        bus.mem_write_slice(0x1fff, &[5, 6]);
+        assert_eq!(bus.cpu_vram[0x07ff], 5);
+        assert_eq!(bus.mem_peek(0x2000), 6);
+    }
+
+    #[test]
+    fn test_take_dirty_reports_changed_ram() {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        // LDA #$05; STA $10; STA $0210; STA $0F00; LDA #$00; STA $20
+        let program = [
+            0xa9, 0x05, 0x85, 0x10, 0x8d, 0x10, 0x02, 0x8d, 0x00, 0x0f, 0xa9, 0x00, 0x85, 0x20,
+        ];
+        cpu.mem_write_slice(0x0600, &program);
+        cpu.program_counter = 0x0600;
+        cpu.bus.track_ram_changes(true);
+
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        // $20 was written with the zero it already held
+        assert_eq!(cpu.bus.take_dirty(), vec![0x0010, 0x0210, 0x0700]);
+        assert!(cpu.bus.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn test_take_dirty_is_empty_without_tracking() {
+        let mut bus = Bus::new(Rom::empty());
+        bus.mem_write(0x0010, 0x05);
+        assert!(bus.take_dirty().is_empty());
+
+        bus.track_ram_changes(true);
+        bus.mem_write_slice(0x0800, &[0x00, 0x07]);
+        assert_eq!(bus.take_dirty(), vec![0x0001]);
+    }
 }
@@ -1,62 +1,3956 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::bus::{Region, RegionKind};
+use crate::hash;
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+// where the trainer goes in PRG RAM, which starts at 0x6000
+const TRAINER_OFFSET: usize = 0x1000;
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
+const PRG_RAM_SIZE: usize = 8192;
 
-#[derive(Debug, PartialEq)]
+/// How the PPU's two nametables fill its four nametable slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mirroring {
-    VERTICAL,
-    HORIZONTAL,
-    FOUR_SCREEN,
+    /// The top two slots are one nametable, the bottom two the other, for
+    /// games that scroll sideways.
+    Horizontal,
+    /// The left two slots are one nametable, the right two the other, for
+    /// games that scroll up and down.
+    Vertical,
+    /// The cartridge brings RAM for all four.
+    FourScreen,
+    /// Every slot shows the first nametable.
+    SingleScreenLower,
+    /// Every slot shows the second nametable.
+    SingleScreenUpper,
+}
+
+/// Why an iNES image could not be loaded.
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    /// The image is shorter than the 16-byte header.
+    TruncatedHeader {
+        actual: usize,
+    },
+    /// The image does not start with "NES" and 0x1A.
+    InvalidMagic,
+    /// The header is neither iNES 1.0 nor NES 2.0.
+    UnsupportedVersion,
+    /// The header declares no PRG ROM, so there is nothing for the CPU to
+    /// run.
+    EmptyPrg,
+    /// The header announces a trainer, but only `actual` of its 512 bytes
+    /// follow it.
+    TruncatedTrainer {
+        actual: usize,
+    },
+    /// The header declares `expected` bytes of PRG ROM, but only `actual`
+    /// follow it.
+    TruncatedPrg {
+        expected: usize,
+        actual: usize,
+    },
+    /// The header declares `expected` bytes of CHR ROM, but only `actual`
+    /// follow the PRG ROM.
+    TruncatedChr {
+        expected: usize,
+        actual: usize,
+    },
+    /// No mapper with this number is implemented.
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "could not read the ROM: {}", err),
+            RomError::TruncatedHeader { actual } => write!(
+                f,
+                "{} bytes is too short for the {}-byte iNES header",
+                actual, HEADER_SIZE
+            ),
+            RomError::InvalidMagic => {
+                write!(f, "not an iNES image: it does not start with \"NES\\x1A\"")
+            }
+            RomError::UnsupportedVersion => {
+                write!(f, "the header is neither iNES 1.0 nor NES 2.0")
+            }
+            RomError::EmptyPrg => write!(f, "the header declares no PRG ROM"),
+            RomError::TruncatedTrainer { actual } => write!(
+                f,
+                "the header announces a {}-byte trainer, but only {} bytes follow it",
+                TRAINER_SIZE, actual
+            ),
+            RomError::TruncatedPrg { expected, actual } => write!(
+                f,
+                "the header declares {} bytes of PRG ROM, but only {} follow it",
+                expected, actual
+            ),
+            RomError::TruncatedChr { expected, actual } => write!(
+                f,
+                "the header declares {} bytes of CHR ROM, but only {} follow the PRG ROM",
+                expected, actual
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+        }
+    }
+}
+
+impl std::error::Error for RomError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RomError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// How much a finding about an image matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Harmless, such as data after the CHR ROM.
+    Info,
+    /// Something wrong that loading works around.
+    Warning,
+    /// Something that stops the image from loading.
+    Error,
+}
+
+/// One problem with an image, the part of it concerned, and what to do
+/// about it if anything can be done.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub field: &'static str,
+    pub message: String,
+    pub fix: Option<String>,
 }
 
+/// Everything `Rom::validate` found wrong with an image, in the order it
+/// found it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error)
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        field: &'static str,
+        message: String,
+        fix: Option<String>,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            field,
+            message,
+            fix,
+        });
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            write!(
+                f,
+                "{:?}: {}: {}",
+                finding.severity, finding.field, finding.message
+            )?;
+            if let Some(fix) = &finding.fix {
+                write!(f, " ({})", fix)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// The video standard a game was made for, from the header. It decides
+/// the frame timing a game expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TvSystem {
+    Ntsc,
+    Pal,
+    /// The Famiclones sold in Russia, PAL with NTSC-like timing.
+    Dendy,
+    /// Runs on either NTSC or PAL.
+    Dual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Rom {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
-    pub mapper: u8,
+    /// The PRG and CHR ROM sizes in the image, before `from_bytes` padded
+    /// them to a power of two. The dump is what comes first in each.
+    pub dumped_sizes: (usize, usize),
+    pub mapper: u16,
+    /// The board variant within the mapper; 0 unless the header is NES 2.0.
+    pub submapper: u8,
     pub screen_mirroring: Mirroring,
+    /// Bytes of volatile and battery-backed PRG RAM, and of CHR RAM. NES 2.0
+    /// headers give them exactly; for iNES 1.0 they are guessed: the PRG RAM
+    /// byte, 0 meaning 8KB, is battery-backed if flag 6 says so, and there
+    /// are 8KB of CHR RAM when there is no CHR ROM.
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub tv_system: TvSystem,
+    /// The 512 bytes some dumps carry between the header and PRG ROM, meant
+    /// for 0x7000-0x71FF.
+    pub trainer: Option<[u8; TRAINER_SIZE]>,
+    /// Whether a battery keeps the PRG RAM, and the game's saves, while the
+    /// console is off.
+    pub battery: bool,
+    /// The file the image came from, if it came from one.
+    pub path: Option<PathBuf>,
+    /// What was wrong with the image and how loading worked around it.
+    pub report: ValidationReport,
 }
 
 impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES file format".to_string());
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        Rom::from_bytes(raw)
+    }
+
+    /// Reads and parses the iNES file at `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+        let raw = std::fs::read(path.as_ref()).map_err(RomError::Io)?;
+        let mut rom = Rom::from_bytes(&raw)?;
+        rom.path = Some(path.as_ref().to_path_buf());
+        Ok(rom)
+    }
+
+    /// Everything wrong with an iNES image, without giving up at the first
+    /// problem the way `from_bytes` does. The warnings are what
+    /// `from_bytes` works around and records in `Rom::report`.
+    pub fn validate(raw: &[u8]) -> ValidationReport {
+        let err = match Rom::from_bytes(raw) {
+            Ok(rom) => return rom.report,
+            Err(err) => err,
+        };
+        let mut report = ValidationReport::default();
+        if raw.len() >= HEADER_SIZE && raw[0..4] == NES_TAG {
+            clean_header(raw, &mut report);
         }
+        let field = match err {
+            RomError::Io(_) => "file",
+            RomError::TruncatedHeader { .. } => "header",
+            RomError::InvalidMagic => "magic",
+            RomError::UnsupportedVersion => "version",
+            RomError::EmptyPrg => "PRG ROM",
+            RomError::TruncatedTrainer { .. } => "trainer",
+            RomError::TruncatedPrg { .. } => "PRG ROM",
+            RomError::TruncatedChr { .. } => "CHR ROM",
+            RomError::UnsupportedMapper(_) => "mapper",
+        };
+        report.push(Severity::Error, field, err.to_string(), None);
+        report
+    }
 
-        let mapper = (raw[7] & 0b_1111_0000) | (raw[6 >> 4]);
-        let ines_ver = (raw[7] >> 2) & 0b11;
-        if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+    /// Parses an iNES image, working around the problems `validate` reports
+    /// as warnings. Anything past the CHR ROM is ignored, and PRG and CHR
+    /// ROM whose size is not a power of two are padded up to one, so that
+    /// mappers can wrap bank numbers around them the way the address lines
+    /// of real boards do.
+    pub fn from_bytes(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::TruncatedHeader { actual: raw.len() });
         }
+        if raw[0..4] != NES_TAG {
+            return Err(RomError::InvalidMagic);
+        }
+
+        let mut report = ValidationReport::default();
+        let header = clean_header(raw, &mut report);
 
-        let four_screen = raw[6] & 0b1000 != 0;
-        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let nes2 = match (header[7] >> 2) & 0b11 {
+            0 => false,
+            2 => true,
+            _ => return Err(RomError::UnsupportedVersion),
+        };
+
+        let four_screen = header[6] & 0b1000 != 0;
+        let vertical_mirroring = header[6] & 0b1 != 0;
         let screen_mirroring = match (four_screen, vertical_mirroring) {
-            (true, _) => Mirroring::FOUR_SCREEN,
-            (false, true) => Mirroring::VERTICAL,
-            (false, false) => Mirroring::HORIZONTAL,
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
         };
 
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let mut mapper = ((header[7] & 0b_1111_0000) | (header[6] >> 4)) as u16;
+        let battery = header[6] & 0b10 != 0;
+        let (submapper, prg_rom_size, chr_rom_size);
+        let (prg_ram_size, prg_nvram_size, chr_ram_size, tv_system);
+        if nes2 {
+            mapper |= ((header[8] & 0x0F) as u16) << 8;
+            submapper = header[8] >> 4;
+            prg_rom_size = nes2_rom_size(header[4], header[9] & 0x0F, PRG_ROM_PAGE_SIZE);
+            chr_rom_size = nes2_rom_size(header[5], header[9] >> 4, CHR_ROM_PAGE_SIZE);
+            prg_ram_size = nes2_ram_size(header[10] & 0x0F);
+            prg_nvram_size = nes2_ram_size(header[10] >> 4);
+            chr_ram_size = nes2_ram_size(header[11] & 0x0F);
+            tv_system = match header[12] & 0b11 {
+                0 => TvSystem::Ntsc,
+                1 => TvSystem::Pal,
+                2 => TvSystem::Dual,
+                _ => TvSystem::Dendy,
+            };
+        } else {
+            submapper = 0;
+            prg_rom_size = header[4] as usize * PRG_ROM_PAGE_SIZE;
+            chr_rom_size = header[5] as usize * CHR_ROM_PAGE_SIZE;
+            let prg_ram = header[8].max(1) as usize * PRG_RAM_SIZE;
+            (prg_ram_size, prg_nvram_size) = if battery { (0, prg_ram) } else { (prg_ram, 0) };
+            chr_ram_size = if chr_rom_size == 0 {
+                CHR_ROM_PAGE_SIZE
+            } else {
+                0
+            };
+            tv_system = ines_tv_system(&header);
+        }
+        if prg_rom_size == 0 {
+            return Err(RomError::EmptyPrg);
+        }
+
+        let has_trainer = header[6] & 0b100 != 0;
+        let trainer = if has_trainer {
+            let bytes = raw.get(HEADER_SIZE..HEADER_SIZE + TRAINER_SIZE).ok_or(
+                RomError::TruncatedTrainer {
+                    actual: raw.len() - HEADER_SIZE,
+                },
+            )?;
+            Some(bytes.try_into().unwrap())
+        } else {
+            None
+        };
 
-        let skip_trainer = raw[6] & 0b100 != 0;
+        let prg_rom_start = HEADER_SIZE + if has_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start.saturating_add(prg_rom_size);
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let prg_rom = raw
+            .get(prg_rom_start..chr_rom_start)
+            .ok_or(RomError::TruncatedPrg {
+                expected: prg_rom_size,
+                actual: raw.len().saturating_sub(prg_rom_start),
+            })?;
+        let chr_rom = raw
+            .get(chr_rom_start..chr_rom_start.saturating_add(chr_rom_size))
+            .ok_or(RomError::TruncatedChr {
+                expected: chr_rom_size,
+                actual: raw.len().saturating_sub(chr_rom_start),
+            })?;
+        let extra = raw.len() - (chr_rom_start + chr_rom_size);
+        if extra > 0 {
+            report.push(
+                Severity::Info,
+                "file size",
+                format!("{} bytes follow the CHR ROM", extra),
+                Some("they are ignored".to_string()),
+            );
+        }
 
         Ok(Rom {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
-            mapper: mapper,
-            screen_mirroring: screen_mirroring,
+            prg_rom: pad_to_power_of_two(prg_rom, "PRG ROM", &mut report),
+            chr_rom: pad_to_power_of_two(chr_rom, "CHR ROM", &mut report),
+            dumped_sizes: (prg_rom.len(), chr_rom.len()),
+            mapper,
+            submapper,
+            screen_mirroring,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            tv_system,
+            trainer,
+            battery,
+            path: None,
+            report,
         })
     }
+
+    /// A blank NROM cartridge with 64KB of PRG ROM and CHR RAM. See
+    /// `test_rom` for anything more specific.
     pub fn empty() -> Self {
-        Self {
-            prg_rom: vec![0; 0x10000],
-            chr_rom: vec![],
-            mapper: 0,
-            screen_mirroring: Mirroring::FOUR_SCREEN,
+        test_rom()
+            .prg_banks(4)
+            .mirroring(Mirroring::FourScreen)
+            .build()
+    }
+
+    /// The image `from_bytes` would parse back into this ROM: iNES 1.0 if
+    /// its header can say everything, NES 2.0 otherwise. PRG and CHR ROM
+    /// go out as dumped, without padding. Headers cannot ask for a single
+    /// nametable, which comes from the mapper, so that is written out as
+    /// horizontal.
+    pub fn to_ines_bytes(&self) -> Vec<u8> {
+        let prg = self.dumped_sizes.0.min(self.prg_rom.len());
+        let chr = self.dumped_sizes.1.min(self.chr_rom.len());
+        let (prg_ram, prg_nvram) = self.ines_prg_ram();
+        let prg_units = prg / PRG_ROM_PAGE_SIZE;
+        let chr_units = chr / CHR_ROM_PAGE_SIZE;
+        let fits_ines = self.mapper <= 0xFF
+            && self.submapper == 0
+            && self.tv_system != TvSystem::Dendy
+            && prg.is_multiple_of(PRG_ROM_PAGE_SIZE)
+            && prg_units <= 0xFF
+            && chr.is_multiple_of(CHR_ROM_PAGE_SIZE)
+            && chr_units <= 0xFF
+            && (self.prg_ram_size, self.prg_nvram_size) == (prg_ram, prg_nvram)
+            && self.chr_ram_size == if chr == 0 { CHR_ROM_PAGE_SIZE } else { 0 };
+        if !fits_ines {
+            return self.to_nes2_bytes();
+        }
+
+        let mut header = self.common_header();
+        header[4] = prg_units as u8;
+        header[5] = chr_units as u8;
+        header[7] = self.mapper as u8 & 0xF0;
+        // 0 means 8KB, as in most images
+        let ram_units = (prg_ram + prg_nvram) / PRG_RAM_SIZE;
+        header[8] = if ram_units == 1 { 0 } else { ram_units as u8 };
+        match self.tv_system {
+            TvSystem::Pal => header[9] = 1,
+            TvSystem::Dual => header[10] = 3,
+            _ => {}
+        }
+        self.with_body(header, &self.prg_rom[..prg], &self.chr_rom[..chr])
+    }
+
+    /// The image as NES 2.0, whatever the ROM needs. Sizes a NES 2.0 header
+    /// cannot give exactly are written padded to a power of two.
+    pub fn to_nes2_bytes(&self) -> Vec<u8> {
+        let mut header = self.common_header();
+        let dumped = |rom: &[u8], size: usize, unit: usize| -> Vec<u8> {
+            let rom = &rom[..size.min(rom.len())];
+            match nes2_size_fields(rom.len(), unit) {
+                Some(_) => rom.to_vec(),
+                None => mirror_to_power_of_two(rom),
+            }
+        };
+        let prg = dumped(&self.prg_rom, self.dumped_sizes.0, PRG_ROM_PAGE_SIZE);
+        let chr = dumped(&self.chr_rom, self.dumped_sizes.1, CHR_ROM_PAGE_SIZE);
+        let (prg_lsb, prg_msb) = nes2_size_fields(prg.len(), PRG_ROM_PAGE_SIZE).unwrap();
+        let (chr_lsb, chr_msb) = nes2_size_fields(chr.len(), CHR_ROM_PAGE_SIZE).unwrap();
+        header[4] = prg_lsb;
+        header[5] = chr_lsb;
+        header[7] = self.mapper as u8 & 0xF0 | 0b1000;
+        header[8] = self.submapper << 4 | (self.mapper >> 8) as u8 & 0x0F;
+        header[9] = chr_msb << 4 | prg_msb;
+        header[10] = nes2_ram_shift(self.prg_nvram_size) << 4 | nes2_ram_shift(self.prg_ram_size);
+        header[11] = nes2_ram_shift(self.chr_ram_size);
+        header[12] = match self.tv_system {
+            TvSystem::Ntsc => 0,
+            TvSystem::Pal => 1,
+            TvSystem::Dual => 2,
+            TvSystem::Dendy => 3,
+        };
+        self.with_body(header, &prg, &chr)
+    }
+
+    // the PRG RAM an iNES 1.0 header would give this ROM: the same amount,
+    // all of it battery-backed or none of it
+    fn ines_prg_ram(&self) -> (usize, usize) {
+        let total = self.prg_ram_size + self.prg_nvram_size;
+        let units = if total.is_multiple_of(PRG_RAM_SIZE) {
+            (total / PRG_RAM_SIZE).clamp(1, 0xFF)
+        } else {
+            1
+        };
+        if self.battery {
+            (0, units * PRG_RAM_SIZE)
+        } else {
+            (units * PRG_RAM_SIZE, 0)
+        }
+    }
+
+    // the tag and flags 6, which both header versions share
+    fn common_header(&self) -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+        header[0..4].copy_from_slice(&NES_TAG);
+        header[6] = (self.mapper as u8) << 4;
+        header[6] |= match self.screen_mirroring {
+            Mirroring::Vertical => 0b0001,
+            Mirroring::FourScreen => 0b1000,
+            _ => 0b0000,
+        };
+        if self.battery {
+            header[6] |= 0b10;
+        }
+        if self.trainer.is_some() {
+            header[6] |= 0b100;
+        }
+        header
+    }
+
+    fn with_body(&self, header: [u8; HEADER_SIZE], prg: &[u8], chr: &[u8]) -> Vec<u8> {
+        let mut raw = header.to_vec();
+        if let Some(trainer) = &self.trainer {
+            raw.extend_from_slice(trainer);
+        }
+        raw.extend_from_slice(prg);
+        raw.extend_from_slice(chr);
+        raw
+    }
+
+    /// The CRC-32 of PRG and CHR ROM as dumped, leaving out the header,
+    /// any trainer and any padding, as ROM databases compute it.
+    pub fn crc32(&self) -> u32 {
+        hash::crc32(&self.dumped_rom())
+    }
+
+    /// The SHA-1 of PRG and CHR ROM, like `crc32`.
+    #[cfg(feature = "sha1")]
+    pub fn sha1(&self) -> [u8; 20] {
+        hash::sha1(&self.dumped_rom())
+    }
+
+    fn dumped_rom(&self) -> Vec<u8> {
+        let prg = self.dumped_sizes.0.min(self.prg_rom.len());
+        let chr = self.dumped_sizes.1.min(self.chr_rom.len());
+        [&self.prg_rom[..prg], &self.chr_rom[..chr]].concat()
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    /// Where a battery-backed game's saves belong: next to the image it was
+    /// read from, as `game.sav` for `game.nes`. None for images that did not
+    /// come from a file. Nothing is read or written here; see
+    /// `Bus::save_prg_ram` and `Bus::load_prg_ram`.
+    pub fn save_path(&self) -> Option<PathBuf> {
+        self.path.as_ref().map(|path| path.with_extension("sav"))
+    }
+
+    /// The nametable layout the header asks for. Mappers that switch it at
+    /// run time report the current one through `Mapper::mirroring`.
+    pub fn screen_mirroring(&self) -> Mirroring {
+        self.screen_mirroring
+    }
+
+    pub fn tv_system(&self) -> TvSystem {
+        self.tv_system
+    }
+
+    /// The cartridge hardware for the mapper number in the header.
+    pub fn into_mapper(self) -> Result<Box<dyn Mapper>, RomError> {
+        match self.mapper {
+            0 => Ok(Box::new(Nrom::new(self))),
+            2 => Ok(Box::new(Uxrom::new(self))),
+            4 => Ok(Box::new(Mmc3::new(self))),
+            5 => Ok(Box::new(Mmc5::new(self))),
+            7 => Ok(Box::new(Axrom::new(self))),
+            9 => Ok(Box::new(Mmc2::new(self))),
+            34 if is_nina_001(&self) => Ok(Box::new(Nina001::new(self))),
+            34 => Ok(Box::new(Bnrom::new(self))),
+            69 => Ok(Box::new(Fme7::new(self))),
+            71 => Ok(Box::new(Camerica::new(self))),
+            mapper => Err(RomError::UnsupportedMapper(mapper)),
+        }
+    }
+}
+
+// the 16-byte header with the usual fixups applied. Some old tools left
+// their name in bytes 7-15, like "DiskDude!"; when bytes 12-15, which
+// iNES 1.0 leaves zero, hold text, bytes 7-15 are ignored, which among
+// other things drops the upper mapper nibble
+fn clean_header(raw: &[u8], report: &mut ValidationReport) -> [u8; HEADER_SIZE] {
+    let mut header: [u8; HEADER_SIZE] = raw[..HEADER_SIZE].try_into().unwrap();
+    let tail = &header[12..16];
+    let nes2 = (header[7] >> 2) & 0b11 == 2;
+    let text = tail.iter().any(|&byte| byte != 0)
+        && tail
+            .iter()
+            .all(|&byte| byte == 0 || byte.is_ascii_graphic() || byte == b' ');
+    if !nes2 && text {
+        report.push(
+            Severity::Warning,
+            "header bytes 7-15",
+            format!(
+                "hold {:?} instead of header fields",
+                String::from_utf8_lossy(&header[7..16])
+            ),
+            Some(format!(
+                "treat them as zero, making the mapper {} rather than {}",
+                header[6] >> 4,
+                (header[7] & 0xF0) | (header[6] >> 4)
+            )),
+        );
+        header[7..16].fill(0);
+    }
+    header
+}
+
+// iNES 1.0 only has a PAL bit in byte 9. Byte 10 was later given a TV
+// system too, but so few tools write it that it is only believed when the
+// rest of the header after it is zero
+fn ines_tv_system(header: &[u8; HEADER_SIZE]) -> TvSystem {
+    if header[11..16].iter().all(|&byte| byte == 0) {
+        match header[10] & 0b11 {
+            1 | 3 => return TvSystem::Dual,
+            2 => return TvSystem::Pal,
+            _ => {}
+        }
+    }
+    if header[9] & 1 == 0 {
+        TvSystem::Ntsc
+    } else {
+        TvSystem::Pal
+    }
+}
+
+// `rom` mirrored up to a power of two the way two chips would be, one the
+// largest power of two that fits and the other repeating the rest: 48KB
+// becomes banks 0, 1, 2 and 2 again
+fn pad_to_power_of_two(rom: &[u8], field: &'static str, report: &mut ValidationReport) -> Vec<u8> {
+    let padded = mirror_to_power_of_two(rom);
+    if padded.len() != rom.len() {
+        report.push(
+            Severity::Warning,
+            field,
+            format!("{} bytes is not a power of two", rom.len()),
+            Some(format!("pad it to {} bytes by mirroring", padded.len())),
+        );
+    }
+    padded
+}
+
+fn mirror_to_power_of_two(rom: &[u8]) -> Vec<u8> {
+    if rom.is_empty() || rom.len().is_power_of_two() {
+        return rom.to_vec();
+    }
+    let half = rom.len().next_power_of_two() / 2;
+    let rest = mirror_to_power_of_two(&rom[half..]);
+    let mut padded = rom[..half].to_vec();
+    while padded.len() < half * 2 {
+        padded.extend_from_slice(&rest);
+    }
+    padded
+}
+
+// a NES 2.0 ROM size: `msb` and `lsb` count `unit`s, unless `msb` is 0xF,
+// in which case `lsb` holds an exponent E and multiplier M for 2^E * (M*2+1)
+// bytes
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl((lsb >> 2) as u32)
+            .map_or(usize::MAX, |size| size.saturating_mul(multiplier))
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+// a NES 2.0 RAM size: none, or 64 bytes shifted left by the nibble
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+// the NES 2.0 size fields for `size` bytes, counting `unit`s if it can and
+// falling back on an exponent and multiplier; None if neither gives it
+fn nes2_size_fields(size: usize, unit: usize) -> Option<(u8, u8)> {
+    if size.is_multiple_of(unit) && size / unit < 0xF00 {
+        let units = size / unit;
+        return Some((units as u8, (units >> 8) as u8));
+    }
+    let exponent = size.trailing_zeros();
+    let multiplier = size >> exponent;
+    if exponent > 63 || multiplier > 7 {
+        return None;
+    }
+    Some(((exponent as u8) << 2 | (multiplier / 2) as u8, 0x0F))
+}
+
+// the nibble `nes2_ram_size` reads back as at least `size` bytes
+fn nes2_ram_shift(size: usize) -> u8 {
+    if size == 0 {
+        return 0;
+    }
+    let shift = size
+        .div_ceil(64)
+        .next_power_of_two()
+        .trailing_zeros()
+        .max(1);
+    shift.min(15) as u8
+}
+
+/// An iNES 1.0 image for tests, described one option at a time and filled
+/// with zeros elsewhere. Start from `test_rom`.
+#[derive(Debug, Clone)]
+pub struct TestRom {
+    prg_banks: u8,
+    chr_banks: u8,
+    mapper: u8,
+    mirroring: Mirroring,
+    battery: bool,
+    reset_vector: Option<u16>,
+    prg_bytes: Vec<(usize, Vec<u8>)>,
+    chr_bytes: Vec<(usize, Vec<u8>)>,
+}
+
+/// 32KB of blank PRG ROM on NROM, with CHR RAM and horizontal mirroring.
+pub fn test_rom() -> TestRom {
+    TestRom {
+        prg_banks: 2,
+        chr_banks: 0,
+        mapper: 0,
+        mirroring: Mirroring::Horizontal,
+        battery: false,
+        reset_vector: None,
+        prg_bytes: vec![],
+        chr_bytes: vec![],
+    }
+}
+
+impl TestRom {
+    /// How many 16KB banks of PRG ROM there are.
+    pub fn prg_banks(mut self, banks: u8) -> Self {
+        self.prg_banks = banks;
+        self
+    }
+
+    /// How many 8KB banks of CHR ROM there are; none means CHR RAM.
+    pub fn chr_banks(mut self, banks: u8) -> Self {
+        self.chr_banks = banks;
+        self
+    }
+
+    pub fn mapper(mut self, mapper: u8) -> Self {
+        self.mapper = mapper;
+        self
+    }
+
+    /// Single-screen layouts have no header bits, so they are rejected.
+    pub fn mirroring(mut self, mirroring: Mirroring) -> Self {
+        self.mirroring = mirroring;
+        self
+    }
+
+    pub fn battery(mut self, battery: bool) -> Self {
+        self.battery = battery;
+        self
+    }
+
+    /// Points the reset vector, 4 bytes from the end of PRG ROM, at `addr`.
+    pub fn reset_vector(mut self, addr: u16) -> Self {
+        self.reset_vector = Some(addr);
+        self
+    }
+
+    /// Places `bytes` at `offset` into PRG ROM. Later calls, and the reset
+    /// vector, win over earlier ones.
+    pub fn prg_bytes(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.prg_bytes.push((offset, bytes.to_vec()));
+        self
+    }
+
+    /// Places `bytes` at `offset` into CHR ROM.
+    pub fn chr_bytes(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.chr_bytes.push((offset, bytes.to_vec()));
+        self
+    }
+
+    /// The image as a file would hold it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags6 = self.mapper << 4;
+        flags6 |= match self.mirroring {
+            Mirroring::Horizontal => 0b0000,
+            Mirroring::Vertical => 0b0001,
+            Mirroring::FourScreen => 0b1000,
+            layout => panic!("iNES headers cannot ask for {:?}", layout),
+        };
+        if self.battery {
+            flags6 |= 0b10;
+        }
+        let mut raw = NES_TAG.to_vec();
+        raw.extend_from_slice(&[self.prg_banks, self.chr_banks, flags6, self.mapper & 0xF0]);
+        raw.resize(HEADER_SIZE, 0);
+
+        let mut prg = vec![0; self.prg_banks as usize * PRG_ROM_PAGE_SIZE];
+        for (offset, bytes) in &self.prg_bytes {
+            prg[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        if let Some(addr) = self.reset_vector {
+            let at = prg.len() - 4;
+            prg[at..at + 2].copy_from_slice(&addr.to_le_bytes());
+        }
+        let mut chr = vec![0; self.chr_banks as usize * CHR_ROM_PAGE_SIZE];
+        for (offset, bytes) in &self.chr_bytes {
+            chr[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        raw.extend_from_slice(&prg);
+        raw.extend_from_slice(&chr);
+        raw
+    }
+
+    pub fn build(&self) -> Rom {
+        Rom::from_bytes(&self.to_bytes()).expect("test images always parse")
+    }
+}
+
+/// Where one window of the address space reads from, see `BankInfo`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bank {
+    /// The bank number, counting in the mapper's own bank size.
+    pub number: usize,
+    /// Where the window starts in PRG or CHR, ROM or RAM.
+    pub offset: usize,
+    /// Whether the window shows RAM rather than ROM.
+    pub ram: bool,
+}
+
+/// What the cartridge has mapped right now, for debugging bank switching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankInfo {
+    /// The 8KB windows at 0x8000, 0xA000, 0xC000 and 0xE000.
+    pub prg: [Bank; 4],
+    /// The 1KB windows at 0x0000-0x1FFF.
+    pub chr: [Bank; 8],
+    pub mirroring: Mirroring,
+}
+
+impl BankInfo {
+    // the banks `prg` and `chr` give for the first address of each window
+    fn from_windows(
+        prg: impl Fn(u16) -> Bank,
+        chr: impl Fn(u16) -> Bank,
+        mirroring: Mirroring,
+    ) -> Self {
+        BankInfo {
+            prg: std::array::from_fn(|window| prg(0x8000 + window as u16 * 0x2000)),
+            chr: std::array::from_fn(|window| chr(window as u16 * 0x0400)),
+            mirroring,
+        }
+    }
+}
+
+// a bank of PRG ROM
+fn rom_bank(offset: usize, bank_size: usize) -> Bank {
+    Bank {
+        number: offset / bank_size,
+        offset,
+        ram: false,
+    }
+}
+
+/// `PRG 8000:00 A000:01 C000:0E E000:0F CHR 0000:00 ... Vertical`, the
+/// bank numbers in hex, with an `r` in front of RAM banks.
+impl fmt::Display for BankInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bank = |bank: &Bank| format!("{}{:02X}", if bank.ram { "r" } else { "" }, bank.number);
+        write!(f, "PRG")?;
+        for (window, prg) in self.prg.iter().enumerate() {
+            write!(f, " {:04X}:{}", 0x8000 + window * 0x2000, bank(prg))?;
+        }
+        write!(f, " CHR")?;
+        for (window, chr) in self.chr.iter().enumerate() {
+            write!(f, " {:04X}:{}", window * 0x0400, bank(chr))?;
+        }
+        write!(f, " {:?}", self.mirroring)
+    }
+}
+
+/// The cartridge as the bus sees it. The CPU side covers 0x4020-0xFFFF:
+/// the expansion area, PRG RAM at 0x6000-0x7FFF and PRG ROM at
+/// 0x8000-0xFFFF. The PPU side covers the pattern tables at 0x0000-0x1FFF.
+/// Mappers that switch banks or nametable layouts do it from writes to the
+/// CPU side.
+pub trait Mapper {
+    /// None where nothing on the cartridge answers, leaving the last value
+    /// on the data bus.
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>;
+
+    /// What `cpu_read` would return, without its side effects.
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    fn ppu_read(&mut self, addr: u16) -> u8;
+
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    /// How the nametables are laid out right now.
+    fn mirroring(&self) -> Mirroring;
+
+    /// The 2KB of RAM a four-screen board adds for the third and fourth
+    /// nametables, if it has it.
+    fn nametable_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    fn irq_pending(&mut self) -> bool;
+
+    /// Lets the cartridge count the CPU cycles that went by, for timers such
+    /// as FME-7's IRQ counter.
+    fn tick(&mut self, _cpu_cycles: u64) {}
+
+    /// The level of PPU address line A12, whenever it changes. Rendering
+    /// raises it once per scanline, which MMC3 counts.
+    fn notify_a12(&mut self, _high: bool) {}
+
+    /// The scanline the PPU has just started: 0-239 are drawn, 240-260
+    /// are vertical blank and 261 is the pre-render line.
+    fn notify_scanline(&mut self, _scanline: u16) {}
+
+    /// What is mapped between 0x4020 and 0xFFFF right now, in order. Gaps
+    /// are unmapped.
+    fn cpu_regions(&self) -> Vec<Region>;
+
+    /// Which banks fill the PRG and CHR windows right now.
+    fn bank_info(&self) -> BankInfo;
+
+    /// The address of a write the cartridge could not take since the last
+    /// call, if any.
+    fn take_fault(&mut self) -> Option<u16> {
+        None
+    }
+
+    /// Writes the cartridge had nothing to do with, since power on.
+    fn rom_write_violations(&self) -> u64 {
+        0
+    }
+
+    /// All of the PRG RAM at 0x6000-0x7FFF, battery-backed or not, if the
+    /// cartridge has any.
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// The PRG RAM a battery keeps, if the cartridge has one.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// Whatever running a program changes, such as RAM and bank registers,
+    /// in a form `restore` takes back.
+    fn snapshot(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn restore(&mut self, _state: &[u8]) {}
+
+    /// What the reset button does to the cartridge, e.g. putting MMC1's
+    /// shift register back to empty. PRG RAM keeps its contents.
+    fn reset(&mut self) {}
+}
+
+/// The pattern tables on the cartridge: the image's CHR ROM, or RAM the
+/// program uploads tiles into if the image has none. Indexes wrap around
+/// its size, and writes only stick to RAM.
+struct Chr {
+    data: Vec<u8>,
+    is_ram: bool,
+}
+
+impl Chr {
+    fn new(chr_rom: Vec<u8>, ram_size: usize) -> Self {
+        if chr_rom.is_empty() {
+            Chr {
+                data: vec![0; ram_size.max(CHR_ROM_PAGE_SIZE)],
+                is_ram: true,
+            }
+        } else {
+            Chr {
+                data: chr_rom,
+                is_ram: false,
+            }
+        }
+    }
+
+    fn read(&self, index: usize) -> u8 {
+        self.data[index % self.data.len()]
+    }
+
+    fn write(&mut self, index: usize, data: u8) {
+        if self.is_ram {
+            let len = self.data.len();
+            self.data[index % len] = data;
+        }
+    }
+
+    fn bank(&self, index: usize, bank_size: usize) -> Bank {
+        let offset = index % self.data.len();
+        Bank {
+            number: offset / bank_size,
+            offset,
+            ram: self.is_ram,
+        }
+    }
+
+    // RAM is part of a mapper's state, ROM is not
+    fn snapshot(&self, state: &mut Vec<u8>) {
+        if self.is_ram {
+            state.extend_from_slice(&self.data);
+        }
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        if self.is_ram {
+            self.data.copy_from_slice(state);
+        }
+    }
+}
+
+/// Mapper 0: up to 32KB of PRG ROM, a 16KB image showing in both halves,
+/// and 8KB of CHR, which is RAM if the header declares none. PRG RAM, which
+/// only Family Basic carts have, is sized from the header and repeats
+/// through 0x6000-0x7FFF. Any trainer is loaded at 0x7000.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    chr: Chr,
+    mirroring: Mirroring,
+    fault: Option<u16>,
+    rom_writes: u64,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        let mut size = (rom.prg_ram_size + rom.prg_nvram_size).min(PRG_RAM_SIZE);
+        if rom.trainer.is_some() {
+            size = PRG_RAM_SIZE;
+        }
+        let mut prg_ram = vec![0; size];
+        if let Some(trainer) = rom.trainer {
+            prg_ram[TRAINER_OFFSET..TRAINER_OFFSET + TRAINER_SIZE].copy_from_slice(&trainer);
+        }
+        Nrom {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            battery: rom.battery,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            mirroring: rom.screen_mirroring,
+            fault: None,
+            rom_writes: 0,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram.is_empty() => None,
+            0x6000..=0x7FFF => Some(self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]),
+            0x8000..=0xFFFF => Some(self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()]),
+            _ => None,
         }
     }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram.is_empty() => {}
+            0x6000..=0x7FFF => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            // nothing on the board answers below PRG RAM
+            0x4020..=0x5FFF => {}
+            // there is nothing to switch, so this is a write to ROM
+            _ => {
+                self.fault = Some(addr);
+                self.rom_writes += 1;
+            }
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        let mut regions = vec![];
+        if !self.prg_ram.is_empty() {
+            regions.push(Region::new(0x6000, 0x7FFF, RegionKind::PrgRam, true));
+        }
+        if self.prg_rom.len() == PRG_ROM_PAGE_SIZE {
+            regions.push(Region::new(0x8000, 0xBFFF, RegionKind::PrgRom, false));
+            regions.push(Region::new(0xC000, 0xFFFF, RegionKind::PrgRom, false).mirroring(0x8000));
+        } else {
+            regions.push(Region::new(0x8000, 0xFFFF, RegionKind::PrgRom, false));
+        }
+        regions
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        BankInfo::from_windows(
+            |addr| {
+                rom_bank(
+                    (addr - 0x8000) as usize % self.prg_rom.len(),
+                    PRG_ROM_PAGE_SIZE,
+                )
+            },
+            |addr| self.chr.bank(addr as usize, CHR_ROM_PAGE_SIZE),
+            self.mirroring,
+        )
+    }
+
+    fn take_fault(&mut self) -> Option<u16> {
+        self.fault.take()
+    }
+
+    fn rom_write_violations(&self) -> u64 {
+        self.rom_writes
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        (!self.prg_ram.is_empty()).then_some(&self.prg_ram[..])
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.battery.then_some(&self.prg_ram[..])
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(&mut self.prg_ram[..])
+    }
+
+    // PRG RAM, then CHR RAM if there is any
+    fn snapshot(&self) -> Vec<u8> {
+        let mut state = self.prg_ram.clone();
+        self.chr.snapshot(&mut state);
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        let (prg_ram, chr_ram) = state.split_at(self.prg_ram.len());
+        self.prg_ram.copy_from_slice(prg_ram);
+        self.chr.restore(chr_ram);
+    }
+}
+
+/// Mapper 2: any write to 0x8000-0xFFFF picks the 16KB PRG bank shown at
+/// 0x8000-0xBFFF, while 0xC000-0xFFFF always shows the last one. CHR is
+/// 8KB of RAM unless the image brings ROM.
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bank: u8,
+    bus_conflicts: bool,
+}
+
+impl Uxrom {
+    /// Boards have bus conflicts if the NES 2.0 header says so with
+    /// submapper 2.
+    pub fn new(rom: Rom) -> Self {
+        Uxrom {
+            bus_conflicts: rom.submapper == 2,
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            mirroring: rom.screen_mirroring,
+            bank: 0,
+        }
+    }
+
+    /// Whether the ROM drives the data bus during a bank switch, so that
+    /// only the bits both it and the CPU leave set get through.
+    pub fn set_bus_conflicts(&mut self, on: bool) {
+        self.bus_conflicts = on;
+    }
+
+    // PRG ROM smaller than a bank counts as one, mirrored to fill it
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_ROM_PAGE_SIZE).max(1);
+        let bank = match addr {
+            0x8000..=0xBFFF => self.bank as usize % banks,
+            _ => banks - 1,
+        };
+        (bank * PRG_ROM_PAGE_SIZE + (addr as usize & (PRG_ROM_PAGE_SIZE - 1))) % self.prg_rom.len()
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        self.bank = if self.bus_conflicts {
+            data & self.prg_rom[self.prg_index(addr)]
+        } else {
+            data
+        };
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        vec![
+            Region::new(0x8000, 0xBFFF, RegionKind::PrgRom, true),
+            Region::new(0xC000, 0xFFFF, RegionKind::PrgRom, true),
+        ]
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        BankInfo::from_windows(
+            |addr| rom_bank(self.prg_index(addr), PRG_ROM_PAGE_SIZE),
+            |addr| self.chr.bank(addr as usize, CHR_ROM_PAGE_SIZE),
+            self.mirroring,
+        )
+    }
+
+    // the bank register, then CHR RAM if there is any
+    fn snapshot(&self) -> Vec<u8> {
+        let mut state = vec![self.bank];
+        self.chr.snapshot(&mut state);
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        self.bank = state[0];
+        self.chr.restore(&state[1..]);
+    }
+}
+
+const MMC3_PRG_BANK_SIZE: usize = 0x2000;
+const FOUR_SCREEN_RAM_SIZE: usize = 0x0800;
+const MMC3_CHR_BANK_SIZE: usize = 0x0400;
+
+/// Mapper 4: four 8KB PRG windows and eight 1KB CHR windows, filled from
+/// the eight bank registers R0-R7 and the last two PRG banks. Its IRQ
+/// counter is clocked by rising edges of PPU A12, which rendering produces
+/// once per scanline.
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    chr: Chr,
+    four_screen: bool,
+    // the third and fourth nametables of four-screen boards, empty on the
+    // others
+    vram: Vec<u8>,
+    // 0x8000: the register the next 0x8001 write fills in bits 0-2, the
+    // PRG mode in bit 6 and the CHR inversion in bit 7
+    bank_select: u8,
+    registers: [u8; 8],
+    horizontal: bool,
+    prg_ram_enabled: bool,
+    prg_ram_protected: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc3 {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            battery: rom.battery,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            four_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            vram: match rom.screen_mirroring {
+                Mirroring::FourScreen => vec![0; FOUR_SCREEN_RAM_SIZE],
+                _ => vec![],
+            },
+            bank_select: 0,
+            registers: [0; 8],
+            horizontal: rom.screen_mirroring == Mirroring::Horizontal,
+            prg_ram_enabled: true,
+            prg_ram_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = (self.prg_rom.len() / MMC3_PRG_BANK_SIZE).max(1);
+        let swapped = self.bank_select & 0x40 != 0;
+        // the fixed banks count back from the end, wrapping on boards with
+        // fewer than two
+        let bank = match (addr - 0x8000) as usize / MMC3_PRG_BANK_SIZE {
+            0 if swapped => banks * 2 - 2,
+            0 => self.registers[6] as usize,
+            1 => self.registers[7] as usize,
+            2 if swapped => self.registers[6] as usize,
+            2 => banks * 2 - 2,
+            _ => banks * 2 - 1,
+        };
+        (bank % banks * MMC3_PRG_BANK_SIZE + (addr as usize & (MMC3_PRG_BANK_SIZE - 1)))
+            % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let mut addr = addr as usize & 0x1FFF;
+        if self.bank_select & 0x80 != 0 {
+            addr ^= 0x1000;
+        }
+        // R0 and R1 pick 2KB banks, ignoring their low bit
+        let bank = match addr / MMC3_CHR_BANK_SIZE {
+            0 => self.registers[0] & 0xFE,
+            1 => self.registers[0] | 1,
+            2 => self.registers[1] & 0xFE,
+            3 => self.registers[1] | 1,
+            window => self.registers[window - 2],
+        };
+        bank as usize * MMC3_CHR_BANK_SIZE + addr % MMC3_CHR_BANK_SIZE
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => Some(self.prg_ram[(addr - 0x6000) as usize]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_protected => {
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+            }
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => self.registers[(self.bank_select & 0x07) as usize] = data,
+            0xA000..=0xBFFF if even => self.horizontal = data & 1 == 1,
+            0xA000..=0xBFFF => {
+                self.prg_ram_enabled = data & 0x80 != 0;
+                self.prg_ram_protected = data & 0x40 != 0;
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            // disabling also acknowledges
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_index(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_index(addr);
+        self.chr.write(index, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FourScreen
+        } else if self.horizontal {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn nametable_ram(&mut self) -> Option<&mut [u8]> {
+        self.four_screen.then_some(&mut self.vram[..])
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.irq_pending
+    }
+
+    fn notify_a12(&mut self, high: bool) {
+        if high && !self.a12 {
+            self.clock_irq_counter();
+        }
+        self.a12 = high;
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        let mut regions = vec![];
+        if self.prg_ram_enabled {
+            regions.push(Region::new(
+                0x6000,
+                0x7FFF,
+                RegionKind::PrgRam,
+                !self.prg_ram_protected,
+            ));
+        }
+        for start in (0x8000..=0xE000).step_by(MMC3_PRG_BANK_SIZE) {
+            regions.push(Region::new(start, start + 0x1FFF, RegionKind::PrgRom, true));
+        }
+        regions
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        BankInfo::from_windows(
+            |addr| rom_bank(self.prg_index(addr), MMC3_PRG_BANK_SIZE),
+            |addr| self.chr.bank(self.chr_index(addr), MMC3_CHR_BANK_SIZE),
+            self.mirroring(),
+        )
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.battery.then_some(&self.prg_ram[..])
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(&mut self.prg_ram[..])
+    }
+
+    // PRG RAM, the registers, the four-screen nametables, then CHR RAM if
+    // there is any
+    fn snapshot(&self) -> Vec<u8> {
+        let mut state = self.prg_ram.clone();
+        state.extend_from_slice(&self.registers);
+        state.extend_from_slice(&[
+            self.bank_select,
+            self.horizontal as u8,
+            self.prg_ram_enabled as u8,
+            self.prg_ram_protected as u8,
+            self.irq_latch,
+            self.irq_counter,
+            self.irq_reload as u8,
+            self.irq_enabled as u8,
+            self.irq_pending as u8,
+            self.a12 as u8,
+        ]);
+        state.extend_from_slice(&self.vram);
+        self.chr.snapshot(&mut state);
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        let (prg_ram, state) = state.split_at(PRG_RAM_SIZE);
+        let (registers, state) = state.split_at(8);
+        let (flags, state) = state.split_at(10);
+        let (vram, chr_ram) = state.split_at(self.vram.len());
+        self.prg_ram.copy_from_slice(prg_ram);
+        self.vram.copy_from_slice(vram);
+        self.registers.copy_from_slice(registers);
+        self.bank_select = flags[0];
+        self.horizontal = flags[1] != 0;
+        self.prg_ram_enabled = flags[2] != 0;
+        self.prg_ram_protected = flags[3] != 0;
+        self.irq_latch = flags[4];
+        self.irq_counter = flags[5];
+        self.irq_reload = flags[6] != 0;
+        self.irq_enabled = flags[7] != 0;
+        self.irq_pending = flags[8] != 0;
+        self.a12 = flags[9] != 0;
+        self.chr.restore(chr_ram);
+    }
+}
+
+const AXROM_PRG_BANK_SIZE: usize = 0x8000;
+
+/// Mapper 7: any write to 0x8000-0xFFFF picks the 32KB PRG bank in bits
+/// 0-2 and, with bit 4, which nametable fills all four slots. CHR is 8KB of
+/// RAM.
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    bank: u8,
+}
+
+impl Axrom {
+    pub fn new(rom: Rom) -> Self {
+        Axrom {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            bank: 0,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = (self.prg_rom.len() / AXROM_PRG_BANK_SIZE).max(1);
+        let bank = (self.bank & 0x07) as usize % banks;
+        (bank * AXROM_PRG_BANK_SIZE + (addr - 0x8000) as usize) % self.prg_rom.len()
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.bank = data;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.bank & 0x10 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        vec![Region::new(0x8000, 0xFFFF, RegionKind::PrgRom, true)]
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        BankInfo::from_windows(
+            |addr| rom_bank(self.prg_index(addr), AXROM_PRG_BANK_SIZE),
+            |addr| self.chr.bank(addr as usize, CHR_ROM_PAGE_SIZE),
+            self.mirroring(),
+        )
+    }
+
+    // the bank register, then CHR RAM
+    fn snapshot(&self) -> Vec<u8> {
+        let mut state = vec![self.bank];
+        self.chr.snapshot(&mut state);
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        self.bank = state[0];
+        self.chr.restore(&state[1..]);
+    }
+}
+
+const MMC2_PRG_BANK_SIZE: usize = 0x2000;
+const MMC2_CHR_BANK_SIZE: usize = 0x1000;
+
+/// Mapper 9: an 8KB PRG bank at 0x8000 with the last three fixed after
+/// it, and two 4KB CHR windows. Each window has a bank for tile 0xFD and
+/// one for tile 0xFE, and switches between them on its own whenever the
+/// PPU fetches either tile.
+pub struct Mmc2 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_bank: u8,
+    // the 0xFD and 0xFE banks for 0x0000, then for 0x1000
+    chr_banks: [[u8; 2]; 2],
+    // per window, whether tile 0xFE was fetched last rather than 0xFD
+    latches: [bool; 2],
+    horizontal: bool,
+}
+
+impl Mmc2 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc2 {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [true; 2],
+            horizontal: rom.screen_mirroring == Mirroring::Horizontal,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = self.prg_rom.len() / MMC2_PRG_BANK_SIZE;
+        let bank = match (addr - 0x8000) as usize / MMC2_PRG_BANK_SIZE {
+            0 => self.prg_bank as usize % banks,
+            // the last three banks, as far as the board has four
+            window => (banks * 4 + window - 4) % banks,
+        };
+        bank * MMC2_PRG_BANK_SIZE + (addr as usize & (MMC2_PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let window = (addr as usize & 0x1FFF) / MMC2_CHR_BANK_SIZE;
+        let bank = self.chr_banks[window][self.latches[window] as usize];
+        bank as usize * MMC2_CHR_BANK_SIZE + (addr as usize & (MMC2_CHR_BANK_SIZE - 1))
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = data & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = data & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = data & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = data & 0x1F,
+            0xF000..=0xFFFF => self.horizontal = data & 1 == 1,
+            _ => {}
+        }
+    }
+
+    // the fetch that trips a latch still comes from the old bank
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let data = self.chr.read(self.chr_index(addr));
+        match addr & 0x1FFF {
+            0x0FD8 => self.latches[0] = false,
+            0x0FE8 => self.latches[0] = true,
+            0x1FD8..=0x1FDF => self.latches[1] = false,
+            0x1FE8..=0x1FEF => self.latches[1] = true,
+            _ => {}
+        }
+        data
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        if self.horizontal {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        vec![
+            Region::new(0x8000, 0x9FFF, RegionKind::PrgRom, false),
+            Region::new(0xA000, 0xFFFF, RegionKind::PrgRom, true),
+        ]
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        BankInfo::from_windows(
+            |addr| rom_bank(self.prg_index(addr), MMC2_PRG_BANK_SIZE),
+            |addr| self.chr.bank(self.chr_index(addr), MMC2_CHR_BANK_SIZE),
+            self.mirroring(),
+        )
+    }
+
+    // the PRG bank, the four CHR banks, the latches and the mirroring
+    fn snapshot(&self) -> Vec<u8> {
+        vec![
+            self.prg_bank,
+            self.chr_banks[0][0],
+            self.chr_banks[0][1],
+            self.chr_banks[1][0],
+            self.chr_banks[1][1],
+            self.latches[0] as u8,
+            self.latches[1] as u8,
+            self.horizontal as u8,
+        ]
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        self.prg_bank = state[0];
+        self.chr_banks = [[state[1], state[2]], [state[3], state[4]]];
+        self.latches = [state[5] != 0, state[6] != 0];
+        self.horizontal = state[7] != 0;
+    }
+}
+
+const BNROM_PRG_BANK_SIZE: usize = 0x8000;
+const NINA_001_CHR_BANK_SIZE: usize = 0x1000;
+
+// Mapper 34 is two boards. NES 2.0 tells them apart with submapper 1 for
+// NINA-001 and 2 for BNROM; otherwise, as in other emulators, more than 8KB
+// of CHR ROM means NINA-001, since BNROM has no CHR banking.
+fn is_nina_001(rom: &Rom) -> bool {
+    match rom.submapper {
+        1 => true,
+        2 => false,
+        _ => rom.chr_rom.len() > CHR_ROM_PAGE_SIZE,
+    }
+}
+
+/// Mapper 34 on a BNROM board: any write to 0x8000-0xFFFF picks the 32KB
+/// PRG bank. CHR is 8KB of RAM unless the image brings ROM.
+pub struct Bnrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bank: u8,
+}
+
+impl Bnrom {
+    pub fn new(rom: Rom) -> Self {
+        Bnrom {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            mirroring: rom.screen_mirroring,
+            bank: 0,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let base = self.bank as usize * BNROM_PRG_BANK_SIZE;
+        (base + (addr - 0x8000) as usize) % self.prg_rom.len()
+    }
+}
+
+impl Mapper for Bnrom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            self.bank = data;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        vec![Region::new(0x8000, 0xFFFF, RegionKind::PrgRom, true)]
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        BankInfo::from_windows(
+            |addr| rom_bank(self.prg_index(addr), BNROM_PRG_BANK_SIZE),
+            |addr| self.chr.bank(addr as usize, CHR_ROM_PAGE_SIZE),
+            self.mirroring,
+        )
+    }
+
+    // the bank register, then CHR RAM if there is any
+    fn snapshot(&self) -> Vec<u8> {
+        let mut state = vec![self.bank];
+        self.chr.snapshot(&mut state);
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        self.bank = state[0];
+        self.chr.restore(&state[1..]);
+    }
+}
+
+/// Mapper 34 on AVE's NINA-001 board: 8KB of PRG RAM, whose last three
+/// bytes also pick the 32KB PRG bank (0x7FFD) and the 4KB CHR banks at
+/// 0x0000 (0x7FFE) and 0x1000 (0x7FFF).
+pub struct Nina001 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    chr: Chr,
+    mirroring: Mirroring,
+}
+
+impl Nina001 {
+    pub fn new(rom: Rom) -> Self {
+        Nina001 {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            battery: rom.battery,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            mirroring: rom.screen_mirroring,
+        }
+    }
+
+    fn register(&self, addr: u16) -> usize {
+        self.prg_ram[(addr - 0x6000) as usize] as usize
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let base = (self.register(0x7FFD) & 1) * BNROM_PRG_BANK_SIZE;
+        (base + (addr - 0x8000) as usize) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let register = if addr & 0x1000 == 0 { 0x7FFE } else { 0x7FFF };
+        let bank = self.register(register) & 0x0F;
+        bank * NINA_001_CHR_BANK_SIZE + (addr as usize & (NINA_001_CHR_BANK_SIZE - 1))
+    }
+}
+
+impl Mapper for Nina001 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.prg_ram[(addr - 0x6000) as usize]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    // the registers are write-only, so the RAM under them keeps the value
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if (0x6000..=0x7FFF).contains(&addr) {
+            self.prg_ram[(addr - 0x6000) as usize] = data;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_index(addr))
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        vec![
+            Region::new(0x6000, 0x7FFF, RegionKind::PrgRam, true),
+            Region::new(0x8000, 0xFFFF, RegionKind::PrgRom, false),
+        ]
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        BankInfo::from_windows(
+            |addr| rom_bank(self.prg_index(addr), BNROM_PRG_BANK_SIZE),
+            |addr| self.chr.bank(self.chr_index(addr), NINA_001_CHR_BANK_SIZE),
+            self.mirroring,
+        )
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.battery.then_some(&self.prg_ram[..])
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(&mut self.prg_ram[..])
+    }
+
+    // PRG RAM, which holds the bank registers too
+    fn snapshot(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        self.prg_ram.copy_from_slice(state);
+    }
+}
+
+const FME7_PRG_BANK_SIZE: usize = 0x2000;
+const FME7_CHR_BANK_SIZE: usize = 0x0400;
+
+/// Mapper 69: a write to 0x8000-0x9FFF picks one of sixteen commands, and
+/// a write to 0xA000-0xBFFF gives it its parameter. Commands 0-7 pick 1KB
+/// CHR banks, 8 what shows at 0x6000, 9-B the 8KB PRG banks before the
+/// fixed last one, C the mirroring, and D-F drive a 16-bit IRQ counter
+/// that counts down once per CPU cycle.
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    chr: Chr,
+    command: u8,
+    // the parameters of commands 0-C
+    registers: [u8; 13],
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: u16,
+    irq_pending: bool,
+}
+
+impl Fme7 {
+    pub fn new(rom: Rom) -> Self {
+        Fme7 {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            battery: rom.battery,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            command: 0,
+            registers: [0; 13],
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = self.prg_rom.len() / FME7_PRG_BANK_SIZE;
+        let bank = match addr {
+            0x6000..=0x7FFF => self.registers[8] & 0x3F,
+            0x8000..=0x9FFF => self.registers[9],
+            0xA000..=0xBFFF => self.registers[10],
+            0xC000..=0xDFFF => self.registers[11],
+            _ => (banks - 1) as u8,
+        };
+        bank as usize % banks * FME7_PRG_BANK_SIZE + (addr as usize & (FME7_PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let window = (addr as usize & 0x1FFF) / FME7_CHR_BANK_SIZE;
+        self.registers[window] as usize * FME7_CHR_BANK_SIZE
+            + (addr as usize & (FME7_CHR_BANK_SIZE - 1))
+    }
+
+    // whether 0x6000-0x7FFF shows RAM rather than ROM, and if RAM, whether
+    // it is enabled
+    fn prg_ram_selected(&self) -> (bool, bool) {
+        let register = self.registers[8];
+        (register & 0x40 != 0, register & 0x80 != 0)
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0xC => self.registers[self.command as usize] = data,
+            // any write here also acknowledges
+            0xD => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.counter_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.counter = self.counter & 0xFF00 | data as u16,
+            _ => self.counter = self.counter & 0x00FF | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => match self.prg_ram_selected() {
+                (true, true) => Some(self.prg_ram[(addr - 0x6000) as usize]),
+                (true, false) => None,
+                (false, _) => Some(self.prg_rom[self.prg_index(addr)]),
+            },
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_selected() == (true, true) => {
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+            }
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_index(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_index(addr);
+        self.chr.write(index, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.registers[12] & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.irq_pending
+    }
+
+    // the IRQ fires as the counter wraps from 0 to 0xFFFF
+    fn tick(&mut self, cpu_cycles: u64) {
+        if !self.counter_enabled {
+            return;
+        }
+        if cpu_cycles > self.counter as u64 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+        self.counter = self.counter.wrapping_sub(cpu_cycles as u16);
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        let mut regions = vec![];
+        match self.prg_ram_selected() {
+            (true, true) => regions.push(Region::new(0x6000, 0x7FFF, RegionKind::PrgRam, true)),
+            (true, false) => {}
+            (false, _) => regions.push(Region::new(0x6000, 0x7FFF, RegionKind::PrgRom, false)),
+        }
+        for start in (0x8000..=0xE000).step_by(FME7_PRG_BANK_SIZE) {
+            regions.push(Region::new(
+                start,
+                start + 0x1FFF,
+                RegionKind::PrgRom,
+                start < 0xC000,
+            ));
+        }
+        regions
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        BankInfo::from_windows(
+            |addr| rom_bank(self.prg_index(addr), FME7_PRG_BANK_SIZE),
+            |addr| self.chr.bank(self.chr_index(addr), FME7_CHR_BANK_SIZE),
+            self.mirroring(),
+        )
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.battery.then_some(&self.prg_ram[..])
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(&mut self.prg_ram[..])
+    }
+
+    // PRG RAM, the command and its parameters, the IRQ state, then CHR RAM
+    // if there is any
+    fn snapshot(&self) -> Vec<u8> {
+        let mut state = self.prg_ram.clone();
+        state.push(self.command);
+        state.extend_from_slice(&self.registers);
+        state.extend_from_slice(&[
+            self.irq_enabled as u8,
+            self.counter_enabled as u8,
+            self.irq_pending as u8,
+        ]);
+        state.extend_from_slice(&self.counter.to_le_bytes());
+        self.chr.snapshot(&mut state);
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        let (prg_ram, state) = state.split_at(PRG_RAM_SIZE);
+        let (registers, state) = state.split_at(14);
+        let (irq, chr_ram) = state.split_at(5);
+        self.prg_ram.copy_from_slice(prg_ram);
+        self.command = registers[0];
+        self.registers.copy_from_slice(&registers[1..]);
+        self.irq_enabled = irq[0] != 0;
+        self.counter_enabled = irq[1] != 0;
+        self.irq_pending = irq[2] != 0;
+        self.counter = u16::from_le_bytes([irq[3], irq[4]]);
+        self.chr.restore(chr_ram);
+    }
+}
+
+/// Mapper 71: UxROM with the bank register at 0xC000-0xFFFF only. The
+/// Fire Hawk board, NES 2.0 submapper 1, also picks a single nametable with
+/// bit 4 of writes to 0x8000-0x9FFF; other boards ignore those.
+pub struct Camerica {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    fire_hawk: bool,
+    bank: u8,
+}
+
+impl Camerica {
+    pub fn new(rom: Rom) -> Self {
+        Camerica {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            mirroring: rom.screen_mirroring,
+            fire_hawk: rom.submapper == 1,
+            bank: 0,
+        }
+    }
+
+    // PRG ROM smaller than a bank counts as one, mirrored to fill it
+    fn prg_index(&self, addr: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_ROM_PAGE_SIZE).max(1);
+        let bank = match addr {
+            0x8000..=0xBFFF => self.bank as usize % banks,
+            _ => banks - 1,
+        };
+        (bank * PRG_ROM_PAGE_SIZE + (addr as usize & (PRG_ROM_PAGE_SIZE - 1))) % self.prg_rom.len()
+    }
+}
+
+impl Mapper for Camerica {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9FFF if self.fire_hawk => {
+                self.mirroring = if data & 0x10 == 0 {
+                    Mirroring::SingleScreenLower
+                } else {
+                    Mirroring::SingleScreenUpper
+                };
+            }
+            0xC000..=0xFFFF => self.bank = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        vec![
+            Region::new(0x8000, 0xBFFF, RegionKind::PrgRom, self.fire_hawk),
+            Region::new(0xC000, 0xFFFF, RegionKind::PrgRom, true),
+        ]
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        BankInfo::from_windows(
+            |addr| rom_bank(self.prg_index(addr), PRG_ROM_PAGE_SIZE),
+            |addr| self.chr.bank(addr as usize, CHR_ROM_PAGE_SIZE),
+            self.mirroring,
+        )
+    }
+
+    // the bank register, the mirroring, then CHR RAM
+    fn snapshot(&self) -> Vec<u8> {
+        let mut state = vec![self.bank, self.mirroring as u8];
+        self.chr.snapshot(&mut state);
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        self.bank = state[0];
+        self.mirroring = match state[1] {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        };
+        self.chr.restore(&state[2..]);
+    }
+}
+
+const MMC5_PRG_BANK_SIZE: usize = 0x2000;
+const MMC5_PRG_RAM_SIZE: usize = 0x10000;
+const MMC5_EXRAM_SIZE: usize = 0x0400;
+
+/// Mapper 5, as far as Castlevania III needs it: the four PRG modes of
+/// 0x5100 with ROM or RAM banks from 0x5113-0x5117, the four CHR modes of
+/// 0x5101 with the 8x8 sprite banks from 0x5120-0x5127, the nametable
+/// layout at 0x5105, 1KB of ExRAM at 0x5C00 and the scanline IRQ at
+/// 0x5203/0x5204.
+///
+/// The real chip finds scanlines by watching the PPU fetch nametables.
+/// Here the PPU reports them through `notify_scanline` instead, and the
+/// frame counts as in progress from scanline 0 to 239. ExRAM as extended
+/// attributes, fill mode and the vertical split are not drawn; their
+/// registers only keep what is written to them.
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    chr: Chr,
+    exram: Vec<u8>,
+    prg_mode: u8,
+    chr_mode: u8,
+    // 0x5102 and 0x5103, which must be 2 and 1 for PRG RAM to take writes
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametables: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    // 0x5113-0x5117
+    prg_banks: [u8; 5],
+    // 0x5120-0x512B
+    chr_banks: [u8; 12],
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+    multiplicand: u8,
+    multiplier: u8,
+}
+
+impl Mmc5 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc5 {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; MMC5_PRG_RAM_SIZE],
+            battery: rom.battery,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            exram: vec![0; MMC5_EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks: [0; 12],
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+        }
+    }
+
+    // where `addr` in 0x6000-0xFFFF lands: true and an offset into PRG ROM,
+    // or false and an offset into PRG RAM
+    fn prg_target(&self, addr: u16) -> (bool, usize) {
+        let (register, size) = match (self.prg_mode, addr) {
+            (_, 0x6000..=0x7FFF) => (0, 0x2000),
+            (0, _) => (4, 0x8000),
+            (1, 0x8000..=0xBFFF) | (2, 0x8000..=0xBFFF) => (2, 0x4000),
+            (1, _) => (4, 0x4000),
+            (2, 0xC000..=0xDFFF) => (3, 0x2000),
+            (2, _) => (4, 0x2000),
+            _ => (1 + (addr as usize - 0x8000) / MMC5_PRG_BANK_SIZE, 0x2000),
+        };
+        let value = self.prg_banks[register];
+        // 0x5117 always picks ROM and 0x5113 always RAM
+        let rom = register == 4 || register != 0 && value & 0x80 != 0;
+        let bank = (value & 0x7F) as usize & !(size / MMC5_PRG_BANK_SIZE - 1);
+        let offset = bank * MMC5_PRG_BANK_SIZE + (addr as usize & (size - 1));
+        if rom {
+            (true, offset % self.prg_rom.len())
+        } else {
+            (false, offset % self.prg_ram.len())
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [2, 1]
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let size = 0x2000 >> self.chr_mode;
+        let window = (addr as usize & 0x1FFF) / size;
+        let register = (window + 1) * (8 >> self.chr_mode) - 1;
+        self.chr_banks[register] as usize * size + (addr as usize & (size - 1))
+    }
+
+    fn status(&self) -> u8 {
+        (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        let data = self.cpu_peek(addr);
+        if addr == 0x5204 {
+            self.irq_pending = false;
+        }
+        data
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5204 => Some(self.status()),
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[(addr - 0x5C00) as usize]),
+            0x6000..=0xFFFF => match self.prg_target(addr) {
+                (true, offset) => Some(self.prg_rom[offset]),
+                (false, offset) => Some(self.prg_ram[offset]),
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5100 => self.prg_mode = data & 0x03,
+            0x5101 => self.chr_mode = data & 0x03,
+            0x5102 => self.prg_ram_protect[0] = data & 0x03,
+            0x5103 => self.prg_ram_protect[1] = data & 0x03,
+            0x5104 => self.exram_mode = data & 0x03,
+            0x5105 => self.nametables = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0x03,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x512B => self.chr_banks[(addr - 0x5120) as usize] = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            // mode 3 makes ExRAM read-only
+            0x5C00..=0x5FFF if self.exram_mode != 3 => {
+                self.exram[(addr - 0x5C00) as usize] = data;
+            }
+            0x6000..=0xFFFF if self.prg_ram_writable() => {
+                if let (false, offset) = self.prg_target(addr) {
+                    self.prg_ram[offset] = data;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_index(addr))
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let index = self.chr_index(addr);
+        self.chr.write(index, data);
+    }
+
+    // layouts that use ExRAM or the fill tile have no Mirroring of their
+    // own, and need the cartridge's nametables
+    fn mirroring(&self) -> Mirroring {
+        match self.nametables {
+            0x44 => Mirroring::Vertical,
+            0x50 => Mirroring::Horizontal,
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            _ => Mirroring::FourScreen,
+        }
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn notify_scanline(&mut self, scanline: u16) {
+        if scanline >= 240 {
+            self.in_frame = false;
+            return;
+        }
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        let mut regions = vec![Region::new(
+            0x5C00,
+            0x5FFF,
+            RegionKind::PrgRam,
+            self.exram_mode != 3,
+        )];
+        let mut start: u16 = 0x6000;
+        loop {
+            let (rom, _) = self.prg_target(start);
+            let end = match (self.prg_mode, start) {
+                (_, 0x6000) => 0x7FFF,
+                (0, _) => 0xFFFF,
+                (1, _) | (2, 0x8000) => start + 0x3FFF,
+                _ => start + 0x1FFF,
+            };
+            regions.push(if rom {
+                Region::new(start, end, RegionKind::PrgRom, false)
+            } else {
+                Region::new(start, end, RegionKind::PrgRam, self.prg_ram_writable())
+            });
+            if end == 0xFFFF {
+                return regions;
+            }
+            start = end + 1;
+        }
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        let prg = |addr| match self.prg_target(addr) {
+            (true, offset) => rom_bank(offset, MMC5_PRG_BANK_SIZE),
+            (false, offset) => Bank {
+                number: offset / MMC5_PRG_BANK_SIZE,
+                offset,
+                ram: true,
+            },
+        };
+        let chr = |addr| self.chr.bank(self.chr_index(addr), 0x2000 >> self.chr_mode);
+        BankInfo::from_windows(prg, chr, self.mirroring())
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.battery.then_some(&self.prg_ram[..])
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(&mut self.prg_ram[..])
+    }
+
+    // PRG RAM, ExRAM, the registers, then CHR RAM if there is any
+    fn snapshot(&self) -> Vec<u8> {
+        let mut state = self.prg_ram.clone();
+        state.extend_from_slice(&self.exram);
+        state.extend_from_slice(&self.prg_banks);
+        state.extend_from_slice(&self.chr_banks);
+        state.extend_from_slice(&[
+            self.prg_mode,
+            self.chr_mode,
+            self.prg_ram_protect[0],
+            self.prg_ram_protect[1],
+            self.exram_mode,
+            self.nametables,
+            self.fill_tile,
+            self.fill_attribute,
+            self.irq_compare,
+            self.irq_enabled as u8,
+            self.irq_pending as u8,
+            self.in_frame as u8,
+            self.scanline,
+            self.multiplicand,
+            self.multiplier,
+        ]);
+        self.chr.snapshot(&mut state);
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) {
+        let (prg_ram, state) = state.split_at(MMC5_PRG_RAM_SIZE);
+        let (exram, state) = state.split_at(MMC5_EXRAM_SIZE);
+        let (prg_banks, state) = state.split_at(5);
+        let (chr_banks, state) = state.split_at(12);
+        let (registers, chr_ram) = state.split_at(15);
+        self.prg_ram.copy_from_slice(prg_ram);
+        self.exram.copy_from_slice(exram);
+        self.prg_banks.copy_from_slice(prg_banks);
+        self.chr_banks.copy_from_slice(chr_banks);
+        self.prg_mode = registers[0];
+        self.chr_mode = registers[1];
+        self.prg_ram_protect = [registers[2], registers[3]];
+        self.exram_mode = registers[4];
+        self.nametables = registers[5];
+        self.fill_tile = registers[6];
+        self.fill_attribute = registers[7];
+        self.irq_compare = registers[8];
+        self.irq_enabled = registers[9] != 0;
+        self.irq_pending = registers[10] != 0;
+        self.in_frame = registers[11] != 0;
+        self.scanline = registers[12];
+        self.multiplicand = registers[13];
+        self.multiplier = registers[14];
+        self.chr.restore(chr_ram);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::{Mem, CPU};
+
+    fn ines(flags6: u8, flags7: u8, chr_banks: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, chr_banks, flags6, flags7];
+        raw.resize(16, 0);
+        raw.resize(
+            16 + PRG_ROM_PAGE_SIZE + chr_banks as usize * CHR_ROM_PAGE_SIZE,
+            0,
+        );
+        raw
+    }
+
+    #[test]
+    fn test_from_bytes_splits_prg_and_chr() {
+        let mut raw = ines(0, 0, 1);
+        raw[16] = 0x11;
+        raw[16 + PRG_ROM_PAGE_SIZE - 1] = 0x22;
+        raw[16 + PRG_ROM_PAGE_SIZE] = 0x33;
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(
+            (rom.prg_rom[0], rom.prg_rom[PRG_ROM_PAGE_SIZE - 1]),
+            (0x11, 0x22)
+        );
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom[0], 0x33);
+    }
+
+    #[test]
+    fn test_from_bytes_reports_truncation() {
+        let raw = ines(0, 0, 1);
+        assert!(matches!(
+            Rom::from_bytes(&raw[..100]),
+            Err(RomError::TruncatedPrg {
+                expected: PRG_ROM_PAGE_SIZE,
+                actual: 84
+            })
+        ));
+        assert!(matches!(
+            Rom::from_bytes(&raw[..raw.len() - 1]),
+            Err(RomError::TruncatedChr {
+                expected: CHR_ROM_PAGE_SIZE,
+                actual: 8191
+            })
+        ));
+        assert!(matches!(
+            Rom::from_bytes(&raw[..10]),
+            Err(RomError::TruncatedHeader { actual: 10 })
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_wrong_magic() {
+        let mut raw = ines(0, 0, 0);
+        raw[3] = 0x1b;
+        assert!(matches!(Rom::from_bytes(&raw), Err(RomError::InvalidMagic)));
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_unknown_header_versions() {
+        let raw = ines(0, 0b0000_0100, 0);
+        assert!(matches!(
+            Rom::from_bytes(&raw),
+            Err(RomError::UnsupportedVersion)
+        ));
+    }
+
+    fn disk_dude(raw: &[u8]) -> Vec<u8> {
+        let mut raw = raw.to_vec();
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        raw
+    }
+
+    #[test]
+    fn test_odd_sizes_are_padded_to_a_power_of_two() {
+        let rom = test_rom().mapper(2).prg_banks(3).chr_banks(3);
+        let rom = numbered_banks(rom, PRG_ROM_PAGE_SIZE, CHR_ROM_PAGE_SIZE).build();
+        assert_eq!(rom.prg_rom.len(), 4 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), 4 * CHR_ROM_PAGE_SIZE);
+        assert_eq!(
+            rom.dumped_sizes,
+            (3 * PRG_ROM_PAGE_SIZE, 3 * CHR_ROM_PAGE_SIZE)
+        );
+        let fields: Vec<_> = rom
+            .report
+            .findings
+            .iter()
+            .map(|finding| finding.field)
+            .collect();
+        assert_eq!(fields, ["PRG ROM", "CHR ROM"]);
+        assert_eq!(
+            rom.report.findings[0].message,
+            "49152 bytes is not a power of two"
+        );
+
+        // the fourth bank is the third again, and the fifth the first
+        let mut mapper = rom.into_mapper().unwrap();
+        for (bank, expected) in [(0, 0), (2, 2), (3, 2), (4, 0), (7, 2)] {
+            mapper.cpu_write(0x8000, bank);
+            assert_eq!(mapper.cpu_read(0x8000), Some(expected), "bank {}", bank);
+        }
+    }
+
+    #[test]
+    fn test_padding_leaves_the_hashes_alone() {
+        let raw = test_rom().prg_banks(3).prg_bytes(0, &[1, 2, 3]).to_bytes();
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.crc32(), hash::crc32(&raw[HEADER_SIZE..]));
+        assert_eq!(
+            mirror_to_power_of_two(&[1, 2, 3, 4, 5]),
+            [1, 2, 3, 4, 5, 5, 5, 5]
+        );
+        assert_eq!(
+            mirror_to_power_of_two(&[1, 2, 3, 4, 5, 6]),
+            [1, 2, 3, 4, 5, 6, 5, 6]
+        );
+    }
+
+    #[test]
+    fn test_to_ines_bytes_round_trips_test_roms() {
+        let mut roms = vec![];
+        for (mapper, prg_banks, chr_banks) in
+            [(0, 1, 0), (0, 2, 1), (2, 3, 0), (4, 8, 16), (69, 16, 32)]
+        {
+            for mirroring in [
+                Mirroring::Horizontal,
+                Mirroring::Vertical,
+                Mirroring::FourScreen,
+            ] {
+                for battery in [false, true] {
+                    let rom = test_rom()
+                        .mapper(mapper)
+                        .prg_banks(prg_banks)
+                        .chr_banks(chr_banks)
+                        .mirroring(mirroring)
+                        .battery(battery)
+                        .reset_vector(0x8000);
+                    let rom = numbered_banks(rom, PRG_ROM_PAGE_SIZE, CHR_ROM_PAGE_SIZE);
+                    // what iNES 1.0 can say comes out as the builder wrote it
+                    assert_eq!(rom.build().to_ines_bytes(), rom.to_bytes());
+                    roms.push(rom.build());
+                }
+            }
+        }
+
+        // and what needs NES 2.0
+        let base = roms[0].clone();
+        let mut rom = base.clone();
+        rom.trainer = Some([0x5A; TRAINER_SIZE]);
+        roms.push(rom);
+        let mut rom = base.clone();
+        rom.tv_system = TvSystem::Dual;
+        roms.push(rom);
+        let mut rom = base.clone();
+        (rom.mapper, rom.submapper) = (0x123, 5);
+        roms.push(rom);
+        let mut rom = base.clone();
+        rom.tv_system = TvSystem::Dendy;
+        (rom.prg_ram_size, rom.prg_nvram_size, rom.chr_ram_size) = (2048, 32768, 16384);
+        roms.push(rom);
+
+        for rom in roms {
+            let raw = rom.to_ines_bytes();
+            assert_eq!(
+                Rom::from_bytes(&raw).unwrap(),
+                rom,
+                "{:02x?}",
+                &raw[..HEADER_SIZE]
+            );
+        }
+    }
+
+    #[test]
+    fn test_to_nes2_bytes_keeps_odd_sizes() {
+        // 3 * 2^10 bytes of PRG ROM, which only the exponent form can give
+        let mut raw = ines(0, 0x08, 0);
+        raw[4] = 10 << 2 | 1;
+        raw[9] = 0x0F;
+        raw.truncate(HEADER_SIZE);
+        raw.extend((0..3072).map(|i| i as u8));
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.prg_rom.len(), 4096);
+        assert_eq!(rom.to_ines_bytes(), raw);
+        assert_eq!(rom.to_nes2_bytes(), raw);
+        assert_eq!(Rom::from_bytes(&rom.to_ines_bytes()).unwrap(), rom);
+    }
+
+    #[test]
+    fn test_dirty_header_is_cleaned_and_reported() {
+        let raw = disk_dude(&ines(0x21, 0, 0));
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.mapper, 2);
+        assert_eq!(rom.screen_mirroring(), Mirroring::Vertical);
+        assert_eq!(rom.prg_ram_size, PRG_RAM_SIZE);
+        assert_eq!(rom.tv_system, TvSystem::Ntsc);
+
+        let report = Rom::validate(&raw);
+        assert_eq!(report, rom.report);
+        assert_eq!(report.findings.len(), 1);
+        let finding = &report.findings[0];
+        assert_eq!(finding.severity, Severity::Warning);
+        assert_eq!(finding.field, "header bytes 7-15");
+        assert!(finding.message.contains("DiskDude!"), "{}", finding.message);
+        assert!(finding
+            .fix
+            .as_ref()
+            .unwrap()
+            .contains("mapper 2 rather than 66"));
+    }
+
+    #[test]
+    fn test_validate_lists_every_problem() {
+        assert!(Rom::validate(&ines(0, 0, 1)).is_clean());
+
+        let mut raw = ines(0, 0, 1);
+        raw.extend_from_slice(&[0; 5]);
+        let report = Rom::validate(&raw);
+        assert!(!report.has_errors());
+        assert_eq!(report.findings[0].severity, Severity::Info);
+        assert_eq!(report.findings[0].message, "5 bytes follow the CHR ROM");
+
+        let mut raw = disk_dude(&ines(0, 0, 1));
+        raw.truncate(100);
+        let report = Rom::validate(&raw);
+        assert!(report.has_errors());
+        let fields: Vec<_> = report
+            .findings
+            .iter()
+            .map(|finding| finding.field)
+            .collect();
+        assert_eq!(fields, ["header bytes 7-15", "PRG ROM"]);
+        assert_eq!(
+            report.findings[1].message,
+            Rom::from_bytes(&raw).err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_ines_1_0_fields() {
+        // mapper 0x12, battery, PRG RAM byte 0
+        let rom = Rom::from_bytes(&ines(0x22, 0x10, 0)).unwrap();
+        assert_eq!((rom.mapper, rom.submapper), (0x12, 0));
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size), (0, 8192));
+        assert_eq!(rom.chr_ram_size, 8192);
+        assert_eq!(rom.tv_system, TvSystem::Ntsc);
+
+        let mut raw = ines(0, 0, 1);
+        raw[8] = 2;
+        raw[9] = 1;
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size), (16384, 0));
+        assert_eq!(rom.chr_ram_size, 0);
+        assert_eq!(rom.tv_system, TvSystem::Pal);
+    }
+
+    #[test]
+    fn test_ines_1_0_ignores_nes_2_0_bytes() {
+        // what NES 2.0 would read as mapper 0x512 and a Dendy
+        let mut raw = ines(0x20, 0x10, 0);
+        raw[8] = 0x35;
+        raw[12] = 3;
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.mapper, 0x12);
+        assert_eq!(rom.submapper, 0);
+        assert_eq!(rom.tv_system, TvSystem::Ntsc);
+    }
+
+    #[test]
+    fn test_ines_1_0_tv_system() {
+        let tv_system = |byte_9: u8, byte_10: u8, byte_11: u8| {
+            let mut raw = ines(0, 0, 1);
+            raw[9] = byte_9;
+            raw[10] = byte_10;
+            raw[11] = byte_11;
+            Rom::from_bytes(&raw).unwrap().tv_system()
+        };
+        assert_eq!(tv_system(0, 0, 0), TvSystem::Ntsc);
+        assert_eq!(tv_system(1, 0, 0), TvSystem::Pal);
+        assert_eq!(tv_system(0, 2, 0), TvSystem::Pal);
+        assert_eq!(tv_system(0, 1, 0), TvSystem::Dual);
+        assert_eq!(tv_system(1, 3, 0), TvSystem::Dual);
+        // byte 10 is not trusted when the bytes after it hold something
+        assert_eq!(tv_system(0, 1, 0x20), TvSystem::Ntsc);
+    }
+
+    #[test]
+    fn test_nes_2_0_tv_system() {
+        let tv_system = |byte_12: u8| {
+            let mut raw = ines(0, 0x08, 1);
+            raw[12] = byte_12;
+            Rom::from_bytes(&raw).unwrap().tv_system()
+        };
+        assert_eq!(tv_system(0), TvSystem::Ntsc);
+        assert_eq!(tv_system(1), TvSystem::Pal);
+        assert_eq!(tv_system(2), TvSystem::Dual);
+        assert_eq!(tv_system(3), TvSystem::Dendy);
+    }
+
+    #[test]
+    fn test_nes_2_0_fields() {
+        let mut raw = ines(0x42, 0x18, 1);
+        // mapper 0x514, submapper 3
+        raw[8] = 0x35;
+        // 8KB of PRG RAM, 32KB of PRG NVRAM, 2KB of CHR RAM, dual
+        raw[10] = 0x97;
+        raw[11] = 0x05;
+        raw[12] = 2;
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!((rom.mapper, rom.submapper), (0x514, 3));
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size), (8192, 32768));
+        assert_eq!(rom.chr_ram_size, 2048);
+        assert_eq!(rom.tv_system, TvSystem::Dual);
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_nes_2_0_rom_sizes() {
+        assert_eq!(
+            nes2_rom_size(2, 1, PRG_ROM_PAGE_SIZE),
+            0x102 * PRG_ROM_PAGE_SIZE
+        );
+        // 2^5 * 3
+        assert_eq!(nes2_rom_size(0b0001_0101, 0x0F, PRG_ROM_PAGE_SIZE), 96);
+        let mut raw = ines(0, 0x08, 0);
+        raw[9] = 0x0F;
+        raw[4] = 0xFF;
+        assert!(matches!(
+            Rom::from_bytes(&raw),
+            Err(RomError::TruncatedPrg { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_an_empty_prg_rom() {
+        let mut raw = ines(0, 0, 1);
+        raw[4] = 0;
+        raw.truncate(16 + CHR_ROM_PAGE_SIZE);
+        assert!(matches!(Rom::from_bytes(&raw), Err(RomError::EmptyPrg)));
+
+        // NES 2.0 sizes zero the same way
+        raw[7] = 0x08;
+        assert!(matches!(Rom::from_bytes(&raw), Err(RomError::EmptyPrg)));
+
+        let report = Rom::validate(&raw);
+        assert_eq!(report.findings.last().unwrap().field, "PRG ROM");
+        assert_eq!(
+            report.findings.last().unwrap().message,
+            "the header declares no PRG ROM"
+        );
+    }
+
+    #[test]
+    fn test_errors_name_the_sizes() {
+        let err = Rom::from_bytes(&ines(0, 0, 0)[..100]).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "the header declares 16384 bytes of PRG ROM, but only 84 follow it"
+        );
+    }
+
+    // `raw` with a trainer of `fill` bytes inserted after the header
+    fn with_trainer(raw: &[u8], fill: u8) -> Vec<u8> {
+        let mut trained = raw[..HEADER_SIZE].to_vec();
+        trained[6] |= 0b100;
+        trained.extend([fill; TRAINER_SIZE]);
+        trained.extend_from_slice(&raw[HEADER_SIZE..]);
+        trained
+    }
+
+    #[test]
+    fn test_trainer_is_skipped_and_kept() {
+        let mut raw = ines(0, 0, 1);
+        raw[16] = 0x11;
+        raw[16 + PRG_ROM_PAGE_SIZE] = 0x22;
+        let plain = Rom::from_bytes(&raw).unwrap();
+        let trained = Rom::from_bytes(&with_trainer(&raw, 0xab)).unwrap();
+        assert_eq!(trained.prg_rom, plain.prg_rom);
+        assert_eq!(trained.chr_rom, plain.chr_rom);
+        assert_eq!(plain.trainer, None);
+        assert_eq!(trained.trainer, Some([0xab; TRAINER_SIZE]));
+    }
+
+    #[test]
+    fn test_nrom_loads_the_trainer_at_0x7000() {
+        let raw = with_trainer(&ines(0, 0, 0), 0xab);
+        let mut mapper = Rom::from_bytes(&raw).unwrap().into_mapper().unwrap();
+        assert_eq!(mapper.cpu_read(0x6fff), Some(0));
+        assert_eq!(mapper.cpu_read(0x7000), Some(0xab));
+        assert_eq!(mapper.cpu_read(0x71ff), Some(0xab));
+        assert_eq!(mapper.cpu_read(0x7200), Some(0));
+    }
+
+    #[test]
+    fn test_truncated_trainer() {
+        let raw = with_trainer(&ines(0, 0, 0), 0);
+        assert!(matches!(
+            Rom::from_bytes(&raw[..100]),
+            Err(RomError::TruncatedTrainer { actual: 84 })
+        ));
+    }
+
+    #[test]
+    fn test_hashes_cover_prg_and_chr_only() {
+        let rom = Rom::from_file("snake.nes").unwrap();
+        assert_eq!(rom.crc32(), 0x862A_5C36);
+        // snake.nes is a header and 32KB of PRG ROM
+        #[cfg(feature = "sha1")]
+        assert_eq!(
+            rom.sha1(),
+            hash::sha1(&std::fs::read("snake.nes").unwrap()[HEADER_SIZE..])
+        );
+
+        let plain = Rom::new(&ines(0, 0, 1)).unwrap();
+        let trained = Rom::new(&with_trainer(&ines(0, 0, 1), 0xab)).unwrap();
+        assert_eq!(plain.crc32(), trained.crc32());
+    }
+
+    #[test]
+    fn test_battery_flag() {
+        assert!(!Rom::new(&ines(0, 0, 0)).unwrap().has_battery());
+        let rom = Rom::new(&ines(0b10, 0, 0)).unwrap();
+        assert!(rom.has_battery());
+        let mut mapper = rom.into_mapper().unwrap();
+        mapper.cpu_write(0x6000, 0x12);
+        assert_eq!(mapper.battery_ram().unwrap()[0], 0x12);
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path = std::env::temp_dir().join(format!("nes-rs-rom-{}.nes", std::process::id()));
+        std::fs::write(&path, ines(0, 0, 0)).unwrap();
+        let rom = Rom::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let rom = rom.unwrap();
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.save_path(), Some(path.with_extension("sav")));
+        assert_eq!(Rom::new(&ines(0, 0, 0)).unwrap().save_path(), None);
+
+        assert!(matches!(Rom::from_file(&path), Err(RomError::Io(_))));
+    }
+
+    #[test]
+    fn test_mapper_number_comes_from_both_flag_nibbles() {
+        let rom = Rom::new(&ines(0x20, 0x10, 0)).unwrap();
+        assert_eq!(rom.mapper, 0x12);
+        assert_eq!(Rom::new(&ines(0, 0, 0)).unwrap().mapper, 0);
+    }
+
+    #[test]
+    fn test_into_mapper_rejects_unknown_mappers() {
+        let rom = Rom::new(&ines(0x10, 0, 0)).unwrap();
+        assert!(matches!(
+            rom.into_mapper(),
+            Err(RomError::UnsupportedMapper(1))
+        ));
+    }
+
+    #[test]
+    fn test_test_rom_header() {
+        let raw = test_rom()
+            .prg_banks(3)
+            .chr_banks(2)
+            .mapper(0x42)
+            .mirroring(Mirroring::Vertical)
+            .battery(true)
+            .to_bytes();
+        assert_eq!(raw[0..8], [0x4E, 0x45, 0x53, 0x1A, 3, 2, 0x23, 0x40]);
+        assert_eq!(raw[8..16], [0; 8]);
+        assert_eq!(
+            raw.len(),
+            HEADER_SIZE + 3 * PRG_ROM_PAGE_SIZE + 2 * CHR_ROM_PAGE_SIZE
+        );
+
+        let rom = test_rom().mirroring(Mirroring::FourScreen).build();
+        assert_eq!((rom.mapper, rom.prg_rom.len()), (0, 2 * PRG_ROM_PAGE_SIZE));
+        assert_eq!(rom.screen_mirroring(), Mirroring::FourScreen);
+        assert!(rom.chr_rom.is_empty());
+        assert!(!rom.has_battery());
+    }
+
+    #[test]
+    fn test_test_rom_contents() {
+        let rom = test_rom()
+            .prg_banks(1)
+            .chr_banks(1)
+            .prg_bytes(0x0010, &[1, 2, 3])
+            .prg_bytes(0x0011, &[4])
+            .reset_vector(0xc123)
+            .chr_bytes(0x1fff, &[5])
+            .build();
+        assert_eq!(rom.prg_rom[0x0010..0x0013], [1, 4, 3]);
+        assert_eq!(rom.prg_rom[0x3ffc..0x3ffe], [0x23, 0xc1]);
+        assert_eq!(rom.chr_rom[0x1fff], 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "SingleScreenLower")]
+    fn test_test_rom_rejects_single_screen() {
+        test_rom().mirroring(Mirroring::SingleScreenLower).build();
+    }
+
+    // marks the first byte of every `prg_size` PRG bank and `chr_size` CHR
+    // bank with the bank's number
+    fn numbered_banks(mut rom: TestRom, prg_size: usize, chr_size: usize) -> TestRom {
+        for bank in 0..rom.prg_banks as usize * PRG_ROM_PAGE_SIZE / prg_size {
+            rom = rom.prg_bytes(bank * prg_size, &[bank as u8]);
+        }
+        for bank in 0..rom.chr_banks as usize * CHR_ROM_PAGE_SIZE / chr_size {
+            rom = rom.chr_bytes(bank * chr_size, &[bank as u8]);
+        }
+        rom
+    }
+
+    // one CHR bank, and every PRG bank starting with 0x10 + its number and
+    // ending with 0x20 + its number
+    fn nrom_with_sentinels(prg_banks: u8) -> Box<dyn Mapper> {
+        let mut rom = test_rom()
+            .prg_banks(prg_banks)
+            .chr_banks(1)
+            .chr_bytes(0, &[0x30])
+            .chr_bytes(CHR_ROM_PAGE_SIZE - 1, &[0x31]);
+        for bank in 0..prg_banks {
+            let start = bank as usize * PRG_ROM_PAGE_SIZE;
+            rom = rom
+                .prg_bytes(start, &[0x10 + bank])
+                .prg_bytes(start + PRG_ROM_PAGE_SIZE - 1, &[0x20 + bank]);
+        }
+        rom.build().into_mapper().unwrap()
+    }
+
+    #[test]
+    fn test_nrom_16kb_prg_shows_in_both_halves() {
+        let mut mapper = nrom_with_sentinels(1);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x10));
+        assert_eq!(mapper.cpu_read(0xbfff), Some(0x20));
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x10));
+        assert_eq!(mapper.cpu_read(0xffff), Some(0x20));
+    }
+
+    #[test]
+    fn test_nrom_32kb_prg_fills_the_window() {
+        let mut mapper = nrom_with_sentinels(2);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x10));
+        assert_eq!(mapper.cpu_read(0xbfff), Some(0x20));
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x11));
+        assert_eq!(mapper.cpu_read(0xffff), Some(0x21));
+    }
+
+    #[test]
+    fn test_nrom_ignores_and_counts_rom_writes() {
+        let mut mapper = nrom_with_sentinels(2);
+        mapper.cpu_write(0xc000, 0xff);
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x11));
+        assert_eq!(mapper.take_fault(), Some(0xc000));
+        assert_eq!(mapper.rom_write_violations(), 1);
+    }
+
+    #[test]
+    fn test_nrom_serves_chr_rom_to_the_ppu() {
+        let mut mapper = nrom_with_sentinels(1);
+        assert_eq!(mapper.ppu_read(0x0000), 0x30);
+        assert_eq!(mapper.ppu_read(0x1fff), 0x31);
+        mapper.ppu_write(0x0000, 0xff);
+        assert_eq!(mapper.ppu_read(0x0000), 0x30);
+    }
+
+    // 64KB of PRG with each bank's number at its start and end and 0xFF
+    // after the first, and no CHR
+    // a single 8KB PRG bank, smaller than the banks these boards switch,
+    // with 0x42 at its start and 0x43 at its end
+    fn small_prg(mapper: u8) -> Box<dyn Mapper> {
+        let mut rom = test_rom().mapper(mapper).build();
+        rom.prg_rom = vec![0; 0x2000];
+        rom.prg_rom[0] = 0x42;
+        rom.prg_rom[0x1fff] = 0x43;
+        rom.into_mapper().unwrap()
+    }
+
+    fn assert_small_prg_mirrors(mapper: &mut Box<dyn Mapper>) {
+        for window in [0x8000, 0xa000, 0xc000, 0xe000] {
+            assert_eq!(mapper.cpu_read(window), Some(0x42), "{:#06x}", window);
+            assert_eq!(mapper.cpu_read(window + 0x1fff), Some(0x43));
+        }
+    }
+
+    #[test]
+    fn test_uxrom_mirrors_a_prg_rom_smaller_than_a_bank() {
+        let mut mapper = small_prg(2);
+        assert_small_prg_mirrors(&mut mapper);
+        mapper.cpu_write(0x8000, 5);
+        assert_small_prg_mirrors(&mut mapper);
+    }
+
+    fn uxrom() -> Uxrom {
+        let mut rom = test_rom().mapper(2).prg_banks(4);
+        for bank in 0..4 {
+            let start = bank as usize * PRG_ROM_PAGE_SIZE;
+            rom = rom
+                .prg_bytes(start, &[bank, 0xff])
+                .prg_bytes(start + PRG_ROM_PAGE_SIZE - 1, &[bank]);
+        }
+        Uxrom::new(rom.build())
+    }
+
+    #[test]
+    fn test_uxrom_switches_the_lower_window_from_a_program() {
+        let mut cpu = CPU::new(Bus::with_mapper(Box::new(uxrom())));
+        // LDA $8000; STA $00; LDA $FFFF; STA $01;
+        // LDA #$02; STA $8000; LDA $8000; STA $02; LDA $BFFF; STA $03;
+        // LDA $C000; STA $04; BRK
+        cpu.mem_write_slice(
+            0x0600,
+            &[
+                0xad, 0x00, 0x80, 0x85, 0x00, 0xad, 0xff, 0xff, 0x85, 0x01, 0xa9, 0x02, 0x8d, 0x00,
+                0x80, 0xad, 0x00, 0x80, 0x85, 0x02, 0xad, 0xff, 0xbf, 0x85, 0x03, 0xad, 0x00, 0xc0,
+                0x85, 0x04, 0x00,
+            ],
+        );
+        cpu.program_counter = 0x0600;
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x0000), 0);
+        assert_eq!(cpu.mem_read(0x0001), 3);
+        assert_eq!(cpu.mem_read(0x0002), 2);
+        assert_eq!(cpu.mem_read(0x0003), 2);
+        assert_eq!(cpu.mem_read(0x0004), 3);
+    }
+
+    #[test]
+    fn test_uxrom_bank_info() {
+        let mut mapper = uxrom();
+        let numbers = |info: BankInfo| info.prg.map(|bank| bank.number);
+        assert_eq!(numbers(mapper.bank_info()), [0, 0, 3, 3]);
+        mapper.cpu_write(0x8000, 2);
+        let info = mapper.bank_info();
+        assert_eq!(numbers(info), [2, 2, 3, 3]);
+        assert_eq!(info.prg[1].offset, 2 * PRG_ROM_PAGE_SIZE + 0x2000);
+        assert!(info.chr.iter().all(|bank| bank.ram));
+        assert_eq!(
+            info.to_string(),
+            "PRG 8000:02 A000:02 C000:03 E000:03 \
+             CHR 0000:r00 0400:r00 0800:r00 0C00:r00 1000:r00 1400:r00 1800:r00 1C00:r00 \
+             Horizontal"
+        );
+    }
+
+    #[test]
+    fn test_uxrom_bus_conflicts_and_the_value_with_rom() {
+        let mut mapper = uxrom();
+        mapper.set_bus_conflicts(true);
+        // the byte at 0x8000 is bank 0's number
+        mapper.cpu_write(0x8000, 0x03);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0));
+        // 0x8001 holds 0xFF
+        mapper.cpu_write(0x8001, 0x03);
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.cpu_read(0xc000), Some(3));
+    }
+
+    #[test]
+    fn test_uxrom_chr_ram_is_writable() {
+        let mut mapper = uxrom();
+        mapper.ppu_write(0x0123, 0x42);
+        mapper.ppu_write(0x1fff, 0x43);
+        assert_eq!(mapper.ppu_read(0x0123), 0x42);
+        assert_eq!(mapper.ppu_read(0x1fff), 0x43);
+    }
+
+    // 128KB of PRG and 64KB of CHR, every 8KB PRG bank and 1KB CHR bank
+    // starting with its number; the last PRG bank points the IRQ vector at
+    // 0x0700
+    fn mmc3() -> Mmc3 {
+        let rom = test_rom().mapper(4).prg_banks(8).chr_banks(8);
+        let rom = numbered_banks(rom, MMC3_PRG_BANK_SIZE, MMC3_CHR_BANK_SIZE);
+        Mmc3::new(rom.prg_bytes(0x1fffe, &[0x00, 0x07]).build())
+    }
+
+    #[test]
+    fn test_mmc3_mirrors_a_prg_rom_of_one_bank() {
+        let mut mapper = small_prg(4);
+        assert_small_prg_mirrors(&mut mapper);
+        for (register, bank) in [(6, 3), (7, 1)] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+        assert_small_prg_mirrors(&mut mapper);
+        // PRG mode 1 swaps the second to last bank into 0x8000
+        mapper.cpu_write(0x8000, 0x40);
+        assert_small_prg_mirrors(&mut mapper);
+    }
+
+    fn prg_windows(mapper: &Mmc3) -> Vec<u8> {
+        (0..4)
+            .map(|window| mapper.cpu_peek(0x8000 + window * 0x2000).unwrap())
+            .collect()
+    }
+
+    fn chr_windows(mapper: &mut Mmc3) -> Vec<u8> {
+        (0..8)
+            .map(|window| mapper.ppu_read(window * 0x0400))
+            .collect()
+    }
+
+    #[test]
+    fn test_mmc3_bank_windows() {
+        let mut mapper = mmc3();
+        for (register, bank) in [
+            (0, 10),
+            (1, 21),
+            (2, 30),
+            (3, 31),
+            (4, 32),
+            (5, 33),
+            (6, 3),
+            (7, 5),
+        ] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+        assert_eq!(prg_windows(&mapper), [3, 5, 14, 15]);
+        assert_eq!(chr_windows(&mut mapper), [10, 11, 20, 21, 30, 31, 32, 33]);
+
+        // PRG mode 1 swaps 0x8000 and 0xC000, CHR inversion the two halves
+        mapper.cpu_write(0x8000, 0xC0);
+        assert_eq!(prg_windows(&mapper), [14, 5, 3, 15]);
+        assert_eq!(chr_windows(&mut mapper), [30, 31, 32, 33, 10, 11, 20, 21]);
+    }
+
+    #[test]
+    fn test_mmc3_bank_info() {
+        let mut mapper = mmc3();
+        let before = mapper.bank_info();
+        for (register, bank) in [(0, 10), (2, 30), (6, 3), (7, 5)] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+        mapper.cpu_write(0xa000, 1);
+        let info = mapper.bank_info();
+        assert_ne!(info, before);
+        // the reported banks are what reads see
+        let prg: Vec<_> = info.prg.iter().map(|bank| bank.number as u8).collect();
+        assert_eq!(prg, prg_windows(&mapper));
+        let chr: Vec<_> = info.chr.iter().map(|bank| bank.number as u8).collect();
+        assert_eq!(chr, chr_windows(&mut mapper));
+        assert_eq!(info.prg[0].offset, 3 * MMC3_PRG_BANK_SIZE);
+        assert_eq!(info.chr[4].offset, 30 * MMC3_CHR_BANK_SIZE);
+        assert_eq!(info.mirroring, Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_mmc3_mirroring_and_prg_ram_protect() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0xa000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.cpu_write(0xa000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        mapper.cpu_write(0x6000, 0x11);
+        mapper.cpu_write(0xa001, 0xc0);
+        mapper.cpu_write(0x6000, 0x22);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x11));
+        mapper.cpu_write(0xa001, 0x00);
+        assert_eq!(mapper.cpu_read(0x6000), None);
+    }
+
+    fn clock_scanline(mapper: &mut Mmc3) {
+        mapper.notify_a12(false);
+        mapper.notify_a12(true);
+    }
+
+    #[test]
+    fn test_mmc3_irq_fires_when_the_counter_reaches_zero() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0xc000, 3);
+        mapper.cpu_write(0xc001, 0);
+        mapper.cpu_write(0xe001, 0);
+
+        // the first edge loads the latch, then three count down
+        for _ in 0..3 {
+            clock_scanline(&mut mapper);
+            assert!(!mapper.irq_pending());
+        }
+        clock_scanline(&mut mapper);
+        assert!(mapper.irq_pending());
+
+        // it stays asserted until 0xE000 acknowledges it
+        mapper.notify_a12(true);
+        assert!(mapper.irq_pending());
+        mapper.cpu_write(0xe000, 0);
+        assert!(!mapper.irq_pending());
+
+        // disabled, reaching zero again is silent
+        for _ in 0..4 {
+            clock_scanline(&mut mapper);
+        }
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_mmc3_irq_reaches_the_cpu() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0xc000, 1);
+        mapper.cpu_write(0xc001, 0);
+        mapper.cpu_write(0xe001, 0);
+        let mut cpu = CPU::new(Bus::with_mapper(Box::new(mapper)));
+        // CLI; JMP $0601
+        cpu.mem_write_slice(0x0600, &[0x58, 0x4c, 0x01, 0x06]);
+        // LDA #$01; STA $10; STA $E000; BRK
+        cpu.mem_write_slice(0x0700, &[0xa9, 0x01, 0x85, 0x10, 0x8d, 0x00, 0xe0, 0x00]);
+        cpu.program_counter = 0x0600;
+        cpu.step().unwrap();
+
+        cpu.bus.notify_a12(true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0601);
+
+        // the IRQ is taken, then the handler's first instruction runs
+        cpu.bus.notify_a12(false);
+        cpu.bus.notify_a12(true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0702);
+
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x0010), 1);
+        assert!(!cpu.bus.irq_line());
+    }
+
+    // four 32KB PRG banks, each starting with 0x10 + its number and ending
+    // with 0x20 + its number
+    fn axrom() -> Box<dyn Mapper> {
+        let mut rom = test_rom().mapper(7).prg_banks(8);
+        for bank in 0..4 {
+            let start = bank as usize * AXROM_PRG_BANK_SIZE;
+            rom = rom
+                .prg_bytes(start, &[0x10 + bank])
+                .prg_bytes(start + AXROM_PRG_BANK_SIZE - 1, &[0x20 + bank]);
+        }
+        rom.build().into_mapper().unwrap()
+    }
+
+    #[test]
+    fn test_axrom_switches_32kb_banks() {
+        let mut mapper = axrom();
+        for bank in [0, 3, 1, 2] {
+            mapper.cpu_write(0x8000, bank);
+            assert_eq!(mapper.cpu_read(0x8000), Some(0x10 + bank));
+            assert_eq!(mapper.cpu_read(0xffff), Some(0x20 + bank));
+        }
+    }
+
+    #[test]
+    fn test_axrom_picks_a_single_screen() {
+        let mut mapper = axrom();
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        mapper.cpu_write(0xc000, 0x12);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x12));
+        mapper.cpu_write(0xc000, 0x02);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    // 128KB of PRG and 128KB of CHR, every 8KB PRG bank and 4KB CHR bank
+    // starting with its number
+    fn mmc2() -> Box<dyn Mapper> {
+        let rom = test_rom().mapper(9).prg_banks(8).chr_banks(16);
+        numbered_banks(rom, MMC2_PRG_BANK_SIZE, MMC2_CHR_BANK_SIZE)
+            .build()
+            .into_mapper()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_mmc2_prg_windows() {
+        let mut mapper = mmc2();
+        mapper.cpu_write(0xa000, 5);
+        let windows: Vec<_> = (0..4)
+            .map(|window| mapper.cpu_peek(0x8000 + window * 0x2000).unwrap())
+            .collect();
+        assert_eq!(windows, [5, 13, 14, 15]);
+    }
+
+    #[test]
+    fn test_mmc2_tile_fetches_switch_the_chr_latches() {
+        let mut mapper = mmc2();
+        mapper.cpu_write(0xb000, 1);
+        mapper.cpu_write(0xc000, 2);
+        mapper.cpu_write(0xd000, 3);
+        mapper.cpu_write(0xe000, 4);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        assert_eq!(mapper.ppu_read(0x1000), 4);
+
+        // the trigger fetch itself still sees the old bank
+        mapper.ppu_read(0x0fd8);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+        assert_eq!(mapper.ppu_read(0x1000), 4);
+
+        mapper.ppu_read(0x1fdc);
+        assert_eq!(mapper.ppu_read(0x1000), 3);
+
+        mapper.ppu_read(0x0fe8);
+        mapper.ppu_read(0x1fef);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        assert_eq!(mapper.ppu_read(0x1000), 4);
+
+        // only 0x0FD8 and 0x0FE8 trip the lower latch
+        mapper.ppu_read(0x0fd9);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+    }
+
+    #[test]
+    fn test_mmc2_mirroring_register() {
+        let mut mapper = mmc2();
+        mapper.cpu_write(0xf000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.cpu_write(0xf000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    // 128KB of PRG and 128KB of CHR, every 8KB PRG bank and 1KB CHR bank
+    // starting with its number
+    fn mmc5() -> Box<dyn Mapper> {
+        let rom = test_rom().mapper(5).prg_banks(8).chr_banks(16);
+        numbered_banks(rom, MMC5_PRG_BANK_SIZE, 0x0400)
+            .build()
+            .into_mapper()
+            .unwrap()
+    }
+
+    // the bank at the start of each 8KB window from 0x8000
+    fn mmc5_prg_windows(mapper: &dyn Mapper) -> Vec<u8> {
+        (0..4)
+            .map(|window| mapper.cpu_peek(0x8000 + window * 0x2000).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_mmc5_prg_modes() {
+        let mut mapper = mmc5();
+        // it powers on in mode 3 with the last bank at 0xE000
+        assert_eq!(mmc5_prg_windows(&*mapper)[3], 15);
+        for (addr, bank) in [
+            (0x5114, 0x81),
+            (0x5115, 0x86),
+            (0x5116, 0x8b),
+            (0x5117, 0x8d),
+        ] {
+            mapper.cpu_write(addr, bank);
+        }
+        assert_eq!(mmc5_prg_windows(&*mapper), [1, 6, 11, 13]);
+
+        mapper.cpu_write(0x5100, 2);
+        assert_eq!(mmc5_prg_windows(&*mapper), [6, 7, 11, 13]);
+
+        mapper.cpu_write(0x5100, 1);
+        assert_eq!(mmc5_prg_windows(&*mapper), [6, 7, 12, 13]);
+
+        mapper.cpu_write(0x5100, 0);
+        assert_eq!(mmc5_prg_windows(&*mapper), [12, 13, 14, 15]);
+    }
+
+    #[test]
+    fn test_mmc5_prg_ram_banks() {
+        let mut mapper = mmc5();
+        mapper.cpu_write(0x5102, 2);
+        mapper.cpu_write(0x5103, 1);
+        mapper.cpu_write(0x5113, 1);
+        mapper.cpu_write(0x6000, 0x11);
+        // the same RAM bank in a PRG window
+        mapper.cpu_write(0x5114, 0x01);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x11));
+        mapper.cpu_write(0x8001, 0x22);
+        assert_eq!(mapper.cpu_read(0x6001), Some(0x22));
+
+        // unlocking takes both registers
+        mapper.cpu_write(0x5103, 0);
+        mapper.cpu_write(0x6000, 0x33);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x11));
+    }
+
+    #[test]
+    fn test_mmc5_bank_info_reports_ram_windows() {
+        let mut mapper = mmc5();
+        assert_eq!(
+            mapper.bank_info().prg[3],
+            rom_bank(15 * MMC5_PRG_BANK_SIZE, MMC5_PRG_BANK_SIZE)
+        );
+        mapper.cpu_write(0x5114, 0x03);
+        mapper.cpu_write(0x5101, 3);
+        mapper.cpu_write(0x5127, 9);
+        let info = mapper.bank_info();
+        assert_eq!(
+            info.prg[0],
+            Bank {
+                number: 3,
+                offset: 3 * MMC5_PRG_BANK_SIZE,
+                ram: true
+            }
+        );
+        assert_eq!(info.chr[7].number, 9);
+        assert_eq!(info.chr[7].offset, 9 * 0x0400);
+    }
+
+    #[test]
+    fn test_mmc5_chr_modes() {
+        let mut mapper = mmc5();
+        // each register picks the bank of its own number, in units of the
+        // mode's window size
+        for register in 0..8 {
+            mapper.cpu_write(0x5120 + register, register as u8);
+        }
+        for (mode, banks) in [
+            (0, [56, 57, 58, 59, 60, 61, 62, 63]),
+            (1, [12, 13, 14, 15, 28, 29, 30, 31]),
+            (2, [2, 3, 6, 7, 10, 11, 14, 15]),
+            (3, [0, 1, 2, 3, 4, 5, 6, 7]),
+        ] {
+            mapper.cpu_write(0x5101, mode);
+            let windows: Vec<_> = (0..8)
+                .map(|window| mapper.ppu_read(window * 0x0400))
+                .collect();
+            assert_eq!(windows, banks, "mode {}", mode);
+        }
+    }
+
+    #[test]
+    fn test_mmc5_exram() {
+        let mut mapper = mmc5();
+        mapper.cpu_write(0x5104, 2);
+        mapper.cpu_write(0x5c00, 0x12);
+        mapper.cpu_write(0x5fff, 0x34);
+        assert_eq!(mapper.cpu_read(0x5c00), Some(0x12));
+        assert_eq!(mapper.cpu_read(0x5fff), Some(0x34));
+
+        mapper.cpu_write(0x5104, 3);
+        mapper.cpu_write(0x5c00, 0x56);
+        assert_eq!(mapper.cpu_read(0x5c00), Some(0x12));
+    }
+
+    #[test]
+    fn test_mmc5_scanline_irq() {
+        let mut mapper = mmc5();
+        mapper.cpu_write(0x5203, 100);
+        mapper.cpu_write(0x5204, 0x80);
+        for scanline in 0..100 {
+            mapper.notify_scanline(scanline);
+            assert!(!mapper.irq_pending(), "scanline {}", scanline);
+        }
+        assert_eq!(mapper.cpu_peek(0x5204), Some(0x40));
+        mapper.notify_scanline(100);
+        assert!(mapper.irq_pending());
+
+        // reading the status acknowledges
+        assert_eq!(mapper.cpu_read(0x5204), Some(0xc0));
+        assert!(!mapper.irq_pending());
+
+        mapper.notify_scanline(240);
+        assert_eq!(mapper.cpu_peek(0x5204), Some(0x00));
+    }
+
+    #[test]
+    fn test_mmc5_nametable_layouts() {
+        let mut mapper = mmc5();
+        mapper.cpu_write(0x5105, 0x44);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.cpu_write(0x5105, 0x50);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        // ExRAM as a nametable
+        mapper.cpu_write(0x5105, 0xe4);
+        assert_eq!(mapper.mirroring(), Mirroring::FourScreen);
+    }
+
+    // a mapper 34 image of four 32KB PRG banks and `chr_banks` 4KB CHR
+    // banks, each starting with its number
+    fn mapper_34(submapper: Option<u8>, chr_banks: u8) -> Rom {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 8, chr_banks / 2, 0x20, 0x20];
+        if let Some(submapper) = submapper {
+            raw[7] |= 0x08;
+            raw.push(submapper << 4);
+        }
+        raw.resize(HEADER_SIZE, 0);
+        for bank in 0..4 {
+            let mut prg = vec![0; BNROM_PRG_BANK_SIZE];
+            prg[0] = bank;
+            raw.extend_from_slice(&prg);
+        }
+        for bank in 0..chr_banks {
+            let mut chr = vec![0; NINA_001_CHR_BANK_SIZE];
+            chr[0] = bank;
+            raw.extend_from_slice(&chr);
+        }
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.mapper, 34);
+        rom
+    }
+
+    #[test]
+    fn test_mapper_34_board_heuristic() {
+        assert!(!is_nina_001(&mapper_34(None, 0)));
+        assert!(!is_nina_001(&mapper_34(None, 2)));
+        assert!(is_nina_001(&mapper_34(None, 4)));
+        assert!(is_nina_001(&mapper_34(Some(1), 2)));
+        assert!(!is_nina_001(&mapper_34(Some(2), 4)));
+        assert!(is_nina_001(&mapper_34(Some(0), 4)));
+    }
+
+    #[test]
+    fn test_bnrom_switches_32kb_banks() {
+        let mut mapper = mapper_34(None, 0).into_mapper().unwrap();
+        for bank in [2, 0, 3] {
+            mapper.cpu_write(0xffff, bank);
+            assert_eq!(mapper.cpu_read(0x8000), Some(bank));
+        }
+        mapper.ppu_write(0x1000, 0x42);
+        assert_eq!(mapper.ppu_read(0x1000), 0x42);
+    }
+
+    #[test]
+    fn test_nina_001_banks_through_prg_ram() {
+        let mut mapper = mapper_34(None, 8).into_mapper().unwrap();
+        mapper.cpu_write(0x7ffd, 1);
+        mapper.cpu_write(0x7ffe, 5);
+        mapper.cpu_write(0x7fff, 2);
+        assert_eq!(mapper.cpu_read(0x8000), Some(1));
+        assert_eq!(mapper.ppu_read(0x0000), 5);
+        assert_eq!(mapper.ppu_read(0x1000), 2);
+        assert_eq!(mapper.cpu_read(0x7ffe), Some(5));
+
+        // writes to ROM do not switch anything
+        mapper.cpu_write(0x8000, 0);
+        assert_eq!(mapper.cpu_read(0x8000), Some(1));
+    }
+
+    #[test]
+    fn test_nina_001_bank_info() {
+        let mut mapper = mapper_34(None, 8).into_mapper().unwrap();
+        mapper.cpu_write(0x7ffd, 1);
+        mapper.cpu_write(0x7fff, 2);
+        let info = mapper.bank_info();
+        assert_eq!(info.prg.map(|bank| bank.number), [1; 4]);
+        assert_eq!(info.prg[3].offset, BNROM_PRG_BANK_SIZE + 0x6000);
+        assert_eq!(info.chr.map(|bank| bank.number), [0, 0, 0, 0, 2, 2, 2, 2]);
+    }
+
+    // 128KB of PRG and 64KB of CHR, every 8KB PRG bank and 1KB CHR bank
+    // starting with its number
+    fn fme7() -> Box<dyn Mapper> {
+        let rom = test_rom().mapper(69).prg_banks(8).chr_banks(8);
+        numbered_banks(rom, FME7_PRG_BANK_SIZE, FME7_CHR_BANK_SIZE)
+            .build()
+            .into_mapper()
+            .unwrap()
+    }
+
+    fn fme7_command(mapper: &mut Box<dyn Mapper>, command: u8, parameter: u8) {
+        mapper.cpu_write(0x8000, command);
+        mapper.cpu_write(0xa000, parameter);
+    }
+
+    #[test]
+    fn test_fme7_bank_windows() {
+        let mut mapper = fme7();
+        for window in 0..8 {
+            fme7_command(&mut mapper, window, 40 + window);
+        }
+        fme7_command(&mut mapper, 0x8, 2);
+        fme7_command(&mut mapper, 0x9, 3);
+        fme7_command(&mut mapper, 0xa, 4);
+        fme7_command(&mut mapper, 0xb, 5);
+        let prg: Vec<_> = (0..5)
+            .map(|window| mapper.cpu_peek(0x6000 + window * 0x2000).unwrap())
+            .collect();
+        assert_eq!(prg, [2, 3, 4, 5, 15]);
+        let chr: Vec<_> = (0..8)
+            .map(|window| mapper.ppu_read(window * 0x0400))
+            .collect();
+        assert_eq!(chr, [40, 41, 42, 43, 44, 45, 46, 47]);
+    }
+
+    #[test]
+    fn test_fme7_prg_ram_at_0x6000() {
+        let mut mapper = fme7();
+        fme7_command(&mut mapper, 0x8, 0x40);
+        assert_eq!(mapper.cpu_read(0x6000), None);
+        fme7_command(&mut mapper, 0x8, 0xc0);
+        mapper.cpu_write(0x6000, 0x11);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x11));
+        fme7_command(&mut mapper, 0x8, 0x00);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0));
+    }
+
+    #[test]
+    fn test_fme7_mirroring_command() {
+        let mut mapper = fme7();
+        for (parameter, mirroring) in [
+            (0, Mirroring::Vertical),
+            (1, Mirroring::Horizontal),
+            (2, Mirroring::SingleScreenLower),
+            (3, Mirroring::SingleScreenUpper),
+        ] {
+            fme7_command(&mut mapper, 0xc, parameter);
+            assert_eq!(mapper.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_fme7_irq_counter() {
+        let mut mapper = fme7();
+        fme7_command(&mut mapper, 0xe, 10);
+        fme7_command(&mut mapper, 0xf, 0);
+        fme7_command(&mut mapper, 0xd, 0x81);
+
+        // it counts 10 down to 0, then fires on the cycle that wraps it
+        mapper.tick(4);
+        mapper.tick(6);
+        assert!(!mapper.irq_pending());
+        mapper.tick(1);
+        assert!(mapper.irq_pending());
+
+        // writing the control register acknowledges
+        fme7_command(&mut mapper, 0xd, 0x81);
+        assert!(!mapper.irq_pending());
+
+        // with the counter stopped nothing happens
+        fme7_command(&mut mapper, 0xe, 0);
+        fme7_command(&mut mapper, 0xd, 0x01);
+        mapper.tick(100);
+        assert!(!mapper.irq_pending());
+    }
+
+    // a NES 2.0 image of eight 16KB PRG banks, each starting with its
+    // number, and vertical mirroring
+    fn camerica(submapper: u8) -> Box<dyn Mapper> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 8, 0, 0x71, 0x48, submapper << 4];
+        raw.resize(HEADER_SIZE, 0);
+        for bank in 0..8 {
+            let mut prg = vec![0; PRG_ROM_PAGE_SIZE];
+            prg[0] = bank;
+            raw.extend_from_slice(&prg);
+        }
+        Rom::from_bytes(&raw).unwrap().into_mapper().unwrap()
+    }
+
+    #[test]
+    fn test_camerica_mirrors_a_prg_rom_smaller_than_a_bank() {
+        let mut mapper = small_prg(71);
+        assert_small_prg_mirrors(&mut mapper);
+        mapper.cpu_write(0xc000, 3);
+        assert_small_prg_mirrors(&mut mapper);
+    }
+
+    #[test]
+    fn test_camerica_switches_banks_from_0xc000_up() {
+        let mut mapper = camerica(0);
+        mapper.cpu_write(0xc000, 3);
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.cpu_read(0xc000), Some(7));
+        mapper.cpu_write(0xffff, 5);
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+
+        // 0x8000-0xBFFF is not the bank register
+        mapper.cpu_write(0x8000, 1);
+        mapper.cpu_write(0xa000, 1);
+        assert_eq!(mapper.cpu_read(0x8000), Some(5));
+    }
+
+    #[test]
+    fn test_camerica_mirroring_only_on_fire_hawk() {
+        let mut plain = camerica(0);
+        plain.cpu_write(0x9000, 0x10);
+        assert_eq!(plain.mirroring(), Mirroring::Vertical);
+
+        let mut fire_hawk = camerica(1);
+        assert_eq!(fire_hawk.mirroring(), Mirroring::Vertical);
+        fire_hawk.cpu_write(0x9000, 0x10);
+        assert_eq!(fire_hawk.mirroring(), Mirroring::SingleScreenUpper);
+        fire_hawk.cpu_write(0x9000, 0x00);
+        assert_eq!(fire_hawk.mirroring(), Mirroring::SingleScreenLower);
+        assert_eq!(fire_hawk.cpu_read(0x8000), Some(0));
+    }
+
+    #[test]
+    fn test_chr_ram_only_without_chr_rom() {
+        for mapper in [0u8, 2, 4, 5, 7, 34, 69, 71] {
+            let (flags6, flags7) = (mapper << 4, mapper & 0xf0);
+            let mut ram = Rom::new(&ines(flags6, flags7, 0))
+                .unwrap()
+                .into_mapper()
+                .unwrap();
+            ram.ppu_write(0x0010, 0x41);
+            ram.ppu_write(0x1fff, 0x42);
+            assert_eq!(ram.ppu_read(0x0010), 0x41, "mapper {}", mapper);
+            assert_eq!(ram.ppu_read(0x1fff), 0x42, "mapper {}", mapper);
+        }
+        for mapper in [0u8, 2, 4, 5, 34, 69] {
+            let (flags6, flags7) = (mapper << 4, mapper & 0xf0);
+            let mut rom = Rom::new(&ines(flags6, flags7, 1))
+                .unwrap()
+                .into_mapper()
+                .unwrap();
+            rom.ppu_write(0x1fff, 0x42);
+            assert_eq!(rom.ppu_read(0x1fff), 0, "mapper {}", mapper);
+        }
+    }
+
+    #[test]
+    fn test_chr_ram_size_from_nes_2_0() {
+        let mut raw = ines(0, 0x08, 0);
+        // 32KB of CHR RAM
+        raw[11] = 0x09;
+        let rom = Rom::new(&raw).unwrap();
+        let chr = Chr::new(rom.chr_rom, rom.chr_ram_size);
+        assert!(chr.is_ram);
+        assert_eq!(chr.data.len(), 0x8000);
+    }
+
+    #[test]
+    fn test_nrom_leaves_the_expansion_area_open() {
+        let mut mapper = Rom::new(&ines(0, 0, 0)).unwrap().into_mapper().unwrap();
+        assert_eq!(mapper.cpu_read(0x4020), None);
+        assert_eq!(mapper.cpu_peek(0x5fff), None);
+        mapper.cpu_write(0x5000, 0x33);
+        assert_eq!(mapper.take_fault(), None);
+        assert_eq!(mapper.rom_write_violations(), 0);
+    }
+
+    #[test]
+    fn test_nrom_prg_ram_is_not_a_rom_write() {
+        let mut mapper = Rom::new(&ines(0, 0, 0)).unwrap().into_mapper().unwrap();
+        mapper.cpu_write(0x6000, 0x11);
+        mapper.cpu_write(0x7fff, 0x22);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x11));
+        assert_eq!(mapper.cpu_peek(0x7fff), Some(0x22));
+        assert_eq!(mapper.take_fault(), None);
+        assert_eq!(mapper.rom_write_violations(), 0);
+    }
+
+    #[test]
+    fn test_mirroring_from_every_flag_combination() {
+        for (flags6, mirroring) in [
+            (0b0000, Mirroring::Horizontal),
+            (0b0001, Mirroring::Vertical),
+            (0b1000, Mirroring::FourScreen),
+            (0b1001, Mirroring::FourScreen),
+        ] {
+            let rom = Rom::from_bytes(&ines(flags6, 0, 0)).unwrap();
+            assert_eq!(
+                rom.screen_mirroring(),
+                mirroring,
+                "flags 6 = {:#06b}",
+                flags6
+            );
+        }
+    }
+
+    #[test]
+    fn test_nrom_reports_header_mirroring() {
+        let mapper = Rom::new(&ines(1, 0, 0)).unwrap().into_mapper().unwrap();
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
 }
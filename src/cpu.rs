@@ -1,9 +1,14 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::{ControlFlow, RangeInclusive};
 
 use crate::{
-    bus::Bus,
-    cartridge::Rom,
+    bus::{Bus, RamSnapshot},
+    cartridge::{Rom, RomError},
+    disasm,
+    flat_ram::FlatRam,
     opcodes::{self},
+    trace,
 };
 
 #[derive(Debug)]
@@ -18,18 +23,29 @@ pub enum AddressingMode {
     Absolute_Y,
     Indirect_X,
     Indirect_Y,
+    /// JMP ($xxxx): the operand points at the jump target.
+    Indirect,
+    /// Branches: the operand is a signed offset from the next instruction.
+    Relative,
+    /// Shifts and rotates of A, which take no operand.
+    Accumulator,
+    /// Implied instructions, which take no operand.
     NoneAddressing,
 }
 
-pub enum FlgCodes {
-    CARRY,             // 0b0000_0001
-    ZERO,              // 0b0000_0010
-    INTERRUPT_DISABLE, // 0b0000_0100
-    DECIMAL_MODE,      // 0b0000_1000
-    BREAK,             // 0b0001_0000
-    RESERVED,          // 0b0010_0000
-    OVERFLOW,          // 0b0100_0000
-    NEGATIV,           // 0b1000_0000
+bitflags! {
+    /// The processor status register, P.
+    pub struct CpuFlags: u8 {
+        const CARRY             = 0b0000_0001;
+        const ZERO              = 0b0000_0010;
+        const INTERRUPT_DISABLE = 0b0000_0100;
+        const DECIMAL_MODE      = 0b0000_1000;
+        const BREAK             = 0b0001_0000;
+        // reads as 1 whenever P is pushed or pulled
+        const RESERVED          = 0b0010_0000;
+        const OVERFLOW          = 0b0100_0000;
+        const NEGATIVE          = 0b1000_0000;
+    }
 }
 
 pub enum REGISTER {
@@ -38,135 +54,529 @@ pub enum REGISTER {
     REGISTER_Y,
 }
 
+/// What `CPU::step` executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo {
+    pub pc: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub len: u8,
+    pub cycles: u64,
+}
+
+impl StepInfo {
+    fn new(pc: u16, opcode: &opcodes::OpCode, cycles: u64) -> Self {
+        StepInfo {
+            pc,
+            opcode: opcode.code,
+            mnemonic: opcode.mnemonic,
+            len: opcode.len,
+            cycles,
+        }
+    }
+}
+
+/// Counters accumulated by `CPU::step`, see `CPU::take_stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CpuStats {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub nmis: u64,
+    pub irqs: u64,
+    pub branches_taken: u64,
+    /// Both indexed accesses and taken branches that crossed a page.
+    pub page_crosses: u64,
+}
+
+/// A subroutine call on the shadow call stack, see `CPU::track_calls`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    /// The subroutine JSR jumped to.
+    pub target: u16,
+    /// Where RTS should resume, just past the JSR.
+    pub return_address: u16,
+    /// Set when an RTS or RTI went somewhere other than `return_address`
+    /// while this frame was innermost, because the program manipulated the
+    /// stack itself. The frame is kept until a matching return.
+    pub desynced: bool,
+}
+
+/// The instruction at the program counter, decoded but not yet executed.
+#[derive(Debug, Clone, Copy)]
+pub struct Instruction {
+    pub pc: u16,
+    pub opcode: &'static opcodes::OpCode,
+    operands: [u8; 2],
+    /// Where a memory operand lives, or where a branch goes if taken. `None`
+    /// for implied and accumulator instructions.
+    pub address: Option<u16>,
+}
+
+impl Instruction {
+    /// The bytes following the opcode, as many as the instruction uses.
+    pub fn operands(&self) -> &[u8] {
+        &self.operands[..self.opcode.len as usize - 1]
+    }
+}
+
+/// The register file and cycle count, see `CPU::snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuSnapshot {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+    pub pc: u16,
+    pub sp: u8,
+    pub cycles: u64,
+}
+
+/// Everything a save state needs to hold. The cartridge is left out: load
+/// the state into a CPU built around the same ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SaveState {
+    pub cpu: CpuSnapshot,
+    pub ram: RamSnapshot,
+}
+
+/// Why `CPU::run_with_callback` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program executed BRK.
+    Brk,
+    /// The callback returned `ControlFlow::Break`. The instruction it was
+    /// shown has not run yet, so running again resumes from it.
+    Callback,
+    /// The program counter reached a breakpoint. The instruction there has
+    /// not run yet; resuming executes it without stopping again.
+    Breakpoint(u16),
+    /// The instruction at `pc` accessed a watched address. Only the first
+    /// matching access of the instruction is reported, after it completes.
+    Watchpoint {
+        addr: u16,
+        value: u8,
+        kind: WatchKind,
+        pc: u16,
+    },
+    /// The instruction at this address jumps or branches to itself, and
+    /// `TrapDetection` judged that nothing will break the loop.
+    TrappedAt(u16),
+}
+
+/// When the run loop treats an instruction that transfers control to its
+/// own address as the end of the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrapDetection {
+    /// Only when no interrupt can get the CPU out: IRQs are masked and the
+    /// bus has no NMI source.
+    #[default]
+    Inescapable,
+    /// Always, for headless runs of test ROMs that park in such a loop.
+    Always,
+    /// Never, for games that idle in a loop waiting for an interrupt.
+    Never,
+}
+
+/// Which accesses a watchpoint reacts to. A stop reports `Read` or `Write`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn covers(self, access: WatchKind) -> bool {
+        self == WatchKind::ReadWrite || self == access
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WatchHit {
+    addr: u16,
+    value: u8,
+    kind: WatchKind,
+}
+
+/// Adapts a callback that only wants the CPU and never stops the run.
+pub fn keep_running<M: Mem, F>(
+    mut callback: F,
+) -> impl FnMut(&mut CPU<M>, &Instruction) -> ControlFlow<()>
+where
+    F: FnMut(&mut CPU<M>),
+{
+    move |cpu, _| {
+        callback(cpu);
+        ControlFlow::Continue(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    /// The byte at `pc` is not an instruction this CPU implements.
+    UnknownOpcode { pc: u16, opcode: u8 },
+    /// One of the KIL/JAM opcodes locked up the processor at `pc`.
+    Jammed { pc: u16, opcode: u8 },
+    /// The instruction at `pc` accessed `addr`, which the bus cannot service.
+    BusFault { pc: u16, addr: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:#04x} at {:#06x}", opcode, pc)
+            }
+            CpuError::Jammed { pc, opcode } => {
+                write!(f, "CPU jammed by opcode {:#04x} at {:#06x}", opcode, pc)
+            }
+            CpuError::BusFault { pc, addr } => {
+                write!(f, "bus fault accessing {:#06x} from {:#06x}", addr, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// `len` bytes from `origin` would run past 0xFFFF.
+    PastEndOfMemory { origin: u16, len: usize },
+    /// `len` bytes from `origin` would overwrite the interrupt vectors at
+    /// 0xFFFA-0xFFFF.
+    OverlapsVectors { origin: u16, len: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::PastEndOfMemory { origin, len } => write!(
+                f,
+                "{} bytes loaded at {:#06x} run past the end of memory",
+                len, origin
+            ),
+            LoadError::OverlapsVectors { origin, len } => write!(
+                f,
+                "{} bytes loaded at {:#06x} overlap the interrupt vectors",
+                len, origin
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
 pub trait Mem {
-    fn mem_read(&self, addr: u16) -> u8;
+    fn mem_read(&mut self, addr: u16) -> u8;
 
     fn mem_write(&mut self, addr: u16, data: u8);
 
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    /// Reads `addr` the way a debugger would: without recording faults,
+    /// triggering watchpoints or any other side effect of a real access.
+    fn mem_peek(&self, addr: u16) -> u8;
+
+    /// Reports, and forgets, the last access that could not be serviced.
+    fn take_fault(&mut self) -> Option<u16> {
+        None
+    }
+
+    /// Whether something on the bus is holding the IRQ line. The line is
+    /// level-triggered: it stays asserted until the device is acknowledged.
+    fn irq_line(&self) -> bool {
+        false
+    }
+
+    /// Whether anything on the bus can raise an NMI.
+    fn has_nmi_source(&self) -> bool {
+        false
+    }
+
+    /// Whether an NMI was raised since the last call. The line is
+    /// edge-triggered, so each one is taken once.
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+
+    /// Lets devices on the bus catch up with `cpu_cycles` the CPU just spent.
+    fn tick(&mut self, _cpu_cycles: u8) {}
+
+    /// Reports, and forgets, the cycles the CPU was halted for since the last
+    /// call, e.g. by DMA. The bus has already ticked itself for them.
+    fn take_stall_cycles(&mut self) -> u64 {
+        0
+    }
+
+    /// Tells the bus the CPU is about to run the instruction at `pc`.
+    fn begin_instruction(&mut self, _pc: u16) {}
+
+    /// Reads a little-endian word. The high byte comes from `pos + 1`,
+    /// wrapping from 0xFFFF to 0x0000.
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
         (hi << 8) | (lo as u16)
     }
 
+    /// `mem_read_u16` through `mem_peek`, for debuggers.
+    fn mem_peek_u16(&self, pos: u16) -> u16 {
+        let lo = self.mem_peek(pos) as u16;
+        let hi = self.mem_peek(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    /// Reads a pointer from the zero page. The high byte wraps within the
+    /// zero page, so a pointer at $FF is read from $FF and $00.
+    fn mem_read_u16_zp(&mut self, ptr: u8) -> u16 {
+        let lo = self.mem_read(ptr as u16) as u16;
+        let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    /// Reads two bytes whose high byte wraps within the page of `addr`, so
+    /// $xxFF is read with $xx00, as JMP ($xxxx) does on the 6502.
+    fn mem_read_u16_wrap_page(&mut self, addr: u16) -> u16 {
+        let lo = self.mem_read(addr) as u16;
+        let hi = self.mem_read(addr & 0xFF00 | (addr as u8).wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
+        self.mem_write(pos.wrapping_add(1), hi);
+    }
+
+    /// Fills `buf` from `addr` onwards, wrapping from 0xFFFF to 0x0000.
+    fn mem_read_into(&mut self, addr: u16, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = self.mem_read(addr.wrapping_add(i as u16));
+        }
+    }
+
+    /// Writes `data` from `addr` onwards, wrapping from 0xFFFF to 0x0000.
+    fn mem_write_slice(&mut self, addr: u16, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.mem_write(addr.wrapping_add(i as u16), *byte);
+        }
     }
 }
 
-impl Mem for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {
-        self.bus.mem_read(addr)
+impl<M: Mem> Mem for CPU<M> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = self.bus.mem_read(addr);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, data, WatchKind::Read);
+        }
+        data
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.bus.mem_peek(addr)
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
-        self.bus.mem_write(addr, data)
+        self.bus.mem_write(addr, data);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, data, WatchKind::Write);
+        }
     }
-    fn mem_read_u16(&self, pos: u16) -> u16 {
-        self.bus.mem_read_u16(pos)
+
+    fn mem_read_into(&mut self, addr: u16, buf: &mut [u8]) {
+        self.bus.mem_read_into(addr, buf);
+        if !self.watchpoints.is_empty() {
+            for (i, byte) in buf.iter().enumerate() {
+                self.check_watchpoints(addr.wrapping_add(i as u16), *byte, WatchKind::Read);
+            }
+        }
     }
 
-    fn mem_write_u16(&mut self, pos: u16, data: u16) {
-        self.bus.mem_write_u16(pos, data)
+    fn mem_write_slice(&mut self, addr: u16, data: &[u8]) {
+        self.bus.mem_write_slice(addr, data);
+        if !self.watchpoints.is_empty() {
+            for (i, byte) in data.iter().enumerate() {
+                self.check_watchpoints(addr.wrapping_add(i as u16), *byte, WatchKind::Write);
+            }
+        }
+    }
+}
+
+/// `PC:8003 A:C0 X:01 Y:00 P:NV-BDIZC=10100100 SP:FD CYC:1234`
+impl<M: Mem> fmt::Display for CPU<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC:{:04X} {} P:{} SP:{:02X} CYC:{}",
+            self.program_counter,
+            trace::format_accumulator_and_index(self),
+            trace::format_flags(self.status),
+            self.stack_pointer,
+            self.cycles
+        )
+    }
+}
+
+/// The `Display` line followed by the instruction at the program counter.
+impl<M: Mem> fmt::Debug for CPU<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let next = disasm::disassemble(self, self.program_counter);
+        write!(f, "{}  {}", self, next.text)
+    }
+}
+
+// reads through `mem_peek`, for decoding without side effects
+struct Peeked<'a, M: Mem>(&'a CPU<M>);
+
+impl<M: Mem> Mem for Peeked<'_, M> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.0.mem_peek(addr)
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.0.mem_peek(addr)
+    }
+
+    fn mem_write(&mut self, _addr: u16, _data: u8) {
+        unreachable!("decoding never writes")
     }
 }
 
+// decides whether a breakpoint should stop the CPU once its address is reached
+type BreakCondition<M> = Box<dyn Fn(&CPU<M>) -> bool>;
+
+const JAM_OPCODES: [u8; 12] = [
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+];
+
 const STACK: u16 = 0x0100;
 const STACK_RESET: u8 = 0xfd;
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
+const INTERRUPT_CYCLES: u64 = 7;
+// the reset sequence takes as long as an interrupt
+const RESET_CYCLES: u64 = INTERRUPT_CYCLES;
 
-pub struct CPU {
+pub struct CPU<M: Mem> {
     pub register_a: u8,
     pub register_x: u8,
     pub register_y: u8,
-    pub status: u8,
+    pub status: CpuFlags,
     pub program_counter: u16,
     pub stack_pointer: u8,
-    pub bus: Bus,
+    pub cycles: u64,
+    pub bus: M,
+    // None for breakpoints that always stop
+    breakpoints: HashMap<u16, Option<BreakCondition<M>>>,
+    // the breakpoint we just stopped at, so resuming does not hit it again
+    resume_from_breakpoint: Option<u16>,
+    watchpoints: Vec<(RangeInclusive<u16>, WatchKind)>,
+    // first watched access of the current instruction
+    watch_hit: Option<WatchHit>,
+    trap_detection: TrapDetection,
+    // None unless call tracking is on
+    call_stack: Option<Vec<CallFrame>>,
+    stats: CpuStats,
 }
 
-impl CPU {
-    pub fn new(bus: Bus) -> Self {
+/// The CPU wired to the NES bus, which is what the emulator runs.
+pub type NesCpu = CPU<Bus>;
+
+impl<M: Mem> CPU<M> {
+    pub fn new(bus: M) -> Self {
         CPU {
             register_a: 0,
             register_x: 0,
             register_y: 0,
-            status: 0b100100,
+            status: CpuFlags::INTERRUPT_DISABLE | CpuFlags::RESERVED,
             program_counter: 0,
             stack_pointer: STACK_RESET,
+            cycles: 0,
             bus: bus,
+            breakpoints: HashMap::new(),
+            resume_from_breakpoint: None,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            trap_detection: TrapDetection::default(),
+            call_stack: None,
+            stats: CpuStats::default(),
         }
     }
 
-    fn adc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-
-        let sum = self.register_a as u16 + value as u16 + self.get_flg(&FlgCodes::CARRY) as u16;
-        self.set_flg(&FlgCodes::CARRY, if sum > 0xFF { 1 } else { 0 });
+    // A + M + C, shared by ADC and SBC
+    fn add_to_register_a(&mut self, value: u8) {
+        let carry = self.status.contains(CpuFlags::CARRY) as u16;
+        let sum = self.register_a as u16 + value as u16 + carry;
+        self.status.set(CpuFlags::CARRY, sum > 0xFF);
 
-        let result = (sum % 256) as u8;
-        self.set_flg(
-            &FlgCodes::OVERFLOW,
-            if ((value & 0x80) == (self.register_a & 0x80)) & (result & 0x80 != value & 0x80) {
-                1
-            } else {
-                0
-            },
+        let result = sum as u8;
+        // overflow when both operands share a sign that differs from the result's
+        self.status.set(
+            CpuFlags::OVERFLOW,
+            (self.register_a ^ result) & (value ^ result) & 0x80 != 0,
         );
         // set_register_a
         self.register_a = result;
         self.update_zero_and_negative_flags(self.register_a);
     }
-    fn and(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+
+    fn adc(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
+
+        self.add_to_register_a(value);
+    }
+    fn and(&mut self, addr: u16) {
         let value = self.mem_read(addr);
 
         self.register_a &= value;
         self.update_zero_and_negative_flags(self.register_a);
     }
 
-    fn asl_accumulator(&mut self) {
-        let value = self.register_a;
-        self.set_flg(&FlgCodes::CARRY, if value >> 7 == 0 { 0 } else { 1 });
+    // the value a shift or rotate works on, and where the result goes: A
+    // when `target` is None for the accumulator forms, memory otherwise
+    fn read_modify_operand(&mut self, target: Option<u16>) -> (Option<u16>, u8) {
+        match target {
+            None => (None, self.register_a),
+            Some(addr) => (Some(addr), self.mem_read(addr)),
+        }
+    }
 
-        self.register_a = value << 1;
-        self.update_zero_and_negative_flags(self.register_a);
+    fn write_modified_operand(&mut self, addr: Option<u16>, value: u8) {
+        match addr {
+            Some(addr) => self.mem_write(addr, value),
+            None => self.register_a = value,
+        }
     }
 
-    fn asl(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+    fn asl(&mut self, target: Option<u16>) {
+        let (addr, value) = self.read_modify_operand(target);
 
-        self.set_flg(&FlgCodes::CARRY, if value >> 7 == 0 { 0 } else { 1 });
+        self.status.set(CpuFlags::CARRY, value >> 7 == 1);
 
-        self.mem_write(addr, value << 1);
+        self.write_modified_operand(addr, value << 1);
         self.update_zero_and_negative_flags(value << 1);
     }
 
-    fn bit(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn bit(&mut self, addr: u16) {
         let value = self.mem_read(addr);
 
         let result = self.register_a & value;
 
-        self.set_flg(&FlgCodes::ZERO, if result == 0 { 1 } else { 0 });
-        self.set_flg(&FlgCodes::OVERFLOW, result >> 6 & 1);
-        self.set_flg(&FlgCodes::NEGATIV, result >> 7 & 1);
+        self.status.set(CpuFlags::ZERO, result == 0);
+        self.status.set(CpuFlags::OVERFLOW, result >> 6 & 1 == 1);
+        self.status.set(CpuFlags::NEGATIVE, result >> 7 & 1 == 1);
     }
 
-    fn cmp(&mut self, mode: &AddressingMode, compare_with: u8) {
-        let addr = self.get_operand_address(mode);
+    fn cmp(&mut self, addr: u16, compare_with: u8) {
         let value = self.mem_read(addr);
 
-        self.set_flg(&FlgCodes::CARRY, if compare_with >= value { 1 } else { 0 });
+        self.status.set(CpuFlags::CARRY, compare_with >= value);
         self.update_zero_and_negative_flags(compare_with.wrapping_sub(value))
     }
 
-    fn dec(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn dec(&mut self, addr: u16) {
         let value = self.mem_read(addr);
 
         let result = value.wrapping_sub(1);
@@ -188,8 +598,7 @@ impl CPU {
         self.update_zero_and_negative_flags(result)
     }
 
-    fn inc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn inc(&mut self, addr: u16) {
         let value = self.mem_read(addr);
 
         let result = value.wrapping_add(1);
@@ -207,8 +616,7 @@ impl CPU {
         self.update_zero_and_negative_flags(self.register_y);
     }
 
-    fn eor(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn eor(&mut self, addr: u16) {
         let value = self.mem_read(addr);
 
         let result = self.register_a ^ value;
@@ -217,87 +625,43 @@ impl CPU {
         self.update_zero_and_negative_flags(result);
     }
 
-    fn lsr_accumulator(&mut self) {
-        let value = self.register_a;
-        self.set_flg(&FlgCodes::CARRY, if value & 1 == 0 { 0 } else { 1 });
-
-        self.register_a = value >> 1;
-        self.update_zero_and_negative_flags(self.register_a);
-    }
-
-    fn lsr(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+    fn lsr(&mut self, target: Option<u16>) {
+        let (addr, value) = self.read_modify_operand(target);
 
-        self.set_flg(&FlgCodes::CARRY, if value >> 7 == 0 { 0 } else { 1 });
+        self.status.set(CpuFlags::CARRY, value & 1 == 1);
 
-        self.mem_write(addr, value >> 1);
+        self.write_modified_operand(addr, value >> 1);
         self.update_zero_and_negative_flags(value >> 1);
     }
 
-    fn rol_accumulator(&mut self) {
-        let value = self.register_a;
-        let old_carry = self.get_flg(&FlgCodes::CARRY);
-        self.set_flg(&FlgCodes::CARRY, if value >> 7 == 0 { 0 } else { 1 });
-
-        self.register_a = (value << 1) | old_carry;
-        self.update_zero_and_negative_flags(self.register_a);
-    }
-
-    fn rol(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-        let old_carry = self.get_flg(&FlgCodes::CARRY);
+    fn rol(&mut self, target: Option<u16>) {
+        let (addr, value) = self.read_modify_operand(target);
+        let old_carry = self.status.contains(CpuFlags::CARRY) as u8;
 
-        self.set_flg(&FlgCodes::CARRY, if value >> 7 == 0 { 0 } else { 1 });
+        self.status.set(CpuFlags::CARRY, value >> 7 == 1);
 
-        self.mem_write(addr, (value << 1) | old_carry);
+        self.write_modified_operand(addr, (value << 1) | old_carry);
         self.update_zero_and_negative_flags((value << 1) | old_carry);
     }
-    fn ror_accumulator(&mut self) {
-        let value = self.register_a;
-        let old_carry = self.get_flg(&FlgCodes::CARRY);
-        self.set_flg(&FlgCodes::CARRY, if value >> 7 == 0 { 0 } else { 1 });
-
-        self.register_a = (value >> 1) | (old_carry << 7);
-        self.update_zero_and_negative_flags(self.register_a);
-    }
 
-    fn ror(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-        let old_carry = self.get_flg(&FlgCodes::CARRY);
+    fn ror(&mut self, target: Option<u16>) {
+        let (addr, value) = self.read_modify_operand(target);
+        let old_carry = self.status.contains(CpuFlags::CARRY) as u8;
 
-        self.set_flg(&FlgCodes::CARRY, if value >> 7 == 0 { 0 } else { 1 });
+        self.status.set(CpuFlags::CARRY, value >> 7 == 1);
 
-        self.mem_write(addr, (value >> 1) | (old_carry << 7));
+        self.write_modified_operand(addr, (value >> 1) | (old_carry << 7));
         self.update_zero_and_negative_flags((value >> 1) | (old_carry << 7));
     }
 
-    fn sbc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = ((self.mem_read(addr) as i8).wrapping_neg().wrapping_sub(1)) as u8;
-
-        // A - M - (1 - C) = A + (-M) -1 + C
-        let sum = self.register_a as u16 + value as u16 + self.get_flg(&FlgCodes::CARRY) as u16;
-        self.set_flg(&FlgCodes::CARRY, if sum > 0xFF { 1 } else { 0 });
+    fn sbc(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
 
-        let result = (sum % 256) as u8;
-        self.set_flg(
-            &FlgCodes::OVERFLOW,
-            if ((value & 0x80) == (self.register_a & 0x80)) & (result & 0x80 != value & 0x80) {
-                1
-            } else {
-                0
-            },
-        );
-        // set_register_a
-        self.register_a = result;
-        self.update_zero_and_negative_flags(self.register_a);
+        // A - M - (1 - C) = A + !M + C
+        self.add_to_register_a(!value);
     }
 
-    fn ora(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn ora(&mut self, addr: u16) {
         let value = self.mem_read(addr);
 
         let result = self.register_a | value;
@@ -306,8 +670,7 @@ impl CPU {
         self.update_zero_and_negative_flags(result);
     }
 
-    fn ld(&mut self, mode: &AddressingMode, kind: &REGISTER) {
-        let addr = self.get_operand_address(mode);
+    fn ld(&mut self, addr: u16, kind: &REGISTER) {
         let value = self.mem_read(addr);
 
         match kind {
@@ -343,447 +706,2103 @@ impl CPU {
         self.stack_pointer = self.register_x;
     }
 
-    fn store(&mut self, mode: &AddressingMode, kind: &REGISTER) {
-        let addr = self.get_operand_address(mode);
-        match kind {
-            REGISTER::REGISTER_A => self.mem_write(addr, self.register_a),
-            REGISTER::REGISTER_X => self.mem_write(addr, self.register_x),
-            REGISTER::REGISTER_Y => self.mem_write(addr, self.register_y),
+    fn store(&mut self, addr: u16, kind: &REGISTER) {
+        match kind {
+            REGISTER::REGISTER_A => self.mem_write(addr, self.register_a),
+            REGISTER::REGISTER_X => self.mem_write(addr, self.register_x),
+            REGISTER::REGISTER_Y => self.mem_write(addr, self.register_y),
+        }
+    }
+    fn stack_push(&mut self, data: u8) {
+        self.mem_write((STACK as u16) + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1)
+    }
+
+    fn stack_pop(&mut self) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read((STACK as u16) + self.stack_pointer as u16)
+    }
+
+    fn stack_push_u16(&mut self, data: u16) {
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.stack_push(hi);
+        self.stack_push(lo);
+    }
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+
+        hi << 8 | lo
+    }
+
+    fn branch(&mut self, jump_addr: u16, condition: bool) {
+        if condition {
+            let next_instruction = self.program_counter.wrapping_add(1);
+
+            // taken branches cost one cycle, plus one more when leaving the page
+            // of the instruction that follows the branch
+            self.cycles += 1;
+            self.stats.branches_taken += 1;
+            if next_instruction & 0xFF00 != jump_addr & 0xFF00 {
+                self.cycles += 1;
+                self.stats.page_crosses += 1;
+            }
+            self.program_counter = jump_addr;
+        }
+    }
+
+    fn update_zero_and_negative_flags(&mut self, result: u8) {
+        self.status.set(CpuFlags::ZERO, result == 0);
+        self.status
+            .set(CpuFlags::NEGATIVE, result & 0b1000_0000 != 0);
+    }
+
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            status: self.status.bits(),
+            pc: self.program_counter,
+            sp: self.stack_pointer,
+            cycles: self.cycles,
+        }
+    }
+
+    /// Puts the registers back as they were in `snapshot`. Memory is not
+    /// touched; pair this with `Bus::restore_ram` to roll back completely.
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) {
+        self.register_a = snapshot.a;
+        self.register_x = snapshot.x;
+        self.register_y = snapshot.y;
+        // bit 5 is wired high on the real chip, whatever the snapshot says
+        self.set_status_byte(snapshot.status);
+        self.program_counter = snapshot.pc;
+        self.stack_pointer = snapshot.sp;
+        self.cycles = snapshot.cycles;
+        self.resume_from_breakpoint = None;
+        self.clear_call_stack();
+    }
+
+    pub fn reset(&mut self) {
+        self.register_a = 0;
+        self.register_x = 0;
+        self.status = CpuFlags::INTERRUPT_DISABLE | CpuFlags::RESERVED;
+
+        self.program_counter = self.mem_read_u16(0xFFFC);
+        self.cycles += RESET_CYCLES;
+        self.bus.tick(RESET_CYCLES as u8);
+        self.clear_call_stack();
+    }
+
+    pub fn stats(&self) -> &CpuStats {
+        &self.stats
+    }
+
+    /// Returns the counters gathered so far and starts them again from zero.
+    pub fn take_stats(&mut self) -> CpuStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    /// Keeps a shadow stack of the subroutines JSR entered and RTS has not
+    /// left yet, for backtraces. Off by default; turning it on starts empty.
+    pub fn track_calls(&mut self, enabled: bool) {
+        self.call_stack = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// The calls in progress, outermost first. Empty unless tracking is on.
+    pub fn call_stack(&self) -> &[CallFrame] {
+        self.call_stack.as_deref().unwrap_or(&[])
+    }
+
+    fn clear_call_stack(&mut self) {
+        if let Some(frames) = self.call_stack.as_mut() {
+            frames.clear();
+        }
+    }
+
+    fn enter_call(&mut self, target: u16, return_address: u16) {
+        if let Some(frames) = self.call_stack.as_mut() {
+            frames.push(CallFrame {
+                target,
+                return_address,
+                desynced: false,
+            });
+        }
+    }
+
+    // pushes the return address and P, with B clear, and enters the handler
+    // at `vector` with I set, so a held IRQ line does not re-enter it
+    fn interrupt(&mut self, vector: u16) {
+        let return_address = self.program_counter;
+        self.stack_push_u16(return_address);
+        self.stack_push(self.status_byte() & !CpuFlags::BREAK.bits());
+        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        self.program_counter = self.mem_read_u16(vector);
+        self.enter_call(self.program_counter, return_address);
+        self.cycles += INTERRUPT_CYCLES;
+        self.stats.cycles += INTERRUPT_CYCLES;
+        self.bus.tick(INTERRUPT_CYCLES as u8);
+    }
+
+    // called once RTS or RTI has loaded the program counter
+    fn leave_call(&mut self) {
+        let pc = self.program_counter;
+        if let Some(frames) = self.call_stack.as_mut() {
+            match frames.last_mut() {
+                Some(frame) if frame.return_address == pc => {
+                    frames.pop();
+                }
+                Some(frame) => frame.desynced = true,
+                None => {}
+            }
+        }
+    }
+
+    pub fn load(&mut self, program: &[u8]) -> Result<(), LoadError> {
+        self.load_at(0x8000, program)?;
+        self.set_reset_vector(0x8000);
+        Ok(())
+    }
+
+    /// Copies `program` into memory from `origin`, leaving the reset vector
+    /// alone. The program must end before the interrupt vectors.
+    pub fn load_at(&mut self, origin: u16, program: &[u8]) -> Result<(), LoadError> {
+        if origin as usize + program.len() > 0xFFFA {
+            self.check_fits(origin, program)?;
+            return Err(LoadError::OverlapsVectors {
+                origin,
+                len: program.len(),
+            });
+        }
+        self.mem_write_slice(origin, program);
+        Ok(())
+    }
+
+    /// Like `load_at`, but the program may cover the interrupt vectors, for
+    /// images that bring their own.
+    pub fn load_at_with_vectors(&mut self, origin: u16, program: &[u8]) -> Result<(), LoadError> {
+        self.check_fits(origin, program)?;
+        self.mem_write_slice(origin, program);
+        Ok(())
+    }
+
+    fn check_fits(&self, origin: u16, program: &[u8]) -> Result<(), LoadError> {
+        if origin as usize + program.len() > 0x10000 {
+            return Err(LoadError::PastEndOfMemory {
+                origin,
+                len: program.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Points the reset vector at `addr`, so the next `reset` starts there.
+    pub fn set_reset_vector(&mut self, addr: u16) {
+        self.mem_write_u16(0xFFFC, addr);
+    }
+
+    /// Loads `program` like `load` and runs it from the reset vector. Nothing
+    /// runs if the program does not fit.
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<(), LoadError> {
+        self.load(&program)?;
+        self.reset();
+        self.run();
+        Ok(())
+    }
+
+    pub fn carry(&self) -> bool {
+        self.status.contains(CpuFlags::CARRY)
+    }
+
+    pub fn set_carry(&mut self, value: bool) {
+        self.status.set(CpuFlags::CARRY, value);
+    }
+
+    pub fn zero(&self) -> bool {
+        self.status.contains(CpuFlags::ZERO)
+    }
+
+    pub fn set_zero(&mut self, value: bool) {
+        self.status.set(CpuFlags::ZERO, value);
+    }
+
+    pub fn interrupt_disable(&self) -> bool {
+        self.status.contains(CpuFlags::INTERRUPT_DISABLE)
+    }
+
+    pub fn set_interrupt_disable(&mut self, value: bool) {
+        self.status.set(CpuFlags::INTERRUPT_DISABLE, value);
+    }
+
+    /// D is stored and restored, but the NES's 6502 has no decimal mode, so
+    /// it never changes how ADC and SBC add.
+    pub fn decimal_mode(&self) -> bool {
+        self.status.contains(CpuFlags::DECIMAL_MODE)
+    }
+
+    pub fn set_decimal_mode(&mut self, value: bool) {
+        self.status.set(CpuFlags::DECIMAL_MODE, value);
+    }
+
+    pub fn overflow(&self) -> bool {
+        self.status.contains(CpuFlags::OVERFLOW)
+    }
+
+    pub fn set_overflow(&mut self, value: bool) {
+        self.status.set(CpuFlags::OVERFLOW, value);
+    }
+
+    pub fn negative(&self) -> bool {
+        self.status.contains(CpuFlags::NEGATIVE)
+    }
+
+    pub fn set_negative(&mut self, value: bool) {
+        self.status.set(CpuFlags::NEGATIVE, value);
+    }
+
+    /// P with the always-set bit 5. PHP and BRK push it with B set as well,
+    /// interrupts with B clear.
+    pub fn status_byte(&self) -> u8 {
+        (self.status | CpuFlags::RESERVED).bits()
+    }
+
+    /// Loads P from a byte, as PLP and RTI do. B only exists on the stack, so
+    /// it is dropped, and bit 5 stays set.
+    pub fn set_status_byte(&mut self, value: u8) {
+        self.status = (CpuFlags::from_bits_truncate(value) - CpuFlags::BREAK) | CpuFlags::RESERVED;
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr, None);
+    }
+
+    /// Like `add_breakpoint`, but only stops when `condition` holds as the
+    /// program counter reaches `addr`. Replaces any breakpoint already there.
+    pub fn add_conditional_breakpoint<F>(&mut self, addr: u16, condition: F)
+    where
+        F: Fn(&CPU<M>) -> bool + 'static,
+    {
+        self.breakpoints.insert(addr, Some(Box::new(condition)));
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /// Stops the run after any instruction that accesses `range` the way
+    /// `kind` describes.
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) {
+        self.watchpoints.push((range, kind));
+    }
+
+    pub fn remove_watchpoint(&mut self, range: &RangeInclusive<u16>) {
+        self.watchpoints.retain(|(watched, _)| watched != range);
+    }
+
+    fn check_watchpoints(&mut self, addr: u16, value: u8, access: WatchKind) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        let watched = self
+            .watchpoints
+            .iter()
+            .any(|(range, kind)| range.contains(&addr) && kind.covers(access));
+        if watched {
+            self.watch_hit = Some(WatchHit {
+                addr,
+                value,
+                kind: access,
+            });
+        }
+    }
+
+    pub fn run(&mut self) {
+        if let Err(err) = self.try_run() {
+            panic!("{}", err);
+        }
+    }
+
+    pub fn try_run(&mut self) -> Result<StopReason, CpuError> {
+        self.run_with_callback(|_, _| ControlFlow::Continue(()))
+    }
+
+    /// Runs until BRK, handing every instruction to `callback` before it
+    /// executes. Returning `ControlFlow::Break` from the callback stops the
+    /// run with the CPU left at that instruction.
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> Result<StopReason, CpuError>
+    where
+        F: FnMut(&mut CPU<M>, &Instruction) -> ControlFlow<()>,
+    {
+        loop {
+            if let Some(reason) = self.run_until(u64::MAX, &mut callback)? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Runs whole instructions until at least `budget` cycles have elapsed or
+    /// something stops the run, returning the cycles actually consumed and
+    /// what stopped it, or None if the budget ran out. The last instruction
+    /// may overshoot the budget; carry the difference into the next call to
+    /// keep frames paced.
+    pub fn run_for_cycles(&mut self, budget: u64) -> Result<(u64, Option<StopReason>), CpuError> {
+        self.run_for_cycles_with_callback(budget, |_, _| ControlFlow::Continue(()))
+    }
+
+    pub fn run_for_cycles_with_callback<F>(
+        &mut self,
+        budget: u64,
+        mut callback: F,
+    ) -> Result<(u64, Option<StopReason>), CpuError>
+    where
+        F: FnMut(&mut CPU<M>, &Instruction) -> ControlFlow<()>,
+    {
+        let start = self.cycles;
+        let stop = self.run_until(budget, &mut callback)?;
+        Ok((self.cycles - start, stop))
+    }
+
+    // None when the budget ran out before anything stopped the run
+    fn run_until<F>(
+        &mut self,
+        budget: u64,
+        callback: &mut F,
+    ) -> Result<Option<StopReason>, CpuError>
+    where
+        F: FnMut(&mut CPU<M>, &Instruction) -> ControlFlow<()>,
+    {
+        let start = self.cycles;
+        while self.cycles - start < budget {
+            // a pending interrupt is entered first, so breakpoints and the
+            // callback see the handler's first instruction
+            self.poll_interrupts();
+            let pc = self.program_counter;
+            if self.resume_from_breakpoint != Some(pc) && self.breakpoint_hit(pc) {
+                self.resume_from_breakpoint = Some(pc);
+                return Ok(Some(StopReason::Breakpoint(pc)));
+            }
+            let instruction = self.decode()?;
+            if callback(self, &instruction).is_break() {
+                return Ok(Some(StopReason::Callback));
+            }
+            let info = self.step()?;
+            /* BRK */
+            if info.opcode == 0x00 {
+                return Ok(Some(StopReason::Brk));
+            }
+            if let Some(hit) = self.watch_hit.take() {
+                return Ok(Some(StopReason::Watchpoint {
+                    addr: hit.addr,
+                    value: hit.value,
+                    kind: hit.kind,
+                    pc: info.pc,
+                }));
+            }
+            if self.program_counter == info.pc && self.is_trapped() {
+                return Ok(Some(StopReason::TrappedAt(info.pc)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn set_trap_detection(&mut self, detection: TrapDetection) {
+        self.trap_detection = detection;
+    }
+
+    // whether a loop on the current instruction should end the run
+    fn is_trapped(&self) -> bool {
+        match self.trap_detection {
+            TrapDetection::Inescapable => {
+                self.status.contains(CpuFlags::INTERRUPT_DISABLE) && !self.bus.has_nmi_source()
+            }
+            TrapDetection::Always => true,
+            TrapDetection::Never => false,
+        }
+    }
+
+    fn breakpoint_hit(&self, pc: u16) -> bool {
+        match self.breakpoints.get(&pc) {
+            Some(Some(condition)) => condition(self),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Decodes the instruction at the program counter without executing it.
+    /// Memory is only peeked, so decoding has no side effects.
+    pub fn decode(&self) -> Result<Instruction, CpuError> {
+        let pc = self.program_counter;
+        let opcode = Self::opcode_for(pc, self.mem_peek(pc))?;
+        let operand_pos = pc.wrapping_add(1);
+        let mut operands = [0; 2];
+        for i in 0..(opcode.len as u16).saturating_sub(1) {
+            operands[i as usize] = self.mem_peek(operand_pos.wrapping_add(i));
+        }
+        let address = match opcode.mode {
+            AddressingMode::Accumulator | AddressingMode::NoneAddressing => None,
+            _ => Some(
+                Self::operand_address_at(
+                    &opcode.mode,
+                    operand_pos,
+                    self.register_x,
+                    self.register_y,
+                    &mut Peeked(self),
+                )
+                .0,
+            ),
+        };
+        Ok(Instruction {
+            pc,
+            opcode,
+            operands,
+            address,
+        })
+    }
+
+    fn opcode_for(pc: u16, code: u8) -> Result<&'static opcodes::OpCode, CpuError> {
+        if JAM_OPCODES.contains(&code) {
+            return Err(CpuError::Jammed { pc, opcode: code });
+        }
+        opcodes::lookup(code).ok_or(CpuError::UnknownOpcode { pc, opcode: code })
+    }
+
+    /// Executes exactly one instruction at the program counter. A pending
+    /// interrupt is entered first, and then the instruction is the
+    /// handler's first, with the interrupt's 7 cycles in the reported ones.
+    ///
+    /// BRK halts the program: it is reported like any other instruction but
+    /// consumes no cycles, and `run` stops when it sees it.
+    pub fn step(&mut self) -> Result<StepInfo, CpuError> {
+        self.resume_from_breakpoint = None;
+        let interrupt_cycles = self.poll_interrupts();
+        let pc = self.program_counter;
+        self.bus.begin_instruction(pc);
+        let opcode = Self::opcode_for(pc, self.mem_read(pc))?;
+        let code = opcode.code;
+        self.stats.instructions += 1;
+        // only accesses made by the instruction itself count as watchpoint
+        // hits, not the opcode fetch or peeks made between instructions
+        self.watch_hit = None;
+        self.program_counter = self.program_counter.wrapping_add(1);
+        let program_counter_state = self.program_counter;
+        let cycles_before = self.cycles;
+        // resolved once, so operand and pointer bytes are read a single time
+        let (addr, crossed) = self.get_operand_address(&opcode.mode);
+        let page_crossed = opcode.page_cross_penalty && crossed;
+        // shifts and rotates work on A in accumulator mode
+        let target = (!matches!(opcode.mode, AddressingMode::Accumulator)).then_some(addr);
+
+        match code {
+            /* Transfer Instructions */
+            /* LDA */
+            0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
+                self.ld(addr, &REGISTER::REGISTER_A);
+            }
+            /* LDX */
+            0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
+                self.ld(addr, &REGISTER::REGISTER_X);
+            }
+            /* LDY */
+            0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
+                self.ld(addr, &REGISTER::REGISTER_Y);
+            }
+            /* STA */
+            0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
+                self.store(addr, &REGISTER::REGISTER_A);
+            }
+            /* STX */
+            0x86 | 0x96 | 0x8E => {
+                self.store(addr, &REGISTER::REGISTER_X);
+            }
+            /* STY */
+            0x84 | 0x94 | 0x8C => {
+                self.store(addr, &REGISTER::REGISTER_Y);
+            }
+            /* TAX */
+            0xAA => self.tax(),
+            /* TXA */
+            0x8A => self.txa(),
+            /* TAY */
+            0xA8 => self.tay(),
+            /* TYA */
+            0x98 => self.tya(),
+            /* TSX */
+            0xBA => self.tsx(),
+            /* TXS */
+            0x9A => self.txs(),
+            /* Arithmetic Instructions */
+            /* ADC */
+            0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
+                self.adc(addr);
+            }
+            /* AND */
+            0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => self.and(addr),
+            /* ASL */
+            0x0A | 0x06 | 0x16 | 0x0E | 0x1E => self.asl(target),
+            /* BIT */
+            0x24 | 0x2C => self.bit(addr),
+            /* CMP */
+            0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
+                self.cmp(addr, self.register_a)
+            }
+            /* CMX */
+            0xE0 | 0xE4 | 0xEC => self.cmp(addr, self.register_x),
+            /* CMY */
+            0xC0 | 0xC4 | 0xCC => self.cmp(addr, self.register_y),
+            /* DEC */
+            0xC6 | 0xD6 | 0xCE | 0xDE => self.dec(addr),
+            /* DEX */
+            0xCA => self.dex(&opcode.mode),
+            /* DEY */
+            0x88 => self.dey(&opcode.mode),
+            /* EOR */
+            0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => self.eor(addr),
+            /* INC */
+            0xE6 | 0xF6 | 0xEE | 0xFE => self.inc(addr),
+            /* INX */
+            0xE8 => self.inx(),
+            /* INY */
+            0xC8 => self.iny(),
+            /* LSR */
+            0x4A | 0x46 | 0x56 | 0x4E | 0x5E => self.lsr(target),
+            /* ORA */
+            0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => self.ora(addr),
+            /* ROL */
+            0x2A | 0x26 | 0x36 | 0x2E | 0x3E => self.rol(target),
+            /* ROR */
+            0x6A | 0x66 | 0x76 | 0x6E | 0x7E => self.ror(target),
+            /* SBC */
+            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
+                self.sbc(addr);
+            }
+            /* Stack Instructions */
+            /* PHA */
+            0x48 => self.stack_push(self.register_a),
+            /* PHP */
+            0x08 => self.stack_push(self.status_byte() | CpuFlags::BREAK.bits()),
+            /* PLA */
+            0x68 => {
+                let value = self.stack_pop();
+                self.register_a = value;
+                self.update_zero_and_negative_flags(value);
+            }
+            /* PLP */
+            0x28 => {
+                let value = self.stack_pop();
+                self.set_status_byte(value);
+            }
+            /* Jump Instructions */
+            /* JMP */
+            0x4C => self.program_counter = addr,
+            /* JMP Indirect */
+            0x6C => self.program_counter = addr,
+            /* JSR */
+            0x20 => {
+                self.stack_push_u16(self.program_counter + 2 - 1);
+                self.enter_call(addr, self.program_counter + 2);
+                self.program_counter = addr
+            }
+            /* RTS */
+            0x60 => {
+                self.program_counter = self.stack_pop_u16() + 1;
+                self.leave_call();
+            }
+            /* RTI */
+            0x40 => {
+                let value = self.stack_pop();
+                self.set_status_byte(value);
+                self.program_counter = self.stack_pop_u16();
+                self.leave_call();
+            }
+            /* Branching Instructions */
+            /* BCC */
+            0x90 => self.branch(addr, !self.status.contains(CpuFlags::CARRY)),
+            /* BCS */
+            0xB0 => self.branch(addr, self.status.contains(CpuFlags::CARRY)),
+            /* BEQ */
+            0xF0 => self.branch(addr, self.status.contains(CpuFlags::ZERO)),
+            /* BMI */
+            0x30 => self.branch(addr, self.status.contains(CpuFlags::NEGATIVE)),
+            /* BNE */
+            0xD0 => self.branch(addr, !self.status.contains(CpuFlags::ZERO)),
+            /* BPL */
+            0x10 => self.branch(addr, !self.status.contains(CpuFlags::NEGATIVE)),
+            /* BVC */
+            0x50 => self.branch(addr, !self.status.contains(CpuFlags::OVERFLOW)),
+            /* BVS */
+            0x70 => self.branch(addr, self.status.contains(CpuFlags::OVERFLOW)),
+            /* Flag Modification Instructions */
+            /* CLC */
+            0x18 => self.status.remove(CpuFlags::CARRY),
+            /* CLD */
+            0xD8 => self.status.remove(CpuFlags::DECIMAL_MODE),
+            /* CLI */
+            0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
+            /* CLV */
+            0xB8 => self.status.remove(CpuFlags::OVERFLOW),
+            /* SEC */
+            0x38 => self.status.insert(CpuFlags::CARRY),
+            /* SED */
+            0xF8 => self.status.insert(CpuFlags::DECIMAL_MODE),
+            /* SEI */
+            0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
+            /* The Other Instructions */
+            /* BRK */
+            0x00 => return Ok(StepInfo::new(pc, opcode, 0)),
+            /* NOP */
+            0xEA => {}
+            _ => unreachable!("{:#04x} is in the opcode table but not dispatched", code),
+        }
+        if program_counter_state == self.program_counter {
+            self.program_counter += (opcode.len - 1) as u16
+        };
+        self.cycles += opcode.cycles as u64;
+        if page_crossed {
+            self.cycles += 1;
+            self.stats.page_crosses += 1;
+        }
+        self.bus.tick((self.cycles - cycles_before) as u8);
+        self.cycles += self.bus.take_stall_cycles();
+        self.stats.cycles += self.cycles - cycles_before;
+        if let Some(addr) = self.bus.take_fault() {
+            return Err(CpuError::BusFault { pc, addr });
+        }
+        Ok(StepInfo::new(
+            pc,
+            opcode,
+            self.cycles - cycles_before + interrupt_cycles,
+        ))
+    }
+
+    // enters the handler of a pending interrupt, returning the cycles that
+    // took. Polled between instructions, so the one that clears I with CLI
+    // or RTI runs before a pending IRQ is taken. NMI ignores I and goes
+    // first.
+    fn poll_interrupts(&mut self) -> u64 {
+        if self.bus.take_nmi() {
+            self.interrupt(NMI_VECTOR);
+            self.stats.nmis += 1;
+            return INTERRUPT_CYCLES;
+        }
+        if self.bus.irq_line() && !self.status.contains(CpuFlags::INTERRUPT_DISABLE) {
+            self.interrupt(IRQ_VECTOR);
+            self.stats.irqs += 1;
+            return INTERRUPT_CYCLES;
+        }
+        0
+    }
+
+    /// The effective address of the operand at the program counter, and
+    /// whether indexing carried into the next page. Only Absolute_X,
+    /// Absolute_Y and Indirect_Y can cross.
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> (u16, bool) {
+        let (pc, x, y) = (self.program_counter, self.register_x, self.register_y);
+        Self::operand_address_at(mode, pc, x, y, self)
+    }
+
+    // effective address of an operand whose bytes start at `operand`, read
+    // through `mem` so that decoding can resolve it with side-effect-free peeks
+    fn operand_address_at<R: Mem>(
+        mode: &AddressingMode,
+        operand: u16,
+        register_x: u8,
+        register_y: u8,
+        mem: &mut R,
+    ) -> (u16, bool) {
+        let crosses_page = |base: u16, addr: u16| base & 0xFF00 != addr & 0xFF00;
+        match mode {
+            AddressingMode::Immediate => (operand, false),
+
+            AddressingMode::ZeroPage => (mem.mem_read(operand) as u16, false),
+
+            AddressingMode::Absolute => (mem.mem_read_u16(operand), false),
+
+            AddressingMode::ZeroPage_X => {
+                let pos = mem.mem_read(operand);
+                let addr = pos.wrapping_add(register_x) as u16;
+                (addr, false)
+            }
+            AddressingMode::ZeroPage_Y => {
+                let pos = mem.mem_read(operand);
+                let addr = pos.wrapping_add(register_y) as u16;
+                (addr, false)
+            }
+
+            AddressingMode::Absolute_X => {
+                let base = mem.mem_read_u16(operand);
+                let addr = base.wrapping_add(register_x as u16);
+                (addr, crosses_page(base, addr))
+            }
+            AddressingMode::Absolute_Y => {
+                let base = mem.mem_read_u16(operand);
+                let addr = base.wrapping_add(register_y as u16);
+                (addr, crosses_page(base, addr))
+            }
+            AddressingMode::Indirect_X => {
+                let base = mem.mem_read(operand);
+
+                let ptr: u8 = base.wrapping_add(register_x);
+                (mem.mem_read_u16_zp(ptr), false)
+            }
+            AddressingMode::Indirect_Y => {
+                let base = mem.mem_read(operand);
+
+                let deref_base = mem.mem_read_u16_zp(base);
+                let deref = deref_base.wrapping_add(register_y as u16);
+                (deref, crosses_page(deref_base, deref))
+            }
+            AddressingMode::Indirect => {
+                let ptr = mem.mem_read_u16(operand);
+                (mem.mem_read_u16_wrap_page(ptr), false)
+            }
+            AddressingMode::Relative => {
+                let offset = mem.mem_read(operand) as i8;
+                (operand.wrapping_add(1).wrapping_add(offset as u16), false)
+            }
+            // nothing to address; the position after the opcode keeps
+            // callers that ask anyway harmless
+            AddressingMode::Accumulator | AddressingMode::NoneAddressing => (operand, false),
+        }
+    }
+}
+
+impl NesCpu {
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            cpu: self.snapshot(),
+            ram: self.bus.snapshot_ram(),
+        }
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.restore(&state.cpu);
+        self.bus.restore_ram(&state.ram);
+    }
+
+    /// Presses the reset button: the devices on the bus reset first, then
+    /// the CPU fetches the reset vector. RAM is kept.
+    pub fn reset_system(&mut self) {
+        self.bus.reset();
+        self.reset();
+    }
+
+    /// Swaps in the cartridge `rom` and resets, so the CPU starts from its
+    /// reset vector. See `Bus::insert_cartridge`.
+    pub fn insert_cartridge(&mut self, rom: Rom) -> Result<(), RomError> {
+        self.bus.insert_cartridge(rom)?;
+        self.reset();
+        Ok(())
+    }
+}
+
+/// Sets up a CPU with registers, flags and memory in one go, ready to run
+/// from its program counter, so nothing has to be ordered around `reset`.
+///
+/// The program counter defaults to 0x0600, which is RAM on the NES bus too.
+pub struct CpuBuilder<M: Mem> {
+    bus: M,
+    register_a: u8,
+    register_x: u8,
+    register_y: u8,
+    status: CpuFlags,
+    program_counter: u16,
+    stack_pointer: u8,
+    program: Vec<u8>,
+}
+
+impl CpuBuilder<FlatRam> {
+    pub fn new() -> Self {
+        CpuBuilder::with_bus(FlatRam::new())
+    }
+}
+
+impl Default for CpuBuilder<FlatRam> {
+    fn default() -> Self {
+        CpuBuilder::new()
+    }
+}
+
+impl<M: Mem> CpuBuilder<M> {
+    pub fn with_bus(bus: M) -> Self {
+        CpuBuilder {
+            bus,
+            register_a: 0,
+            register_x: 0,
+            register_y: 0,
+            status: CpuFlags::INTERRUPT_DISABLE | CpuFlags::RESERVED,
+            program_counter: 0x0600,
+            stack_pointer: STACK_RESET,
+            program: vec![],
+        }
+    }
+
+    pub fn a(mut self, value: u8) -> Self {
+        self.register_a = value;
+        self
+    }
+
+    pub fn x(mut self, value: u8) -> Self {
+        self.register_x = value;
+        self
+    }
+
+    pub fn y(mut self, value: u8) -> Self {
+        self.register_y = value;
+        self
+    }
+
+    pub fn sp(mut self, value: u8) -> Self {
+        self.stack_pointer = value;
+        self
+    }
+
+    pub fn pc(mut self, addr: u16) -> Self {
+        self.program_counter = addr;
+        self
+    }
+
+    /// Replaces every flag; bit 5 stays set regardless.
+    pub fn status(mut self, flags: CpuFlags) -> Self {
+        self.status = flags | CpuFlags::RESERVED;
+        self
+    }
+
+    pub fn carry(self, value: bool) -> Self {
+        self.flag(CpuFlags::CARRY, value)
+    }
+
+    pub fn zero(self, value: bool) -> Self {
+        self.flag(CpuFlags::ZERO, value)
+    }
+
+    pub fn interrupt_disable(self, value: bool) -> Self {
+        self.flag(CpuFlags::INTERRUPT_DISABLE, value)
+    }
+
+    pub fn decimal_mode(self, value: bool) -> Self {
+        self.flag(CpuFlags::DECIMAL_MODE, value)
+    }
+
+    pub fn overflow(self, value: bool) -> Self {
+        self.flag(CpuFlags::OVERFLOW, value)
+    }
+
+    pub fn negative(self, value: bool) -> Self {
+        self.flag(CpuFlags::NEGATIVE, value)
+    }
+
+    /// Code to place at the program counter when the CPU is built.
+    pub fn program(mut self, program: &[u8]) -> Self {
+        self.program = program.to_vec();
+        self
+    }
+
+    /// Writes `data` to the bus starting at `addr`.
+    pub fn mem(mut self, addr: u16, data: &[u8]) -> Self {
+        for (i, byte) in data.iter().enumerate() {
+            self.bus.mem_write(addr.wrapping_add(i as u16), *byte);
+        }
+        self
+    }
+
+    pub fn build(self) -> CPU<M> {
+        let origin = self.program_counter;
+        let program = self.program;
+        let mut cpu = CPU::new(self.bus);
+        for (i, byte) in program.iter().enumerate() {
+            cpu.mem_write(origin.wrapping_add(i as u16), *byte);
+        }
+        cpu.register_a = self.register_a;
+        cpu.register_x = self.register_x;
+        cpu.register_y = self.register_y;
+        cpu.status = self.status;
+        cpu.program_counter = self.program_counter;
+        cpu.stack_pointer = self.stack_pointer;
+        cpu
+    }
+
+    fn flag(mut self, flag: CpuFlags, value: bool) -> Self {
+        self.status.set(flag, value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::program::ProgramBuilder;
+
+    fn cpu_with_program_at(origin: u16, program: &[u8]) -> CPU<FlatRam> {
+        let mut cpu = CPU::new(FlatRam::from_bytes(origin, program));
+        cpu.program_counter = origin;
+        cpu
+    }
+
+    // back: INX; BRK; start: <branch> back; BRK -- reset into `start`
+    fn cpu_with_backward_branch(branch: u8) -> CPU<FlatRam> {
+        let program = ProgramBuilder::new()
+            .org(0x8000)
+            .label("back")
+            .bytes(&[0xe8, 0x00])
+            .label("start")
+            .bytes(&[branch])
+            .branch_to("back")
+            .bytes(&[0x00])
+            .reset_vector(0x8002)
+            .build();
+        let mut cpu = CPU::new(FlatRam::from_bytes(0x8000, &program));
+        cpu.reset();
+        cpu
+    }
+
+    fn cpu_with_program_in_ram(program: &[u8]) -> CPU<FlatRam> {
+        cpu_with_program_at(0x0600, program)
+    }
+
+    // for tests of the NES bus itself, such as RAM snapshots
+    fn nes_cpu_with_program_in_ram(program: &[u8]) -> NesCpu {
+        let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        cpu.mem_write_slice(0x0600, program);
+        cpu.program_counter = 0x0600;
+        cpu
+    }
+
+    #[test]
+    fn test_0xa9_lda_immediate_load_data() {
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xa9, 0x05, 0x00]).unwrap();
+        cpu.reset();
+        cpu.status = CpuFlags::empty();
+        cpu.run();
+        assert_eq!(cpu.register_a, 5);
+        assert!(!cpu.zero());
+        assert!(!cpu.negative());
+    }
+
+    #[test]
+    fn test_load_at_leaves_the_reset_vector_alone() {
+        let mut cpu = CpuBuilder::new().build();
+        cpu.set_reset_vector(0x8000);
+        // LDX #$00; loop: INX; STX $10; CPX #$03; BNE loop; BRK
+        cpu.load_at(
+            0x0600,
+            &[0xa2, 0x00, 0xe8, 0x86, 0x10, 0xe0, 0x03, 0xd0, 0xf9, 0x00],
+        )
+        .unwrap();
+        assert_eq!(cpu.mem_read_u16(0xfffc), 0x8000);
+
+        cpu.set_reset_vector(0x0600);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x0600);
+        cpu.run();
+        assert_eq!(cpu.register_x, 3);
+        assert_eq!(cpu.mem_read(0x10), 3);
+        assert_eq!(cpu.program_counter, 0x060a);
+    }
+
+    #[test]
+    fn test_load_rejects_a_program_past_the_end_of_memory() {
+        let mut cpu = CpuBuilder::new().build();
+        assert_eq!(
+            cpu.load(&vec![0xea; 40 * 1024]),
+            Err(LoadError::PastEndOfMemory {
+                origin: 0x8000,
+                len: 40 * 1024
+            })
+        );
+        // nothing was written, not even the vector
+        assert_eq!(cpu.mem_read(0x8000), 0);
+        assert_eq!(cpu.mem_read_u16(0xfffc), 0);
+    }
+
+    #[test]
+    fn test_load_and_run_reports_a_program_that_does_not_fit() {
+        let mut cpu = CpuBuilder::new().build();
+        assert_eq!(
+            cpu.load_and_run(vec![0xe8; 40 * 1024]),
+            Err(LoadError::PastEndOfMemory {
+                origin: 0x8000,
+                len: 40 * 1024
+            })
+        );
+        assert_eq!(cpu.register_x, 0);
+        assert_eq!(cpu.cycles, 0);
+    }
+
+    #[test]
+    fn test_load_fills_up_to_the_vectors() {
+        let mut cpu = CpuBuilder::new().build();
+        assert_eq!(cpu.load(&vec![0xea; 0xfffa - 0x8000]), Ok(()));
+        assert_eq!(cpu.mem_read(0xfff9), 0xea);
+        assert_eq!(cpu.mem_read_u16(0xfffc), 0x8000);
+
+        assert_eq!(
+            cpu.load(&vec![0xea; 0xfffb - 0x8000]),
+            Err(LoadError::OverlapsVectors {
+                origin: 0x8000,
+                len: 0xfffb - 0x8000
+            })
+        );
+        assert_eq!(cpu.mem_read(0xfffa), 0);
+    }
+
+    #[test]
+    fn test_load_at_with_vectors_takes_a_whole_image() {
+        let mut image = vec![0; 0x10000];
+        image[0x0400] = 0xe8; // INX
+        image[0xfffc..0xfffe].copy_from_slice(&[0x00, 0x04]);
+        let mut cpu = CpuBuilder::new().build();
+        assert!(cpu.load_at(0x0000, &image).is_err());
+        assert_eq!(cpu.load_at_with_vectors(0x0000, &image), Ok(()));
+
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x0400);
+    }
+
+    #[test]
+    fn test_cycles_accumulate_base_cycles() {
+        // LDA #$05; STA $0200; NOP; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xa9, 0x05, 0x8d, 0x00, 0x02, 0xea, 0x00]);
+        cpu.run();
+        assert_eq!(cpu.cycles, 2 + 4 + 2);
+    }
+
+    #[test]
+    fn test_page_cross_adds_a_cycle_to_indexed_reads() {
+        // LDA $12F0,X; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xbd, 0xf0, 0x12, 0x00]);
+        cpu.register_x = 0x01;
+        cpu.run();
+        assert_eq!(cpu.cycles, 4);
+
+        let mut cpu = cpu_with_program_in_ram(&[0xbd, 0xf0, 0x12, 0x00]);
+        cpu.register_x = 0x20;
+        cpu.run();
+        assert_eq!(cpu.cycles, 5);
+    }
+
+    #[test]
+    fn test_page_cross_penalty_on_indirect_y() {
+        // LDA ($10),Y; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xb1, 0x10, 0x00]);
+        cpu.mem_write_u16(0x10, 0x02ff);
+        cpu.register_y = 0x01;
+        cpu.run();
+        assert_eq!(cpu.cycles, 6);
+    }
+
+    #[test]
+    fn test_page_cross_does_not_affect_stores() {
+        // STA $12F0,X; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0x9d, 0xf0, 0x12, 0x00]);
+        cpu.register_x = 0x01;
+        cpu.run();
+        assert_eq!(cpu.cycles, 5);
+
+        let mut cpu = cpu_with_program_in_ram(&[0x9d, 0xf0, 0x12, 0x00]);
+        cpu.register_x = 0x20;
+        cpu.run();
+        assert_eq!(cpu.cycles, 5);
+    }
+
+    #[test]
+    fn test_branch_not_taken_takes_two_cycles() {
+        // BNE +2; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xd0, 0x02, 0x00]);
+        cpu.status = CpuFlags::ZERO;
+        cpu.run();
+        assert_eq!(cpu.cycles, 2);
+    }
+
+    #[test]
+    fn test_branch_taken_on_same_page_takes_three_cycles() {
+        // BNE +2 lands on the BRK at 0x0604
+        let mut cpu = cpu_with_program_in_ram(&[0xd0, 0x02, 0xe8, 0xe8, 0x00]);
+        cpu.status = CpuFlags::empty();
+        cpu.run();
+        assert_eq!(cpu.register_x, 0);
+        assert_eq!(cpu.cycles, 3);
+    }
+
+    #[test]
+    fn test_branch_taken_across_page_takes_four_cycles() {
+        // BNE +2 at 0x06FC, next instruction at 0x06FE, target 0x0700
+        let mut cpu = cpu_with_program_at(0x06fc, &[0xd0, 0x02, 0xe8, 0xe8, 0x00]);
+        cpu.status = CpuFlags::empty();
+        cpu.run();
+        assert_eq!(cpu.register_x, 0);
+        assert_eq!(cpu.cycles, 4);
+    }
+
+    #[test]
+    fn test_branch_page_cross_is_measured_from_the_next_instruction() {
+        // BNE at 0x06FE: the opcode sits on page 0x06 but the next instruction
+        // starts at 0x0700, so a target on page 0x07 does not cross
+        let mut cpu = cpu_with_program_at(0x06fe, &[0xd0, 0x02, 0xe8, 0xe8, 0x00]);
+        cpu.status = CpuFlags::empty();
+        cpu.run();
+        assert_eq!(cpu.register_x, 0);
+        assert_eq!(cpu.cycles, 3);
+
+        // ...while jumping back to page 0x06 does, although the opcode lives there
+        let mut cpu = cpu_with_program_at(0x06fe, &[0xd0, 0xfd]);
+        cpu.status = CpuFlags::empty();
+        cpu.run();
+        assert_eq!(cpu.program_counter, 0x06fe);
+        assert_eq!(cpu.cycles, 4);
+    }
+
+    #[test]
+    fn test_cycles_per_addressing_mode() {
+        // (program, x, y, expected cycles); each program ends on a BRK
+        let cases: [(&[u8], u8, u8, u64); 19] = [
+            (&[0xa9, 0x01], 0, 0, 2),       // LDA #$01
+            (&[0xa5, 0x10], 0, 0, 3),       // LDA $10
+            (&[0xb5, 0x10], 1, 0, 4),       // LDA $10,X
+            (&[0xb6, 0x10], 0, 1, 4),       // LDX $10,Y
+            (&[0xad, 0x00, 0x02], 0, 0, 4), // LDA $0200
+            (&[0xbd, 0x00, 0x02], 1, 0, 4), // LDA $0200,X
+            (&[0xbd, 0xff, 0x02], 1, 0, 5), // LDA $02FF,X crossing
+            (&[0xb9, 0x00, 0x02], 0, 1, 4), // LDA $0200,Y
+            (&[0xb9, 0xff, 0x02], 0, 1, 5), // LDA $02FF,Y crossing
+            (&[0xa1, 0x10], 2, 0, 6),       // LDA ($10,X)
+            (&[0xb1, 0x20], 0, 1, 5),       // LDA ($20),Y
+            (&[0xb1, 0x20], 0, 0xff, 6),    // LDA ($20),Y crossing
+            (&[0x9d, 0xff, 0x02], 1, 0, 5), // STA $02FF,X
+            (&[0xfe, 0x00, 0x02], 1, 0, 7), // INC $0200,X
+            (&[0x0a], 0, 0, 2),             // ASL A
+            (&[0xe8], 0, 0, 2),             // INX
+            (&[0x48], 0, 0, 3),             // PHA
+            (&[0x6c, 0x00, 0x03], 0, 0, 5), // JMP ($0300)
+            (&[0xd0, 0x00], 0, 0, 3),       // BNE +0, taken
+        ];
+
+        for (program, x, y, expected) in cases.iter() {
+            let mut cpu = cpu_with_program_in_ram(program);
+            cpu.mem_write_u16(0x20, 0x0210);
+            cpu.mem_write_u16(0x0300, 0x0700);
+            cpu.register_x = *x;
+            cpu.register_y = *y;
+            cpu.status = CpuFlags::empty();
+            cpu.run();
+
+            let opcode = opcodes::lookup(program[0]).unwrap();
+            assert_eq!(
+                cpu.cycles, *expected,
+                "{:#04x} {} ({:?}) consumed {} cycles, expected {}",
+                opcode.code, opcode.mnemonic, opcode.mode, cpu.cycles, expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_step_executes_one_instruction() {
+        // LDA #$05; STA $0200; INX; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xa9, 0x05, 0x8d, 0x00, 0x02, 0xe8, 0x00]);
+
+        let info = cpu.step().unwrap();
+        assert_eq!(
+            info,
+            StepInfo {
+                pc: 0x0600,
+                opcode: 0xa9,
+                mnemonic: "LDA",
+                len: 2,
+                cycles: 2,
+            }
+        );
+        assert_eq!(cpu.register_a, 0x05);
+        assert_eq!(cpu.program_counter, 0x0602);
+
+        let info = cpu.step().unwrap();
+        assert_eq!(
+            (info.pc, info.mnemonic, info.len, info.cycles),
+            (0x0602, "STA", 3, 4)
+        );
+        assert_eq!(cpu.mem_read(0x0200), 0x05);
+
+        let info = cpu.step().unwrap();
+        assert_eq!(
+            (info.pc, info.mnemonic, info.len, info.cycles),
+            (0x0605, "INX", 1, 2)
+        );
+        assert_eq!(cpu.register_x, 1);
+
+        let info = cpu.step().unwrap();
+        assert_eq!((info.pc, info.mnemonic, info.cycles), (0x0606, "BRK", 0));
+        assert_eq!(cpu.cycles, 8);
+    }
+
+    #[test]
+    fn test_step_reports_unknown_opcode() {
+        // 0x03 is SLO, which is not implemented; 0x02 would jam
+        let mut cpu = cpu_with_program_in_ram(&[0xe8, 0x03]);
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::UnknownOpcode {
+                pc: 0x0601,
+                opcode: 0x03
+            })
+        );
+        assert_eq!(cpu.program_counter, 0x0601);
+    }
+
+    #[test]
+    fn test_run_for_cycles_stops_once_budget_is_met() {
+        // loop: INX; NOP; JMP loop -- 7 cycles per iteration
+        let program = [0xe8, 0xea, 0x4c, 0x00, 0x06];
+
+        let mut cpu = cpu_with_program_in_ram(&program);
+        assert_eq!(cpu.run_for_cycles(100), Ok((100, None)));
+        // 14 full iterations plus the INX of the 15th
+        assert_eq!(cpu.program_counter, 0x0601);
+        assert_eq!(cpu.register_x, 15);
+
+        let mut cpu = cpu_with_program_in_ram(&program);
+        assert_eq!(cpu.run_for_cycles(101), Ok((102, None)));
+        assert_eq!(cpu.program_counter, 0x0602);
+
+        assert_eq!(cpu.run_for_cycles(3), Ok((3, None)));
+        assert_eq!(cpu.program_counter, 0x0600);
+        assert_eq!(cpu.cycles, 105);
+    }
+
+    #[test]
+    fn test_run_for_cycles_stops_on_brk() {
+        let mut cpu = cpu_with_program_in_ram(&[0xe8, 0xe8, 0x00]);
+        let mut callbacks = 0;
+        let consumed = cpu.run_for_cycles_with_callback(1000, keep_running(|_| callbacks += 1));
+        assert_eq!(consumed, Ok((4, Some(StopReason::Brk))));
+        // the callback sees the BRK before the loop stops on it
+        assert_eq!(callbacks, 3);
+        assert_eq!(cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_run_for_cycles_reports_a_callback_break() {
+        // loop: INX; JMP loop
+        let mut cpu = cpu_with_program_in_ram(&[0xe8, 0x4c, 0x00, 0x06]);
+        let run = cpu.run_for_cycles_with_callback(1000, |cpu, _| {
+            if cpu.register_x == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        // three INX and two JMP ran, well short of the budget, and the third
+        // JMP is left to run
+        assert_eq!(run, Ok((12, Some(StopReason::Callback))));
+        assert_eq!(cpu.program_counter, 0x0601);
+
+        cpu.add_breakpoint(0x0600);
+        assert_eq!(
+            cpu.run_for_cycles(1000),
+            Ok((3, Some(StopReason::Breakpoint(0x0600))))
+        );
+    }
+
+    #[test]
+    fn test_run_reports_unknown_opcode() {
+        let mut cpu = cpu_with_program_in_ram(&[0xe8, 0xff]);
+        assert_eq!(
+            cpu.try_run(),
+            Err(CpuError::UnknownOpcode {
+                pc: 0x0601,
+                opcode: 0xff
+            })
+        );
+    }
+
+    #[test]
+    fn test_run_reports_jam() {
+        let mut cpu = cpu_with_program_in_ram(&[0xe8, 0xe8, 0x02]);
+        assert_eq!(
+            cpu.try_run(),
+            Err(CpuError::Jammed {
+                pc: 0x0602,
+                opcode: 0x02
+            })
+        );
+        assert_eq!(cpu.register_x, 2);
+        // a jammed CPU stays jammed
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::Jammed {
+                pc: 0x0602,
+                opcode: 0x02
+            })
+        );
+    }
+
+    #[test]
+    fn test_run_reports_bus_fault() {
+        // LDA #$01; STA $8000
+        let mut cpu = nes_cpu_with_program_in_ram(&[0xa9, 0x01, 0x8d, 0x00, 0x80, 0x00]);
+        assert_eq!(
+            cpu.try_run(),
+            Err(CpuError::BusFault {
+                pc: 0x0602,
+                addr: 0x8000
+            })
+        );
+    }
+
+    #[test]
+    fn test_jmp_indirect_wraps_pointer_within_its_page() {
+        // JMP ($02FF)
+        let mut cpu = cpu_with_program_in_ram(&[0x6c, 0xff, 0x02]);
+        cpu.mem_write(0x02ff, 0x00);
+        cpu.mem_write(0x0200, 0x07);
+        cpu.mem_write(0x0300, 0x08);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0700);
+    }
+
+    #[test]
+    fn test_jmp_indirect() {
+        // JMP ($0300)
+        let mut cpu = cpu_with_program_in_ram(&[0x6c, 0x00, 0x03]);
+        cpu.mem_write_u16(0x0300, 0x0740);
+
+        assert_eq!(cpu.decode().unwrap().address, Some(0x0740));
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0740);
+    }
+
+    #[test]
+    fn test_decode_resolves_branch_targets() {
+        // BNE -4
+        let cpu = cpu_with_program_in_ram(&[0xd0, 0xfc]);
+        let instruction = cpu.decode().unwrap();
+        assert!(matches!(instruction.opcode.mode, AddressingMode::Relative));
+        assert_eq!(instruction.address, Some(0x05fe));
+    }
+
+    #[test]
+    fn test_operand_address_without_an_operand_does_not_panic() {
+        // ASL A; INX
+        let mut cpu = cpu_with_program_in_ram(&[0x0a, 0xe8]);
+        assert_eq!(cpu.decode().unwrap().address, None);
+        cpu.program_counter = 0x0601;
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Accumulator),
+            (0x0601, false)
+        );
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::NoneAddressing),
+            (0x0601, false)
+        );
+    }
+
+    #[test]
+    fn test_mem_read_u16_zp_wraps_within_zero_page() {
+        let mut ram = FlatRam::new();
+        ram.mem_write(0x00ff, 0x34);
+        ram.mem_write(0x0000, 0x12);
+        ram.mem_write(0x0100, 0x56);
+        assert_eq!(ram.mem_read_u16_zp(0xff), 0x1234);
+
+        ram.mem_write(0x0010, 0x78);
+        ram.mem_write(0x0011, 0x9a);
+        assert_eq!(ram.mem_read_u16_zp(0x10), 0x9a78);
+    }
+
+    #[test]
+    fn test_mem_read_u16_wrap_page_wraps_within_the_page() {
+        let mut ram = FlatRam::new();
+        ram.mem_write(0x02ff, 0x34);
+        ram.mem_write(0x0200, 0x12);
+        ram.mem_write(0x0300, 0x56);
+        assert_eq!(ram.mem_read_u16_wrap_page(0x02ff), 0x1234);
+
+        ram.mem_write(0xffff, 0x01);
+        ram.mem_write(0xff00, 0x80);
+        assert_eq!(ram.mem_read_u16_wrap_page(0xffff), 0x8001);
+        assert_eq!(ram.mem_read_u16_wrap_page(0x02fe), 0x3400);
+    }
+
+    #[test]
+    fn test_mem_u16_wraps_past_0xffff() {
+        let mut ram = FlatRam::new();
+        ram.mem_write_u16(0xffff, 0x1234);
+        assert_eq!(ram.mem_read(0xffff), 0x34);
+        assert_eq!(ram.mem_read(0x0000), 0x12);
+        assert_eq!(ram.mem_read_u16(0xffff), 0x1234);
+        assert_eq!(ram.mem_peek_u16(0xffff), 0x1234);
+    }
+
+    #[test]
+    fn test_mem_write_slice_wraps_past_0xffff() {
+        let mut ram = FlatRam::new();
+        ram.mem_write_slice(0xfffe, &[1, 2, 3, 4]);
+        assert_eq!(ram.mem_read(0xfffe), 1);
+        assert_eq!(ram.mem_read(0xffff), 2);
+        assert_eq!(ram.mem_read(0x0000), 3);
+        assert_eq!(ram.mem_read(0x0001), 4);
+
+        let mut buf = [0; 4];
+        ram.mem_read_into(0xfffe, &mut buf);
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_mem_write_slice_triggers_watchpoints() {
+        let mut cpu = CpuBuilder::new().build();
+        cpu.add_watchpoint(0x0202..=0x0202, WatchKind::Write);
+        cpu.mem_write_slice(0x0200, &[1, 2, 3]);
+        let hit = cpu.watch_hit.unwrap();
+        assert_eq!(
+            (hit.addr, hit.value, hit.kind),
+            (0x0202, 3, WatchKind::Write)
+        );
+    }
+
+    #[test]
+    fn test_operand_address_reports_page_crossings() {
+        let mut cpu = cpu_with_program_at(0x0600, &[0xff, 0x02]);
+        cpu.register_x = 0x01;
+        cpu.register_y = 0x01;
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Absolute_X),
+            (0x0300, true)
+        );
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Absolute_Y),
+            (0x0300, true)
+        );
+        // zero page and plain indirect modes never cross
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::ZeroPage_X),
+            (0x0000, false)
+        );
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Absolute),
+            (0x02ff, false)
+        );
+
+        cpu.register_x = 0x00;
+        cpu.register_y = 0x00;
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Absolute_X),
+            (0x02ff, false)
+        );
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Absolute_Y),
+            (0x02ff, false)
+        );
+    }
+
+    #[test]
+    fn test_indirect_y_reports_page_crossings() {
+        // the pointer at $20 holds $02F0
+        let mut cpu = cpu_with_program_at(0x0600, &[0x20]);
+        cpu.mem_write_u16(0x20, 0x02f0);
+        cpu.register_y = 0x0f;
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Indirect_Y),
+            (0x02ff, false)
+        );
+        cpu.register_y = 0x10;
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Indirect_Y),
+            (0x0300, true)
+        );
+    }
+
+    #[test]
+    fn test_indirect_y_pointer_at_ff_wraps_within_zero_page() {
+        // the pointer's low byte is at $FF and its high byte at $00
+        let mut cpu = cpu_with_program_at(0x0600, &[0xff]);
+        cpu.mem_write(0xff, 0x80);
+        cpu.mem_write(0x00, 0x03);
+        cpu.mem_write(0x0100, 0x07);
+        cpu.register_y = 0x7f;
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Indirect_Y),
+            (0x03ff, false)
+        );
+        cpu.register_y = 0x80;
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::Indirect_Y),
+            (0x0400, true)
+        );
+    }
+
+    #[test]
+    fn test_lsr_memory_shifts_bit_0_into_carry() {
+        // LSR $10
+        let mut cpu = CpuBuilder::new()
+            .status(CpuFlags::empty())
+            .program(&[0x46, 0x10])
+            .mem(0x10, &[0x03])
+            .build();
+        cpu.run();
+
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.status, CpuFlags::RESERVED | CpuFlags::CARRY);
+    }
+
+    #[test]
+    fn test_run_stops_at_jmp_to_self_with_interrupts_disabled() {
+        // SEI; JMP $0601
+        let mut cpu = cpu_with_program_in_ram(&[0x78, 0x4c, 0x01, 0x06]);
+        assert_eq!(cpu.try_run(), Ok(StopReason::TrappedAt(0x0601)));
+        assert_eq!(cpu.program_counter, 0x0601);
+    }
+
+    #[test]
+    fn test_jmp_to_self_with_interrupts_enabled_keeps_running() {
+        // CLI; JMP $0601
+        let mut cpu = cpu_with_program_in_ram(&[0x58, 0x4c, 0x01, 0x06]);
+        assert_eq!(cpu.run_for_cycles(30), Ok((32, None)));
+        assert_eq!(cpu.program_counter, 0x0601);
+    }
+
+    #[test]
+    fn test_run_stops_at_branch_to_self_when_always_detecting() {
+        // CLI; LDA #$00; BEQ $0603
+        let mut cpu = cpu_with_program_in_ram(&[0x58, 0xa9, 0x00, 0xf0, 0xfe]);
+        cpu.set_trap_detection(TrapDetection::Always);
+        assert_eq!(cpu.try_run(), Ok(StopReason::TrappedAt(0x0603)));
+    }
+
+    #[test]
+    fn test_trap_detection_can_be_disabled() {
+        // SEI; BEQ $0601
+        let mut cpu = cpu_with_program_in_ram(&[0x78, 0xf0, 0xfe]);
+        cpu.status.insert(CpuFlags::ZERO);
+        cpu.set_trap_detection(TrapDetection::Never);
+        assert_eq!(cpu.run_for_cycles(30), Ok((32, None)));
+        assert_eq!(cpu.program_counter, 0x0601);
+    }
+
+    #[test]
+    fn test_run_with_callback_propagates_errors() {
+        let mut cpu = cpu_with_program_in_ram(&[0xe8, 0x12]);
+        let mut callbacks = 0;
+        let result = cpu.run_with_callback(keep_running(|_| callbacks += 1));
+        assert_eq!(
+            result,
+            Err(CpuError::Jammed {
+                pc: 0x0601,
+                opcode: 0x12
+            })
+        );
+        assert_eq!(callbacks, 1);
+    }
+
+    #[test]
+    fn test_callback_sees_instruction_before_it_executes() {
+        // LDA #$05; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xa9, 0x05, 0x00]);
+        let mut seen = Vec::new();
+        cpu.run_with_callback(|cpu, instruction| {
+            seen.push((
+                instruction.pc,
+                instruction.opcode.mnemonic,
+                instruction.operands().to_vec(),
+                instruction.address,
+                cpu.register_a,
+            ));
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            seen,
+            vec![
+                (0x0600, "LDA", vec![0x05], Some(0x0601), 0x00),
+                (0x0602, "BRK", vec![], None, 0x05),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_callback_can_stop_and_resume_the_run() {
+        // loop: INX; CPX #$14; BNE loop; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xe8, 0xe0, 0x14, 0xd0, 0xfb, 0x00]);
+        let mut executed = 0;
+        let reason = cpu.run_with_callback(|_, _| {
+            if executed == 10 {
+                return ControlFlow::Break(());
+            }
+            executed += 1;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(reason, Ok(StopReason::Callback));
+        // INX, CPX, BNE three times, then the fourth INX
+        assert_eq!(cpu.register_x, 4);
+        assert_eq!(cpu.program_counter, 0x0601);
+        assert_eq!(cpu.cycles, 3 * 7 + 2);
+
+        let reason = cpu.run_with_callback(|_, _| ControlFlow::Continue(()));
+        assert_eq!(reason, Ok(StopReason::Brk));
+        assert_eq!(cpu.register_x, 0x14);
+        assert_eq!(cpu.program_counter, 0x0606);
+    }
+
+    #[test]
+    fn test_breakpoint_stops_before_the_instruction() {
+        // LDX #$00; loop: INX; CPX #$05; BNE loop; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xa2, 0x00, 0xe8, 0xe0, 0x05, 0xd0, 0xfb, 0x00]);
+        cpu.add_breakpoint(0x0603);
+
+        assert_eq!(cpu.try_run(), Ok(StopReason::Breakpoint(0x0603)));
+        assert_eq!(cpu.program_counter, 0x0603);
+        assert_eq!(cpu.register_x, 1);
+
+        let info = cpu.step().unwrap();
+        assert_eq!(info.mnemonic, "CMX");
+
+        assert_eq!(cpu.try_run(), Ok(StopReason::Breakpoint(0x0603)));
+        assert_eq!(cpu.program_counter, 0x0603);
+        assert_eq!(cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_resuming_does_not_retrigger_the_breakpoint() {
+        let mut cpu = cpu_with_program_in_ram(&[0xa2, 0x00, 0xe8, 0xe0, 0x05, 0xd0, 0xfb, 0x00]);
+        cpu.add_breakpoint(0x0603);
+
+        for x in 1..=5 {
+            assert_eq!(cpu.try_run(), Ok(StopReason::Breakpoint(0x0603)));
+            assert_eq!(cpu.register_x, x);
         }
+        cpu.remove_breakpoint(0x0603);
+        assert_eq!(cpu.try_run(), Ok(StopReason::Brk));
+        assert_eq!(cpu.register_x, 5);
     }
-    fn stack_push(&mut self, data: u8) {
-        self.mem_write((STACK as u16) + self.stack_pointer as u16, data);
-        self.stack_pointer = self.stack_pointer.wrapping_sub(1)
+
+    #[test]
+    fn test_conditional_breakpoint_waits_for_its_condition() {
+        // loop: CLC; ADC #$01; CMP #$0A; BNE loop; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0x18, 0x69, 0x01, 0xc9, 0x0a, 0xd0, 0xf9, 0x00]);
+        cpu.add_conditional_breakpoint(0x0603, |cpu| cpu.register_a == 5);
+
+        assert_eq!(cpu.try_run(), Ok(StopReason::Breakpoint(0x0603)));
+        assert_eq!(cpu.program_counter, 0x0603);
+        assert_eq!(cpu.register_a, 5);
+
+        assert_eq!(cpu.try_run(), Ok(StopReason::Brk));
+        assert_eq!(cpu.register_a, 10);
     }
 
-    fn stack_pop(&mut self) -> u8 {
-        self.stack_pointer = self.stack_pointer.wrapping_add(1);
-        self.mem_read((STACK as u16) + self.stack_pointer as u16)
+    #[test]
+    fn test_conditional_breakpoint_on_flag() {
+        // loop: CLC; ADC #$40; BCC loop; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0x18, 0x69, 0x40, 0x90, 0xfb, 0x00]);
+        cpu.add_conditional_breakpoint(0x0603, |cpu| cpu.carry());
+
+        assert_eq!(cpu.try_run(), Ok(StopReason::Breakpoint(0x0603)));
+        assert_eq!(cpu.register_a, 0x00);
+
+        cpu.remove_breakpoint(0x0603);
+        assert_eq!(cpu.try_run(), Ok(StopReason::Brk));
     }
 
-    fn stack_push_u16(&mut self, data: u16) {
-        let hi = (data >> 8) as u8;
-        let lo = (data & 0xff) as u8;
-        self.stack_push(hi);
-        self.stack_push(lo);
+    #[test]
+    fn test_write_watchpoint_stops_after_sta() {
+        // LDA #$42; STA $FA; INX; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xa9, 0x42, 0x85, 0xfa, 0xe8, 0x00]);
+        cpu.add_watchpoint(0xfa..=0xfa, WatchKind::Write);
+
+        assert_eq!(
+            cpu.try_run(),
+            Ok(StopReason::Watchpoint {
+                addr: 0xfa,
+                value: 0x42,
+                kind: WatchKind::Write,
+                pc: 0x0602,
+            })
+        );
+        assert_eq!(cpu.program_counter, 0x0604);
+        assert_eq!(cpu.register_x, 0);
+
+        assert_eq!(cpu.try_run(), Ok(StopReason::Brk));
+        assert_eq!(cpu.register_x, 1);
     }
 
-    fn stack_pop_u16(&mut self) -> u16 {
-        let lo = self.stack_pop() as u16;
-        let hi = self.stack_pop() as u16;
+    #[test]
+    fn test_read_watchpoint_stops_after_indexed_lda() {
+        // LDX #$03; LDA $0200,X; STA $0210; BRK
+        let mut cpu =
+            cpu_with_program_in_ram(&[0xa2, 0x03, 0xbd, 0x00, 0x02, 0x8d, 0x10, 0x02, 0x00]);
+        cpu.mem_write(0x0203, 0x99);
+        cpu.add_watchpoint(0x0200..=0x020f, WatchKind::Read);
 
-        hi << 8 | lo
+        assert_eq!(
+            cpu.try_run(),
+            Ok(StopReason::Watchpoint {
+                addr: 0x0203,
+                value: 0x99,
+                kind: WatchKind::Read,
+                pc: 0x0602,
+            })
+        );
+        assert_eq!(cpu.register_a, 0x99);
+
+        // the write to $0210 is outside the range and not a read
+        assert_eq!(cpu.try_run(), Ok(StopReason::Brk));
     }
 
-    fn branch(&mut self, condition: bool) {
-        if condition {
-            let jump = self.mem_read(self.program_counter) as i8;
-            let jump_addr = self
-                .program_counter
-                .wrapping_add(1)
-                .wrapping_add(jump as u16);
-            self.program_counter = jump_addr;
-        }
+    #[test]
+    fn test_watchpoint_ignores_other_access_kinds() {
+        // LDA $FA; STA $FB; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xa5, 0xfa, 0x85, 0xfb, 0x00]);
+        cpu.add_watchpoint(0xfa..=0xfa, WatchKind::Write);
+        cpu.add_watchpoint(0xfb..=0xfb, WatchKind::Read);
+        assert_eq!(cpu.try_run(), Ok(StopReason::Brk));
+
+        let mut cpu = cpu_with_program_in_ram(&[0xa5, 0xfa, 0x85, 0xfb, 0x00]);
+        cpu.add_watchpoint(0xfa..=0xfb, WatchKind::ReadWrite);
+        cpu.remove_watchpoint(&(0xfa..=0xfb));
+        assert_eq!(cpu.try_run(), Ok(StopReason::Brk));
     }
 
-    fn update_zero_and_negative_flags(&mut self, result: u8) {
-        if result == 0 {
-            self.status = self.status | 0b0000_0010;
-        } else {
-            self.status = self.status & 0b1111_1101;
+    #[test]
+    fn test_decode_resolves_effective_address() {
+        // STA $02F0,X
+        let mut cpu = cpu_with_program_in_ram(&[0x9d, 0xf0, 0x02]);
+        cpu.register_x = 0x20;
+
+        let instruction = cpu.decode().unwrap();
+        assert_eq!(instruction.opcode.code, 0x9d);
+        assert_eq!(instruction.operands(), &[0xf0, 0x02]);
+        assert_eq!(instruction.address, Some(0x0310));
+        assert_eq!(cpu.program_counter, 0x0600);
+        assert_eq!(cpu.cycles, 0);
+    }
+
+    #[test]
+    fn test_restore_replays_the_same_execution() {
+        // loop: INX; TXA; ADC $10; STA $10; STA $0200,X; PHA; JMP loop
+        let program = [
+            0xe8, 0x8a, 0x65, 0x10, 0x85, 0x10, 0x9d, 0x00, 0x02, 0x48, 0x4c, 0x00, 0x06,
+        ];
+        let mut cpu = nes_cpu_with_program_in_ram(&program);
+        for _ in 0..37 {
+            cpu.step().unwrap();
         }
 
-        if result & 0b1000_0000 != 0 {
-            self.status = self.status | 0b1000_0000;
-        } else {
-            self.status = self.status & 0b0111_1111;
+        let cpu_snapshot = cpu.snapshot();
+        let ram_snapshot = cpu.bus.snapshot_ram();
+        for _ in 0..100 {
+            cpu.step().unwrap();
         }
+        let first = (cpu.snapshot(), cpu.bus.snapshot_ram());
+        assert_ne!(first.0, cpu_snapshot);
+        assert_ne!(first.1, ram_snapshot);
+
+        cpu.restore(&cpu_snapshot);
+        cpu.bus.restore_ram(&ram_snapshot);
+        assert_eq!(cpu.snapshot(), cpu_snapshot);
+        for _ in 0..100 {
+            cpu.step().unwrap();
+        }
+        assert_eq!((cpu.snapshot(), cpu.bus.snapshot_ram()), first);
     }
 
-    pub fn reset(&mut self) {
-        self.register_a = 0;
-        self.register_x = 0;
-        self.status = 0b100100;
+    #[test]
+    fn test_restore_keeps_status_bit_5_set() {
+        let mut cpu = cpu_with_program_in_ram(&[0x00]);
+        let mut snapshot = cpu.snapshot();
+        snapshot.status =
+            (CpuFlags::NEGATIVE | CpuFlags::OVERFLOW | CpuFlags::ZERO | CpuFlags::CARRY).bits();
 
-        self.program_counter = self.mem_read_u16(0xFFFC);
+        cpu.restore(&snapshot);
+        assert_eq!(
+            cpu.status,
+            CpuFlags::NEGATIVE
+                | CpuFlags::OVERFLOW
+                | CpuFlags::RESERVED
+                | CpuFlags::ZERO
+                | CpuFlags::CARRY
+        );
     }
 
-    pub fn load(&mut self, program: Vec<u8>) {
-        for i in 0..(program.len() as u16) {
-            self.mem_write(0x8000 + i, program[i as usize])
-        }
-        self.mem_write_u16(0xFFFC, 0x8000);
+    #[test]
+    fn test_display_is_a_compact_state_line() {
+        // LDX #$01; LDA #$C0
+        let mut cpu = cpu_with_program_in_ram(&[0xa2, 0x01, 0xa9, 0xc0, 0x00]);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        assert_eq!(
+            cpu.to_string(),
+            "PC:0604 A:C0 X:01 Y:00 P:NV-BDIZC=10100100 SP:FD CYC:4"
+        );
     }
 
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.run();
+    #[test]
+    fn test_debug_adds_the_next_instruction() {
+        let cpu = cpu_with_program_in_ram(&[0xbd, 0x33, 0x06]);
+
+        assert_eq!(
+            format!("{:?}", cpu),
+            "PC:0600 A:00 X:00 Y:00 P:NV-BDIZC=00100100 SP:FD CYC:0  LDA $0633,X"
+        );
     }
 
-    fn get_flg(&self, flgCode: &FlgCodes) -> u8 {
-        match flgCode {
-            FlgCodes::CARRY => self.status >> 0 & 1,
-            FlgCodes::ZERO => self.status >> 1 & 1,
-            FlgCodes::INTERRUPT_DISABLE => self.status >> 2 & 1,
-            FlgCodes::DECIMAL_MODE => self.status >> 3 & 1,
-            FlgCodes::BREAK => self.status >> 4 & 1,
-            FlgCodes::RESERVED => self.status >> 5 & 1,
-            FlgCodes::OVERFLOW => self.status >> 6 & 1,
-            FlgCodes::NEGATIV => self.status >> 7 & 1,
+    #[test]
+    fn test_call_stack_follows_nested_calls() {
+        let rom = ProgramBuilder::new()
+            .org(0x8000)
+            .bytes(&[0x20]) // JSR one
+            .address_of("one")
+            .label("main_done")
+            .bytes(&[0x00])
+            .label("one")
+            .bytes(&[0x20]) // JSR two
+            .address_of("two")
+            .bytes(&[0x60])
+            .label("two")
+            .bytes(&[0x20]) // JSR three
+            .address_of("three")
+            .bytes(&[0x60])
+            .label("three")
+            .bytes(&[0x60])
+            .reset_vector(0x8000)
+            .build_rom();
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.reset();
+        cpu.track_calls(true);
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
         }
-    }
+        assert_eq!(
+            cpu.call_stack(),
+            &[
+                CallFrame {
+                    target: 0x8004,
+                    return_address: 0x8003,
+                    desynced: false,
+                },
+                CallFrame {
+                    target: 0x8008,
+                    return_address: 0x8007,
+                    desynced: false,
+                },
+                CallFrame {
+                    target: 0x800c,
+                    return_address: 0x800b,
+                    desynced: false,
+                },
+            ]
+        );
 
-    fn set_flg(&mut self, flgCode: &FlgCodes, value: u8) {
-        if value == 1 {
-            match flgCode {
-                FlgCodes::CARRY => self.status |= 1 << 0,
-                FlgCodes::ZERO => self.status |= 1 << 1,
-                FlgCodes::INTERRUPT_DISABLE => self.status |= 1 << 2,
-                FlgCodes::DECIMAL_MODE => self.status |= 1 << 3,
-                FlgCodes::BREAK => self.status |= 1 << 4,
-                FlgCodes::RESERVED => self.status |= 1 << 5,
-                FlgCodes::OVERFLOW => self.status |= 1 << 6,
-                FlgCodes::NEGATIV => self.status |= 1 << 7,
-            }
-        } else {
-            match flgCode {
-                FlgCodes::CARRY => self.status = self.status & !(1 << 0),
-                FlgCodes::ZERO => self.status = self.status & !(1 << 1),
-                FlgCodes::INTERRUPT_DISABLE => self.status = self.status & !(1 << 2),
-                FlgCodes::DECIMAL_MODE => self.status = self.status & !(1 << 3),
-                FlgCodes::BREAK => self.status = self.status & !(1 << 4),
-                FlgCodes::RESERVED => self.status = self.status & !(1 << 5),
-                FlgCodes::OVERFLOW => self.status = self.status & !(1 << 6),
-                FlgCodes::NEGATIV => self.status = self.status & !(1 << 7),
-            }
+        for depth in (0..3).rev() {
+            cpu.step().unwrap();
+            assert_eq!(cpu.call_stack().len(), depth);
         }
+        assert_eq!(cpu.program_counter, 0x8003);
     }
 
-    // pub fn debug(&mut self, label: String) {
-    //     println!("{:20}... code: {:#06x} a: {:#06x} x: {:#06x} y: {:#06x} pc: {:#06x} sp: {:#06x} status: {:#10b}", label, self.mem_read(self.program_counter), self.register_a, self.register_x, self.register_y, self.program_counter, self.stack_pointer, self.status);
-    // }
+    #[test]
+    fn test_call_stack_marks_stack_trampolines_as_desynced() {
+        // JSR $0610; BRK ... $0610: RTS ... $0620: RTS
+        let mut cpu = cpu_with_program_in_ram(&[0x20, 0x10, 0x06, 0x00]);
+        cpu.mem_write(0x0610, 0x60);
+        cpu.mem_write(0x0620, 0x60);
+        cpu.track_calls(true);
 
-    pub fn run(&mut self) {
-        self.run_with_callback(|_| {});
+        cpu.step().unwrap();
+        // make the subroutine's RTS jump to $0620 instead of returning
+        cpu.stack_push_u16(0x0620 - 1);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0620);
+        assert_eq!(
+            cpu.call_stack(),
+            &[CallFrame {
+                target: 0x0610,
+                return_address: 0x0603,
+                desynced: true,
+            }]
+        );
+
+        // the real return address is still on the stack
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0603);
+        assert!(cpu.call_stack().is_empty());
     }
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
-    where
-        F: FnMut(&mut CPU),
-    {
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
-        loop {
-            let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter;
-            let opcode = opcodes.get(&code).unwrap();
-
-            match code {
-                /* Transfer Instructions */
-                /* LDA */
-                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                    self.ld(&opcode.mode, &REGISTER::REGISTER_A);
-                }
-                /* LDX */
-                0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
-                    self.ld(&opcode.mode, &REGISTER::REGISTER_X);
-                }
-                /* LDY */
-                0xA0 | 0xA4 | 0xB4 | 0xAB | 0xBC => {
-                    self.ld(&opcode.mode, &REGISTER::REGISTER_Y);
-                }
-                /* STA */
-                0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
-                    self.store(&opcode.mode, &REGISTER::REGISTER_A);
-                }
-                /* STX */
-                0x86 | 0x96 | 0x8E => {
-                    self.store(&opcode.mode, &REGISTER::REGISTER_X);
-                }
-                /* STY */
-                0x84 | 0x94 | 0x8C => {
-                    self.store(&opcode.mode, &REGISTER::REGISTER_Y);
-                }
-                /* TAX */
-                0xAA => self.tax(),
-                /* TXA */
-                0x8A => self.txa(),
-                /* TAY */
-                0xA8 => self.tay(),
-                /* TYA */
-                0x98 => self.tya(),
-                /* TSX */
-                0xBA => self.tsx(),
-                /* TXS */
-                0x9A => self.txs(),
-                /* Arithmetic Instructions */
-                /* ADC */
-                0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
-                    self.adc(&opcode.mode);
-                }
-                /* AND */
-                0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => self.and(&opcode.mode),
-                /* ASL Immediate */
-                0x0A => self.asl_accumulator(),
-                /* ASL others */
-                0x06 | 0x16 | 0x0E | 0x1E => self.asl(&opcode.mode),
-                /* BIT */
-                0x24 | 0x2C => self.bit(&opcode.mode),
-                /* CMP */
-                0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
-                    self.cmp(&opcode.mode, self.register_a)
-                }
-                /* CMX */
-                0xE0 | 0xE4 | 0xEC => self.cmp(&opcode.mode, self.register_x),
-                /* CMY */
-                0xC0 | 0xC4 | 0xCC => self.cmp(&opcode.mode, self.register_y),
-                /* DEC */
-                0xC6 | 0xD6 | 0xCE | 0xDE => self.dec(&opcode.mode),
-                /* DEX */
-                0xCA => self.dex(&opcode.mode),
-                /* DEY */
-                0x88 => self.dey(&opcode.mode),
-                /* EOR */
-                0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => self.eor(&opcode.mode),
-                /* INC */
-                0xE6 | 0xF6 | 0xEE | 0xFE => self.inc(&opcode.mode),
-                /* INX */
-                0xE8 => self.inx(),
-                /* INY */
-                0xC8 => self.iny(),
-                /* LSR_accumulator */
-                0x4A => self.lsr_accumulator(),
-                /* LSR others*/
-                0x46 | 0x56 | 0x4E | 0x5E => self.lsr(&opcode.mode),
-                /* ORA */
-                0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => self.ora(&opcode.mode),
-                /* ROL_accumulator */
-                0x2A => self.rol_accumulator(),
-                /* ROL others*/
-                0x26 | 0x36 | 0x2E | 0x3E => self.rol(&opcode.mode),
-                /* ROR_accumulator */
-                0x6A => self.ror_accumulator(),
-                /* ROR others*/
-                0x66 | 0x76 | 0x6E | 0x7E => self.ror(&opcode.mode),
-                /* SBC */
-                0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
-                    self.sbc(&opcode.mode);
-                }
-                /* Stack Instructions */
-                /* PHA */
-                0x48 => self.stack_push(self.register_a),
-                /* PHP */
-                0x08 => self.stack_push(self.status),
-                /* PLA */
-                0x68 => {
-                    let value = self.stack_pop();
-                    self.register_a = value;
-                    self.update_zero_and_negative_flags(value);
-                }
-                /* PLP */
-                0x28 => {
-                    let value = self.stack_pop();
-                    self.status = value;
-                    self.update_zero_and_negative_flags(self.status);
-                }
-                /* Jump Instructions */
-                /* JMP */
-                0x4C => {
-                    let mem_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = mem_address;
-                }
-                /* JMP Indirect */
-                0x6C => {
-                    let mem_address = self.mem_read_u16(self.program_counter);
-
-                    let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-                        let lo = self.mem_read(mem_address);
-                        let hi = self.mem_read(mem_address & 0xFF00);
-                        (hi as u16) << 8 | (lo as u16)
-                    } else {
-                        self.mem_read_u16(mem_address)
-                    };
-
-                    self.program_counter = indirect_ref;
-                }
-                /* JSR */
-                0x20 => {
-                    self.stack_push_u16(self.program_counter + 2 - 1);
-                    let target_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = target_address
-                }
-                /* RTS */
-                0x60 => {
-                    self.program_counter = self.stack_pop_u16() + 1;
-                }
-                /* RTI */
-                0x40 => {
-                    self.status = self.stack_pop();
-                    self.program_counter = self.stack_pop_u16();
-                }
-                /* Branching Instructions */
-                /* BCC */
-                0x90 => self.branch(self.get_flg(&FlgCodes::CARRY) == 0),
-                /* BCS */
-                0xB0 => self.branch(self.get_flg(&FlgCodes::CARRY) == 1),
-                /* BEQ */
-                0xF0 => self.branch(self.get_flg(&FlgCodes::ZERO) == 1),
-                /* BMI */
-                0x30 => self.branch(self.get_flg(&FlgCodes::NEGATIV) == 1),
-                /* BNE */
-                0xD0 => self.branch(self.get_flg(&FlgCodes::ZERO) == 0),
-                /* BPL */
-                0x10 => self.branch(self.get_flg(&FlgCodes::NEGATIV) == 0),
-                /* BVC */
-                0x50 => self.branch(self.get_flg(&FlgCodes::OVERFLOW) == 0),
-                /* BVS */
-                0x70 => self.branch(self.get_flg(&FlgCodes::OVERFLOW) == 1),
-                /* Flag Modification Instructions */
-                /* CLC */
-                0x18 => self.set_flg(&FlgCodes::CARRY, 0),
-                /* CLD */
-                0xD8 => self.set_flg(&FlgCodes::DECIMAL_MODE, 0),
-                /* CLI */
-                0x58 => self.set_flg(&FlgCodes::INTERRUPT_DISABLE, 0),
-                /* CLV */
-                0xB8 => self.set_flg(&FlgCodes::OVERFLOW, 0),
-                /* SEC */
-                0x38 => self.set_flg(&FlgCodes::CARRY, 1),
-                /* SED */
-                0xF8 => self.set_flg(&FlgCodes::DECIMAL_MODE, 1),
-                /* SEI */
-                0x78 => self.set_flg(&FlgCodes::INTERRUPT_DISABLE, 1),
-                /* The Other Instructions */
-                /* BRK */
-                0x00 => return,
-                /* NOP */
-                0xEA => {}
-                _ => {
-                    todo!()
-                }
+    #[test]
+    fn test_call_stack_is_empty_unless_tracked() {
+        let mut cpu = cpu_with_program_in_ram(&[0x20, 0x10, 0x06]);
+        cpu.step().unwrap();
+        assert!(cpu.call_stack().is_empty());
+    }
+
+    #[test]
+    fn test_stats_count_a_small_loop() {
+        // LDX #$10; loop: DEX; BNE loop; BRK
+        let mut cpu = cpu_with_program_in_ram(&[0xa2, 0x10, 0xca, 0xd0, 0xfd, 0x00]);
+        cpu.run();
+
+        assert_eq!(
+            cpu.take_stats(),
+            CpuStats {
+                instructions: 34,
+                cycles: 2 + 16 * 2 + 15 * 3 + 2,
+                nmis: 0,
+                irqs: 0,
+                branches_taken: 15,
+                page_crosses: 0,
             }
-            if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.len - 1) as u16
-            };
-            callback(self);
-        }
+        );
+        assert_eq!(cpu.stats(), &CpuStats::default());
     }
 
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
-        match mode {
-            AddressingMode::Immediate => self.program_counter,
+    #[test]
+    fn test_stats_count_page_crosses() {
+        // LDA $06FF,X; BNE +$7F, taken across into the next page
+        let mut cpu = CpuBuilder::new()
+            .x(0x01)
+            .pc(0x06f0)
+            .program(&[0xbd, 0xff, 0x06, 0xd0, 0x7f])
+            .mem(0x0700, &[0x01])
+            .build();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
 
-            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+        let stats = cpu.take_stats();
+        assert_eq!(stats.instructions, 2);
+        assert_eq!(stats.cycles, 5 + 4);
+        assert_eq!(stats.branches_taken, 1);
+        assert_eq!(stats.page_crosses, 2);
+        assert_eq!(stats.cycles, cpu.cycles);
+    }
 
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+    #[test]
+    fn test_builder_sets_registers_and_memory() {
+        // ADC $0200,X
+        let mut cpu = CpuBuilder::new()
+            .a(0x50)
+            .x(0x01)
+            .carry(true)
+            .pc(0xc000)
+            .program(&[0x7d, 0x00, 0x02])
+            .mem(0x0201, &[0x10])
+            .build();
+        assert_eq!(cpu.program_counter, 0xc000);
+        assert!(cpu.carry());
+        assert!(cpu.interrupt_disable());
 
-            AddressingMode::ZeroPage_X => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
-            }
-            AddressingMode::ZeroPage_Y => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
-            }
+        cpu.run();
+        assert_eq!(cpu.register_a, 0x61);
+        assert!(!cpu.carry());
+    }
 
-            AddressingMode::Absolute_X => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_x as u16);
-                addr
-            }
-            AddressingMode::Absolute_Y => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_y as u16);
-                addr
-            }
-            AddressingMode::Indirect_X => {
-                let base = self.mem_read(self.program_counter);
+    #[test]
+    fn test_builder_keeps_status_bit_5_set() {
+        let cpu = CpuBuilder::new().status(CpuFlags::empty()).build();
+        assert_eq!(cpu.status, CpuFlags::RESERVED);
+    }
 
-                let ptr: u8 = (base as u8).wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                (hi as u16) << 8 | (lo as u16)
-            }
-            AddressingMode::Indirect_Y => {
-                let base = self.mem_read(self.program_counter);
+    #[test]
+    fn test_builder_runs_on_the_nes_bus() {
+        let mut cpu = CpuBuilder::with_bus(Bus::new(Rom::empty()))
+            .y(0x41)
+            .program(&[0xc8, 0x00]) // INY; BRK
+            .build();
+        cpu.run();
 
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read(base.wrapping_add(1) as u16);
-                let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.register_y as u16);
-                deref
-            }
-            AddressingMode::NoneAddressing => {
-                panic!("mode {:?} is not supported", mode);
-            }
+        assert_eq!(cpu.register_y, 0x42);
+        assert_eq!(cpu.program_counter, 0x0602);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_save_state_round_trips_through_serde() {
+        // loop: INX; TXA; ADC $10; STA $10; STA $0200,X; PHA; JMP loop
+        let program = [
+            0xe8, 0x8a, 0x65, 0x10, 0x85, 0x10, 0x9d, 0x00, 0x02, 0x48, 0x4c, 0x00, 0x06,
+        ];
+        let mut cpu = nes_cpu_with_program_in_ram(&program);
+        for _ in 0..150 {
+            cpu.step().unwrap();
+        }
+
+        let json = serde_json::to_string(&cpu.save_state()).unwrap();
+        let state: SaveState = serde_json::from_str(&json).unwrap();
+        let mut restored = CPU::new(Bus::new(Rom::empty()));
+        restored.load_state(&state);
+        assert_eq!(restored.save_state(), cpu.save_state());
+
+        for _ in 0..150 {
+            cpu.step().unwrap();
+            restored.step().unwrap();
         }
+        assert_eq!(restored.save_state(), cpu.save_state());
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_save_state_rejects_truncated_ram() {
+        let cpu = nes_cpu_with_program_in_ram(&[0x00]);
+        let json = serde_json::to_string(&cpu.save_state()).unwrap();
+        let truncated = json.replacen("[0,", "[", 1);
+        assert!(serde_json::from_str::<SaveState>(&truncated).is_err());
+    }
 
     #[test]
-    fn test_0xa9_lda_immediate_load_data() {
-        let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xa9, 0x05, 0x00]);
+    fn test_reset_takes_seven_cycles() {
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.reset();
-        cpu.status = 0;
-        cpu.run();
-        assert_eq!(cpu.register_a, 5);
-        assert!(cpu.status & 0b0000_0010 == 0);
-        assert!(cpu.status & 0b1000_0000 == 0);
+        assert_eq!(cpu.cycles, 7);
     }
 
     #[test]
     fn test_0xa9_lda_zero_flag() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load_and_run(vec![0xa9, 0x00, 0x00]);
-        assert!(cpu.status & 0b0000_0010 == 0b10);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load_and_run(vec![0xa9, 0x00, 0x00]).unwrap();
+        assert!(cpu.zero());
     }
 
     #[test]
     fn test_0xa9_lda_negative_flag() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load_and_run(vec![0xa9, 0xff, 0x00]);
-        assert!(cpu.status & 0b1000_0000 == 0b1000_0000);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load_and_run(vec![0xa9, 0xff, 0x00]).unwrap();
+        assert!(cpu.negative());
     }
     #[test]
     fn test_ldx_negative_flag() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load_and_run(vec![0xa2, 0xff, 0x00]);
-        assert!(cpu.status & 0b1000_0000 == 0b1000_0000);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load_and_run(vec![0xa2, 0xff, 0x00]).unwrap();
+        assert!(cpu.negative());
     }
     #[test]
     fn test_ldy_negative_flag() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load_and_run(vec![0xa0, 0xff, 0x00]);
-        assert!(cpu.status & 0b1000_0000 == 0b1000_0000);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load_and_run(vec![0xa0, 0xff, 0x00]).unwrap();
+        assert!(cpu.negative());
     }
 
     #[test]
     fn test_0xaa_tax_move_a_to_x() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load_and_run(vec![0xa9, 0x0A, 0xaa, 0x00]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load_and_run(vec![0xa9, 0x0A, 0xaa, 0x00]).unwrap();
 
         assert_eq!(cpu.register_x, 10)
     }
 
     #[test]
     fn test_5_ops_working_together() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00])
+            .unwrap();
 
         assert_eq!(cpu.register_x, 0xc1)
     }
     #[test]
     fn test_sta() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x85, 0x00]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x85, 0x00]).unwrap();
         cpu.reset();
         cpu.register_a = 0xff;
         cpu.run();
@@ -791,8 +2810,8 @@ mod test {
     }
     #[test]
     fn test_stx() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x86, 0x00]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x86, 0x00]).unwrap();
         cpu.reset();
         cpu.register_x = 0xff;
         cpu.run();
@@ -800,8 +2819,8 @@ mod test {
     }
     #[test]
     fn test_sty() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x84, 0x00]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x84, 0x00]).unwrap();
         cpu.reset();
         cpu.register_y = 0xff;
         cpu.run();
@@ -809,8 +2828,8 @@ mod test {
     }
     #[test]
     fn test_tax() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xAA]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xAA]).unwrap();
         cpu.reset();
         cpu.register_a = 0xff;
         cpu.run();
@@ -818,8 +2837,8 @@ mod test {
     }
     #[test]
     fn test_txa() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x8A]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x8A]).unwrap();
         cpu.reset();
         cpu.register_x = 0xff;
         cpu.run();
@@ -827,8 +2846,8 @@ mod test {
     }
     #[test]
     fn test_tay() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xA8]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xA8]).unwrap();
         cpu.reset();
         cpu.register_a = 0xff;
         cpu.run();
@@ -836,8 +2855,8 @@ mod test {
     }
     #[test]
     fn test_tya() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x98]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x98]).unwrap();
         cpu.reset();
         cpu.register_y = 0xff;
         cpu.run();
@@ -845,16 +2864,16 @@ mod test {
     }
     #[test]
     fn test_tsx() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xBA]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xBA]).unwrap();
         cpu.reset();
         cpu.run();
         assert_eq!(cpu.register_x, STACK_RESET)
     }
     #[test]
     fn test_txs() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x9A]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x9A]).unwrap();
         cpu.reset();
         cpu.register_x = 0xff;
         cpu.run();
@@ -862,349 +2881,356 @@ mod test {
     }
     #[test]
     fn test_inx_overflow() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.register_x = 0xff;
-        cpu.load_and_run(vec![0xa9, 0xff, 0xaa, 0xe8, 0xe8, 0x00]);
+        cpu.load_and_run(vec![0xa9, 0xff, 0xaa, 0xe8, 0xe8, 0x00])
+            .unwrap();
 
         assert_eq!(cpu.register_x, 1)
     }
 
     #[test]
     fn test_lda_from_memory() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x10, 0x55);
 
-        cpu.load_and_run(vec![0xa5, 0x10, 0x00]);
+        cpu.load_and_run(vec![0xa5, 0x10, 0x00]).unwrap();
 
         assert_eq!(cpu.register_a, 0x55);
     }
 
     #[test]
     fn test_adc_no_carry_and_no_overflow() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x69, 0x01]);
-        cpu.reset();
-        cpu.register_a = 0x01;
-        cpu.status = 0;
+        let mut cpu = CpuBuilder::new()
+            .a(0x01)
+            .status(CpuFlags::empty())
+            .program(&[0x69, 0x01])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x02);
-        assert_eq!(cpu.status, 0b0000_0000);
+        assert_eq!(cpu.status, CpuFlags::RESERVED);
     }
 
     #[test]
     fn test_adc_has_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x69, 0x01]);
-        cpu.reset();
-        cpu.register_a = 0x01;
-        cpu.status = 0x01;
+        let mut cpu = CpuBuilder::new()
+            .a(0x01)
+            .status(CpuFlags::CARRY)
+            .program(&[0x69, 0x01])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x03);
-        assert_eq!(cpu.status, 0x00);
+        assert_eq!(cpu.status, CpuFlags::RESERVED);
     }
 
     #[test]
     fn test_adc_occurs_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x69, 0xd0]);
-        cpu.reset();
-        cpu.register_a = 0x50;
-        cpu.status = 0x00;
+        let mut cpu = CpuBuilder::new()
+            .a(0x50)
+            .status(CpuFlags::empty())
+            .program(&[0x69, 0xd0])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x20);
-        assert_eq!(cpu.status, 0x01);
+        assert_eq!(cpu.status, CpuFlags::CARRY | CpuFlags::RESERVED);
     }
 
     #[test]
     fn test_adc_occurs_overflow_plus() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x69, 0x50]);
-        cpu.reset();
-        cpu.register_a = 0x50;
-        cpu.status = 0x00;
+        let mut cpu = CpuBuilder::new()
+            .a(0x50)
+            .status(CpuFlags::empty())
+            .program(&[0x69, 0x50])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0xA0);
-        assert_eq!(cpu.status, 0xC0);
+        assert_eq!(
+            cpu.status,
+            CpuFlags::NEGATIVE | CpuFlags::OVERFLOW | CpuFlags::RESERVED
+        );
     }
     #[test]
     fn test_adc_occurs_overflow_plus_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x69, 0x50]);
-        cpu.reset();
-        cpu.register_a = 0x4F;
-        cpu.status = 0x01;
+        let mut cpu = CpuBuilder::new()
+            .a(0x4F)
+            .status(CpuFlags::CARRY)
+            .program(&[0x69, 0x50])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0xA0);
-        assert_eq!(cpu.status, 0xC0);
+        assert_eq!(
+            cpu.status,
+            CpuFlags::NEGATIVE | CpuFlags::OVERFLOW | CpuFlags::RESERVED
+        );
     }
     #[test]
     fn test_adc_occurs_no_overflow() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x69, 0x7f]);
-        cpu.reset();
-        cpu.register_a = 0x82;
-        cpu.status = 0x00;
+        let mut cpu = CpuBuilder::new()
+            .a(0x82)
+            .status(CpuFlags::empty())
+            .program(&[0x69, 0x7f])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x01);
-        assert_eq!(cpu.status, 0x01);
+        assert_eq!(cpu.status, CpuFlags::CARRY | CpuFlags::RESERVED);
     }
 
     #[test]
     fn test_and() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x29, 0x01]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x29, 0x01]).unwrap();
         cpu.reset();
         cpu.register_a = 0x01;
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x01);
-        assert_eq!(cpu.status, 0x00);
+        assert_eq!(cpu.status, CpuFlags::empty());
     }
 
     #[test]
     fn test_and_occurs_register_a_0() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x29, 0x00]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x29, 0x00]).unwrap();
         cpu.reset();
         cpu.register_a = 0x01;
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x00);
-        assert_eq!(cpu.status, 0x02);
+        assert_eq!(cpu.status, CpuFlags::ZERO);
     }
 
     #[test]
     fn test_asl_immediate() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x0A]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x0A]).unwrap();
         cpu.reset();
         cpu.register_a = 0x01;
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x02);
-        assert_eq!(cpu.status, 0x00);
+        assert_eq!(cpu.status, CpuFlags::empty());
     }
 
     #[test]
     fn test_asl_accumulate_occurs_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x0A]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x0A]).unwrap();
         cpu.reset();
         cpu.register_a = 0x80;
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x00);
-        assert_eq!(cpu.status, 0x03);
+        assert_eq!(cpu.status, CpuFlags::ZERO | CpuFlags::CARRY);
     }
 
     #[test]
     fn test_asl_zeropage() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x10, 0x01);
-        cpu.load(vec![0x16, 0x10]);
+        cpu.load(&[0x16, 0x10]).unwrap();
         cpu.reset();
-        cpu.status = 0;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.mem_read(0x10), 0x02);
-        assert_eq!(cpu.status, 0x00);
+        assert_eq!(cpu.status, CpuFlags::empty());
     }
 
     #[test]
     fn test_asl_register_x_occurs_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x10, 0x80);
-        cpu.load(vec![0x16, 0x10]);
+        cpu.load(&[0x16, 0x10]).unwrap();
         cpu.reset();
-        cpu.status = 0;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.mem_read(0x10), 0x00);
-        assert_eq!(cpu.status, 0x03);
+        assert_eq!(cpu.status, CpuFlags::ZERO | CpuFlags::CARRY);
     }
 
     #[test]
     fn test_bit_zero() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x00, 0x80);
-        cpu.load(vec![0x24, 0x00]);
+        cpu.load(&[0x24, 0x00]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_a = 0x01;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x2);
+        assert_eq!(cpu.status, CpuFlags::ZERO);
     }
 
     #[test]
     fn test_bit_zero_neg_overflow_flags() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x00, 0xc0);
-        cpu.load(vec![0x24, 0x00]);
+        cpu.load(&[0x24, 0x00]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_a = 0xc0;
         cpu.run();
 
-        assert_eq!(cpu.status, 0xc0);
+        assert_eq!(cpu.status, CpuFlags::NEGATIVE | CpuFlags::OVERFLOW);
     }
 
     #[test]
     fn test_cmp_registera_larger() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xC9, 0x00]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xC9, 0x00]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_a = 0x01;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x01);
+        assert_eq!(cpu.status, CpuFlags::CARRY);
     }
 
     #[test]
     fn test_cmp_registera_equal() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xC9, 0x01]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xC9, 0x01]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_a = 0x01;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x03);
+        assert_eq!(cpu.status, CpuFlags::ZERO | CpuFlags::CARRY);
     }
 
     #[test]
     fn test_cmp_registera_smaller() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xC9, 0x01]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xC9, 0x01]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_a = 0x00;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x80);
+        assert_eq!(cpu.status, CpuFlags::NEGATIVE);
     }
 
     #[test]
     fn test_cmp_registerx_larger() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xE0, 0x00]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xE0, 0x00]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_x = 0x01;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x01);
+        assert_eq!(cpu.status, CpuFlags::CARRY);
     }
 
     #[test]
     fn test_cmp_registerx_equal() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xE0, 0x01]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xE0, 0x01]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_x = 0x01;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x03);
+        assert_eq!(cpu.status, CpuFlags::ZERO | CpuFlags::CARRY);
     }
 
     #[test]
     fn test_cmp_registerx_smaller() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xE0, 0x01]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xE0, 0x01]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_x = 0x00;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x80);
+        assert_eq!(cpu.status, CpuFlags::NEGATIVE);
     }
 
     #[test]
     fn test_cmp_registery_larger() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xC0, 0x00]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xC0, 0x00]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_y = 0x01;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x01);
+        assert_eq!(cpu.status, CpuFlags::CARRY);
     }
 
     #[test]
     fn test_cmp_registery_equal() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xC0, 0x01]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xC0, 0x01]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_y = 0x01;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x03);
+        assert_eq!(cpu.status, CpuFlags::ZERO | CpuFlags::CARRY);
     }
 
     #[test]
     fn test_cmp_registery_smaller() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xC0, 0x01]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xC0, 0x01]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_y = 0x00;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x80);
+        assert_eq!(cpu.status, CpuFlags::NEGATIVE);
     }
     #[test]
     fn test_dec() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x00, 0x01);
-        cpu.load(vec![0xC6, 0x00]);
+        cpu.load(&[0xC6, 0x00]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
-        assert_eq!(cpu.status, 0x02);
+        assert_eq!(cpu.status, CpuFlags::ZERO);
     }
     #[test]
     fn test_dex() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xCA]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xCA]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_x = 0x01;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x02);
+        assert_eq!(cpu.status, CpuFlags::ZERO);
     }
     #[test]
     fn test_dey() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x88]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x88]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_y = 0x01;
         cpu.run();
 
-        assert_eq!(cpu.status, 0x02);
+        assert_eq!(cpu.status, CpuFlags::ZERO);
     }
     #[test]
     fn test_eor() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x49, 0x80]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x49, 0x80]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_a = 0x01;
         cpu.run();
 
@@ -1213,21 +3239,21 @@ mod test {
 
     #[test]
     fn test_inc() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x00, 0x01);
-        cpu.load(vec![0xE6, 0x00]);
+        cpu.load(&[0xE6, 0x00]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.mem_read(0x00), 0x02);
     }
     #[test]
     fn test_inx() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xE8]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xE8]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_x = 0x01;
         cpu.run();
 
@@ -1235,10 +3261,10 @@ mod test {
     }
     #[test]
     fn test_iny() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xC8]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xC8]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_y = 0x01;
         cpu.run();
 
@@ -1246,35 +3272,35 @@ mod test {
     }
     #[test]
     fn test_lsr_accumulator() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x4A]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x4A]).unwrap();
         cpu.reset();
         cpu.register_a = 0x3;
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x01);
-        assert_eq!(cpu.status, 0x01);
+        assert_eq!(cpu.status, CpuFlags::CARRY);
     }
 
     #[test]
     fn test_lsr_zeropage() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x10, 0x02);
-        cpu.load(vec![0x46, 0x10]);
+        cpu.load(&[0x46, 0x10]).unwrap();
         cpu.reset();
-        cpu.status = 0;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.mem_read(0x10), 0x01);
-        assert_eq!(cpu.status, 0x00);
+        assert_eq!(cpu.status, CpuFlags::empty());
     }
     #[test]
     fn test_ora() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x09, 0x02]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x09, 0x02]).unwrap();
         cpu.reset();
-        cpu.status = 0x00;
+        cpu.status = CpuFlags::empty();
         cpu.register_a = 0x01;
         cpu.run();
 
@@ -1283,123 +3309,129 @@ mod test {
 
     #[test]
     fn test_rol_accumulator() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x2A]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x2A]).unwrap();
         cpu.reset();
         cpu.register_a = 0b0000_0010;
-        cpu.status = 0x01;
+        cpu.status = CpuFlags::CARRY;
         cpu.run();
 
         assert_eq!(cpu.register_a, 0b0000_0101);
-        assert_eq!(cpu.status, 0x00);
+        assert_eq!(cpu.status, CpuFlags::empty());
     }
 
     #[test]
     fn test_rol_zeropage() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x10, 0b0000_0001);
-        cpu.load(vec![0x26, 0x10]);
+        cpu.load(&[0x26, 0x10]).unwrap();
         cpu.reset();
-        cpu.status = 0x01;
+        cpu.status = CpuFlags::CARRY;
         cpu.run();
 
         assert_eq!(cpu.mem_read(0x10), 0x03);
-        assert_eq!(cpu.status, 0x00);
+        assert_eq!(cpu.status, CpuFlags::empty());
     }
 
     #[test]
     fn test_ror_accumulator() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x6A]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x6A]).unwrap();
         cpu.reset();
         cpu.register_a = 0b1000_0000;
-        cpu.status = 0x01;
+        cpu.status = CpuFlags::CARRY;
         cpu.run();
 
         assert_eq!(cpu.register_a, 0b1100_0000);
-        assert_eq!(cpu.status, 0x81);
+        assert_eq!(cpu.status, CpuFlags::NEGATIVE | CpuFlags::CARRY);
     }
 
     #[test]
     fn test_ror_zeropage() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
+        let mut cpu = CPU::new(FlatRam::new());
         cpu.mem_write(0x10, 0b1000_0000);
-        cpu.load(vec![0x66, 0x10]);
+        cpu.load(&[0x66, 0x10]).unwrap();
         cpu.reset();
-        cpu.status = 0x01;
+        cpu.status = CpuFlags::CARRY;
         cpu.run();
 
         assert_eq!(cpu.mem_read(0x10), 0b1100_0000);
-        // assert_eq!(cpu.status, 0x81);
+        // assert_eq!(cpu.status, CpuFlags::NEGATIVE | CpuFlags::CARRY);
     }
 
     #[test]
     fn test_sbc_no_carry_and_no_overflow() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xE9, 0xf0]);
-        cpu.reset();
-        cpu.register_a = 0x50;
-        cpu.status = 0x00;
+        let mut cpu = CpuBuilder::new()
+            .a(0x50)
+            .status(CpuFlags::empty())
+            .program(&[0xE9, 0xf0])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x5f);
-        assert_eq!(cpu.status, 0b0000_0000);
+        assert_eq!(cpu.status, CpuFlags::RESERVED);
     }
 
     #[test]
     fn test_sbc_has_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xE9, 0xf0]);
-        cpu.reset();
-        cpu.register_a = 0x50;
-        cpu.status = 0x01;
+        let mut cpu = CpuBuilder::new()
+            .a(0x50)
+            .status(CpuFlags::CARRY)
+            .program(&[0xE9, 0xf0])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x60);
-        assert_eq!(cpu.status, 0b0000_0000);
+        assert_eq!(cpu.status, CpuFlags::RESERVED);
     }
 
     #[test]
     fn test_sbc_occurs_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xE9, 0x30]);
-        cpu.reset();
-        cpu.register_a = 0x50;
-        cpu.status = 0x00;
+        let mut cpu = CpuBuilder::new()
+            .a(0x50)
+            .status(CpuFlags::empty())
+            .program(&[0xE9, 0x30])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x1F);
-        assert_eq!(cpu.status, 0b0000_0001);
+        assert_eq!(cpu.status, CpuFlags::CARRY | CpuFlags::RESERVED);
     }
 
     #[test]
     fn test_sbc_occurs_overflow_plus() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xE9, 0xb0]);
-        cpu.reset();
-        cpu.register_a = 0x50;
-        cpu.status = 0x00;
+        let mut cpu = CpuBuilder::new()
+            .a(0x50)
+            .status(CpuFlags::empty())
+            .program(&[0xE9, 0xb0])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0x9f);
-        assert_eq!(cpu.status, 0b1100_0000);
+        assert_eq!(
+            cpu.status,
+            CpuFlags::NEGATIVE | CpuFlags::OVERFLOW | CpuFlags::RESERVED
+        );
     }
     #[test]
     fn test_sbc_occurs_overflow_plus_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xE9, 0xb0]);
-        cpu.reset();
-        cpu.register_a = 0x50;
-        cpu.status = 0x01;
+        let mut cpu = CpuBuilder::new()
+            .a(0x50)
+            .status(CpuFlags::CARRY)
+            .program(&[0xE9, 0xb0])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_a, 0xa0);
-        assert_eq!(cpu.status, 0b1100_0000);
+        assert_eq!(
+            cpu.status,
+            CpuFlags::NEGATIVE | CpuFlags::OVERFLOW | CpuFlags::RESERVED
+        );
     }
     #[test]
     fn test_pha() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x48]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x48]).unwrap();
         cpu.reset();
         cpu.register_a = 0xff;
         cpu.run();
@@ -1411,10 +3443,10 @@ mod test {
     }
     #[test]
     fn test_php() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x08]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x08]).unwrap();
         cpu.reset();
-        cpu.status = 0xff;
+        cpu.status = CpuFlags::all();
         cpu.run();
 
         assert_eq!(
@@ -1423,279 +3455,395 @@ mod test {
         );
     }
     #[test]
+    fn test_php_pushes_break_and_reserved() {
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x08]).unwrap();
+        cpu.reset();
+        cpu.status = CpuFlags::ZERO;
+        cpu.run();
+
+        assert_eq!(
+            cpu.mem_read(STACK + cpu.stack_pointer.wrapping_add(1) as u16),
+            0x32
+        );
+    }
+    #[test]
+    fn test_php_plp_round_trip_keeps_zero() {
+        // LDA #$00; PHP; LDA #$01; PLP
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xa9, 0x00, 0x08, 0xa9, 0x01, 0x28]).unwrap();
+        cpu.reset();
+        cpu.run();
+
+        assert_eq!(
+            cpu.status,
+            CpuFlags::ZERO | CpuFlags::INTERRUPT_DISABLE | CpuFlags::RESERVED
+        );
+        assert_eq!(cpu.register_a, 0x01);
+    }
+    #[test]
+    fn test_plp_takes_flags_from_the_stack_without_break() {
+        // LDA #$D3; PHA; LDA #$00; PLP
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0xa9, 0xd3, 0x48, 0xa9, 0x00, 0x28]).unwrap();
+        cpu.reset();
+        cpu.run();
+
+        assert_eq!(
+            cpu.status,
+            CpuFlags::NEGATIVE
+                | CpuFlags::OVERFLOW
+                | CpuFlags::RESERVED
+                | CpuFlags::ZERO
+                | CpuFlags::CARRY
+        );
+    }
+    #[test]
     fn test_clc_cld_cli_clv() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x18, 0xD8, 0x58, 0xB8]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x18, 0xD8, 0x58, 0xB8]).unwrap();
         cpu.reset();
-        cpu.status = 0b0100_1101;
+        cpu.status = CpuFlags::OVERFLOW
+            | CpuFlags::DECIMAL_MODE
+            | CpuFlags::INTERRUPT_DISABLE
+            | CpuFlags::CARRY;
         cpu.run();
 
-        assert_eq!(cpu.status, 0);
+        assert_eq!(cpu.status, CpuFlags::empty());
     }
     #[test]
     fn test_sec_sed_sei() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x38, 0xF8, 0x78]);
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load(&[0x38, 0xF8, 0x78]).unwrap();
         cpu.reset();
-        cpu.status = 0;
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
-        assert_eq!(cpu.status, 0b0000_1101);
+        assert_eq!(
+            cpu.status,
+            CpuFlags::DECIMAL_MODE | CpuFlags::INTERRUPT_DISABLE | CpuFlags::CARRY
+        );
     }
     #[test]
     fn test_bcc() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x90, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0000;
+        let mut cpu = CpuBuilder::new()
+            .status(CpuFlags::empty())
+            .program(&[0x90, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bcc_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x90, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0001;
+        let mut cpu = CpuBuilder::new()
+            .carry(true)
+            .program(&[0x90, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 0);
     }
     #[test]
     fn test_bcc_negative_value() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x90, 0xfc]);
-        cpu.reset();
-        cpu.mem_write(0x7FFF, 0x00);
-        cpu.mem_write(0x7FFE, 0xe8);
-        cpu.status = 0b0000_0000;
+        let mut cpu = cpu_with_backward_branch(0x90);
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bcs() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xB0, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0000;
+        let mut cpu = CpuBuilder::new()
+            .status(CpuFlags::empty())
+            .program(&[0xB0, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 0);
     }
     #[test]
     fn test_bcs_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xB0, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0001;
+        let mut cpu = CpuBuilder::new()
+            .carry(true)
+            .program(&[0xB0, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bcs_negative_value() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xB0, 0xFC]);
-        cpu.reset();
-        cpu.mem_write_u16(0x7FFF, 0x00);
-        cpu.mem_write_u16(0x7FFE, 0xe8);
-        cpu.status = 0b0000_0001;
+        let mut cpu = cpu_with_backward_branch(0xB0);
+        cpu.status = CpuFlags::CARRY;
         cpu.run();
 
-        assert_eq!(cpu.register_x, 0);
+        assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_beq() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xF0, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0010;
+        let mut cpu = CpuBuilder::new()
+            .zero(true)
+            .program(&[0xF0, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_beq_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xF0, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0000;
+        let mut cpu = CpuBuilder::new()
+            .status(CpuFlags::empty())
+            .program(&[0xF0, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 0);
     }
     #[test]
     fn test_beq_negative_value() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xF0, 0xFC]);
-        cpu.reset();
-        cpu.mem_write(0x7FFF, 0x00);
-        cpu.mem_write(0x7FFE, 0xE8);
-        cpu.status = 0b0000_0010;
+        let mut cpu = cpu_with_backward_branch(0xF0);
+        cpu.status = CpuFlags::ZERO;
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bmi() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x30, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0000;
+        let mut cpu = CpuBuilder::new()
+            .status(CpuFlags::empty())
+            .program(&[0x30, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 0);
     }
     #[test]
     fn test_bmi_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x30, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b1000_0000;
+        let mut cpu = CpuBuilder::new()
+            .negative(true)
+            .program(&[0x30, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bmi_negative_value() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x30, 0xFC]);
-        cpu.reset();
-        cpu.mem_write(0x7FFF, 0x00);
-        cpu.mem_write(0x7FFE, 0xE8);
-        cpu.status = 0b1000_0000;
+        let mut cpu = cpu_with_backward_branch(0x30);
+        cpu.status = CpuFlags::NEGATIVE;
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bne() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xD0, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0000;
+        let mut cpu = CpuBuilder::new()
+            .status(CpuFlags::empty())
+            .program(&[0xD0, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bne_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xD0, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0010;
+        let mut cpu = CpuBuilder::new()
+            .zero(true)
+            .program(&[0xD0, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 0);
     }
     #[test]
     fn test_bne_negative_value() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0xD0, 0xFC]);
-        cpu.reset();
-        cpu.mem_write(0x7FFF, 0x00);
-        cpu.mem_write(0x7FFE, 0xE8);
-        cpu.status = 0b0000_0000;
+        let mut cpu = cpu_with_backward_branch(0xD0);
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bpl() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x10, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0000;
+        let mut cpu = CpuBuilder::new()
+            .status(CpuFlags::empty())
+            .program(&[0x10, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bpl_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x10, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b1000_0000;
+        let mut cpu = CpuBuilder::new()
+            .negative(true)
+            .program(&[0x10, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 0);
     }
     #[test]
     fn test_bpl_negative_value() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x10, 0xFC]);
-        cpu.reset();
-        cpu.mem_write(0x7FFF, 0x00);
-        cpu.mem_write(0x7FFE, 0xE8);
-        cpu.status = 0b0000_0000;
+        let mut cpu = cpu_with_backward_branch(0x10);
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bvc() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x50, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0000;
+        let mut cpu = CpuBuilder::new()
+            .status(CpuFlags::empty())
+            .program(&[0x50, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bvc_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x50, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0100_0000;
+        let mut cpu = CpuBuilder::new()
+            .overflow(true)
+            .program(&[0x50, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 0);
     }
     #[test]
     fn test_bvc_negative_value() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x50, 0xFC]);
-        cpu.reset();
-        cpu.mem_write(0x7FFF, 0x00);
-        cpu.mem_write(0x7FFE, 0xE8);
-        cpu.status = 0b0000_0000;
+        let mut cpu = cpu_with_backward_branch(0x50);
+        cpu.status = CpuFlags::empty();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bvs() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x70, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0000_0000;
+        let mut cpu = CpuBuilder::new()
+            .status(CpuFlags::empty())
+            .program(&[0x70, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 0);
     }
     #[test]
     fn test_bvs_with_carry() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x70, 0x02, 0x00, 0x00, 0xE8, 0x00]);
-        cpu.reset();
-        cpu.status = 0b0100_0000;
+        let mut cpu = CpuBuilder::new()
+            .overflow(true)
+            .program(&[0x70, 0x02, 0x00, 0x00, 0xE8, 0x00])
+            .build();
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
     #[test]
     fn test_bvs_negative_value() {
-         let mut cpu = CPU::new(Bus::new(Rom::empty()));
-        cpu.load(vec![0x70, 0xFC]);
-        cpu.reset();
-        cpu.mem_write(0x7FFF, 0x00);
-        cpu.mem_write(0x7FFE, 0xE8);
-        cpu.status = 0b0100_0000;
+        let mut cpu = cpu_with_backward_branch(0x70);
+        cpu.status = CpuFlags::OVERFLOW;
         cpu.run();
 
         assert_eq!(cpu.register_x, 1);
     }
 }
+
+#[cfg(test)]
+mod arithmetic_test {
+    use super::*;
+
+    const OPERAND_ADDR: u16 = 0x0010;
+
+    struct Expected {
+        result: u8,
+        carry: u8,
+        overflow: u8,
+    }
+
+    // Textbook 6502 binary addition: A + M + C, with V set when both operands
+    // share a sign that differs from the sign of the result.
+    fn expected_adc(a: u8, operand: u8, carry: u8) -> Expected {
+        let sum = a as u16 + operand as u16 + carry as u16;
+        // V from the signed sum, not from the sign bits the CPU looks at
+        let signed = a as i8 as i16 + operand as i8 as i16 + carry as i16;
+        Expected {
+            result: sum as u8,
+            carry: if sum > 0xFF { 1 } else { 0 },
+            overflow: if (-128..=127).contains(&signed) { 0 } else { 1 },
+        }
+    }
+
+    // a borrow clears the carry
+    fn expected_sbc(a: u8, operand: u8, carry: u8) -> Expected {
+        let borrow = 1 - carry as i16;
+        let difference = a as i16 - operand as i16 - borrow;
+        let signed = a as i8 as i16 - operand as i8 as i16 - borrow;
+        Expected {
+            result: difference as u8,
+            carry: if difference >= 0 { 1 } else { 0 },
+            overflow: if (-128..=127).contains(&signed) { 0 } else { 1 },
+        }
+    }
+
+    fn execute(a: u8, operand: u8, carry: u8, op: fn(&mut CPU<FlatRam>, u16)) -> CPU<FlatRam> {
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.mem_write(OPERAND_ADDR, operand);
+        cpu.register_a = a;
+        cpu.status = CpuFlags::empty();
+        cpu.set_carry(carry == 1);
+        // an immediate operand's address is the byte itself
+        op(&mut cpu, OPERAND_ADDR);
+        cpu
+    }
+
+    fn assert_matches(cpu: &CPU<FlatRam>, expected: &Expected, label: &str) {
+        assert_eq!(cpu.register_a, expected.result, "{} result", label);
+        assert_eq!(cpu.carry() as u8, expected.carry, "{} carry", label);
+        assert_eq!(
+            cpu.overflow() as u8,
+            expected.overflow,
+            "{} overflow",
+            label
+        );
+        assert_eq!(
+            cpu.zero() as u8,
+            if expected.result == 0 { 1 } else { 0 },
+            "{} zero",
+            label
+        );
+        assert_eq!(
+            cpu.negative() as u8,
+            expected.result >> 7,
+            "{} negative",
+            label
+        );
+    }
+
+    #[test]
+    fn test_adc_exhaustive() {
+        for a in 0..=0xFFu8 {
+            for operand in 0..=0xFFu8 {
+                for carry in 0..=1u8 {
+                    let cpu = execute(a, operand, carry, CPU::adc);
+                    let label = format!("ADC a={:#04x} m={:#04x} c={}", a, operand, carry);
+                    assert_matches(&cpu, &expected_adc(a, operand, carry), &label);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sbc_exhaustive() {
+        for a in 0..=0xFFu8 {
+            for operand in 0..=0xFFu8 {
+                for carry in 0..=1u8 {
+                    let cpu = execute(a, operand, carry, CPU::sbc);
+                    let label = format!("SBC a={:#04x} m={:#04x} c={}", a, operand, carry);
+                    assert_matches(&cpu, &expected_sbc(a, operand, carry), &label);
+                }
+            }
+        }
+    }
+}
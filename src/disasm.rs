@@ -0,0 +1,393 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cartridge::Rom;
+use crate::cpu::{AddressingMode, Mem};
+use crate::opcodes::{self, OpCode};
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// One instruction, or the bytes that could not be decoded as one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    /// Names of the interrupt vectors that point at `addr`.
+    pub labels: Vec<&'static str>,
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for label in &self.labels {
+            writeln!(f, "{}:", label)?;
+        }
+        let hex_str = self
+            .bytes
+            .iter()
+            .map(|z| format!("{:02X}", z))
+            .collect::<Vec<String>>()
+            .join(" ");
+        write!(f, "{:04X}  {:8}  {}", self.addr, hex_str, self.text)
+    }
+}
+
+/// Disassembles the single instruction at `addr`.
+pub fn disassemble<M: Mem>(mem: &M, addr: u16) -> DisasmLine {
+    disassemble_bounded(mem, addr, 0xFFFF)
+}
+
+/// Disassembles `start..=end` linearly. An instruction that would run past
+/// `end` is listed as data instead.
+pub fn disassemble_range<M: Mem>(mem: &M, start: u16, end: u16) -> Vec<DisasmLine> {
+    let mut lines = vec![];
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        let line = disassemble_bounded(mem, addr as u16, end);
+        addr += line.bytes.len() as u32;
+        lines.push(line);
+    }
+    lines
+}
+
+/// Disassembles only the code reachable from `entry_points` by following
+/// fall-through, branches, JMP and JSR. Indirect jumps end a path.
+pub fn disassemble_reachable<M: Mem>(mem: &M, entry_points: &[u16]) -> Vec<DisasmLine> {
+    let mut found: BTreeMap<u16, DisasmLine> = BTreeMap::new();
+    let mut pending = entry_points.to_vec();
+
+    while let Some(addr) = pending.pop() {
+        if found.contains_key(&addr) {
+            continue;
+        }
+        let line = disassemble(mem, addr);
+        if let Some(ops) = opcodes::lookup(line.bytes[0]) {
+            if line.bytes.len() == ops.len as usize {
+                pending.extend(successors(ops, addr, &line.bytes[1..]));
+            }
+        }
+        found.insert(addr, line);
+    }
+    found.into_values().collect()
+}
+
+/// Lists a cartridge's PRG ROM as it appears at 0x8000-0xFFFF, starting at
+/// the reset vector, with the vector targets labelled. With `reachable_only`
+/// only code reachable from the vectors is listed.
+pub fn disassemble_rom(rom: &Rom, reachable_only: bool) -> Vec<DisasmLine> {
+    let prg = PrgView { rom };
+    let vectors = [
+        ("NMI", prg.mem_peek_u16(NMI_VECTOR)),
+        ("RESET", prg.mem_peek_u16(RESET_VECTOR)),
+        ("IRQ", prg.mem_peek_u16(IRQ_VECTOR)),
+    ];
+
+    let mut lines = if reachable_only {
+        let entry_points: Vec<u16> = vectors.iter().map(|(_, target)| *target).collect();
+        disassemble_reachable(&prg, &entry_points)
+    } else {
+        let reset = vectors[1].1.max(0x8000);
+        if reset < NMI_VECTOR {
+            disassemble_range(&prg, reset, NMI_VECTOR - 1)
+        } else {
+            vec![]
+        }
+    };
+    for line in lines.iter_mut() {
+        for &(name, target) in vectors.iter() {
+            if line.addr == target {
+                line.labels.push(name);
+            }
+        }
+    }
+    if !reachable_only {
+        for (name, vector) in [
+            ("NMI", NMI_VECTOR),
+            ("RESET", RESET_VECTOR),
+            ("IRQ", IRQ_VECTOR),
+        ] {
+            let target = prg.mem_peek_u16(vector);
+            lines.push(DisasmLine {
+                addr: vector,
+                bytes: vec![target as u8, (target >> 8) as u8],
+                text: format!(".word ${:04X} ; {}", target, name),
+                labels: vec![],
+            });
+        }
+    }
+    lines
+}
+
+/// Joins lines into a listing suitable for writing to a file.
+pub fn listing(lines: &[DisasmLine]) -> String {
+    lines
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect::<String>()
+}
+
+fn disassemble_bounded<M: Mem>(mem: &M, addr: u16, end: u16) -> DisasmLine {
+    let code = mem.mem_peek(addr);
+    let available = (end as u32 - addr as u32 + 1).min(3) as u8;
+    let ops = match opcodes::lookup(code) {
+        Some(ops) if ops.len <= available => ops,
+        Some(ops) => {
+            // the range ends inside this instruction
+            let bytes: Vec<u8> = (0..available as u16)
+                .map(|i| mem.mem_peek(addr.wrapping_add(i)))
+                .collect();
+            return data_line(addr, bytes, ops);
+        }
+        None => {
+            return DisasmLine {
+                addr,
+                bytes: vec![code],
+                text: format!(".byte ${:02X}", code),
+                labels: vec![],
+            }
+        }
+    };
+
+    let bytes: Vec<u8> = (0..ops.len as u16)
+        .map(|i| mem.mem_peek(addr.wrapping_add(i)))
+        .collect();
+    let text = format!(
+        "{} {}",
+        ops.mnemonic,
+        format_operand(ops, addr, &bytes[1..])
+    )
+    .trim_end()
+    .to_string();
+    DisasmLine {
+        addr,
+        bytes,
+        text,
+        labels: vec![],
+    }
+}
+
+fn data_line(addr: u16, bytes: Vec<u8>, ops: &OpCode) -> DisasmLine {
+    let text = format!(
+        ".byte {} ; truncated {}",
+        bytes
+            .iter()
+            .map(|b| format!("${:02X}", b))
+            .collect::<Vec<String>>()
+            .join(","),
+        ops.mnemonic
+    );
+    DisasmLine {
+        addr,
+        bytes,
+        text,
+        labels: vec![],
+    }
+}
+
+// the operand as written in assembly, e.g. `($20),Y`; traces add the
+// addresses and values it resolved to
+pub(crate) fn format_operand(ops: &OpCode, addr: u16, operands: &[u8]) -> String {
+    match operands.len() {
+        0 => match ops.mode {
+            AddressingMode::Accumulator => String::from("A"),
+            _ => String::from(""),
+        },
+        1 => {
+            let value = operands[0];
+            match ops.mode {
+                AddressingMode::Immediate => format!("#${:02X}", value),
+                AddressingMode::ZeroPage => format!("${:02X}", value),
+                AddressingMode::ZeroPage_X => format!("${:02X},X", value),
+                AddressingMode::ZeroPage_Y => format!("${:02X},Y", value),
+                AddressingMode::Indirect_X => format!("(${:02X},X)", value),
+                AddressingMode::Indirect_Y => format!("(${:02X}),Y", value),
+                AddressingMode::Relative => format!("${:04X}", branch_target(addr, value)),
+                _ => format!("${:02X}", value),
+            }
+        }
+        _ => {
+            let value = (operands[1] as u16) << 8 | (operands[0] as u16);
+            match ops.mode {
+                AddressingMode::Absolute_X => format!("${:04X},X", value),
+                AddressingMode::Absolute_Y => format!("${:04X},Y", value),
+                AddressingMode::Indirect => format!("(${:04X})", value),
+                _ => format!("${:04X}", value),
+            }
+        }
+    }
+}
+
+fn branch_target(addr: u16, offset: u8) -> u16 {
+    addr.wrapping_add(2).wrapping_add(offset as i8 as u16)
+}
+
+// where execution can continue after the instruction at `addr`
+fn successors(ops: &OpCode, addr: u16, operands: &[u8]) -> Vec<u16> {
+    let next = addr.wrapping_add(ops.len as u16);
+    if let AddressingMode::Relative = ops.mode {
+        return vec![branch_target(addr, operands[0]), next];
+    }
+    match ops.code {
+        /* BRK, RTI, RTS, JMP indirect */
+        0x00 | 0x40 | 0x60 | 0x6c => vec![],
+        /* JMP */
+        0x4c => vec![(operands[1] as u16) << 8 | (operands[0] as u16)],
+        /* JSR */
+        0x20 => vec![(operands[1] as u16) << 8 | (operands[0] as u16), next],
+        _ => vec![next],
+    }
+}
+
+// PRG ROM mapped the way the bus maps it, without taking ownership of the Rom
+struct PrgView<'a> {
+    rom: &'a Rom,
+}
+
+impl Mem for PrgView<'_> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.mem_peek(addr)
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        if addr < 0x8000 || self.rom.prg_rom.is_empty() {
+            return 0;
+        }
+        let offset = (addr - 0x8000) as usize % self.rom.prg_rom.len();
+        self.rom.prg_rom[offset]
+    }
+
+    fn mem_write(&mut self, _addr: u16, _data: u8) {
+        // PRG ROM is read-only
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    fn bus_with_program(origin: u16, program: &[u8]) -> Bus {
+        let mut bus = Bus::new(Rom::empty());
+        for (i, byte) in program.iter().enumerate() {
+            bus.mem_write(origin + i as u16, *byte);
+        }
+        bus
+    }
+
+    fn rom_with_code(code: &[(u16, &[u8])]) -> Rom {
+        let mut rom = Rom::empty();
+        rom.prg_rom = vec![0xff; 0x8000];
+        for (addr, bytes) in code {
+            let start = (addr - 0x8000) as usize;
+            rom.prg_rom[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        rom
+    }
+
+    #[test]
+    fn test_disassemble_range_lists_each_mode() {
+        let bus = bus_with_program(
+            0x0600,
+            &[
+                0xa9, 0x05, // LDA #$05
+                0x95, 0x10, // STA $10,X
+                0xbd, 0x00, 0x02, // LDA $0200,X
+                0xb1, 0x33, // LDA ($33),Y
+                0x0a, // ASL A
+                0xd0, 0xf4, // BNE $0600
+                0x6c, 0x00, 0x03, // JMP ($0300)
+                0xff, // not an opcode
+            ],
+        );
+
+        let expected = "\
+0600  A9 05     LDA #$05
+0602  95 10     STA $10,X
+0604  BD 00 02  LDA $0200,X
+0607  B1 33     LDA ($33),Y
+0609  0A        ASL A
+060A  D0 F4     BNE $0600
+060C  6C 00 03  JMP ($0300)
+060F  FF        .byte $FF
+";
+        assert_eq!(listing(&disassemble_range(&bus, 0x0600, 0x060f)), expected);
+    }
+
+    #[test]
+    fn test_disassemble_range_stops_mid_instruction() {
+        // LDA #$05; STA $0200 cut after its first operand byte
+        let bus = bus_with_program(0x0600, &[0xa9, 0x05, 0x8d, 0x00, 0x02]);
+
+        let lines = disassemble_range(&bus, 0x0600, 0x0603);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].addr, 0x0602);
+        assert_eq!(lines[1].bytes, vec![0x8d, 0x00]);
+        assert_eq!(lines[1].text, ".byte $8D,$00 ; truncated STA");
+    }
+
+    #[test]
+    fn test_disassemble_rom_labels_vectors() {
+        let rom = rom_with_code(&[
+            // reset: LDX #$00; INX; JMP $8002
+            (0x8000, &[0xa2, 0x00, 0xe8, 0x4c, 0x02, 0x80]),
+            // nmi: RTI
+            (0x8006, &[0x40]),
+            (0xfffa, &[0x06, 0x80, 0x00, 0x80, 0x06, 0x80]),
+        ]);
+
+        let expected = "\
+RESET:
+8000  A2 00     LDX #$00
+8002  E8        INX
+8003  4C 02 80  JMP $8002
+NMI:
+IRQ:
+8006  40        RTI
+";
+        assert_eq!(listing(&disassemble_rom(&rom, true)), expected);
+    }
+
+    #[test]
+    fn test_disassemble_rom_follows_branches_and_subroutines() {
+        let rom = rom_with_code(&[
+            // reset: JSR $8010; BEQ $8008; JMP $8000
+            (0x8000, &[0x20, 0x10, 0x80, 0xf0, 0x03, 0x4c, 0x00, 0x80]),
+            // NOP; RTS
+            (0x8008, &[0xea, 0x60]),
+            // subroutine: LDA #$00; RTS
+            (0x8010, &[0xa9, 0x00, 0x60]),
+            (0xfffa, &[0x08, 0x80, 0x00, 0x80, 0x08, 0x80]),
+        ]);
+
+        let addrs: Vec<u16> = disassemble_rom(&rom, true)
+            .iter()
+            .map(|line| line.addr)
+            .collect();
+        assert_eq!(
+            addrs,
+            vec![0x8000, 0x8003, 0x8005, 0x8008, 0x8009, 0x8010, 0x8012]
+        );
+    }
+
+    #[test]
+    fn test_linear_rom_listing_ends_with_vectors() {
+        let rom = rom_with_code(&[
+            (0xfff8, &[0xea, 0xea]),
+            (0xfffa, &[0xf8, 0xff, 0xf8, 0xff, 0xf9, 0xff]),
+        ]);
+
+        let expected = "\
+NMI:
+RESET:
+FFF8  EA        NOP
+IRQ:
+FFF9  EA        NOP
+FFFA  F8 FF     .word $FFF8 ; NMI
+FFFC  F8 FF     .word $FFF8 ; RESET
+FFFE  F9 FF     .word $FFF9 ; IRQ
+";
+        assert_eq!(listing(&disassemble_rom(&rom, false)), expected);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::cpu::Mem;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// 64KB of plain RAM with nothing mapped into it, for running the CPU core
+/// without the NES bus, e.g. on generic 6502 programs. Code and vectors can
+/// go anywhere; `CPU<FlatRam>` is what `CPU<Bus>` is for the NES.
+pub struct FlatRam([u8; 0x10000]);
+
+impl FlatRam {
+    pub fn new() -> Self {
+        FlatRam([0; 0x10000])
+    }
+
+    /// Zeroed RAM with `data` copied in from `origin`, wrapping past 0xFFFF.
+    pub fn from_bytes(origin: u16, data: &[u8]) -> Self {
+        let mut ram = FlatRam::new();
+        ram.mem_write_slice(origin, data);
+        ram
+    }
+
+    pub fn set_reset_vector(&mut self, addr: u16) {
+        self.mem_write_u16(RESET_VECTOR, addr);
+    }
+
+    pub fn set_nmi_vector(&mut self, addr: u16) {
+        self.mem_write_u16(NMI_VECTOR, addr);
+    }
+
+    pub fn set_irq_vector(&mut self, addr: u16) {
+        self.mem_write_u16(IRQ_VECTOR, addr);
+    }
+}
+
+impl Default for FlatRam {
+    fn default() -> Self {
+        FlatRam::new()
+    }
+}
+
+impl Mem for FlatRam {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.0[addr as usize] = data;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn test_cpu_runs_on_flat_ram() {
+        // LDA #$42; STA $FFF0; BRK
+        let mut ram = FlatRam::from_bytes(0xc000, &[0xa9, 0x42, 0x8d, 0xf0, 0xff, 0x00]);
+        ram.set_reset_vector(0xc000);
+
+        let mut cpu = CPU::new(ram);
+        cpu.reset();
+        cpu.run();
+
+        assert_eq!(cpu.bus.mem_read(0xfff0), 0x42);
+        assert_eq!(cpu.program_counter, 0xc006);
+    }
+
+    #[test]
+    fn test_program_and_reset_vector_in_low_ram() {
+        // LDX #$00; loop: INX; STX $10; CPX #$05; BNE loop; BRK
+        let program = [0xa2, 0x00, 0xe8, 0x86, 0x10, 0xe0, 0x05, 0xd0, 0xf9, 0x00];
+        let mut cpu = CPU::new(FlatRam::new());
+        cpu.load_at(0x0200, &program).unwrap();
+        cpu.set_reset_vector(0x0200);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x0200);
+
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x0010), 5);
+        assert_eq!(cpu.program_counter, 0x020a);
+    }
+
+    #[test]
+    fn test_vector_setters() {
+        let mut ram = FlatRam::new();
+        ram.set_nmi_vector(0x1234);
+        ram.set_reset_vector(0x5678);
+        ram.set_irq_vector(0x9abc);
+        let mut vectors = [0; 6];
+        ram.mem_read_into(0xfffa, &mut vectors);
+        assert_eq!(vectors, [0x34, 0x12, 0x78, 0x56, 0xbc, 0x9a]);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::cartridge::Rom;
+
+/// What the database knows about a dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameInfo {
+    pub title: &'static str,
+    pub region: &'static str,
+    /// The mapper the game really uses, which bad headers get wrong.
+    pub known_mapper: u16,
+}
+
+// by CRC-32 of PRG and CHR ROM, as `Rom::crc32` computes it
+const GAMES: &[(u32, GameInfo)] = &[(
+    0x862A_5C36,
+    GameInfo {
+        title: "Snake",
+        region: "World",
+        known_mapper: 0,
+    },
+)];
+
+/// Looks up a dump by the CRC-32 of its PRG and CHR ROM.
+pub fn lookup(crc32: u32) -> Option<GameInfo> {
+    GAMES
+        .iter()
+        .find(|(crc, _)| *crc == crc32)
+        .map(|(_, info)| *info)
+}
+
+impl Rom {
+    /// What the database knows about this dump, if anything.
+    pub fn identify(&self) -> Option<GameInfo> {
+        lookup(self.crc32())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    #[test]
+    fn test_identify_a_known_dump() {
+        let rom = Rom::from_file("snake.nes").unwrap();
+        let info = rom.identify().unwrap();
+        assert_eq!(info.title, "Snake");
+        assert_eq!(info.known_mapper, rom.mapper);
+    }
+
+    #[test]
+    fn test_identify_an_unknown_dump() {
+        assert_eq!(test_rom().build().identify(), None);
+        assert_eq!(lookup(0), None);
+    }
+}
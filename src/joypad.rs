@@ -0,0 +1,106 @@
+bitflags! {
+    /// The buttons of a standard controller, in the order it reports them.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct JoypadButton: u8 {
+        const BUTTON_A = 0b0000_0001;
+        const BUTTON_B = 0b0000_0010;
+        const SELECT   = 0b0000_0100;
+        const START    = 0b0000_1000;
+        const UP       = 0b0001_0000;
+        const DOWN     = 0b0010_0000;
+        const LEFT     = 0b0100_0000;
+        const RIGHT    = 0b1000_0000;
+    }
+}
+
+/// A standard controller. Writing 1 then 0 to its strobe latches the
+/// buttons into a shift register, which each read then shifts out one bit
+/// of, starting with A.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    buttons: JoypadButton,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            buttons: JoypadButton::empty(),
+        }
+    }
+
+    pub fn set_button(&mut self, button: JoypadButton, pressed: bool) {
+        self.buttons.set(button, pressed);
+    }
+
+    /// Releases the strobe and rewinds to A. Held buttons stay held.
+    pub fn reset(&mut self) {
+        self.strobe = false;
+        self.button_index = 0;
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    /// The next button's state in bit 0. Once all eight are out, reads
+    /// return 1, as official controllers do.
+    pub fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe && self.button_index < 8 {
+            self.button_index += 1;
+        }
+        bit
+    }
+
+    /// What `read` would return, without shifting.
+    pub fn peek(&self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        (self.buttons.bits() >> self.button_index) & 1
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strobe_held_keeps_reporting_a() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(JoypadButton::BUTTON_A, true);
+        joypad.write(1);
+        for _ in 0..10 {
+            assert_eq!(joypad.read(), 1);
+        }
+    }
+
+    #[test]
+    fn test_strobe_restarts_from_a() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(JoypadButton::BUTTON_B, true);
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 0);
+        assert_eq!(joypad.read(), 1);
+
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 0);
+        assert_eq!(joypad.read(), 1);
+    }
+}
@@ -0,0 +1,22 @@
+#[macro_use]
+extern crate bitflags;
+
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod disasm;
+pub mod flat_ram;
+pub mod frame;
+#[cfg(feature = "gamedb")]
+pub mod gamedb;
+pub mod hash;
+pub mod joypad;
+pub mod mirroring;
+pub mod opcodes;
+pub mod palette;
+pub mod ppu;
+pub mod profile;
+pub mod program;
+pub mod testing;
+pub mod trace;
@@ -1,12 +1,8 @@
-pub mod bus;
-pub mod cartridge;
-pub mod cpu;
-pub mod opcodes;
-
-use bus::Bus;
-use cartridge::Rom;
-use cpu::Mem;
-use cpu::CPU;
+use nes_rs::bus::Bus;
+use nes_rs::cartridge::Rom;
+use nes_rs::cpu::keep_running;
+use nes_rs::cpu::Mem;
+use nes_rs::cpu::{NesCpu, CPU};
 use rand::Rng;
 use sdl2::{
     event::Event,
@@ -15,9 +11,6 @@ use sdl2::{
     *,
 };
 
-#[macro_use]
-extern crate lazy_static;
-
 fn main() {
     // init sdl2
     let sdl_context = sdl2::init().unwrap();
@@ -38,10 +31,15 @@ fn main() {
         .unwrap();
 
     //load the game
-    let bytes: Vec<u8> = std::fs::read("snake.nes").unwrap();
-    let rom = Rom::new(&bytes).unwrap();
+    let mapper = match Rom::from_file("snake.nes").and_then(Rom::into_mapper) {
+        Ok(mapper) => mapper,
+        Err(err) => {
+            eprintln!("snake.nes: {}", err);
+            std::process::exit(1);
+        }
+    };
 
-    let bus = Bus::new(rom);
+    let bus = Bus::with_mapper(mapper);
     let mut cpu = CPU::new(bus);
     cpu.reset();
 
@@ -49,7 +47,7 @@ fn main() {
     let mut rng = rand::thread_rng();
 
     // run the game cycle
-    cpu.run_with_callback(move |cpu| {
+    let result = cpu.run_with_callback(keep_running(move |cpu| {
         handle_user_input(cpu, &mut event_pump);
 
         cpu.mem_write(0xfe, rng.gen_range(1, 16));
@@ -63,10 +61,15 @@ fn main() {
         }
 
         ::std::thread::sleep(std::time::Duration::new(0, 70_000));
-    });
+    }));
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 }
 
-fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
+fn handle_user_input(cpu: &mut NesCpu, event_pump: &mut EventPump) {
     for event in event_pump.poll_iter() {
         match event {
             Event::Quit { .. }
@@ -117,7 +120,7 @@ fn color(byte: u8) -> Color {
     }
 }
 
-fn read_screen_state(cpu: &mut CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
+fn read_screen_state(cpu: &mut NesCpu, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     let mut frame_idx = 0;
     let mut update = false;
     for i in 0x0200..0x600 {
@@ -0,0 +1,57 @@
+/// Folds `addr` back into the `size` bytes at `base`, for a block that
+/// repeats every `size` bytes from `base` upwards. `addr` must not be below
+/// `base`.
+pub fn mirror_range(addr: u16, base: u16, size: u16) -> u16 {
+    debug_assert!(addr >= base, "{:#06x} is below {:#06x}", addr, base);
+    base + (addr - base) % size
+}
+
+/// The 2KB of CPU RAM repeat four times over 0x0000-0x1FFF.
+pub fn mirror_cpu_ram(addr: u16) -> u16 {
+    mirror_range(addr, 0x0000, 0x0800)
+}
+
+/// The eight PPU registers at 0x2000-0x2007 repeat up to 0x3FFF.
+pub fn mirror_ppu_register(addr: u16) -> u16 {
+    mirror_range(addr, 0x2000, 8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mirror_cpu_ram_boundaries() {
+        assert_eq!(mirror_cpu_ram(0x0000), 0x0000);
+        assert_eq!(mirror_cpu_ram(0x07ff), 0x07ff);
+        assert_eq!(mirror_cpu_ram(0x0800), 0x0000);
+        assert_eq!(mirror_cpu_ram(0x0fff), 0x07ff);
+        assert_eq!(mirror_cpu_ram(0x1000), 0x0000);
+        assert_eq!(mirror_cpu_ram(0x1800), 0x0000);
+        assert_eq!(mirror_cpu_ram(0x1fff), 0x07ff);
+    }
+
+    #[test]
+    fn test_mirror_cpu_ram_matches_masking() {
+        for addr in 0x0000..=0x1fff {
+            assert_eq!(mirror_cpu_ram(addr), addr & 0x07ff, "{:#06x}", addr);
+        }
+    }
+
+    #[test]
+    fn test_mirror_ppu_register_boundaries() {
+        assert_eq!(mirror_ppu_register(0x2000), 0x2000);
+        assert_eq!(mirror_ppu_register(0x2007), 0x2007);
+        assert_eq!(mirror_ppu_register(0x2008), 0x2000);
+        assert_eq!(mirror_ppu_register(0x200f), 0x2007);
+        assert_eq!(mirror_ppu_register(0x3ff8), 0x2000);
+        assert_eq!(mirror_ppu_register(0x3fff), 0x2007);
+    }
+
+    #[test]
+    fn test_mirror_range_with_an_uneven_size() {
+        assert_eq!(mirror_range(0x3f20, 0x3f00, 0x20), 0x3f00);
+        assert_eq!(mirror_range(0x3f3f, 0x3f00, 0x20), 0x3f1f);
+        assert_eq!(mirror_range(0x0007, 0x0002, 3), 0x0004);
+    }
+}
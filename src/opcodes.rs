@@ -1,248 +1,462 @@
 use crate::cpu::AddressingMode;
-use std::collections::HashMap;
 
+#[derive(Debug)]
 pub struct OpCode {
     pub code: u8,
     pub mnemonic: &'static str,
     pub len: u8,
     pub cycles: u8,
     pub mode: AddressingMode,
+    pub page_cross_penalty: bool,
 }
 
 impl OpCode {
-    fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
+    const fn new(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
         OpCode {
             code: code,
             mnemonic: mnemonic,
             len: len,
             cycles: cycles,
             mode: mode,
+            page_cross_penalty: false,
         }
     }
+
+    // indexed reads take one more cycle when the effective address crosses a page
+    const fn with_page_cross_penalty(mut self) -> Self {
+        self.page_cross_penalty = true;
+        self
+    }
+}
+
+pub static CPU_OPS_CODES: &[OpCode] = &[
+    /* Transfer Instructions */
+    /* LDA */
+    OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA5, "LDA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB5, "LDA", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xAD, "LDA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBD, "LDA", 3, 4, AddressingMode::Absolute_X).with_page_cross_penalty(),
+    OpCode::new(0xB9, "LDA", 3, 4, AddressingMode::Absolute_Y).with_page_cross_penalty(),
+    OpCode::new(0xA1, "LDA", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0xB1, "LDA", 2, 5, AddressingMode::Indirect_Y).with_page_cross_penalty(),
+    /* LDX */
+    OpCode::new(0xA2, "LDX", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA6, "LDX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB6, "LDX", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::new(0xAE, "LDX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBE, "LDX", 3, 4, AddressingMode::Absolute_Y).with_page_cross_penalty(),
+    /* LDY */
+    OpCode::new(0xA0, "LDY", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA4, "LDY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB4, "LDY", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xAC, "LDY", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBC, "LDY", 3, 4, AddressingMode::Absolute_X).with_page_cross_penalty(),
+    /* STA */
+    OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9D, "STA", 3, 5, AddressingMode::Absolute_X),
+    OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
+    OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y),
+    /* STX */
+    OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::new(0x8E, "STX", 3, 4, AddressingMode::Absolute),
+    /* STY */
+    OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::new(0x8C, "STY", 3, 4, AddressingMode::Absolute),
+    /* TAX */
+    OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing),
+    /* TXA */
+    OpCode::new(0x8A, "TXA", 1, 2, AddressingMode::NoneAddressing),
+    /* TAY */
+    OpCode::new(0xA8, "TAY", 1, 2, AddressingMode::NoneAddressing),
+    /* TYA */
+    OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
+    /* TSX */
+    OpCode::new(0xBA, "TSX", 1, 2, AddressingMode::NoneAddressing),
+    /* TXS */
+    OpCode::new(0x9A, "TXS", 1, 2, AddressingMode::NoneAddressing),
+    /* Arithmetic Instructions */
+    /* ADC */
+    OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x6D, "ADC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x7D, "ADC", 3, 4, AddressingMode::Absolute_X).with_page_cross_penalty(),
+    OpCode::new(0x79, "ADC", 3, 4, AddressingMode::Absolute_Y).with_page_cross_penalty(),
+    OpCode::new(0x61, "ADC", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x71, "ADC", 2, 5, AddressingMode::Indirect_Y).with_page_cross_penalty(),
+    /* AND */
+    OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x2D, "AND", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x3D, "AND", 3, 4, AddressingMode::Absolute_X).with_page_cross_penalty(),
+    OpCode::new(0x39, "AND", 3, 4, AddressingMode::Absolute_Y).with_page_cross_penalty(),
+    OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x31, "AND", 2, 5, AddressingMode::Indirect_Y).with_page_cross_penalty(),
+    /* ASL */
+    OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x1E, "ASL", 3, 7, AddressingMode::Absolute_X),
+    /* BIT */
+    OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute),
+    /* CMP */
+    OpCode::new(0xC9, "CMP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xC5, "CMP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xD5, "CMP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xCD, "CMP", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xDD, "CMP", 3, 4, AddressingMode::Absolute_X).with_page_cross_penalty(),
+    OpCode::new(0xD9, "CMP", 3, 4, AddressingMode::Absolute_Y).with_page_cross_penalty(),
+    OpCode::new(0xC1, "CMP", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0xD1, "CMP", 2, 5, AddressingMode::Indirect_Y).with_page_cross_penalty(),
+    /* CMX */
+    OpCode::new(0xE0, "CMX", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE4, "CMX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xEC, "CMX", 3, 4, AddressingMode::Absolute),
+    /* CMY */
+    OpCode::new(0xC0, "CMY", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xC4, "CMY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xCC, "CMY", 3, 4, AddressingMode::Absolute),
+    /* DEC */
+    OpCode::new(0xC6, "DEC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xD6, "DEC", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0xCE, "DEC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xDE, "DEC", 3, 7, AddressingMode::Absolute_X),
+    /* DEX */
+    OpCode::new(0xCA, "DEX", 1, 2, AddressingMode::NoneAddressing),
+    /* DEY */
+    OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
+    /* EOR */
+    OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x4D, "EOR", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x5D, "EOR", 3, 4, AddressingMode::Absolute_X).with_page_cross_penalty(),
+    OpCode::new(0x59, "EOR", 3, 4, AddressingMode::Absolute_Y).with_page_cross_penalty(),
+    OpCode::new(0x41, "EOR", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x51, "EOR", 2, 5, AddressingMode::Indirect_Y).with_page_cross_penalty(),
+    /* INC */
+    OpCode::new(0xE6, "INC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xF6, "INC", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0xEE, "INC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xFE, "INC", 3, 7, AddressingMode::Absolute_X),
+    /* INX */
+    OpCode::new(0xE8, "INX", 1, 2, AddressingMode::NoneAddressing),
+    /* INY */
+    OpCode::new(0xC8, "INY", 1, 2, AddressingMode::NoneAddressing),
+    /* LSR */
+    OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x4E, "LSR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x5E, "LSR", 3, 7, AddressingMode::Absolute_X),
+    /* ORA */
+    OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x0D, "ORA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x1D, "ORA", 3, 4, AddressingMode::Absolute_X).with_page_cross_penalty(),
+    OpCode::new(0x19, "ORA", 3, 4, AddressingMode::Absolute_Y).with_page_cross_penalty(),
+    OpCode::new(0x01, "ORA", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x11, "ORA", 2, 5, AddressingMode::Indirect_Y).with_page_cross_penalty(),
+    /* ROL */
+    OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x2E, "ROL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x3E, "ROL", 3, 7, AddressingMode::Absolute_X),
+    /* ROR */
+    OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x6E, "ROR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x7E, "ROR", 3, 7, AddressingMode::Absolute_X),
+    /* SBC */
+    OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xFD, "SBC", 3, 4, AddressingMode::Absolute_X).with_page_cross_penalty(),
+    OpCode::new(0xF9, "SBC", 3, 4, AddressingMode::Absolute_Y).with_page_cross_penalty(),
+    OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0xF1, "SBC", 2, 5, AddressingMode::Indirect_Y).with_page_cross_penalty(),
+    /* Stack Instructions */
+    /* PHA */
+    OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
+    /* PHP */
+    OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
+    /* PLA */
+    OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
+    /* PLP */
+    OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
+    /* Jump Instructions */
+    /* JMP */
+    OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Absolute),
+    OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::Indirect),
+    /* JSR */
+    OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
+    /* RTS */
+    OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
+    /* RTI */
+    OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
+    /* Branching Instructions */
+    /* BCC */
+    OpCode::new(0x90, "BCC", 2, 2, AddressingMode::Relative),
+    /* BCS */
+    OpCode::new(0xB0, "BCS", 2, 2, AddressingMode::Relative),
+    /* BEQ */
+    OpCode::new(0xF0, "BEQ", 2, 2, AddressingMode::Relative),
+    /* BMI */
+    OpCode::new(0x30, "BMI", 2, 2, AddressingMode::Relative),
+    /* BNE */
+    OpCode::new(0xD0, "BNE", 2, 2, AddressingMode::Relative),
+    /* BPL */
+    OpCode::new(0x10, "BPL", 2, 2, AddressingMode::Relative),
+    /* BVC */
+    OpCode::new(0x50, "BVC", 2, 2, AddressingMode::Relative),
+    /* BVS */
+    OpCode::new(0x70, "BVS", 2, 2, AddressingMode::Relative),
+    /* Flag Modification Instructions */
+    /* CLC */
+    OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
+    /* CLD */
+    OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing),
+    /* CLI */
+    OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing),
+    /* CLV */
+    OpCode::new(0xB8, "CLV", 1, 2, AddressingMode::NoneAddressing),
+    /* SEC */
+    OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
+    /* SED */
+    OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing),
+    /* SEI */
+    OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing),
+    /* The Other Instructions */
+    /* BRK */
+    OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
+    /* NOP */
+    OpCode::new(0xEA, "NOP", 1, 2, AddressingMode::NoneAddressing),
+];
+
+const NO_OPCODE: u8 = 0xFF;
+
+// position of each opcode byte in CPU_OPS_CODES, built at compile time so
+// decoding is a single array access without any lazy initialisation
+static LOOKUP: [u8; 256] = {
+    let mut table = [NO_OPCODE; 256];
+    let mut i = 0;
+    while i < CPU_OPS_CODES.len() {
+        table[CPU_OPS_CODES[i].code as usize] = i as u8;
+        i += 1;
+    }
+    table
+};
+
+#[inline]
+pub fn lookup(code: u8) -> Option<&'static OpCode> {
+    match LOOKUP[code as usize] {
+        NO_OPCODE => None,
+        index => Some(&CPU_OPS_CODES[index as usize]),
+    }
+}
+
+/// What the lazily built `HashMap<u8, &OpCode>` used to offer, kept for code
+/// that still does `OPCODES_MAP[&code]` or `OPCODES_MAP.get(&code)`. It is
+/// built at compile time like `LOOKUP`; new code should call `lookup`.
+pub static OPCODES_MAP: OpcodesMap = {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < CPU_OPS_CODES.len() {
+        table[CPU_OPS_CODES[i].code as usize] = Some(&CPU_OPS_CODES[i]);
+        i += 1;
+    }
+    OpcodesMap(table)
+};
+
+pub struct OpcodesMap([Option<&'static OpCode>; 256]);
+
+impl OpcodesMap {
+    pub fn get(&self, code: &u8) -> Option<&&'static OpCode> {
+        self.0[*code as usize].as_ref()
+    }
+
+    pub fn contains_key(&self, code: &u8) -> bool {
+        self.0[*code as usize].is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        CPU_OPS_CODES.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        CPU_OPS_CODES.is_empty()
+    }
 }
 
-lazy_static! {
-    pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
-        /* Transfer Instructions */
-        /* LDA */
-        OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA5, "LDA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB5, "LDA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xAD, "LDA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBD, "LDA", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new(0xB9, "LDA", 3, 4, AddressingMode::Absolute_Y),
-        OpCode::new(0xA1, "LDA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xB1, "LDA", 2, 5, AddressingMode::Indirect_Y),
-        /* LDX */
-        OpCode::new(0xA2, "LDX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA6, "LDX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB6, "LDX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0xAE, "LDX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBE, "LDX", 3, 4, AddressingMode::Absolute_Y),
-        /* LDY */
-        OpCode::new(0xA0, "LDY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA4, "LDY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB4, "LDY", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xAB, "LDY", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBC, "LDY", 3, 4, AddressingMode::Absolute_X),
-        /* STA */
-        OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x9D, "STA", 3, 5, AddressingMode::Absolute_X),
-        OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
-        OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y),
-        /* STX */
-        OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0x8E, "STX", 3, 4, AddressingMode::Absolute),
-        /* STY */
-        OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0x8C, "STY", 3, 4, AddressingMode::Absolute),
-        /* TAX */
-        OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing),
-        /* TXA */
-        OpCode::new(0x8A, "TXA", 1, 2, AddressingMode::NoneAddressing),
-        /* TAY */
-        OpCode::new(0xA8, "TAY", 1, 2, AddressingMode::NoneAddressing),
-        /* TYA */
-        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
-        /* TSX */
-        OpCode::new(0xBA, "TSX", 1, 2, AddressingMode::NoneAddressing),
-        /* TXS */
-        OpCode::new(0x9A, "TXS", 1, 2, AddressingMode::NoneAddressing),
-        /* Arithmetic Instructions */
-        /* ADC */
-        OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6D, "ADC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x7D, "ADC", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new(0x79, "ADC", 3, 4, AddressingMode::Absolute_Y),
-        OpCode::new(0x61, "ADC", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x71, "ADC", 2, 5, AddressingMode::Indirect_Y),
-        /* AND */
-        OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2D, "AND", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x3D, "AND", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new(0x39, "AND", 3, 4, AddressingMode::Absolute_Y),
-        OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x31, "AND", 2, 5, AddressingMode::Indirect_Y),
-        /* ASL */
-        OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x1E, "ASL", 3, 7, AddressingMode::Absolute_X),
-        /* BIT */
-        OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute),
-        /* CMP */
-        OpCode::new(0xC9, "CMP", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xC5, "CMP", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xD5, "CMP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xCD, "CMP", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xDD, "CMP", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new(0xD9, "CMP", 3, 4, AddressingMode::Absolute_Y),
-        OpCode::new(0xC1, "CMP", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xD1, "CMP", 2, 5, AddressingMode::Indirect_Y),
-        /* CMX */
-        OpCode::new(0xE0, "CMX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xE4, "CMX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xEC, "CMX", 3, 4, AddressingMode::Absolute),
-        /* CMY */
-        OpCode::new(0xC0, "CMY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xC4, "CMY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xCC, "CMY", 3, 4, AddressingMode::Absolute),
-         /* DEC */
-        OpCode::new(0xC6, "DEC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xD6, "DEC", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xCE, "DEC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xDE, "DEC", 3, 7, AddressingMode::Absolute_X),
-        /* DEX */
-        OpCode::new(0xCA, "DEX", 1, 2, AddressingMode::NoneAddressing),
-        /* DEY */
-        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
-        /* EOR */
-        OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x4D, "EOR", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x5D, "EOR", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new(0x59, "EOR", 3, 4, AddressingMode::Absolute_Y),
-        OpCode::new(0x41, "EOR", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x51, "EOR", 2, 5, AddressingMode::Indirect_Y),
-        /* INC */
-        OpCode::new(0xE6, "INC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xF6, "INC", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xEE, "INC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xFE, "INC", 3, 7, AddressingMode::Absolute_X),
-        /* INX */
-        OpCode::new(0xE8, "INX", 1, 2, AddressingMode::NoneAddressing),
-        /* INY */
-        OpCode::new(0xC8, "INY", 1, 2, AddressingMode::NoneAddressing),
-        /* LSR */
-        OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x4E, "LSR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x5E, "LSR", 3, 7, AddressingMode::Absolute_X),
-        /* ORA */
-        OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0D, "ORA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x1D, "ORA", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new(0x19, "ORA", 3, 4, AddressingMode::Absolute_Y),
-        OpCode::new(0x01, "ORA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x11, "ORA", 2, 5, AddressingMode::Indirect_Y),
-        /* ROL */
-        OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2E, "ROL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x3E, "ROL", 3, 7, AddressingMode::Absolute_X),
-        /* ROR */
-        OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6E, "ROR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x7E, "ROR", 3, 7, AddressingMode::Absolute_X),
-        /* SBC */
-        OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xFD, "SBC", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new(0xF9, "SBC", 3, 4, AddressingMode::Absolute_Y),
-        OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xF1, "SBC", 2, 5, AddressingMode::Indirect_Y),
-        /* Stack Instructions */
-        /* PHA */
-        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
-        /* PHP */
-        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
-        /* PLA */
-        OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
-        /* PLP */
-        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
-        /* Jump Instructions */
-        /* JMP */
-        OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Absolute),
-        OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::NoneAddressing),
-        /* JSR */
-        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
-        /* RTS */
-        OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
-        /* RTI */
-        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
-        /* Branching Instructions */
-        /* BCC */
-        OpCode::new(0x90, "BCC", 2, 2, AddressingMode::NoneAddressing),
-        /* BCS */
-        OpCode::new(0xB0, "BCS", 2, 2, AddressingMode::NoneAddressing),
-        /* BEQ */
-        OpCode::new(0xF0, "BEQ", 2, 2, AddressingMode::NoneAddressing),
-        /* BMI */
-        OpCode::new(0x30, "BMI", 2, 2, AddressingMode::NoneAddressing),
-        /* BNE */
-        OpCode::new(0xD0, "BNE", 2, 2, AddressingMode::NoneAddressing),
-        /* BPL */
-        OpCode::new(0x10, "BPL", 2, 2, AddressingMode::NoneAddressing),
-        /* BVC */
-        OpCode::new(0x50, "BVC", 2, 2, AddressingMode::NoneAddressing),
-        /* BVS */
-        OpCode::new(0x70, "BVS", 2, 2, AddressingMode::NoneAddressing),
-        /* Flag Modification Instructions */
-        /* CLC */
-        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
-        /* CLD */
-        OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing),
-        /* CLI */
-        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing),
-        /* CLV */
-        OpCode::new(0xB8, "CLV", 1, 2, AddressingMode::NoneAddressing),
-        /* SEC */
-        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
-        /* SED */
-        OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing),
-        /* SEI */
-        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing),
-        /* The Other Instructions */
-        /* BRK */
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
-        /* NOP */
-        OpCode::new(0xEA, "NOP", 1, 2, AddressingMode::NoneAddressing),
+impl std::ops::Index<&u8> for OpcodesMap {
+    type Output = &'static OpCode;
+
+    fn index(&self, code: &u8) -> &Self::Output {
+        self.get(code)
+            .unwrap_or_else(|| panic!("no opcode {:#04x}", code))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Base cycle counts for all 256 NMOS 6502 opcodes, without page-cross or
+    // branch penalties. 0 marks the JAM opcodes that halt the processor.
+    #[rustfmt::skip]
+    const CANONICAL_CYCLES: [u8; 256] = [
+        /*        0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F */
+        /* 0 */  7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+        /* 1 */  2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        /* 2 */  6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+        /* 3 */  2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        /* 4 */  6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+        /* 5 */  2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        /* 6 */  6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+        /* 7 */  2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        /* 8 */  2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        /* 9 */  2, 6, 0, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+        /* A */  2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        /* B */  2, 5, 0, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+        /* C */  2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        /* D */  2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        /* E */  2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        /* F */  2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    ];
+
+    #[test]
+    fn test_base_cycles_match_canonical_table() {
+        let mismatches: Vec<String> = CPU_OPS_CODES
+            .iter()
+            .filter(|op| op.cycles != CANONICAL_CYCLES[op.code as usize])
+            .map(|op| {
+                format!(
+                    "{:#04x} {} ({:?}): table has {} cycles, expected {}",
+                    op.code, op.mnemonic, op.mode, op.cycles, CANONICAL_CYCLES[op.code as usize]
+                )
+            })
+            .collect();
+
+        assert!(mismatches.is_empty(), "\n{}", mismatches.join("\n"));
+    }
+
+    // Mnemonic and instruction length of every opcode byte the CPU decodes;
+    // "" / 0 marks bytes that have no entry in CPU_OPS_CODES.
+    #[rustfmt::skip]
+    const EXPECTED_MNEMONICS: [&str; 256] = [
+        /* 0 */ "BRK", "ORA", ""   , ""   , ""   , "ORA", "ASL", ""   , "PHP", "ORA", "ASL", ""   , ""   , "ORA", "ASL", ""   ,
+        /* 1 */ "BPL", "ORA", ""   , ""   , ""   , "ORA", "ASL", ""   , "CLC", "ORA", ""   , ""   , ""   , "ORA", "ASL", ""   ,
+        /* 2 */ "JSR", "AND", ""   , ""   , "BIT", "AND", "ROL", ""   , "PLP", "AND", "ROL", ""   , "BIT", "AND", "ROL", ""   ,
+        /* 3 */ "BMI", "AND", ""   , ""   , ""   , "AND", "ROL", ""   , "SEC", "AND", ""   , ""   , ""   , "AND", "ROL", ""   ,
+        /* 4 */ "RTI", "EOR", ""   , ""   , ""   , "EOR", "LSR", ""   , "PHA", "EOR", "LSR", ""   , "JMP", "EOR", "LSR", ""   ,
+        /* 5 */ "BVC", "EOR", ""   , ""   , ""   , "EOR", "LSR", ""   , "CLI", "EOR", ""   , ""   , ""   , "EOR", "LSR", ""   ,
+        /* 6 */ "RTS", "ADC", ""   , ""   , ""   , "ADC", "ROR", ""   , "PLA", "ADC", "ROR", ""   , "JMP", "ADC", "ROR", ""   ,
+        /* 7 */ "BVS", "ADC", ""   , ""   , ""   , "ADC", "ROR", ""   , "SEI", "ADC", ""   , ""   , ""   , "ADC", "ROR", ""   ,
+        /* 8 */ ""   , "STA", ""   , ""   , "STY", "STA", "STX", ""   , "DEY", ""   , "TXA", ""   , "STY", "STA", "STX", ""   ,
+        /* 9 */ "BCC", "STA", ""   , ""   , "STY", "STA", "STX", ""   , "TYA", "STA", "TXS", ""   , ""   , "STA", ""   , ""   ,
+        /* A */ "LDY", "LDA", "LDX", ""   , "LDY", "LDA", "LDX", ""   , "TAY", "LDA", "TAX", ""   , "LDY", "LDA", "LDX", ""   ,
+        /* B */ "BCS", "LDA", ""   , ""   , "LDY", "LDA", "LDX", ""   , "CLV", "LDA", "TSX", ""   , "LDY", "LDA", "LDX", ""   ,
+        /* C */ "CMY", "CMP", ""   , ""   , "CMY", "CMP", "DEC", ""   , "INY", "CMP", "DEX", ""   , "CMY", "CMP", "DEC", ""   ,
+        /* D */ "BNE", "CMP", ""   , ""   , ""   , "CMP", "DEC", ""   , "CLD", "CMP", ""   , ""   , ""   , "CMP", "DEC", ""   ,
+        /* E */ "CMX", "SBC", ""   , ""   , "CMX", "SBC", "INC", ""   , "INX", "SBC", "NOP", ""   , "CMX", "SBC", "INC", ""   ,
+        /* F */ "BEQ", "SBC", ""   , ""   , ""   , "SBC", "INC", ""   , "SED", "SBC", ""   , ""   , ""   , "SBC", "INC", ""   ,
+    ];
+
+    #[rustfmt::skip]
+    const EXPECTED_LENGTHS: [u8; 256] = [
+        /* 0 */ 1, 2, 0, 0, 0, 2, 2, 0, 1, 2, 1, 0, 0, 3, 3, 0,
+        /* 1 */ 2, 2, 0, 0, 0, 2, 2, 0, 1, 3, 0, 0, 0, 3, 3, 0,
+        /* 2 */ 3, 2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0,
+        /* 3 */ 2, 2, 0, 0, 0, 2, 2, 0, 1, 3, 0, 0, 0, 3, 3, 0,
+        /* 4 */ 1, 2, 0, 0, 0, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0,
+        /* 5 */ 2, 2, 0, 0, 0, 2, 2, 0, 1, 3, 0, 0, 0, 3, 3, 0,
+        /* 6 */ 1, 2, 0, 0, 0, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0,
+        /* 7 */ 2, 2, 0, 0, 0, 2, 2, 0, 1, 3, 0, 0, 0, 3, 3, 0,
+        /* 8 */ 0, 2, 0, 0, 2, 2, 2, 0, 1, 0, 1, 0, 3, 3, 3, 0,
+        /* 9 */ 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 0, 3, 0, 0,
+        /* A */ 2, 2, 2, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0,
+        /* B */ 2, 2, 0, 0, 2, 2, 2, 0, 1, 3, 1, 0, 3, 3, 3, 0,
+        /* C */ 2, 2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0,
+        /* D */ 2, 2, 0, 0, 0, 2, 2, 0, 1, 3, 0, 0, 0, 3, 3, 0,
+        /* E */ 2, 2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0,
+        /* F */ 2, 2, 0, 0, 0, 2, 2, 0, 1, 3, 0, 0, 0, 3, 3, 0,
     ];
-    pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-        let mut map = HashMap::new();
-        for cpuop in &*CPU_OPS_CODES {
-            map.insert(cpuop.code, cpuop);
+
+    #[test]
+    fn test_opcodes_are_unique() {
+        let mut seen = [false; 256];
+        for op in CPU_OPS_CODES.iter() {
+            assert!(!seen[op.code as usize], "{:#04x} listed twice", op.code);
+            seen[op.code as usize] = true;
+        }
+        assert_eq!(CPU_OPS_CODES.len(), 151);
+    }
+
+    #[test]
+    fn test_lookup_resolves_every_opcode_byte() {
+        for code in 0..=0xFFu8 {
+            let expected = EXPECTED_MNEMONICS[code as usize];
+            match lookup(code) {
+                Some(op) => {
+                    assert_eq!(op.code, code);
+                    assert_eq!(op.mnemonic, expected, "{:#04x}", code);
+                    assert_eq!(op.len, EXPECTED_LENGTHS[code as usize], "{:#04x}", code);
+                }
+                None => assert_eq!(expected, "", "{:#04x} is not decoded", code),
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookup_agrees_with_opcode_list() {
+        for code in 0..=0xFFu8 {
+            let linear = CPU_OPS_CODES.iter().find(|op| op.code == code);
+            match (lookup(code), linear) {
+                (Some(found), Some(listed)) => {
+                    // same entry, so mode and cycles agree as well
+                    assert!(std::ptr::eq(found, listed), "{:#04x}", code);
+                }
+                (None, None) => {}
+                (found, listed) => panic!(
+                    "{:#04x}: lookup {:?}, list {:?}",
+                    code,
+                    found.map(|op| op.mnemonic),
+                    listed.map(|op| op.mnemonic)
+                ),
+            }
         }
-        map
-    };
+    }
+    #[test]
+    fn test_opcodes_map_shim_matches_lookup() {
+        assert_eq!(OPCODES_MAP.len(), CPU_OPS_CODES.len());
+        for code in 0..=0xFFu8 {
+            match lookup(code) {
+                Some(op) => assert!(std::ptr::eq(OPCODES_MAP[&code], op), "{:#04x}", code),
+                None => assert!(!OPCODES_MAP.contains_key(&code), "{:#04x}", code),
+            }
+            assert_eq!(
+                OPCODES_MAP.get(&code).copied().map(|op| op.code),
+                lookup(code).map(|op| op.code)
+            );
+        }
+    }
 }
@@ -0,0 +1,1265 @@
+use crate::cartridge::{Mapper, Mirroring};
+use crate::frame::Frame;
+use crate::mirroring::mirror_range;
+use crate::palette::SYSTEM_PALETTE;
+
+pub const PPUCTRL: u8 = 0;
+pub const PPUMASK: u8 = 1;
+pub const PPUSTATUS: u8 = 2;
+pub const OAMADDR: u8 = 3;
+pub const OAMDATA: u8 = 4;
+pub const PPUSCROLL: u8 = 5;
+pub const PPUADDR: u8 = 6;
+pub const PPUDATA: u8 = 7;
+
+/// The PPU as the CPU sees it: eight registers, numbered 0-7 for
+/// 0x2000-0x2007. The bus folds the mirrors onto them and only forwards
+/// reads of readable registers and writes to writable ones. The pattern
+/// tables, and the nametable layout, live on the cartridge, which the bus
+/// hands over with every access.
+pub trait PpuRegisters {
+    fn read_register(&mut self, register: u8, cartridge: &mut dyn Mapper) -> u8;
+
+    /// What `read_register` would return, without its side effects.
+    fn peek_register(&self, register: u8, cartridge: &dyn Mapper) -> u8;
+
+    fn write_register(&mut self, register: u8, data: u8, cartridge: &mut dyn Mapper);
+
+    /// Lets the PPU run for the CPU cycles that went by, three dots each.
+    fn tick(&mut self, _cpu_cycles: u64, _cartridge: &mut dyn Mapper) {}
+
+    /// Whether the PPU raised an NMI since the last call.
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+
+    /// The picture, for PPUs that draw one.
+    fn frame(&self) -> Option<&Frame> {
+        None
+    }
+
+    /// Whether the picture was finished since the last call. The bus ends
+    /// frames on this for PPUs that draw, and on a count of CPU cycles for
+    /// the rest.
+    fn take_frame_end(&mut self) -> bool {
+        false
+    }
+
+    /// What the reset button does to the PPU, e.g. clearing its write latch.
+    fn reset(&mut self) {}
+}
+
+pub fn is_readable(register: u8) -> bool {
+    matches!(register, PPUSTATUS | OAMDATA | PPUDATA)
+}
+
+pub fn is_writable(register: u8) -> bool {
+    register != PPUSTATUS
+}
+
+bitflags! {
+    /// PPUCTRL, 0x2000.
+    pub struct ControlRegister: u8 {
+        // which of the four nametables the picture starts in
+        const NAMETABLE_X      = 0b0000_0001;
+        const NAMETABLE_Y      = 0b0000_0010;
+        // add 32 rather than 1 to the address after each PPUDATA access
+        const INCREMENT_32     = 0b0000_0100;
+        const SPRITE_TABLE     = 0b0000_1000;
+        const BACKGROUND_TABLE = 0b0001_0000;
+        const SPRITES_8X16     = 0b0010_0000;
+        const MASTER_SLAVE     = 0b0100_0000;
+        const NMI_ENABLE       = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    /// PPUMASK, 0x2001.
+    pub struct MaskRegister: u8 {
+        const GREYSCALE        = 0b0000_0001;
+        // whether the leftmost 8 pixels show the background and sprites
+        const BACKGROUND_LEFT  = 0b0000_0010;
+        const SPRITES_LEFT     = 0b0000_0100;
+        const BACKGROUND       = 0b0000_1000;
+        const SPRITES          = 0b0001_0000;
+        const EMPHASIZE_RED    = 0b0010_0000;
+        const EMPHASIZE_GREEN  = 0b0100_0000;
+        const EMPHASIZE_BLUE   = 0b1000_0000;
+    }
+}
+
+// PPUSTATUS: the PPU is in vertical blank
+const STATUS_VBLANK: u8 = 0b1000_0000;
+const STATUS_SPRITE_ZERO_HIT: u8 = 0b0100_0000;
+const STATUS_SPRITE_OVERFLOW: u8 = 0b0010_0000;
+// the bits of PPUSTATUS that come from the latch rather than the flags
+const STATUS_OPEN_BUS: u8 = 0b0001_1111;
+
+const VRAM_SIZE: usize = 0x0800;
+
+const VISIBLE_DOTS: u16 = 256;
+const VISIBLE_SCANLINES: u16 = 240;
+const DOTS_PER_SCANLINE: u16 = 341;
+const SCANLINES_PER_FRAME: u16 = 262;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+// where rendering lines fetch the next line's sprites, then its first two
+// background tiles
+const SPRITE_FETCH_DOT: u16 = 257;
+const BACKGROUND_FETCH_DOT: u16 = 321;
+
+/// The picture processor: 2KB of nametable RAM, 32 bytes of palette RAM,
+/// 256 bytes of OAM and the latches behind the registers. The pattern
+/// tables come from the cartridge. It counts dots and scanlines to time
+/// vertical blank, and draws each visible scanline whole once its dots
+/// have gone by, rather than a pixel per dot.
+pub struct PPU {
+    vram: [u8; VRAM_SIZE],
+    palette: [u8; 32],
+    oam: [u8; 256],
+    ctrl: ControlRegister,
+    mask: MaskRegister,
+    status: u8,
+    oam_addr: u8,
+    // the VRAM address, laid out as the scroll registers see it:
+    // 0yyy NNYY YYYX XXXX, fine Y, nametable, coarse Y and coarse X.
+    // PPUDATA goes through it.
+    v: u16,
+    // the address the picture starts from, which PPUCTRL, PPUSCROLL and
+    // PPUADDR build up
+    t: u16,
+    // the fine X scroll, 0-7
+    x: u8,
+    // whether the next PPUSCROLL or PPUADDR write is the second of a pair
+    w: bool,
+    // what PPUDATA reads below the palettes return: the byte from the read
+    // before
+    read_buffer: u8,
+    // the last byte on the PPU's side of the data bus, which the unused
+    // bits of PPUSTATUS read back
+    latch: u8,
+    // an NMI the CPU has not been told about
+    nmi: bool,
+    // PPUSTATUS was read just before vertical blank, so this frame's flag
+    // and NMI never happen
+    suppress_vblank: bool,
+    // the picture was finished, and the bus has not been told
+    frame_end: bool,
+    scanline: u16,
+    dot: u16,
+    frame: Frame,
+}
+
+impl PPU {
+    pub fn new() -> Self {
+        PPU {
+            vram: [0; VRAM_SIZE],
+            palette: [0; 32],
+            oam: [0; 256],
+            ctrl: ControlRegister::empty(),
+            mask: MaskRegister::empty(),
+            status: 0,
+            oam_addr: 0,
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            latch: 0,
+            nmi: false,
+            suppress_vblank: false,
+            frame_end: false,
+            scanline: 0,
+            dot: 0,
+            frame: Frame::new(),
+        }
+    }
+
+    /// Writes PPUCTRL. Turning NMIs on during vertical blank raises one
+    /// straight away, as the NMI line is the AND of the flag and the enable
+    /// bit.
+    pub fn write_ctrl(&mut self, data: u8) {
+        let was_enabled = self.nmi_enabled();
+        self.ctrl = ControlRegister::from_bits_truncate(data);
+        self.t = self.t & !0x0C00 | (data as u16 & 0b11) << 10;
+        if !was_enabled && self.nmi_enabled() && self.status & STATUS_VBLANK != 0 {
+            self.nmi = true;
+        }
+    }
+
+    pub fn nmi_enabled(&self) -> bool {
+        self.ctrl.contains(ControlRegister::NMI_ENABLE)
+    }
+
+    /// Where the 8x8 sprites' tiles are; 8x16 sprites pick their own.
+    pub fn sprite_pattern_table(&self) -> u16 {
+        if self.ctrl.contains(ControlRegister::SPRITE_TABLE) {
+            0x1000
+        } else {
+            0x0000
+        }
+    }
+
+    pub fn background_pattern_table(&self) -> u16 {
+        if self.ctrl.contains(ControlRegister::BACKGROUND_TABLE) {
+            0x1000
+        } else {
+            0x0000
+        }
+    }
+
+    pub fn sprite_height(&self) -> u8 {
+        if self.ctrl.contains(ControlRegister::SPRITES_8X16) {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Whether an NMI was raised since the last call.
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi)
+    }
+
+    /// Whether the last visible scanline was drawn since the last call.
+    pub fn take_frame_end(&mut self) -> bool {
+        std::mem::take(&mut self.frame_end)
+    }
+
+    pub fn write_mask(&mut self, data: u8) {
+        self.mask = MaskRegister::from_bits_truncate(data);
+    }
+
+    /// Whether the background or sprites are drawn. With both off the PPU
+    /// leaves the VRAM address alone during the frame and sprite 0 cannot
+    /// hit, so programs can fill VRAM at any time.
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask
+            .intersects(MaskRegister::BACKGROUND | MaskRegister::SPRITES)
+    }
+
+    /// The red, green and blue emphasis bits of PPUMASK, in that order.
+    pub fn emphasis(&self) -> u8 {
+        self.mask.bits() >> 5
+    }
+
+    /// Reads PPUSTATUS, which ends the vertical blank flag and starts the
+    /// PPUSCROLL/PPUADDR write pairs over.
+    ///
+    /// A read racing the flag cancels that frame's NMI: a dot before it is
+    /// set, the flag reads clear and is never set; as it is set, or a dot
+    /// after, the flag reads set but no NMI follows. The PPU only learns
+    /// where it is when the bus ticks it, so with instruction granularity
+    /// the race is caught against the start of the reading instruction.
+    pub fn read_status(&mut self) -> u8 {
+        match self.position() {
+            (VBLANK_SCANLINE, 0) => self.suppress_vblank = true,
+            (VBLANK_SCANLINE, 1..=2) => self.nmi = false,
+            _ => {}
+        }
+        let status = self.peek_status();
+        self.status &= !STATUS_VBLANK;
+        self.w = false;
+        status
+    }
+
+    /// The vertical blank, sprite 0 hit and sprite overflow flags, over
+    /// the latch in the low 5 bits.
+    pub fn peek_status(&self) -> u8 {
+        self.status | self.latch & STATUS_OPEN_BUS
+    }
+
+    /// Sets the vertical blank flag, as the PPU does on reaching scanline
+    /// 241, raising an NMI if they are on.
+    pub fn set_vblank(&mut self) {
+        if std::mem::take(&mut self.suppress_vblank) {
+            return;
+        }
+        if self.status & STATUS_VBLANK == 0 && self.nmi_enabled() {
+            self.nmi = true;
+        }
+        self.status |= STATUS_VBLANK;
+    }
+
+    pub fn write_oam_addr(&mut self, data: u8) {
+        self.oam_addr = data;
+    }
+
+    /// Reads OAMDATA, leaving OAMADDR where it is. Bits 2-4 of the sprite
+    /// attribute bytes are not there and read as 0.
+    pub fn read_oam_data(&self) -> u8 {
+        let data = self.oam[self.oam_addr as usize];
+        if self.oam_addr % 4 == 2 {
+            data & 0b1110_0011
+        } else {
+            data
+        }
+    }
+
+    /// Writes OAMDATA, moving OAMADDR on. OAM DMA is 256 of these, so it
+    /// starts at OAMADDR too.
+    pub fn write_oam_data(&mut self, data: u8) {
+        self.oam[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    /// The X scroll, then the Y scroll, each split into the coarse scroll
+    /// in `t` and the fine scroll, in `x` for X and `t` for Y.
+    pub fn write_scroll(&mut self, data: u8) {
+        let data = data as u16;
+        if self.w {
+            self.t = self.t & !0x73E0 | (data & 0x07) << 12 | (data >> 3) << 5;
+        } else {
+            self.t = self.t & !0x001F | data >> 3;
+            self.x = data as u8 & 0x07;
+        }
+        self.w = !self.w;
+    }
+
+    /// The high byte of the PPUDATA address, then the low byte, into `t`.
+    /// The second write copies `t` to `v`.
+    pub fn write_addr(&mut self, data: u8) {
+        let data = data as u16;
+        if self.w {
+            self.t = self.t & 0xFF00 | data;
+            self.v = self.t;
+        } else {
+            // the top bit of `t` is cleared too
+            self.t = self.t & 0x00FF | (data & 0x3F) << 8;
+        }
+        self.w = !self.w;
+    }
+
+    /// Reads PPUDATA and moves the address on. Below the palettes the read
+    /// takes a byte into a buffer and returns the one already there. The
+    /// palettes answer straight away, but the buffer still takes the
+    /// nametable byte underneath them.
+    pub fn read_data(&mut self, cartridge: &mut dyn Mapper) -> u8 {
+        let addr = self.data_addr();
+        self.increment_addr();
+        match addr {
+            0x3F00..=0x3FFF => {
+                self.read_buffer = self.read(addr - 0x1000, cartridge);
+                self.palette[palette_index(addr)]
+            }
+            _ => {
+                let data = self.read_buffer;
+                self.read_buffer = self.read(addr, cartridge);
+                data
+            }
+        }
+    }
+
+    /// What `read_data` would return, without moving the address or
+    /// refilling the buffer.
+    pub fn peek_data(&self) -> u8 {
+        match self.data_addr() {
+            addr @ 0x3F00..=0x3FFF => self.palette[palette_index(addr)],
+            _ => self.read_buffer,
+        }
+    }
+
+    pub fn write_data(&mut self, data: u8, cartridge: &mut dyn Mapper) {
+        match self.data_addr() {
+            addr @ 0x0000..=0x1FFF => cartridge.ppu_write(addr, data),
+            addr @ 0x2000..=0x3EFF => self.write_nametable(addr, data, cartridge),
+            addr => self.palette[palette_index(addr)] = data,
+        }
+        self.increment_addr();
+    }
+
+    /// The address PPUDATA goes to: `v` without its top bit, which only
+    /// the fine Y scroll uses.
+    pub fn data_addr(&self) -> u16 {
+        self.v & 0x3FFF
+    }
+
+    /// Runs for `dots`, setting the vertical blank flag as scanline 241
+    /// starts and clearing it, with the sprite flags, as the pre-render
+    /// scanline does. The cartridge hears of every scanline as it starts,
+    /// and while rendering, of A12 as pattern fetches move between the
+    /// tables.
+    pub fn tick_dots(&mut self, dots: u64, cartridge: &mut dyn Mapper) {
+        for _ in 0..dots {
+            self.dot += 1;
+            if self.dot == DOTS_PER_SCANLINE {
+                self.dot = 0;
+                self.scanline = (self.scanline + 1) % SCANLINES_PER_FRAME;
+                cartridge.notify_scanline(self.scanline);
+            }
+            if self.rendering_enabled()
+                && (self.scanline < VISIBLE_SCANLINES || self.scanline == PRE_RENDER_SCANLINE)
+            {
+                match self.dot {
+                    SPRITE_FETCH_DOT => cartridge.notify_a12(self.sprite_fetch_table() != 0),
+                    BACKGROUND_FETCH_DOT => {
+                        cartridge.notify_a12(self.background_pattern_table() != 0)
+                    }
+                    _ => {}
+                }
+            }
+            if self.dot == VISIBLE_DOTS && self.scanline < VISIBLE_SCANLINES {
+                self.render_scanline(cartridge);
+            }
+            if self.dot == 1 {
+                match self.scanline {
+                    VBLANK_SCANLINE => {
+                        self.set_vblank();
+                        self.frame_end = true;
+                    }
+                    PRE_RENDER_SCANLINE => {
+                        self.status &=
+                            !(STATUS_VBLANK | STATUS_SPRITE_ZERO_HIT | STATUS_SPRITE_OVERFLOW);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// The scanline and the dot in it the PPU is at.
+    pub fn position(&self) -> (u16, u16) {
+        (self.scanline, self.dot)
+    }
+
+    /// The picture as drawn so far.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    // draws the current scanline, setting the sprite flags on the way. The
+    // scroll is taken from `t` and `x` as they stand, rather than from `v`
+    // as the PPU copies and steps it through the frame.
+    fn render_scanline(&mut self, cartridge: &mut dyn Mapper) {
+        let y = self.scanline as usize;
+        let sprites = self.sprites_on(self.scanline, cartridge);
+        for x in 0..Frame::WIDTH {
+            let background =
+                if self.shows(MaskRegister::BACKGROUND, MaskRegister::BACKGROUND_LEFT, x) {
+                    self.background_pixel(x, y, cartridge)
+                } else {
+                    None
+                };
+            let sprite = if self.shows(MaskRegister::SPRITES, MaskRegister::SPRITES_LEFT, x) {
+                sprites.iter().find_map(|sprite| sprite.pixel(x))
+            } else {
+                None
+            };
+            if let (Some(_), Some((_, _, true))) = (background, sprite) {
+                if x != 255 {
+                    self.status |= STATUS_SPRITE_ZERO_HIT;
+                }
+            }
+            let entry = match (background, sprite) {
+                (Some(entry), None) | (Some(entry), Some((_, true, _))) => entry,
+                (_, Some((entry, _, _))) => entry,
+                (None, None) => 0,
+            };
+            self.frame.set_pixel(x, y, self.colour(entry));
+        }
+    }
+
+    // whether pixel `x` shows what `layer` enables, given the left column
+    // is cut unless `left` is set too
+    fn shows(&self, layer: MaskRegister, left: MaskRegister, x: usize) -> bool {
+        self.mask.contains(layer) && (x >= 8 || self.mask.contains(left))
+    }
+
+    // the palette entry of the background at `x`, `y`, if it is not
+    // transparent
+    fn background_pixel(&self, x: usize, y: usize, cartridge: &mut dyn Mapper) -> Option<u8> {
+        let t = self.t as usize;
+        let nametable = t >> 10 & 0b11;
+        let scroll_x = (t & 0x1F) * 8 + self.x as usize;
+        let scroll_y = (t >> 5 & 0x1F) * 8 + (t >> 12 & 0b111);
+        let x = (x + scroll_x + (nametable & 1) * 256) % 512;
+        let y = (y + scroll_y + (nametable >> 1) * 240) % 480;
+        let base = 0x2000 + (x / 256 + y / 240 * 2) * 0x0400;
+        let (column, row) = (x % 256 / 8, y % 240 / 8);
+        let tile = self.read((base + row * 32 + column) as u16, cartridge);
+        let attribute = self.read((base + 0x03C0 + row / 4 * 8 + column / 4) as u16, cartridge);
+        let palette = attribute >> (row % 4 / 2 * 4 + column % 4 / 2 * 2) & 0b11;
+        let pattern = self.background_pattern_table() + tile as u16 * 16 + (y % 8) as u16;
+        let colour = pattern_bit(pattern, 7 - x % 8, cartridge);
+        (colour != 0).then_some(palette * 4 + colour)
+    }
+
+    // the table sprite fetches come from. 8x16 sprites pick their own, but
+    // slots without a sprite fetch tile 0xFF, which is in 0x1000.
+    fn sprite_fetch_table(&self) -> u16 {
+        if self.sprite_height() == 16 {
+            0x1000
+        } else {
+            self.sprite_pattern_table()
+        }
+    }
+
+    // the first 8 sprites on `scanline`, in OAM order, flagging overflow if
+    // there are more
+    fn sprites_on(&mut self, scanline: u16, cartridge: &mut dyn Mapper) -> Vec<SpriteRow> {
+        let height = self.sprite_height() as u16;
+        let mut sprites = vec![];
+        for (index, sprite) in self.oam.chunks(4).enumerate() {
+            // sprites show a line below their Y
+            let top = sprite[0] as u16 + 1;
+            if !(top..top + height).contains(&scanline) {
+                continue;
+            }
+            if sprites.len() == 8 {
+                self.status |= STATUS_SPRITE_OVERFLOW;
+                break;
+            }
+            let (tile, attributes) = (sprite[1] as u16, sprite[2]);
+            let mut row = scanline - top;
+            if attributes & 0x80 != 0 {
+                row = height - 1 - row;
+            }
+            let pattern = if height == 16 {
+                (tile & 1) * 0x1000 + (tile & 0xFE) * 16 + row / 8 * 16 + row % 8
+            } else {
+                self.sprite_pattern_table() + tile * 16 + row
+            };
+            let planes = [cartridge.ppu_read(pattern), cartridge.ppu_read(pattern + 8)];
+            sprites.push(SpriteRow {
+                x: sprite[3] as usize,
+                planes,
+                attributes,
+                sprite_zero: index == 0,
+            });
+        }
+        sprites
+    }
+
+    // the RGB colour of palette entry `entry`
+    fn colour(&self, entry: u8) -> (u8, u8, u8) {
+        let mut value = self.palette[palette_index(0x3F00 + entry as u16)];
+        if self.mask.contains(MaskRegister::GREYSCALE) {
+            value &= 0x30;
+        }
+        SYSTEM_PALETTE[(value & 0x3F) as usize]
+    }
+
+    /// Where nametable address `addr`, 0x2000-0x3EFF, lands under
+    /// `mirroring`: an index into the 2KB of nametable RAM, or for the third
+    /// and fourth nametables of four-screen layouts, 0x0800 past its start
+    /// into the cartridge's. 0x3000-0x3EFF repeats 0x2000-0x2EFF. Pattern
+    /// table addresses, below 0x2000, are the cartridge's to map, and panic
+    /// in debug builds.
+    pub fn mirror_vram_addr(addr: u16, mirroring: Mirroring) -> usize {
+        let addr = (mirror_range(addr, 0x2000, 0x1000) - 0x2000) as usize;
+        let table = addr / 0x0400;
+        let offset = addr % 0x0400;
+        let physical = match mirroring {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        physical * 0x0400 + offset
+    }
+
+    fn read(&self, addr: u16, cartridge: &mut dyn Mapper) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => cartridge.ppu_read(addr),
+            _ => self.read_nametable(addr, cartridge),
+        }
+    }
+
+    // the mirroring is asked for on every access, as mappers like MMC1 and
+    // AxROM change it as they run. Four-screen layouts need RAM on the
+    // cartridge, so without it they fold like vertical mirroring.
+    fn read_nametable(&self, addr: u16, cartridge: &mut dyn Mapper) -> u8 {
+        let index = PPU::mirror_vram_addr(addr, cartridge.mirroring());
+        match cartridge.nametable_ram() {
+            Some(ram) if index >= VRAM_SIZE => ram[index - VRAM_SIZE],
+            _ => self.vram[index % VRAM_SIZE],
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8, cartridge: &mut dyn Mapper) {
+        let index = PPU::mirror_vram_addr(addr, cartridge.mirroring());
+        match cartridge.nametable_ram() {
+            Some(ram) if index >= VRAM_SIZE => ram[index - VRAM_SIZE] = data,
+            _ => self.vram[index % VRAM_SIZE] = data,
+        }
+    }
+
+    fn increment_addr(&mut self) {
+        let step = if self.ctrl.contains(ControlRegister::INCREMENT_32) {
+            32
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        PPU::new()
+    }
+}
+
+impl PpuRegisters for PPU {
+    fn read_register(&mut self, register: u8, cartridge: &mut dyn Mapper) -> u8 {
+        self.latch = match register {
+            PPUSTATUS => self.read_status(),
+            OAMDATA => self.read_oam_data(),
+            PPUDATA => self.read_data(cartridge),
+            _ => self.latch,
+        };
+        self.latch
+    }
+
+    fn peek_register(&self, register: u8, _cartridge: &dyn Mapper) -> u8 {
+        match register {
+            PPUSTATUS => self.peek_status(),
+            OAMDATA => self.read_oam_data(),
+            PPUDATA => self.peek_data(),
+            _ => self.latch,
+        }
+    }
+
+    fn write_register(&mut self, register: u8, data: u8, cartridge: &mut dyn Mapper) {
+        self.latch = data;
+        match register {
+            PPUCTRL => self.write_ctrl(data),
+            PPUMASK => self.write_mask(data),
+            OAMADDR => self.write_oam_addr(data),
+            OAMDATA => self.write_oam_data(data),
+            PPUSCROLL => self.write_scroll(data),
+            PPUADDR => self.write_addr(data),
+            PPUDATA => self.write_data(data, cartridge),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cpu_cycles: u64, cartridge: &mut dyn Mapper) {
+        self.tick_dots(cpu_cycles * 3, cartridge);
+    }
+
+    fn frame(&self) -> Option<&Frame> {
+        Some(PPU::frame(self))
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        PPU::take_nmi(self)
+    }
+
+    fn take_frame_end(&mut self) -> bool {
+        PPU::take_frame_end(self)
+    }
+
+    // the flags and the memories keep what they had
+    fn reset(&mut self) {
+        self.ctrl = ControlRegister::empty();
+        self.mask = MaskRegister::empty();
+        self.t = 0;
+        self.x = 0;
+        self.w = false;
+        self.read_buffer = 0;
+    }
+}
+
+// one row of a sprite's 8 pixels
+struct SpriteRow {
+    x: usize,
+    planes: [u8; 2],
+    attributes: u8,
+    sprite_zero: bool,
+}
+
+impl SpriteRow {
+    // at `x`, if opaque there: the palette entry, whether it is behind the
+    // background and whether it is sprite 0
+    fn pixel(&self, x: usize) -> Option<(u8, bool, bool)> {
+        let column = x.checked_sub(self.x).filter(|&column| column < 8)?;
+        let bit = if self.attributes & 0x40 != 0 {
+            column
+        } else {
+            7 - column
+        };
+        let colour = (self.planes[0] >> bit & 1) | (self.planes[1] >> bit & 1) << 1;
+        let entry = 0x10 + (self.attributes & 0b11) * 4 + colour;
+        (colour != 0).then_some((entry, self.attributes & 0x20 != 0, self.sprite_zero))
+    }
+}
+
+// the 2-bit colour of pixel `bit` of the tile row at `pattern`
+fn pattern_bit(pattern: u16, bit: usize, cartridge: &mut dyn Mapper) -> u8 {
+    let low = cartridge.ppu_read(pattern) >> bit & 1;
+    let high = cartridge.ppu_read(pattern + 8) >> bit & 1;
+    low | high << 1
+}
+
+// where in the 32 bytes of palette RAM `addr`, 0x3F00-0x3FFF, lands. The
+// palettes repeat up to 0x3FFF, and the first colour of each sprite
+// palette is the one of the background palette below it, so 0x3F10 is the
+// backdrop too.
+fn palette_index(addr: u16) -> usize {
+    let index = (mirror_range(addr, 0x3F00, 0x20) - 0x3F00) as usize;
+    if index >= 0x10 && index.is_multiple_of(4) {
+        index - 0x10
+    } else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    // 8KB of CHR ROM holding the low byte of each address
+    fn cartridge(mirroring: Mirroring) -> Box<dyn Mapper> {
+        let chr: Vec<u8> = (0..0x2000).map(|addr| addr as u8).collect();
+        test_rom()
+            .chr_banks(1)
+            .chr_bytes(0, &chr)
+            .mirroring(mirroring)
+            .build()
+            .into_mapper()
+            .unwrap()
+    }
+
+    fn set_addr(ppu: &mut PPU, addr: u16) {
+        ppu.write_addr((addr >> 8) as u8);
+        ppu.write_addr(addr as u8);
+    }
+
+    #[test]
+    fn test_ppudata_reads_are_buffered() {
+        let mut cartridge = cartridge(Mirroring::Horizontal);
+        let mut ppu = PPU::new();
+        set_addr(&mut ppu, 0x0123);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x23);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x24);
+
+        set_addr(&mut ppu, 0x2405);
+        ppu.write_data(0x66, &mut *cartridge);
+        ppu.write_data(0x77, &mut *cartridge);
+        set_addr(&mut ppu, 0x2405);
+        ppu.read_data(&mut *cartridge);
+        assert_eq!(ppu.peek_data(), 0x66);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x66);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x77);
+        // horizontal mirroring shows 0x2400 at 0x2000 too
+        set_addr(&mut ppu, 0x2005);
+        ppu.read_data(&mut *cartridge);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x66);
+    }
+
+    #[test]
+    fn test_palette_reads_are_direct_and_fill_the_buffer_from_below() {
+        let mut cartridge = cartridge(Mirroring::Vertical);
+        let mut ppu = PPU::new();
+        set_addr(&mut ppu, 0x2f05);
+        ppu.write_data(0x44, &mut *cartridge);
+        set_addr(&mut ppu, 0x3f05);
+        ppu.write_data(0x2a, &mut *cartridge);
+
+        set_addr(&mut ppu, 0x3f05);
+        assert_eq!(ppu.peek_data(), 0x2a);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x2a);
+        // the buffer took 0x2f05 on the way
+        set_addr(&mut ppu, 0x2000);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x44);
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut cartridge = cartridge(Mirroring::Vertical);
+        let mut ppu = PPU::new();
+        set_addr(&mut ppu, 0x3f10);
+        ppu.write_data(0x21, &mut *cartridge);
+        set_addr(&mut ppu, 0x3f00);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x21);
+
+        for (addr, mirror) in [(0x3f04, 0x3f14), (0x3f08, 0x3f18), (0x3f0c, 0x3f1c)] {
+            set_addr(&mut ppu, addr);
+            ppu.write_data(addr as u8, &mut *cartridge);
+            set_addr(&mut ppu, mirror);
+            assert_eq!(
+                ppu.read_data(&mut *cartridge),
+                addr as u8,
+                "{:#06x}",
+                mirror
+            );
+        }
+        // the other sprite colours are their own
+        set_addr(&mut ppu, 0x3f11);
+        ppu.write_data(0x30, &mut *cartridge);
+        set_addr(&mut ppu, 0x3f01);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x00);
+
+        // and the 32 bytes repeat up to 0x3fff
+        for addr in [0x3f20, 0x3f40, 0x3fe0] {
+            set_addr(&mut ppu, addr);
+            assert_eq!(ppu.read_data(&mut *cartridge), 0x21, "{:#06x}", addr);
+        }
+        set_addr(&mut ppu, 0x3ff1);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x30);
+        set_addr(&mut ppu, 0x3fe4);
+        ppu.write_data(0x15, &mut *cartridge);
+        assert_eq!(ppu.palette[0x04], 0x15);
+    }
+
+    #[test]
+    fn test_ppudata_address_wraps_past_0x3fff() {
+        let mut cartridge = test_rom().build().into_mapper().unwrap();
+        let mut ppu = PPU::new();
+        set_addr(&mut ppu, 0x3fff);
+        ppu.write_data(0x0f, &mut *cartridge);
+        ppu.write_data(0x5a, &mut *cartridge);
+        assert_eq!(ppu.palette[0x1f], 0x0f);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x5a);
+        assert_eq!(ppu.data_addr(), 0x0001);
+
+        ppu.write_ctrl(0b0000_0100);
+        set_addr(&mut ppu, 0x3fe0);
+        ppu.read_data(&mut *cartridge);
+        assert_eq!(ppu.data_addr(), 0x0000);
+        ppu.read_data(&mut *cartridge);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x5a);
+    }
+
+    #[test]
+    fn test_palette_index_folds_the_mirrors() {
+        assert_eq!(palette_index(0x3f00), 0x00);
+        assert_eq!(palette_index(0x3f1f), 0x1f);
+        assert_eq!(palette_index(0x3f20), 0x00);
+        assert_eq!(palette_index(0x3f35), 0x15);
+        assert_eq!(palette_index(0x3fff), 0x1f);
+        // the backdrop step applies in the mirrors too
+        assert_eq!(palette_index(0x3f30), 0x00);
+        assert_eq!(palette_index(0x3ffc), 0x0c);
+    }
+
+    #[test]
+    fn test_mirror_vram_addr() {
+        let addresses = [
+            0x2000, 0x23ff, 0x2400, 0x27ff, 0x2800, 0x2bff, 0x2c00, 0x2fff, 0x3000, 0x3eff,
+        ];
+        let expected = [
+            (
+                Mirroring::Horizontal,
+                [
+                    0x000, 0x3ff, 0x000, 0x3ff, 0x400, 0x7ff, 0x400, 0x7ff, 0x000, 0x6ff,
+                ],
+            ),
+            (
+                Mirroring::Vertical,
+                [
+                    0x000, 0x3ff, 0x400, 0x7ff, 0x000, 0x3ff, 0x400, 0x7ff, 0x000, 0x6ff,
+                ],
+            ),
+            (
+                Mirroring::SingleScreenLower,
+                [
+                    0x000, 0x3ff, 0x000, 0x3ff, 0x000, 0x3ff, 0x000, 0x3ff, 0x000, 0x2ff,
+                ],
+            ),
+            (
+                Mirroring::SingleScreenUpper,
+                [
+                    0x400, 0x7ff, 0x400, 0x7ff, 0x400, 0x7ff, 0x400, 0x7ff, 0x400, 0x6ff,
+                ],
+            ),
+            (
+                Mirroring::FourScreen,
+                [
+                    0x000, 0x3ff, 0x400, 0x7ff, 0x800, 0xbff, 0xc00, 0xfff, 0x000, 0xeff,
+                ],
+            ),
+        ];
+        for (mirroring, indexes) in expected {
+            for (addr, index) in addresses.iter().zip(indexes) {
+                assert_eq!(
+                    PPU::mirror_vram_addr(*addr, mirroring),
+                    index,
+                    "{:#06x} {:?}",
+                    addr,
+                    mirroring
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_four_screen_uses_cartridge_ram() {
+        for (mapper, separate) in [(4, true), (0, false)] {
+            let mut cartridge = test_rom()
+                .mapper(mapper)
+                .mirroring(Mirroring::FourScreen)
+                .build()
+                .into_mapper()
+                .unwrap();
+            let mut ppu = PPU::new();
+            for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2c00].into_iter().enumerate() {
+                set_addr(&mut ppu, addr);
+                ppu.write_data(i as u8 + 1, &mut *cartridge);
+            }
+            set_addr(&mut ppu, 0x2000);
+            ppu.read_data(&mut *cartridge);
+            let first = ppu.read_data(&mut *cartridge);
+            set_addr(&mut ppu, 0x2800);
+            ppu.read_data(&mut *cartridge);
+            let third = ppu.read_data(&mut *cartridge);
+            // without RAM on the board, 0x2800 is 0x2000 again
+            assert_eq!((first, third), if separate { (1, 3) } else { (3, 3) });
+            assert_eq!(cartridge.nametable_ram().is_some(), separate);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "0x1fff is below 0x2000")]
+    fn test_mirror_vram_addr_rejects_pattern_tables() {
+        PPU::mirror_vram_addr(0x1fff, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_mirroring_changes_take_effect_at_once() {
+        // AxROM picks the nametable with bit 4 of its bank register
+        let mut cartridge = test_rom().mapper(7).build().into_mapper().unwrap();
+        let mut ppu = PPU::new();
+        cartridge.cpu_write(0x8000, 0x00);
+        set_addr(&mut ppu, 0x2c10);
+        ppu.write_data(0x99, &mut *cartridge);
+
+        let read_2010 = |ppu: &mut PPU, cartridge: &mut dyn Mapper| {
+            set_addr(ppu, 0x2010);
+            ppu.read_data(cartridge);
+            ppu.read_data(cartridge)
+        };
+        assert_eq!(read_2010(&mut ppu, &mut *cartridge), 0x99);
+        cartridge.cpu_write(0x8000, 0x10);
+        assert_eq!(read_2010(&mut ppu, &mut *cartridge), 0x00);
+        cartridge.cpu_write(0x8000, 0x00);
+        assert_eq!(read_2010(&mut ppu, &mut *cartridge), 0x99);
+    }
+
+    #[test]
+    fn test_ppudata_goes_through_chr_ram() {
+        let mut cartridge = test_rom().build().into_mapper().unwrap();
+        let mut ppu = PPU::new();
+        set_addr(&mut ppu, 0x1ff0);
+        ppu.write_data(0xab, &mut *cartridge);
+        assert_eq!(cartridge.ppu_read(0x1ff0), 0xab);
+    }
+
+    #[test]
+    fn test_status_read_clears_vblank() {
+        let mut ppu = PPU::new();
+        assert_eq!(ppu.read_status() & 0x80, 0);
+        ppu.set_vblank();
+        assert_eq!(ppu.peek_status() & 0x80, 0x80);
+        assert_eq!(ppu.read_status() & 0x80, 0x80);
+        assert_eq!(ppu.read_status() & 0x80, 0);
+    }
+
+    #[test]
+    fn test_status_read_restarts_address_writes() {
+        let mut cartridge = cartridge(Mirroring::Vertical);
+        let mut ppu = PPU::new();
+        ppu.write_addr(0x21);
+        ppu.read_status();
+        // a first write again, so 0x02 is the high byte
+        ppu.write_addr(0x02);
+        ppu.write_addr(0x34);
+        ppu.read_data(&mut *cartridge);
+        assert_eq!(ppu.read_data(&mut *cartridge), 0x34);
+    }
+
+    #[test]
+    fn test_status_low_bits_are_open_bus() {
+        let mut cartridge = cartridge(Mirroring::Vertical);
+        let mut ppu = PPU::new();
+        ppu.set_vblank();
+        ppu.write_register(PPUMASK, 0b0101_1010, &mut *cartridge);
+        assert_eq!(ppu.peek_register(PPUSTATUS, &*cartridge), 0b1001_1010);
+        assert_eq!(ppu.read_register(PPUSTATUS, &mut *cartridge), 0b1001_1010);
+        assert_eq!(ppu.read_register(PPUSTATUS, &mut *cartridge), 0b0001_1010);
+    }
+
+    #[test]
+    fn test_rendering_clocks_the_mmc3_irq_counter() {
+        let mut cartridge = test_rom()
+            .mapper(4)
+            .prg_banks(2)
+            .build()
+            .into_mapper()
+            .unwrap();
+        cartridge.cpu_write(0xc000, 10);
+        cartridge.cpu_write(0xc001, 0);
+        cartridge.cpu_write(0xe001, 0);
+        let mut ppu = PPU::new();
+        // sprites from 0x1000, so A12 rises once a line
+        ppu.write_ctrl(0x08);
+
+        // nothing while rendering is off
+        ppu.tick_dots(262 * 341, &mut *cartridge);
+        assert!(!cartridge.irq_pending());
+
+        ppu.write_mask(0x18);
+        // the first rise loads the counter, the tenth after it takes it to 0
+        ppu.tick_dots(10 * 341 + 256, &mut *cartridge);
+        assert!(!cartridge.irq_pending());
+        ppu.tick_dots(1, &mut *cartridge);
+        assert_eq!(ppu.position(), (10, 257));
+        assert!(cartridge.irq_pending());
+    }
+
+    #[test]
+    fn test_scanlines_reach_the_mmc5_irq() {
+        let mut cartridge = test_rom()
+            .mapper(5)
+            .prg_banks(2)
+            .build()
+            .into_mapper()
+            .unwrap();
+        let mut ppu = PPU::new();
+        // out of the frame in vertical blank, so counting starts at line 0
+        ppu.tick_dots(241 * 341, &mut *cartridge);
+        cartridge.cpu_write(0x5203, 20);
+        cartridge.cpu_write(0x5204, 0x80);
+        ppu.tick_dots(21 * 341 + 20 * 341 - 1, &mut *cartridge);
+        assert!(!cartridge.irq_pending());
+        ppu.tick_dots(1, &mut *cartridge);
+        assert_eq!(ppu.position(), (20, 0));
+        assert!(cartridge.irq_pending());
+    }
+
+    #[test]
+    fn test_status_read_racing_vblank_cancels_the_nmi() {
+        let mut cartridge = cartridge(Mirroring::Vertical);
+        let mut ppu = PPU::new();
+        ppu.write_ctrl(0x80);
+
+        // a dot early: the flag reads clear and stays clear
+        ppu.tick_dots(241 * 341, &mut *cartridge);
+        assert_eq!(ppu.read_status() & 0x80, 0);
+        ppu.tick_dots(1, &mut *cartridge);
+        assert_eq!(ppu.peek_status() & 0x80, 0);
+        assert!(!ppu.take_nmi());
+
+        // as it is set: the flag reads set but there is no NMI
+        ppu.tick_dots(262 * 341, &mut *cartridge);
+        assert_eq!(ppu.position(), (241, 1));
+        assert_eq!(ppu.read_status() & 0x80, 0x80);
+        assert!(!ppu.take_nmi());
+
+        // later on the NMI stands
+        ppu.tick_dots(262 * 341 + 2, &mut *cartridge);
+        assert_eq!(ppu.read_status() & 0x80, 0x80);
+        assert!(ppu.take_nmi());
+    }
+
+    #[test]
+    fn test_scroll_writes_fill_t_and_x() {
+        let mut ppu = PPU::new();
+        ppu.write_ctrl(0b0000_0010);
+        assert_eq!(ppu.t, 0b000_1000_0000_0000);
+        ppu.write_scroll(0x7D);
+        assert_eq!(ppu.t, 0b000_1000_0000_1111);
+        assert_eq!(ppu.x, 0b101);
+        assert!(ppu.w);
+        ppu.write_scroll(0x5E);
+        assert_eq!(ppu.t, 0b110_1001_0110_1111);
+        assert!(!ppu.w);
+        assert_eq!(ppu.v, 0);
+
+        ppu.write_addr(0x3D);
+        assert_eq!(ppu.t, 0b011_1101_0110_1111);
+        ppu.write_addr(0xF0);
+        assert_eq!(ppu.t, 0b011_1101_1111_0000);
+        assert_eq!(ppu.v, ppu.t);
+        assert_eq!(ppu.x, 0b101);
+    }
+
+    // the split X/Y scroll example on the wiki's PPU scrolling page
+    #[test]
+    fn test_interleaved_scroll_and_addr_writes() {
+        let mut ppu = PPU::new();
+        ppu.write_addr(0x04);
+        assert_eq!(ppu.t, 0b000_0100_0000_0000);
+        ppu.write_scroll(0x3E);
+        assert_eq!(ppu.t, 0b110_0100_1110_0000);
+        ppu.write_scroll(0x7D);
+        assert_eq!(ppu.t, 0b110_0100_1110_1111);
+        assert_eq!(ppu.x, 0b101);
+        ppu.write_addr(0xEF);
+        assert_eq!(ppu.t, 0b110_0100_1110_1111);
+        assert_eq!(ppu.v, 0b110_0100_1110_1111);
+
+        // reading PPUSTATUS in between starts the pair over
+        ppu.write_scroll(0xFF);
+        ppu.read_status();
+        ppu.write_scroll(0x00);
+        assert_eq!(ppu.t, 0b110_0100_1110_0000);
+        assert_eq!(ppu.x, 0);
+    }
+
+    #[test]
+    fn test_oam_data_round_trip() {
+        let mut ppu = PPU::new();
+        ppu.write_oam_addr(0x10);
+        for byte in [0x40, 0x05, 0xff, 0x80] {
+            ppu.write_oam_data(byte);
+        }
+        assert_eq!(ppu.oam_addr, 0x14);
+
+        ppu.write_oam_addr(0x10);
+        assert_eq!(ppu.read_oam_data(), 0x40);
+        // reads do not move the address
+        assert_eq!(ppu.read_oam_data(), 0x40);
+        let sprite: Vec<u8> = (0x10..0x14)
+            .map(|addr| {
+                ppu.write_oam_addr(addr);
+                ppu.read_oam_data()
+            })
+            .collect();
+        assert_eq!(sprite, [0x40, 0x05, 0xe3, 0x80]);
+    }
+
+    #[test]
+    fn test_vblank_spans_scanlines_241_to_261() {
+        let mut cartridge = cartridge(Mirroring::Vertical);
+        let mut ppu = PPU::new();
+        ppu.tick_dots(241 * 341, &mut *cartridge);
+        assert_eq!(ppu.peek_status() & 0x80, 0);
+        ppu.tick_dots(1, &mut *cartridge);
+        assert_eq!(ppu.position(), (241, 1));
+        assert_eq!(ppu.peek_status() & 0x80, 0x80);
+        ppu.tick_dots(20 * 341, &mut *cartridge);
+        assert_eq!(ppu.position(), (261, 1));
+        assert_eq!(ppu.peek_status() & 0x80, 0);
+    }
+
+    #[test]
+    fn test_ctrl_increment_32() {
+        let mut cartridge = cartridge(Mirroring::Vertical);
+        let mut ppu = PPU::new();
+        ppu.write_ctrl(0b0000_0100);
+        set_addr(&mut ppu, 0x2000);
+        ppu.read_data(&mut *cartridge);
+        ppu.read_data(&mut *cartridge);
+        assert_eq!(ppu.v, 0x2040);
+
+        ppu.write_ctrl(0);
+        ppu.write_data(0x12, &mut *cartridge);
+        assert_eq!(ppu.v, 0x2041);
+    }
+
+    #[test]
+    fn test_ctrl_fields() {
+        let mut ppu = PPU::new();
+        ppu.write_ctrl(0b0011_1011);
+        assert_eq!(ppu.t, 0x0C00);
+        assert_eq!(ppu.sprite_pattern_table(), 0x1000);
+        assert_eq!(ppu.background_pattern_table(), 0x1000);
+        assert_eq!(ppu.sprite_height(), 16);
+        assert!(!ppu.nmi_enabled());
+
+        ppu.t = 0x7FFF;
+        ppu.write_ctrl(0b1000_0001);
+        assert_eq!(ppu.t, 0x77FF);
+        assert_eq!(ppu.sprite_pattern_table(), 0x0000);
+        assert_eq!(ppu.sprite_height(), 8);
+        assert!(ppu.nmi_enabled());
+    }
+
+    #[test]
+    fn test_enabling_nmi_during_vblank_raises_one() {
+        let mut ppu = PPU::new();
+        ppu.set_vblank();
+        assert!(!ppu.take_nmi());
+
+        ppu.write_ctrl(0x80);
+        assert!(ppu.take_nmi());
+        assert!(!ppu.take_nmi());
+        // staying enabled is not another edge
+        ppu.write_ctrl(0x84);
+        assert!(!ppu.take_nmi());
+
+        // nor is enabling once vertical blank has been acknowledged
+        ppu.write_ctrl(0x00);
+        ppu.read_status();
+        ppu.write_ctrl(0x80);
+        assert!(!ppu.take_nmi());
+    }
+
+    #[test]
+    fn test_vblank_raises_nmi_when_enabled() {
+        let mut cartridge = cartridge(Mirroring::Vertical);
+        let mut ppu = PPU::new();
+        ppu.write_ctrl(0x80);
+        ppu.tick_dots(241 * 341 + 1, &mut *cartridge);
+        assert!(ppu.take_nmi());
+        ppu.tick_dots(341 * 262, &mut *cartridge);
+        assert!(ppu.take_nmi());
+        assert!(!ppu.take_nmi());
+    }
+
+    // every tile solid in colour 3, the backdrop blue, background colour 3
+    // red and sprite colour 3 green, sprite 0 at 16, 16 and nothing else on
+    // screen
+    fn solid_screen() -> (PPU, Box<dyn Mapper>) {
+        let cartridge = test_rom()
+            .chr_banks(1)
+            .chr_bytes(0, &[0xff; 16])
+            .build()
+            .into_mapper()
+            .unwrap();
+        let mut ppu = PPU::new();
+        ppu.palette[0x00] = 0x12;
+        ppu.palette[0x03] = 0x16;
+        ppu.palette[0x13] = 0x1a;
+        ppu.oam = [0xff; 256];
+        ppu.oam[0..4].copy_from_slice(&[15, 0, 0, 16]);
+        (ppu, cartridge)
+    }
+
+    fn pixel(ppu: &PPU, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * Frame::WIDTH + x) * 3;
+        let data = &ppu.frame().data;
+        (data[base], data[base + 1], data[base + 2])
+    }
+
+    #[test]
+    fn test_mask_hides_the_background() {
+        let (mut ppu, mut cartridge) = solid_screen();
+        ppu.write_mask(0b0001_0110);
+        ppu.tick_dots(240 * 341, &mut *cartridge);
+        for (x, y) in [(0, 0), (15, 16), (24, 16), (255, 239)] {
+            assert_eq!(pixel(&ppu, x, y), SYSTEM_PALETTE[0x12], "{}, {}", x, y);
+        }
+        assert_eq!(pixel(&ppu, 16, 16), SYSTEM_PALETTE[0x1a]);
+        assert_eq!(ppu.peek_status() & 0x40, 0);
+
+        ppu.write_mask(0b0001_1110);
+        ppu.tick_dots(262 * 341, &mut *cartridge);
+        assert_eq!(pixel(&ppu, 0, 0), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(&ppu, 16, 16), SYSTEM_PALETTE[0x1a]);
+        assert_eq!(ppu.peek_status() & 0x40, 0x40);
+    }
+
+    #[test]
+    fn test_mask_left_column_and_greyscale() {
+        let (mut ppu, mut cartridge) = solid_screen();
+        assert!(!ppu.rendering_enabled());
+        ppu.write_mask(0b1010_1001);
+        assert!(ppu.rendering_enabled());
+        assert_eq!(ppu.emphasis(), 0b101);
+        ppu.tick_dots(240 * 341, &mut *cartridge);
+        // 0x12 and 0x16 in grey
+        assert_eq!(pixel(&ppu, 7, 0), SYSTEM_PALETTE[0x10]);
+        assert_eq!(pixel(&ppu, 8, 0), SYSTEM_PALETTE[0x10]);
+        ppu.write_mask(0b0000_1000);
+        ppu.tick_dots(262 * 341, &mut *cartridge);
+        assert_eq!(pixel(&ppu, 7, 0), SYSTEM_PALETTE[0x12]);
+        assert_eq!(pixel(&ppu, 8, 0), SYSTEM_PALETTE[0x16]);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+use crate::cpu::{CpuError, Instruction, Mem, StopReason, CPU};
+use crate::disasm;
+
+/// Counts how often each opcode and each address executes, and how many
+/// cycles each address costs, to find where a program spends its time.
+///
+/// Either call `run`, or hand `record` every instruction from a callback.
+pub struct Profiler {
+    opcodes: [u64; 256],
+    addresses: HashMap<u16, AddressStats>,
+    // the instruction recorded last, and the cycle count when it started;
+    // its cycles are only known once the next one comes along
+    pending: Option<(u16, u64)>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct AddressStats {
+    count: u64,
+    cycles: u64,
+}
+
+/// What a `Profiler` has seen so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileReport {
+    pub instructions: u64,
+    pub cycles: u64,
+    /// The most executed addresses, hottest first.
+    pub hot_spots: Vec<HotSpot>,
+    /// Every opcode that executed, most frequent first.
+    pub opcodes: Vec<OpcodeCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotSpot {
+    pub addr: u16,
+    pub count: u64,
+    pub cycles: u64,
+    pub disassembly: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeCount {
+    pub code: u8,
+    pub count: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            opcodes: [0; 256],
+            addresses: HashMap::new(),
+            pending: None,
+        }
+    }
+
+    /// Runs `cpu` like `CPU::run_with_callback`, profiling every instruction.
+    pub fn run<M: Mem>(&mut self, cpu: &mut CPU<M>) -> Result<StopReason, CpuError> {
+        cpu.run_with_callback(|cpu, instruction| self.record(cpu, instruction))
+    }
+
+    /// Counts `instruction`, which is about to execute, and charges the cycles
+    /// since the previous call to the previous instruction. Meant to be
+    /// returned from a run callback.
+    pub fn record<M: Mem>(&mut self, cpu: &CPU<M>, instruction: &Instruction) -> ControlFlow<()> {
+        self.settle(cpu.cycles);
+        self.opcodes[instruction.opcode.code as usize] += 1;
+        self.addresses.entry(instruction.pc).or_default().count += 1;
+        self.pending = Some((instruction.pc, cpu.cycles));
+        ControlFlow::Continue(())
+    }
+
+    /// The `top` hottest addresses, disassembled from `cpu`'s memory, along
+    /// with the opcode counts.
+    pub fn report<M: Mem>(&mut self, cpu: &CPU<M>, top: usize) -> ProfileReport {
+        self.settle(cpu.cycles);
+
+        let mut addresses: Vec<(u16, AddressStats)> = self
+            .addresses
+            .iter()
+            .map(|(addr, stats)| (*addr, *stats))
+            .collect();
+        addresses
+            .sort_by(|(a, a_stats), (b, b_stats)| b_stats.count.cmp(&a_stats.count).then(a.cmp(b)));
+        let hot_spots = addresses
+            .iter()
+            .take(top)
+            .map(|(addr, stats)| HotSpot {
+                addr: *addr,
+                count: stats.count,
+                cycles: stats.cycles,
+                disassembly: disasm::disassemble(cpu, *addr).text,
+            })
+            .collect();
+
+        let mut opcodes: Vec<OpcodeCount> = self
+            .opcodes
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(code, count)| OpcodeCount {
+                code: code as u8,
+                count: *count,
+            })
+            .collect();
+        opcodes.sort_by(|a, b| b.count.cmp(&a.count).then(a.code.cmp(&b.code)));
+
+        ProfileReport {
+            instructions: self.opcodes.iter().sum(),
+            cycles: self.addresses.values().map(|stats| stats.cycles).sum(),
+            hot_spots,
+            opcodes,
+        }
+    }
+
+    fn settle(&mut self, cycles: u64) {
+        if let Some((addr, start)) = self.pending.take() {
+            self.addresses.entry(addr).or_default().cycles += cycles - start;
+            self.pending = Some((addr, cycles));
+        }
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CpuBuilder;
+
+    #[test]
+    fn test_loop_body_is_the_hottest() {
+        // LDX #$10; loop: DEX; BNE loop; BRK
+        let mut cpu = CpuBuilder::new()
+            .program(&[0xa2, 0x10, 0xca, 0xd0, 0xfd, 0x00])
+            .build();
+        let mut profiler = Profiler::new();
+        assert_eq!(profiler.run(&mut cpu), Ok(StopReason::Brk));
+
+        let report = profiler.report(&cpu, 2);
+        assert_eq!(
+            report.hot_spots,
+            vec![
+                HotSpot {
+                    addr: 0x0602,
+                    count: 16,
+                    cycles: 32,
+                    disassembly: String::from("DEX"),
+                },
+                HotSpot {
+                    addr: 0x0603,
+                    count: 16,
+                    // taken 15 times, falling through once
+                    cycles: 15 * 3 + 2,
+                    disassembly: String::from("BNE $0602"),
+                },
+            ]
+        );
+        assert_eq!(report.instructions, 1 + 16 + 16 + 1);
+        assert_eq!(report.cycles, cpu.cycles);
+        assert_eq!(
+            report.opcodes[..2],
+            [
+                OpcodeCount {
+                    code: 0xca,
+                    count: 16
+                },
+                OpcodeCount {
+                    code: 0xd0,
+                    count: 16
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_works_as_a_callback() {
+        // INX; INX; BRK
+        let mut cpu = CpuBuilder::new().program(&[0xe8, 0xe8, 0x00]).build();
+        let mut profiler = Profiler::new();
+        cpu.run_with_callback(|cpu, instruction| profiler.record(cpu, instruction))
+            .unwrap();
+
+        let report = profiler.report(&cpu, 10);
+        assert_eq!(report.instructions, 3);
+        assert_eq!(report.cycles, 4);
+        assert_eq!(report.hot_spots.len(), 3);
+        assert_eq!(
+            report.opcodes[0],
+            OpcodeCount {
+                code: 0xe8,
+                count: 2
+            }
+        );
+    }
+}
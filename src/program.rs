@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::cartridge::Rom;
+
+const PRG_ROM_START: u16 = 0x8000;
+const PRG_ROM_BANKS: u8 = 2;
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+enum Fixup {
+    // signed offset of a branch whose operand is at the fixup address
+    Relative,
+    // little-endian address for JMP/JSR and data tables
+    Absolute,
+}
+
+/// Assembles test programs from raw bytes, labels and vectors.
+///
+/// Labels may be referenced before they are defined; references are
+/// resolved when the program is built. Misuse panics, since this is meant
+/// for tests.
+#[derive(Default)]
+pub struct ProgramBuilder {
+    memory: BTreeMap<u16, u8>,
+    position: u16,
+    labels: HashMap<String, u16>,
+    fixups: Vec<(u16, String, Fixup)>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        ProgramBuilder::default()
+    }
+
+    /// Moves the write position to `addr`.
+    pub fn org(mut self, addr: u16) -> Self {
+        self.position = addr;
+        self
+    }
+
+    pub fn bytes(mut self, bytes: &[u8]) -> Self {
+        for byte in bytes {
+            self.emit(*byte);
+        }
+        self
+    }
+
+    /// Writes `value` until the position reaches `addr`.
+    pub fn pad_to(mut self, addr: u16, value: u8) -> Self {
+        assert!(
+            addr >= self.position,
+            "cannot pad backwards from {:#06x} to {:#06x}",
+            self.position,
+            addr
+        );
+        while self.position < addr {
+            self.emit(value);
+        }
+        self
+    }
+
+    /// Names the current position.
+    pub fn label(mut self, name: &str) -> Self {
+        let previous = self.labels.insert(name.to_string(), self.position);
+        assert!(previous.is_none(), "label {} defined twice", name);
+        self
+    }
+
+    /// Emits the operand of a branch instruction, so it should follow the
+    /// branch opcode, e.g. `.bytes(&[0xd0]).branch_to("loop")`.
+    pub fn branch_to(mut self, name: &str) -> Self {
+        self.fixups
+            .push((self.position, name.to_string(), Fixup::Relative));
+        self.emit(0);
+        self
+    }
+
+    /// Emits the address of a label, e.g. after a JMP or JSR opcode.
+    pub fn address_of(mut self, name: &str) -> Self {
+        self.fixups
+            .push((self.position, name.to_string(), Fixup::Absolute));
+        self.emit(0);
+        self.emit(0);
+        self
+    }
+
+    pub fn reset_vector(self, addr: u16) -> Self {
+        self.vector(RESET_VECTOR, addr)
+    }
+
+    pub fn nmi_vector(self, addr: u16) -> Self {
+        self.vector(NMI_VECTOR, addr)
+    }
+
+    pub fn irq_vector(self, addr: u16) -> Self {
+        self.vector(IRQ_VECTOR, addr)
+    }
+
+    /// The bytes from the lowest to the highest address written, with gaps
+    /// zero-filled, e.g. for `CPU::load`.
+    pub fn build(self) -> Vec<u8> {
+        let memory = self.resolve();
+        let (start, end) = match (memory.keys().next(), memory.keys().next_back()) {
+            (Some(start), Some(end)) => (*start, *end),
+            _ => return vec![],
+        };
+        (start..=end)
+            .map(|addr| *memory.get(&addr).unwrap_or(&0))
+            .collect()
+    }
+
+    /// An iNES image with the program in a 32KB NROM PRG ROM at 0x8000.
+    pub fn build_ines(self) -> Vec<u8> {
+        let memory = self.resolve();
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, PRG_ROM_BANKS, 0, 0, 0];
+        raw.resize(16, 0);
+        let mut prg = vec![0; PRG_ROM_BANKS as usize * 0x4000];
+        for (addr, value) in memory {
+            assert!(
+                addr >= PRG_ROM_START,
+                "{:#06x} is outside PRG ROM; the cartridge only covers 0x8000-0xFFFF",
+                addr
+            );
+            prg[(addr - PRG_ROM_START) as usize] = value;
+        }
+        raw.extend(prg);
+        raw
+    }
+
+    pub fn build_rom(self) -> Rom {
+        Rom::new(&self.build_ines()).unwrap()
+    }
+
+    fn vector(mut self, vector: u16, addr: u16) -> Self {
+        self.memory.insert(vector, addr as u8);
+        self.memory.insert(vector + 1, (addr >> 8) as u8);
+        self
+    }
+
+    fn emit(&mut self, byte: u8) {
+        self.memory.insert(self.position, byte);
+        self.position = self.position.wrapping_add(1);
+    }
+
+    fn resolve(mut self) -> BTreeMap<u16, u8> {
+        for (at, name, fixup) in self.fixups.iter() {
+            let target = match self.labels.get(name) {
+                Some(target) => *target,
+                None => panic!("label {} is never defined", name),
+            };
+            match fixup {
+                Fixup::Relative => {
+                    let offset = target as i32 - (*at as i32 + 1);
+                    assert!(
+                        (-128..=127).contains(&offset),
+                        "branch at {:#06x} cannot reach {} at {:#06x}",
+                        at.wrapping_sub(1),
+                        name,
+                        target
+                    );
+                    self.memory.insert(*at, offset as i8 as u8);
+                }
+                Fixup::Absolute => {
+                    self.memory.insert(*at, target as u8);
+                    self.memory.insert(at.wrapping_add(1), (target >> 8) as u8);
+                }
+            }
+        }
+        self.memory
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::{Mem, CPU};
+
+    #[test]
+    fn test_build_resolves_forward_and_backward_branches() {
+        let program = ProgramBuilder::new()
+            .org(0x0600)
+            .label("loop")
+            .bytes(&[0xe8, 0xf0]) // INX; BEQ done
+            .branch_to("done")
+            .bytes(&[0xd0]) // BNE loop
+            .branch_to("loop")
+            .label("done")
+            .bytes(&[0x00])
+            .build();
+
+        assert_eq!(program, vec![0xe8, 0xf0, 0x02, 0xd0, 0xfb, 0x00]);
+    }
+
+    #[test]
+    fn test_build_zero_fills_gaps() {
+        let program = ProgramBuilder::new()
+            .org(0x0600)
+            .bytes(&[0x4c])
+            .address_of("target")
+            .org(0x0605)
+            .label("target")
+            .bytes(&[0xea])
+            .build();
+
+        assert_eq!(program, vec![0x4c, 0x05, 0x06, 0x00, 0x00, 0xea]);
+    }
+
+    #[test]
+    fn test_built_rom_runs_on_the_bus() {
+        let rom = ProgramBuilder::new()
+            .org(0x8000)
+            .bytes(&[0xa2, 0x00]) // LDX #$00
+            .label("loop")
+            .bytes(&[0x20]) // JSR count
+            .address_of("count")
+            .bytes(&[0xe0, 0x05, 0xd0]) // CPX #$05; BNE loop
+            .branch_to("loop")
+            .bytes(&[0x00])
+            .org(0x9000)
+            .label("count")
+            .bytes(&[0xe8, 0x8e, 0x00, 0x02, 0x60]) // INX; STX $0200; RTS
+            .reset_vector(0x8000)
+            .build_rom();
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x8000);
+        cpu.run();
+
+        assert_eq!(cpu.register_x, 5);
+        assert_eq!(cpu.mem_read(0x0200), 5);
+    }
+
+    #[test]
+    fn test_build_ines_has_a_valid_header() {
+        let raw = ProgramBuilder::new()
+            .org(0xc000)
+            .pad_to(0xc010, 0xea)
+            .nmi_vector(0xc000)
+            .reset_vector(0xc004)
+            .irq_vector(0xc008)
+            .build_ines();
+
+        assert_eq!(&raw[0..6], &[0x4E, 0x45, 0x53, 0x1A, 2, 0]);
+        assert_eq!(raw.len(), 16 + 0x8000);
+        assert_eq!(raw[16 + 0x4000], 0xea);
+        assert_eq!(raw[16 + 0x4010], 0x00);
+        assert_eq!(&raw[16 + 0x7ffa..], &[0x00, 0xc0, 0x04, 0xc0, 0x08, 0xc0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot reach far")]
+    fn test_out_of_range_branch_panics() {
+        ProgramBuilder::new()
+            .org(0x0600)
+            .bytes(&[0xd0])
+            .branch_to("far")
+            .org(0x0700)
+            .label("far")
+            .build();
+    }
+}
@@ -0,0 +1,405 @@
+use crate::bus::Region;
+use crate::cartridge::{BankInfo, Mapper, Mirroring, Nrom, Rom};
+use crate::cpu::Mem;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// One access seen by a `RecordingMem`. `index` is its position among all
+/// recorded accesses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusAccess {
+    pub kind: Access,
+    pub addr: u16,
+    pub value: u8,
+    pub index: usize,
+}
+
+/// Wraps memory and records every read and write that goes through it, in
+/// order, so tests can check exactly which accesses an instruction makes.
+/// Peeks are forwarded but not recorded.
+pub struct RecordingMem<M: Mem> {
+    pub inner: M,
+    accesses: Vec<BusAccess>,
+}
+
+impl<M: Mem> RecordingMem<M> {
+    pub fn new(inner: M) -> Self {
+        RecordingMem {
+            inner,
+            accesses: Vec::new(),
+        }
+    }
+
+    pub fn accesses(&self) -> &[BusAccess] {
+        &self.accesses
+    }
+
+    /// Forgets what was recorded so far, e.g. after setting up memory.
+    pub fn clear(&mut self) {
+        self.accesses.clear();
+    }
+
+    /// Panics unless the recorded accesses are exactly `expected`, given as
+    /// `(kind, addr, value)`.
+    pub fn assert_access_sequence(&self, expected: &[(Access, u16, u8)]) {
+        let actual: Vec<(Access, u16, u8)> = self
+            .accesses
+            .iter()
+            .map(|access| (access.kind, access.addr, access.value))
+            .collect();
+        assert!(
+            actual == expected,
+            "unexpected bus accesses\n  expected: {}\n    actual: {}",
+            format_sequence(expected),
+            format_sequence(&actual)
+        );
+    }
+
+    fn record(&mut self, kind: Access, addr: u16, value: u8) {
+        let index = self.accesses.len();
+        self.accesses.push(BusAccess {
+            kind,
+            addr,
+            value,
+            index,
+        });
+    }
+}
+
+impl<M: Mem> Mem for RecordingMem<M> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = self.inner.mem_read(addr);
+        self.record(Access::Read, addr, value);
+        value
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.inner.mem_write(addr, data);
+        self.record(Access::Write, addr, data);
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.inner.mem_peek(addr)
+    }
+
+    fn take_fault(&mut self) -> Option<u16> {
+        self.inner.take_fault()
+    }
+
+    fn has_nmi_source(&self) -> bool {
+        self.inner.has_nmi_source()
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        self.inner.take_nmi()
+    }
+
+    fn tick(&mut self, cpu_cycles: u8) {
+        self.inner.tick(cpu_cycles)
+    }
+
+    fn take_stall_cycles(&mut self) -> u64 {
+        self.inner.take_stall_cycles()
+    }
+
+    fn begin_instruction(&mut self, pc: u16) {
+        self.inner.begin_instruction(pc)
+    }
+
+    fn irq_line(&self) -> bool {
+        self.inner.irq_line()
+    }
+}
+
+/// An NROM cartridge that raises an IRQ once, `cycles` CPU cycles after
+/// power on, and holds it until a write to 0xE000-0xFFFF acknowledges it,
+/// like MMC3's IRQ disable register. For testing the path from a mapper to
+/// the CPU without a real one.
+pub struct TestIrqMapper {
+    inner: Nrom,
+    // cycles left before the IRQ; None once it has fired
+    fire_in: Option<u64>,
+    pending: bool,
+}
+
+impl TestIrqMapper {
+    pub fn new(rom: Rom, cycles: u64) -> Self {
+        TestIrqMapper {
+            inner: Nrom::new(rom),
+            fire_in: Some(cycles),
+            pending: false,
+        }
+    }
+}
+
+impl Mapper for TestIrqMapper {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.inner.cpu_read(addr)
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        self.inner.cpu_peek(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xE000..=0xFFFF => self.pending = false,
+            _ => self.inner.cpu_write(addr, data),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.inner.ppu_read(addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.inner.ppu_write(addr, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.inner.mirroring()
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.pending
+    }
+
+    fn tick(&mut self, cpu_cycles: u64) {
+        if let Some(left) = self.fire_in {
+            if cpu_cycles >= left {
+                self.fire_in = None;
+                self.pending = true;
+            } else {
+                self.fire_in = Some(left - cpu_cycles);
+            }
+        }
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        self.inner.cpu_regions()
+    }
+
+    fn bank_info(&self) -> BankInfo {
+        self.inner.bank_info()
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.inner.prg_ram()
+    }
+
+    fn take_fault(&mut self) -> Option<u16> {
+        self.inner.take_fault()
+    }
+}
+
+// `R $0600=8D, W $0200=42`
+fn format_sequence(accesses: &[(Access, u16, u8)]) -> String {
+    accesses
+        .iter()
+        .map(|(kind, addr, value)| {
+            let kind = match kind {
+                Access::Read => 'R',
+                Access::Write => 'W',
+            };
+            format!("{} ${:04X}={:02X}", kind, addr, value)
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::{StopReason, CPU};
+    use crate::flat_ram::FlatRam;
+    use crate::ppu::PPU;
+    use std::ops::ControlFlow;
+
+    #[test]
+    fn test_sta_absolute_fetches_then_writes_once() {
+        let mut mem = RecordingMem::new(FlatRam::new());
+        // STA $0200
+        mem.mem_write_slice(0x0600, &[0x8d, 0x00, 0x02]);
+        mem.clear();
+        let mut cpu = CPU::new(mem);
+        cpu.program_counter = 0x0600;
+        cpu.register_a = 0x42;
+
+        cpu.step().unwrap();
+
+        cpu.bus.assert_access_sequence(&[
+            (Access::Read, 0x0600, 0x8d),
+            (Access::Read, 0x0601, 0x00),
+            (Access::Read, 0x0602, 0x02),
+            (Access::Write, 0x0200, 0x42),
+        ]);
+        assert_eq!(cpu.bus.accesses()[3].index, 3);
+    }
+
+    #[test]
+    fn test_indexed_loads_read_their_operands_once() {
+        let mut mem = RecordingMem::new(FlatRam::new());
+        // LDA $0200,X; LDA ($10),Y
+        mem.mem_write_slice(0x0600, &[0xbd, 0x00, 0x02, 0xb1, 0x10]);
+        mem.mem_write_slice(0x0010, &[0xff, 0x02]);
+        mem.mem_write(0x0201, 0x11);
+        mem.mem_write(0x0300, 0x22);
+        mem.clear();
+        let mut cpu = CPU::new(mem);
+        cpu.program_counter = 0x0600;
+        (cpu.register_x, cpu.register_y) = (1, 1);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        cpu.bus.assert_access_sequence(&[
+            (Access::Read, 0x0600, 0xbd),
+            (Access::Read, 0x0601, 0x00),
+            (Access::Read, 0x0602, 0x02),
+            (Access::Read, 0x0201, 0x11),
+            (Access::Read, 0x0603, 0xb1),
+            (Access::Read, 0x0604, 0x10),
+            (Access::Read, 0x0010, 0xff),
+            (Access::Read, 0x0011, 0x02),
+            (Access::Read, 0x0300, 0x22),
+        ]);
+        // the second load crossed into page 3
+        assert_eq!(cpu.cycles, 4 + 6);
+    }
+
+    // CLI (or SEI); loop: INX; JMP loop, with an IRQ handler at 0x9000 that
+    // counts in $10, acknowledges if `acknowledge` and returns
+    fn irq_cpu(cli: bool, acknowledge: bool) -> CPU<Bus> {
+        let mut rom = Rom::empty();
+        let flag = if cli { 0x58 } else { 0x78 };
+        rom.prg_rom[..5].copy_from_slice(&[flag, 0xe8, 0x4c, 0x01, 0x80]);
+        // INC $10; STA $E000 or NOP NOP NOP; RTI
+        let ack: [u8; 3] = if acknowledge {
+            [0x8d, 0x00, 0xe0]
+        } else {
+            [0xea, 0xea, 0xea]
+        };
+        rom.prg_rom[0x1000..0x1002].copy_from_slice(&[0xe6, 0x10]);
+        rom.prg_rom[0x1002..0x1005].copy_from_slice(&ack);
+        rom.prg_rom[0x1005] = 0x40;
+        rom.prg_rom[0x7ffc..0x8000].copy_from_slice(&[0x00, 0x80, 0x00, 0x90]);
+        let mut cpu = CPU::new(Bus::with_mapper(Box::new(TestIrqMapper::new(rom, 50))));
+        cpu.reset();
+        cpu
+    }
+
+    fn run_steps(cpu: &mut CPU<Bus>, steps: usize) {
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_mapper_irq_reaches_the_cpu() {
+        let mut cpu = irq_cpu(true, true);
+        run_steps(&mut cpu, 10);
+        assert_eq!(cpu.mem_read(0x0010), 0);
+
+        let mut entered = false;
+        for _ in 0..20 {
+            cpu.step().unwrap();
+            entered |= cpu.program_counter & 0xff00 == 0x9000;
+        }
+        assert!(entered);
+        assert_eq!(cpu.mem_read(0x0010), 1);
+        assert_eq!(cpu.stats().irqs, 1);
+        assert!(!cpu.interrupt_disable());
+    }
+
+    #[test]
+    fn test_breakpoint_on_the_irq_handler_fires() {
+        let mut cpu = irq_cpu(true, true);
+        cpu.add_breakpoint(0x9000);
+        assert_eq!(cpu.try_run(), Ok(StopReason::Breakpoint(0x9000)));
+        assert_eq!(cpu.stats().irqs, 1);
+        assert_eq!(cpu.mem_read(0x0010), 0);
+
+        // resuming runs the handler, shown to the callback from its start
+        let mut seen = vec![];
+        let run = cpu.run_for_cycles_with_callback(20, |_, instruction| {
+            seen.push(instruction.pc);
+            ControlFlow::Continue(())
+        });
+        assert!(run.is_ok());
+        assert_eq!(&seen[..3], &[0x9000, 0x9002, 0x9005]);
+        assert_eq!(cpu.mem_read(0x0010), 1);
+    }
+
+    #[test]
+    fn test_step_into_an_irq_reports_the_handler() {
+        let mut cpu = irq_cpu(true, true);
+        loop {
+            let info = cpu.step().unwrap();
+            if cpu.stats().irqs == 1 {
+                // INC $10 after the 7 cycles of the interrupt
+                assert_eq!(
+                    (info.pc, info.mnemonic, info.cycles),
+                    (0x9000, "INC", 7 + 5)
+                );
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_vblank_nmi_reaches_the_cpu() {
+        let mut rom = Rom::empty();
+        // LDA #$80; STA $2000; loop: JMP loop
+        rom.prg_rom[..8].copy_from_slice(&[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0x80]);
+        // INC $10; RTI
+        rom.prg_rom[0x1000..0x1003].copy_from_slice(&[0xe6, 0x10, 0x40]);
+        rom.prg_rom[0x7ffa..0x7ffe].copy_from_slice(&[0x00, 0x90, 0x00, 0x80]);
+        let mut bus = Bus::new(rom);
+        bus.attach_ppu(Box::new(PPU::new()));
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+
+        // vertical blank starts 241 scanlines in, at 27394 CPU cycles; the
+        // loop runs on with I set, as only the NMI can break it
+        cpu.add_breakpoint(0x9000);
+        let (_, stop) = cpu.run_for_cycles(30000).unwrap();
+        assert_eq!(stop, Some(StopReason::Breakpoint(0x9000)));
+        assert!((27394..27394 + 10).contains(&cpu.cycles), "{}", cpu.cycles);
+        assert_eq!(cpu.stats().nmis, 1);
+        assert!(cpu.interrupt_disable());
+
+        cpu.run_for_cycles(20).unwrap();
+        assert_eq!(cpu.mem_read(0x0010), 1);
+        assert_eq!(cpu.program_counter, 0x8005);
+        assert_eq!(cpu.stats().nmis, 1);
+    }
+
+    #[test]
+    fn test_mapper_irq_is_masked_by_i() {
+        let mut cpu = irq_cpu(false, true);
+        run_steps(&mut cpu, 40);
+        assert_eq!(cpu.mem_read(0x0010), 0);
+        assert_eq!(cpu.stats().irqs, 0);
+    }
+
+    #[test]
+    fn test_unacknowledged_irq_is_taken_again() {
+        let mut cpu = irq_cpu(true, false);
+        run_steps(&mut cpu, 40);
+        assert!(cpu.stats().irqs > 1);
+        assert_eq!(cpu.mem_read(0x0010) as u64, cpu.stats().irqs);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected: W $0200=42\n    actual: R $0200=00")]
+    fn test_assert_access_sequence_shows_both_sequences() {
+        let mut mem = RecordingMem::new(FlatRam::new());
+        mem.mem_read(0x0200);
+        mem.assert_access_sequence(&[(Access::Write, 0x0200, 0x42)]);
+    }
+}
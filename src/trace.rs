@@ -0,0 +1,321 @@
+use crate::cpu::{AddressingMode, CpuFlags, Mem, NesCpu, CPU};
+use crate::disasm;
+
+/// Formats the instruction at the program counter the way nestest.log does,
+/// so runs can be diffed against the reference log. Memory is only peeked.
+pub fn trace<M: Mem>(cpu: &CPU<M>) -> String {
+    let begin = cpu.program_counter;
+    let instruction = match cpu.decode() {
+        Ok(instruction) => instruction,
+        Err(_) => {
+            let asm_str = format!("{:04x}  {:02x}        ???", begin, cpu.mem_peek(begin));
+            return format_registers(cpu, &asm_str);
+        }
+    };
+    let ops = instruction.opcode;
+    let operands = instruction.operands();
+
+    let mut hex_dump = vec![ops.code];
+    hex_dump.extend_from_slice(operands);
+
+    let (mem_addr, stored_value) = match instruction.address {
+        Some(addr) => (addr, cpu.mem_peek(addr)),
+        None => (0, 0),
+    };
+
+    let operand = disasm::format_operand(ops, begin, operands);
+    let tmp = match ops.mode {
+        AddressingMode::ZeroPage => format!("{} = {:02x}", operand, stored_value),
+        AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+            format!("{} @ {:02x} = {:02x}", operand, mem_addr, stored_value)
+        }
+        AddressingMode::Indirect_X => format!(
+            "{} @ {:02x} = {:04x} = {:02x}",
+            operand,
+            operands[0].wrapping_add(cpu.register_x),
+            mem_addr,
+            stored_value
+        ),
+        AddressingMode::Indirect_Y => format!(
+            "{} = {:04x} @ {:04x} = {:02x}",
+            operand,
+            mem_addr.wrapping_sub(cpu.register_y as u16),
+            mem_addr,
+            stored_value
+        ),
+        AddressingMode::Indirect => format!("{} = {:04x}", operand, mem_addr),
+        // JMP and JSR only use the address
+        AddressingMode::Absolute if ops.code == 0x4c || ops.code == 0x20 => operand,
+        AddressingMode::Absolute => format!("{} = {:02x}", operand, stored_value),
+        AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+            format!("{} @ {:04x} = {:02x}", operand, mem_addr, stored_value)
+        }
+        _ => operand,
+    };
+
+    let hex_str = hex_dump
+        .iter()
+        .map(|z| format!("{:02x}", z))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let asm_str = format!("{:04x}  {:8} {: >4} {}", begin, hex_str, ops.mnemonic, tmp)
+        .trim()
+        .to_string();
+
+    format_registers(cpu, &asm_str)
+}
+
+/// `trace` followed by the banks the cartridge has mapped, for following a
+/// game through its bank switches.
+pub fn trace_with_banks(cpu: &NesCpu) -> String {
+    format!("{} {}", trace(cpu), cpu.bus.bank_info())
+}
+
+fn format_registers<M: Mem>(cpu: &CPU<M>, asm_str: &str) -> String {
+    format!(
+        "{:47} {} P:{:02X} SP:{:02X}",
+        asm_str.to_ascii_uppercase(),
+        format_accumulator_and_index(cpu),
+        cpu.status.bits(),
+        cpu.stack_pointer,
+    )
+}
+
+/// `A:C0 X:01 Y:00`, shared by trace lines and the CPU's `Display`.
+pub(crate) fn format_accumulator_and_index<M: Mem>(cpu: &CPU<M>) -> String {
+    format!(
+        "A:{:02X} X:{:02X} Y:{:02X}",
+        cpu.register_a, cpu.register_x, cpu.register_y
+    )
+}
+
+/// `NV-BDIZC=10100100`: the flag letters over their bits.
+pub(crate) fn format_flags(status: CpuFlags) -> String {
+    format!("NV-BDIZC={:08b}", status.bits())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::{test_rom, Rom};
+    use crate::cpu::NesCpu;
+    use crate::flat_ram::FlatRam;
+    use std::ops::ControlFlow;
+
+    // RAM with a status register at $2002 that, like the PPU's, clears its
+    // top bit when read
+    struct StatusDevice {
+        ram: FlatRam,
+        status: u8,
+        reads: u32,
+    }
+
+    impl Mem for StatusDevice {
+        fn mem_read(&mut self, addr: u16) -> u8 {
+            if addr != 0x2002 {
+                return self.ram.mem_read(addr);
+            }
+            self.reads += 1;
+            let status = self.status;
+            self.status &= 0x7f;
+            status
+        }
+
+        fn mem_peek(&self, addr: u16) -> u8 {
+            match addr {
+                0x2002 => self.status,
+                _ => self.ram.mem_peek(addr),
+            }
+        }
+
+        fn mem_write(&mut self, addr: u16, data: u8) {
+            self.ram.mem_write(addr, data);
+        }
+    }
+
+    // places `code` in PRG ROM so it appears at `origin` in the CPU's address space
+    fn cpu_with_rom_code(origin: u16, code: &[u8]) -> NesCpu {
+        let mut rom = Rom::empty();
+        let start = (origin - 0x8000) as usize;
+        rom.prg_rom[start..start + code.len()].copy_from_slice(code);
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.program_counter = origin;
+        cpu
+    }
+
+    fn trace_until_brk(cpu: &mut NesCpu) -> Vec<String> {
+        let mut result = vec![];
+        cpu.run_with_callback(|cpu, _| {
+            result.push(trace(cpu));
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        result
+    }
+
+    #[test]
+    fn test_matches_the_start_of_nestest() {
+        let mut rom = Rom::empty();
+        // JMP $C5F5
+        rom.prg_rom[0x4000..0x4003].copy_from_slice(&[0x4c, 0xf5, 0xc5]);
+        // LDX #$00; STX $00; STX $10; STX $11; JSR $C72D
+        rom.prg_rom[0x45f5..0x4600].copy_from_slice(&[
+            0xa2, 0x00, 0x86, 0x00, 0x86, 0x10, 0x86, 0x11, 0x20, 0x2d, 0xc7,
+        ]);
+        // NOP; SEC; BCS $C735
+        rom.prg_rom[0x472d..0x4731].copy_from_slice(&[0xea, 0x38, 0xb0, 0x04]);
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.program_counter = 0xc000;
+
+        let result = trace_until_brk(&mut cpu);
+        let expected = [
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD",
+            "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD",
+            "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD",
+            "C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD",
+            "C5FB  86 11     STX $11 = 00                    A:00 X:00 Y:00 P:26 SP:FD",
+            "C5FD  20 2D C7  JSR $C72D                       A:00 X:00 Y:00 P:26 SP:FD",
+            "C72D  EA        NOP                             A:00 X:00 Y:00 P:26 SP:FB",
+            "C72E  38        SEC                             A:00 X:00 Y:00 P:26 SP:FB",
+            "C72F  B0 04     BCS $C735                       A:00 X:00 Y:00 P:27 SP:FB",
+        ];
+        assert_eq!(&result[..expected.len()], &expected[..]);
+    }
+
+    #[test]
+    fn test_format_indexed_modes() {
+        // LDA $33,X; LDX $40,Y; LDA $0633,X; LDA $0633,Y
+        let mut cpu = cpu_with_rom_code(
+            0xc000,
+            &[0xb5, 0x33, 0xb6, 0x40, 0xbd, 0x33, 0x06, 0xb9, 0x33, 0x06],
+        );
+        cpu.register_x = 0x02;
+        cpu.register_y = 0x05;
+        cpu.mem_write(0x35, 0x11);
+        cpu.mem_write(0x45, 0x03);
+        cpu.mem_write(0x0636, 0x22);
+        cpu.mem_write(0x0638, 0x33);
+
+        let result = trace_until_brk(&mut cpu);
+        assert_eq!(
+            result[..4],
+            [
+                "C000  B5 33     LDA $33,X @ 35 = 11             A:00 X:02 Y:05 P:24 SP:FD",
+                "C002  B6 40     LDX $40,Y @ 45 = 03             A:11 X:02 Y:05 P:24 SP:FD",
+                "C004  BD 33 06  LDA $0633,X @ 0636 = 22         A:11 X:03 Y:05 P:24 SP:FD",
+                "C007  B9 33 06  LDA $0633,Y @ 0638 = 33         A:22 X:03 Y:05 P:24 SP:FD",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_indirect_modes() {
+        // LDA ($80,X); ORA ($33),Y; JMP ($0200)
+        let mut cpu = cpu_with_rom_code(0xc000, &[0xa1, 0x80, 0x11, 0x33, 0x6c, 0x00, 0x02]);
+        cpu.register_y = 0x04;
+        cpu.mem_write_u16(0x80, 0x0300);
+        cpu.mem_write(0x0300, 0x5a);
+        cpu.mem_write_u16(0x33, 0x0400);
+        cpu.mem_write(0x0404, 0xaa);
+        cpu.mem_write_u16(0x0200, 0xc100);
+
+        let result = trace_until_brk(&mut cpu);
+        assert_eq!(
+            result[..3],
+            [
+                "C000  A1 80     LDA ($80,X) @ 80 = 0300 = 5A    A:00 X:00 Y:04 P:24 SP:FD",
+                "C002  11 33     ORA ($33),Y = 0400 @ 0404 = AA  A:5A X:00 Y:04 P:24 SP:FD",
+                "C004  6C 00 02  JMP ($0200) = C100              A:FA X:00 Y:04 P:A4 SP:FD",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_accumulator_and_backward_branch() {
+        // loop: ASL A; BNE loop
+        let mut cpu = cpu_with_rom_code(0xc000, &[0x0a, 0xd0, 0xfd]);
+        cpu.register_a = 0x40;
+
+        let result = trace_until_brk(&mut cpu);
+        assert_eq!(
+            result[..3],
+            [
+                "C000  0A        ASL A                           A:40 X:00 Y:00 P:24 SP:FD",
+                "C001  D0 FD     BNE $C000                       A:80 X:00 Y:00 P:A4 SP:FD",
+                "C000  0A        ASL A                           A:80 X:00 Y:00 P:A4 SP:FD",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_flags() {
+        assert_eq!(
+            format_flags(CpuFlags::NEGATIVE | CpuFlags::RESERVED | CpuFlags::INTERRUPT_DISABLE),
+            "NV-BDIZC=10100100"
+        );
+        assert_eq!(format_flags(CpuFlags::empty()), "NV-BDIZC=00000000");
+    }
+
+    #[test]
+    fn test_trace_does_not_touch_state() {
+        // LDA $2002 would fault on a real read
+        let cpu = cpu_with_rom_code(0xc000, &[0xad, 0x02, 0x20]);
+        let line = trace(&cpu);
+        assert!(
+            line.starts_with("C000  AD 02 20  LDA $2002 = 00"),
+            "{}",
+            line
+        );
+
+        let mut cpu = cpu;
+        assert_eq!(cpu.bus.take_fault(), None);
+    }
+
+    #[test]
+    fn test_trace_peeks_devices_without_side_effects() {
+        let mut ram = FlatRam::new();
+        // LDA $2002
+        ram.mem_write_slice(0x0600, &[0xad, 0x02, 0x20]);
+        let mut cpu = CPU::new(StatusDevice {
+            ram,
+            status: 0x80,
+            reads: 0,
+        });
+        cpu.program_counter = 0x0600;
+
+        let line = trace(&cpu);
+        assert!(
+            line.starts_with("0600  AD 02 20  LDA $2002 = 80"),
+            "{}",
+            line
+        );
+        assert_eq!(cpu.mem_peek(0x2002), 0x80);
+        assert_eq!(cpu.bus.reads, 0);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.register_a, 0x80);
+        assert_eq!(cpu.bus.reads, 1);
+        assert_eq!(cpu.mem_peek(0x2002), 0x00);
+    }
+
+    #[test]
+    fn test_trace_with_banks_follows_bank_switches() {
+        // LDA #$01; STA $8000 on UxROM
+        let rom = test_rom()
+            .mapper(2)
+            .prg_banks(4)
+            .prg_bytes(0x0000, &[0xa9, 0x01, 0x8d, 0x00, 0x80])
+            .build();
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.program_counter = 0x8000;
+        let line = trace_with_banks(&cpu);
+        assert!(line.starts_with(&trace(&cpu)), "{}", line);
+        assert!(line.contains(" PRG 8000:00 A000:00 C000:03"), "{}", line);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let line = trace_with_banks(&cpu);
+        assert!(line.contains(" PRG 8000:01 A000:01 C000:03"), "{}", line);
+    }
+}